@@ -0,0 +1,217 @@
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::inliner::{Decision, Expansion};
+use crate::parser::function_db::{Definition, FunctionDatabase};
+use crate::rewriter;
+
+/// A single self-contained C file built around an expansion
+pub struct Amalgamation {
+    pub source: String,
+    /// Source files whose file-scope declarations were copied in
+    pub files: Vec<PathBuf>,
+    /// Functions still called after expansion, in the order they are defined
+    pub functions: Vec<String>,
+}
+
+/// Build a SQLite-style amalgamation of the expansion of `entry`: the project headers inlined once each,
+/// the file-scope declarations of every file contributing code, the functions
+/// that are still called after expansion (callees first), then the expansion.
+pub fn amalgamate(db: &FunctionDatabase, entry: &Definition, expansion: &Expansion, project_root: &Path) -> Result<Amalgamation> {
+    let functions = remaining_functions(db, expansion);
+    if functions.iter().any(|def| def.signature.name == expansion.entry_point) {
+        anyhow::bail!(
+            "{} is called back from its own callees and cannot be replaced by its expansion",
+            expansion.entry_point
+        );
+    }
+
+    // Every file contributing code may hold globals and types that code uses
+    let mut files = vec![entry.source_file.clone()];
+    let contributing = expansion.reports.iter()
+        .filter(|report| report.decision == Decision::Inline)
+        .filter_map(|report| db.get_function_definition(&report.callee))
+        .chain(functions.iter().cloned())
+        .map(|def| def.source_file.clone());
+    for file in contributing {
+        if !files.contains(&file) && !is_header(&file) {
+            files.push(file);
+        }
+    }
+
+    let mut includer = Includer {
+        project_root: project_root.canonicalize().unwrap_or_else(|_| project_root.to_path_buf()),
+        include_dirs: include_dirs(db, &files),
+        seen: HashSet::new(),
+        system_includes: HashSet::new(),
+        output: format!("/* Amalgamation of {} and everything it calls */\n", expansion.entry_point),
+    };
+
+    for file in &files {
+        let text = std::fs::read_to_string(file)
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", file.display(), e))?;
+        includer.output.push_str(&format!("\n/************** Declarations from {} **************/\n", file.display()));
+        includer.process(file, &without_definitions(db, file, &text))?;
+    }
+
+    let mut output = includer.output;
+    output.push_str("\n/************** Functions still called **************/\n");
+    for def in &functions {
+        output.push_str(&format!("{}{};\n", if def.is_static { "static " } else { "" }, def.signature.to_c_declaration()));
+    }
+    for def in &functions {
+        output.push('\n');
+        if def.is_static {
+            output.push_str("static ");
+        }
+        output.push_str(&def.signature.to_c_definition());
+        output.push('\n');
+        output.push_str(&rewriter::render(&rewriter::tokenize(&def.body)));
+    }
+
+    output.push_str("\n/************** Expansion **************/\n");
+    output.push_str(&expansion.source);
+
+    Ok(Amalgamation {
+        source: output,
+        files,
+        functions: functions.iter().map(|def| def.signature.name.clone()).collect(),
+    })
+}
+
+/// Defined functions the expansion still calls, directly or through each
+/// other, callees before callers. Those defined in headers come with them.
+fn remaining_functions(db: &FunctionDatabase, expansion: &Expansion) -> Vec<Arc<Definition>> {
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    let kept: BTreeSet<&str> = expansion.reports.iter()
+        .filter(|report| report.decision != Decision::Inline)
+        .map(|report| report.callee.as_str())
+        .collect();
+
+    for name in kept {
+        visit(db, name, &mut visited, &mut order);
+    }
+    order.retain(|def| !is_header(&def.source_file));
+    order
+}
+
+fn visit(db: &FunctionDatabase, name: &str, visited: &mut HashSet<String>, order: &mut Vec<Arc<Definition>>) {
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+    let Some(def) = db.get_function_definition(name).filter(|def| !def.body.is_empty()) else {
+        return;
+    };
+    for call in &def.calls {
+        visit(db, &call.function_name, visited, order);
+    }
+    order.push(def);
+}
+
+/// `text` with the definitions of every function in the database cut out
+fn without_definitions(db: &FunctionDatabase, file: &Path, text: &str) -> String {
+    let mut extents: Vec<_> = db.iter()
+        .filter(|def| def.source_file == file && !def.extent.is_empty() && def.extent.end <= text.len())
+        .map(|def| def.extent.clone())
+        .collect();
+    extents.sort_by_key(|extent| extent.start);
+
+    let mut out = String::with_capacity(text.len());
+    let mut position = 0;
+    for extent in extents {
+        if extent.start < position {
+            continue;
+        }
+        out.push_str(&text[position..extent.start]);
+        position = extent.end;
+    }
+    out.push_str(&text[position..]);
+    out
+}
+
+/// `-I` directories of the compile commands of `files`, made absolute
+fn include_dirs(db: &FunctionDatabase, files: &[PathBuf]) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for unit in files.iter().filter_map(|file| db.unit_for(file)) {
+        let mut arguments = unit.arguments.iter();
+        while let Some(argument) = arguments.next() {
+            let dir = match argument.strip_prefix("-I") {
+                Some("") => arguments.next().cloned(),
+                Some(dir) => Some(dir.to_string()),
+                None => None,
+            };
+            if let Some(dir) = dir {
+                let dir = unit.directory.join(dir);
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+    }
+    dirs
+}
+
+fn is_header(file: &Path) -> bool {
+    file.extension().is_some_and(|ext| ext == "h")
+}
+
+/// Splices project headers into the output the first time they are included
+struct Includer {
+    project_root: PathBuf,
+    include_dirs: Vec<PathBuf>,
+    seen: HashSet<PathBuf>,
+    system_includes: HashSet<String>,
+    output: String,
+}
+
+impl Includer {
+    fn process(&mut self, file: &Path, text: &str) -> Result<()> {
+        for line in text.lines() {
+            let Some(target) = Self::include_target(line) else {
+                self.output.push_str(line);
+                self.output.push('\n');
+                continue;
+            };
+
+            match self.resolve(file, target) {
+                Some(header) => {
+                    if !self.seen.insert(header.clone()) {
+                        continue;
+                    }
+                    let contents = std::fs::read_to_string(&header)
+                        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", header.display(), e))?;
+                    self.output.push_str(&format!("/************** Begin {} **************/\n", target));
+                    self.process(&header, &contents)?;
+                    self.output.push_str(&format!("/************** End {} **************/\n", target));
+                }
+                None => {
+                    if self.system_includes.insert(line.trim().to_string()) {
+                        self.output.push_str(line.trim());
+                        self.output.push('\n');
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The quoted file name of an `#include "..."` line
+    fn include_target(line: &str) -> Option<&str> {
+        let directive = line.trim_start().strip_prefix('#')?.trim_start();
+        let target = directive.strip_prefix("include")?.trim();
+        target.strip_prefix('"')?.split('"').next()
+    }
+
+    /// A header inside the project, looked up like the compiler would
+    fn resolve(&self, file: &Path, target: &str) -> Option<PathBuf> {
+        let local = file.parent().map(|dir| dir.join(target));
+        local.into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(target)))
+            .filter_map(|candidate| candidate.canonicalize().ok())
+            .find(|candidate| candidate.starts_with(&self.project_root))
+    }
+}
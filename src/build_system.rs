@@ -0,0 +1,193 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::Result;
+
+use crate::compile_commands::{self, CompileEntry};
+use crate::{cmake, eclipse_project, iar_project, keil_project, makefile_parser, ninja_parser};
+
+/// How the compile commands of a project are obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildSystem {
+    /// A compile_commands.json already at the project root
+    CompilationDatabase,
+    /// The flags of a compile_flags.txt at the root, for every C file under it
+    CompileFlags,
+    /// Configured in a build directory exporting compile_commands.json
+    CMake,
+    /// What `make -nB` would run
+    Make,
+    /// From `ninja -t compdb`, or build.ninja itself, in a build directory
+    Ninja,
+    /// Set up in a build directory, where Meson always writes compile_commands.json
+    Meson,
+    /// The settings of an Eclipse CDT .cproject, as STM32CubeIDE writes
+    Eclipse,
+    /// The target settings of a Keil uVision .uvprojx
+    Keil,
+    /// The configuration settings of an IAR Embedded Workbench .ewp
+    Iar,
+}
+
+impl FromStr for BuildSystem {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "compdb" => Ok(BuildSystem::CompilationDatabase),
+            "flags" => Ok(BuildSystem::CompileFlags),
+            "cmake" => Ok(BuildSystem::CMake),
+            "make" => Ok(BuildSystem::Make),
+            "ninja" => Ok(BuildSystem::Ninja),
+            "meson" => Ok(BuildSystem::Meson),
+            "eclipse" => Ok(BuildSystem::Eclipse),
+            "keil" => Ok(BuildSystem::Keil),
+            "iar" => Ok(BuildSystem::Iar),
+            _ => Err(format!("unknown build system '{}', expected compdb, flags, cmake, make, ninja, meson, eclipse, keil or iar", s)),
+        }
+    }
+}
+
+impl BuildSystem {
+    /// The build system of the project in `directory`, by the files at its
+    /// root, an existing compile_commands.json first
+    pub fn detect(directory: &Path) -> Option<BuildSystem> {
+        if directory.join("compile_commands.json").is_file() {
+            Some(BuildSystem::CompilationDatabase)
+        } else if directory.join("compile_flags.txt").is_file() {
+            Some(BuildSystem::CompileFlags)
+        } else if cmake::is_cmake_project(directory) {
+            Some(BuildSystem::CMake)
+        } else if directory.join("meson.build").is_file() {
+            Some(BuildSystem::Meson)
+        } else if directory.join("build.ninja").is_file() {
+            Some(BuildSystem::Ninja)
+        } else if eclipse_project::is_eclipse_project(directory) {
+            Some(BuildSystem::Eclipse)
+        } else if keil_project::find_project(directory).is_some() {
+            Some(BuildSystem::Keil)
+        } else if iar_project::find_project(directory).is_some() {
+            Some(BuildSystem::Iar)
+        } else if makefile_parser::find_makefile(directory).is_some() {
+            Some(BuildSystem::Make)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildSystem::CompilationDatabase => "compile_commands.json",
+            BuildSystem::CompileFlags => "compile_flags.txt",
+            BuildSystem::CMake => "CMake",
+            BuildSystem::Make => "Make",
+            BuildSystem::Ninja => "Ninja",
+            BuildSystem::Meson => "Meson",
+            BuildSystem::Eclipse => "Eclipse CDT",
+            BuildSystem::Keil => "Keil uVision",
+            BuildSystem::Iar => "IAR Embedded Workbench",
+        }
+    }
+
+    /// Directory holding the compile_commands.json of the project in
+    /// `project`, generated first when the build system does not keep one.
+    /// `config` names the configuration or target of IDE projects to use.
+    pub fn database_dir(&self, project: &Path, make_args: &[String], cmake_args: &[String], config: Option<&str>) -> Result<PathBuf> {
+        match self {
+            BuildSystem::CompilationDatabase => {
+                if !project.join("compile_commands.json").is_file() {
+                    anyhow::bail!("Compile commands file not found at: {}", project.join("compile_commands.json").display());
+                }
+                Ok(project.to_path_buf())
+            }
+            BuildSystem::CompileFlags => {
+                let project = project.canonicalize()?;
+                write_generated(&compile_commands::from_compile_flags(&project)?, &project, "flags")
+            }
+            BuildSystem::CMake => cmake::configure(project, cmake_args),
+            BuildSystem::Make => {
+                tracing::info!("Generating compile_commands.json from what make -nB would run");
+                let entries = makefile_parser::generate(project, make_args)?;
+                if entries.is_empty() {
+                    anyhow::bail!("make -nB in {} runs no C compiler", project.display());
+                }
+                write_database(&entries, project)
+            }
+            BuildSystem::Ninja => {
+                tracing::info!("Generating compile_commands.json from build.ninja");
+                let entries = ninja_parser::generate(project)?;
+                if entries.is_empty() {
+                    anyhow::bail!("build.ninja in {} runs no C compiler", project.display());
+                }
+                write_database(&entries, project)
+            }
+            BuildSystem::Meson => meson_setup(project),
+            BuildSystem::Eclipse => {
+                let project = project.canonicalize()?;
+                write_generated(&eclipse_project::generate(&project, config)?, &project, "eclipse")
+            }
+            BuildSystem::Keil => {
+                let project = project.canonicalize()?;
+                let file = keil_project::find_project(&project)
+                    .ok_or_else(|| anyhow::anyhow!("No .uvprojx found in {}", project.display()))?;
+                write_generated(&keil_project::generate(&file, config)?, &project, "keil")
+            }
+            BuildSystem::Iar => {
+                let project = project.canonicalize()?;
+                let file = iar_project::find_project(&project)
+                    .ok_or_else(|| anyhow::anyhow!("No .ewp found in {}", project.display()))?;
+                write_generated(&iar_project::generate(&file, config)?, &project, "iar")
+            }
+        }
+    }
+}
+
+/// Write `entries`, made up from the settings of the project in `project`,
+/// in a build directory of their own, kept out of the project where the
+/// database would be found first next time
+fn write_generated(entries: &[CompileEntry], project: &Path, tool: &str) -> Result<PathBuf> {
+    if entries.is_empty() {
+        anyhow::bail!("No C files to compile under {}", project.display());
+    }
+    let dir = build_dir(project, tool);
+    std::fs::create_dir_all(&dir)?;
+    write_database(entries, &dir)
+}
+
+/// Write `entries` as the compile_commands.json of `directory`
+fn write_database(entries: &[CompileEntry], directory: &Path) -> Result<PathBuf> {
+    let path = directory.join("compile_commands.json");
+    compile_commands::write(entries, &path)?;
+    tracing::info!("Wrote {} compile commands to {}", entries.len(), path.display());
+    Ok(directory.to_path_buf())
+}
+
+/// Build directory `tool` configures the project in `source` in, named
+/// after the project and kept so that later runs only reconfigure
+pub fn build_dir(source: &Path, tool: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let name = source.file_name().and_then(|name| name.to_str()).unwrap_or("project");
+    std::env::temp_dir().join(format!("expansion-{}-{}-{:016x}", tool, name, hasher.finish()))
+}
+
+/// Set the Meson project in `source` up in a build directory of its own
+/// and return it, as `cmake::configure` does for CMake
+fn meson_setup(source: &Path) -> Result<PathBuf> {
+    let source = source.canonicalize()?;
+    let build = build_dir(&source, "meson");
+    tracing::info!("Setting {} up with Meson in {}", source.display(), build.display());
+    let output = Command::new("meson")
+        .arg("setup")
+        .args(build.join("meson-private").is_dir().then_some("--reconfigure"))
+        .arg(&build)
+        .arg(&source)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run meson: {}", e))?;
+    if !output.status.success() || !build.join("compile_commands.json").is_file() {
+        anyhow::bail!("meson setup failed for {}: {}", source.display(), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(build)
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::{CallGraph, CallGraphNode};
+use crate::parser::function_db::CallInfo;
+
+/// One function on the deepest call chain
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainFrame {
+    pub function: String,
+    /// Unset for functions defined outside the project
+    pub file: Option<PathBuf>,
+    /// Line of the call to the next frame, or of the definition for the last one
+    pub line: u32,
+    /// Members of the recursion group this frame enters, counted as
+    /// `recursion_depth` nested passes through the group
+    pub recursion: Option<Vec<String>>,
+}
+
+/// Deepest nesting of calls from the roots, in frames
+#[derive(Debug, Clone, Serialize)]
+pub struct CallDepthReport {
+    pub depth: usize,
+    /// How many times a recursion group is assumed to nest
+    pub recursion_depth: usize,
+    pub chain: Vec<ChainFrame>,
+}
+
+/// Heaviest chain below a strongly connected component, and the call it
+/// leaves the component through
+struct ComponentCost<'a> {
+    cost: usize,
+    exit: Option<(&'a str, &'a CallInfo)>,
+}
+
+/// Heaviest call chain below every function of a call graph, where each
+/// function weighs `cost(node)` and a recursion group weighs the cost of all
+/// its members times `recursion_depth`, since the parser cannot tell how deep
+/// the recursion goes
+pub struct ChainAnalysis<'a> {
+    call_graph: &'a CallGraph,
+    components: Vec<Vec<String>>,
+    component_of: HashMap<String, usize>,
+    costs: Vec<ComponentCost<'a>>,
+}
+
+impl<'a> ChainAnalysis<'a> {
+    pub fn new(call_graph: &'a CallGraph, recursion_depth: usize, cost: impl Fn(&CallGraphNode) -> usize) -> Self {
+        let components = call_graph.strongly_connected_components();
+        let component_of: HashMap<String, usize> = components.iter()
+            .enumerate()
+            .flat_map(|(i, group)| group.iter().map(move |name| (name.clone(), i)))
+            .collect();
+
+        // Tarjan's algorithm yields callees before their callers
+        let mut costs: Vec<ComponentCost> = Vec::with_capacity(components.len());
+        for (i, group) in components.iter().enumerate() {
+            let mut best = ComponentCost { cost: 0, exit: None };
+            for node in group.iter().filter_map(|name| call_graph.node(name)) {
+                for call in &node.calls {
+                    let Some(&callee) = component_of.get(&call.function_name) else {
+                        continue;
+                    };
+                    if callee != i && (best.exit.is_none() || costs[callee].cost > best.cost) {
+                        best = ComponentCost { cost: costs[callee].cost, exit: Some((node.function.signature.name.as_str(), call)) };
+                    }
+                }
+            }
+            let own: usize = group.iter().filter_map(|name| call_graph.node(name)).map(&cost).sum();
+            best.cost += if Self::is_recursive(call_graph, group) { own * recursion_depth } else { own };
+            costs.push(best);
+        }
+
+        ChainAnalysis { call_graph, components, component_of, costs }
+    }
+
+    fn is_recursive(call_graph: &CallGraph, group: &[String]) -> bool {
+        group.len() > 1 || call_graph.node(&group[0])
+            .is_some_and(|node| node.calls.iter().any(|call| call.function_name == group[0]))
+    }
+
+    /// Cost of the heaviest chain starting at `name`, itself included
+    pub fn cost(&self, name: &str) -> Option<usize> {
+        self.component_of.get(name).map(|&i| self.costs[i].cost)
+    }
+
+    /// The heaviest chain starting at `name`, one frame per function or
+    /// recursion group
+    pub fn chain(&self, name: &str) -> Vec<ChainFrame> {
+        let mut chain = Vec::new();
+        let mut current = self.component_of.get_key_value(name).map(|(name, &i)| (name.as_str(), i));
+        while let Some((name, i)) = current {
+            let group = &self.components[i];
+            let exit = self.costs[i].exit;
+            let node = self.call_graph.node(name);
+            let is_external = node.is_none_or(|node| node.function.signature.return_type == "extern");
+            // The call out of a recursion group may sit in another member's file
+            let file = match exit {
+                Some((caller, _)) => self.call_graph.node(caller).map(|node| node.function.source_file.clone()),
+                None if is_external => None,
+                None => node.map(|node| node.function.source_file.clone()),
+            };
+            chain.push(ChainFrame {
+                function: name.to_string(),
+                file,
+                line: exit.map(|(_, call)| call.line).or(node.map(|node| node.function.line)).unwrap_or(0),
+                recursion: Self::is_recursive(self.call_graph, group).then(|| group.clone()),
+            });
+            current = exit.and_then(|(_, call)| {
+                self.component_of.get_key_value(&call.function_name).map(|(name, &j)| (name.as_str(), j))
+            });
+        }
+        chain
+    }
+}
+
+impl CallDepthReport {
+    pub fn build(call_graph: &CallGraph, recursion_depth: usize) -> Self {
+        let analysis = ChainAnalysis::new(call_graph, recursion_depth, |_| 1);
+        let deepest_root = call_graph.roots().iter()
+            .filter_map(|root| analysis.cost(root).map(|depth| (root, depth)))
+            .max_by_key(|(_, depth)| *depth);
+
+        CallDepthReport {
+            depth: deepest_root.map(|(_, depth)| depth).unwrap_or(0),
+            recursion_depth,
+            chain: deepest_root.map(|(root, _)| analysis.chain(root)).unwrap_or_default(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Maximum Call Depth: {} frames", self.depth);
+        for (index, frame) in self.chain.iter().enumerate() {
+            let location = match &frame.file {
+                Some(file) => format!("{}:{}", file.display(), frame.line),
+                None => "(external)".to_string(),
+            };
+            println!("  {:>3}. {}  {}", index + 1, frame.function, location);
+            if let Some(group) = &frame.recursion {
+                println!("       recursion through {}, counted {} deep", group.join(", "), self.recursion_depth);
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -1,24 +1,273 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
 use graphviz_rust::cmd::{CommandArg, Format};
 use graphviz_rust::printer::PrinterContext;
 use graphviz_rust::dot_structures::*;
 use graphviz_rust::dot_generator::*;
 
+use crate::dominators::DominatorTree;
+use crate::scc;
+use crate::theme::{EdgeStyle, GraphTheme};
 use crate::parser::function_db::{Definition, FunctionDatabase, CallInfo, CallContext};
 
 #[derive(Debug, Clone)]
 pub struct CallGraphNode {
     pub function: Arc<Definition>,
     pub calls: Vec<CallInfo>,
+    /// Some of the calls were left out, by the depth limit or a filter
+    pub truncated: bool,
+}
+
+/// Page of the HTML viewer, with `{{title}}` and `{{graph}}` placeholders
+const VIEWER_TEMPLATE: &str = include_str!("viewer.html");
+
+/// A node as written to the JSON export
+#[derive(Serialize)]
+struct JsonNode<'a> {
+    name: &'a str,
+    /// None for external functions
+    file: Option<&'a Path>,
+    line: Option<u32>,
+    is_static: bool,
+    is_external: bool,
+    is_root: bool,
+    /// Size and complexity, none for external functions
+    lines: Option<u32>,
+    branches: Option<u32>,
+    call_count: Option<u32>,
+    cyclomatic_complexity: Option<u32>,
+    /// Formatted body, only included in the HTML viewer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+}
+
+/// A call as written to the JSON export
+#[derive(Serialize)]
+struct JsonEdge<'a> {
+    from: &'a str,
+    to: &'a str,
+    order: u32,
+    line: u32,
+    column: u32,
+    /// sequential, conditional, loop or switch
+    context: &'static str,
+    /// Branch of a conditional or case of a switch
+    branch: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct JsonGraph<'a> {
+    entry_point: &'a str,
+    roots: &'a [String],
+    nodes: Vec<JsonNode<'a>>,
+    edges: Vec<JsonEdge<'a>>,
+}
+
+/// Which defined functions a graph follows. Functions filtered out still
+/// appear where they are called, but their own calls are left out.
+#[derive(Debug, Clone, Default)]
+pub struct NodeFilter {
+    /// Directory file patterns are relative to
+    pub base_dir: PathBuf,
+    /// When not empty, only follow functions defined in files matching one of these
+    pub only_files: Vec<glob::Pattern>,
+    pub exclude_files: Vec<glob::Pattern>,
+    /// When not empty, only follow functions whose name matches one of these
+    pub only_functions: Vec<Regex>,
+    pub exclude_functions: Vec<Regex>,
+}
+
+impl NodeFilter {
+    fn follows(&self, def: &Definition) -> bool {
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let file = def.source_file.strip_prefix(&self.base_dir).unwrap_or(&def.source_file);
+        let file_matches = |patterns: &[glob::Pattern]| {
+            patterns.iter().any(|pattern| pattern.matches_path_with(file, options))
+        };
+        let name = def.signature.name.as_str();
+        let name_matches = |patterns: &[Regex]| patterns.iter().any(|pattern| pattern.is_match(name));
+
+        (self.only_files.is_empty() || file_matches(&self.only_files))
+            && !file_matches(&self.exclude_files)
+            && (self.only_functions.is_empty() || name_matches(&self.only_functions))
+            && !name_matches(&self.exclude_functions)
+    }
+}
+
+/// How far `CallGraph::build_from_roots` follows calls
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Only include functions at most this many calls away from a root
+    pub max_depth: Option<usize>,
+    pub filter: NodeFilter,
+}
+
+/// File formats the graph can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Dot,
+    Png,
+    Svg,
+    Pdf,
+    /// Client-side image map for the PNG, for use with `<img usemap>`
+    Cmapx,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Dot => "dot",
+            ExportFormat::Png => "png",
+            ExportFormat::Svg => "svg",
+            ExportFormat::Pdf => "pdf",
+            ExportFormat::Cmapx => "map",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(ExportFormat::Dot),
+            "png" => Ok(ExportFormat::Png),
+            "svg" => Ok(ExportFormat::Svg),
+            "pdf" => Ok(ExportFormat::Pdf),
+            "cmapx" => Ok(ExportFormat::Cmapx),
+            _ => Err(format!("unknown format '{}', expected dot, png, svg, pdf or cmapx", s)),
+        }
+    }
+}
+
+/// How `to_dot` shows functions without a definition in the project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExternalDisplay {
+    /// One node per external function
+    #[default]
+    Show,
+    /// A single node standing for all of them, labelled with their count
+    Collapse,
+    /// Leave them and the calls to them out
+    Hide,
+}
+
+impl FromStr for ExternalDisplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "show" => Ok(ExternalDisplay::Show),
+            "collapse" => Ok(ExternalDisplay::Collapse),
+            "hide" => Ok(ExternalDisplay::Hide),
+            _ => Err(format!("unknown external display '{}', expected show, collapse or hide", s)),
+        }
+    }
+}
+
+/// How `to_dot` groups the defined functions into Graphviz clusters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clustering {
+    #[default]
+    None,
+    /// One cluster per source file
+    File,
+    /// One cluster per top-level directory of the project
+    Directory,
+}
+
+impl FromStr for Clustering {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Clustering::None),
+            "file" => Ok(Clustering::File),
+            "directory" => Ok(Clustering::Directory),
+            _ => Err(format!("unknown clustering '{}', expected none, file or directory", s)),
+        }
+    }
+}
+
+/// Rendering choices for `to_dot`
+#[derive(Debug, Clone, Default)]
+pub struct DotOptions {
+    pub externals: ExternalDisplay,
+    pub clusters: Clustering,
+    /// Directory cluster names are relative to
+    pub base_dir: PathBuf,
+    /// Draw one edge per caller and callee, as thick as the number of call
+    /// sites, instead of one edge per call site
+    pub weighted_edges: bool,
+    pub theme: GraphTheme,
+}
+
+impl DotOptions {
+    /// Name of the cluster holding a defined function, if any
+    fn cluster_of(&self, def: &Definition) -> Option<String> {
+        let file = def.source_file.strip_prefix(&self.base_dir).unwrap_or(&def.source_file);
+        match self.clusters {
+            Clustering::None => None,
+            Clustering::File => Some(file.to_string_lossy().replace('\\', "/")),
+            Clustering::Directory => {
+                let mut components = file.components();
+                let first = components.next()?;
+                // Files at the top of the project form their own cluster
+                Some(if components.next().is_some() {
+                    first.as_os_str().to_string_lossy().to_string()
+                } else {
+                    ".".to_string()
+                })
+            }
+        }
+    }
+}
+
+/// How nodes link back to the definitions they represent in rendered graphs
+#[derive(Debug, Clone)]
+pub struct SourceLinks {
+    /// URL with `{file}`, `{line}` and `{name}` placeholders, e.g.
+    /// `https://github.com/org/repo/blob/main/{file}#L{line}`
+    pub url_template: String,
+    /// Directory `{file}` is made relative to
+    pub base_dir: PathBuf,
+}
+
+impl SourceLinks {
+    fn relative_file(&self, file: &Path) -> String {
+        file.strip_prefix(&self.base_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn url(&self, def: &Definition) -> String {
+        self.url_template
+            .replace("{file}", &self.relative_file(&def.source_file))
+            .replace("{line}", &def.line.to_string())
+            .replace("{name}", &def.signature.name)
+    }
 }
 
 pub struct CallGraph {
-    nodes: HashMap<String, CallGraphNode>,
+    /// Sorted by name so that DOT output and summaries are reproducible
+    nodes: BTreeMap<String, CallGraphNode>,
     entry_point: String,
+    roots: Vec<String>,
+    source_links: Option<SourceLinks>,
+    /// Calls to emphasize, by caller and call order
+    highlighted: HashSet<(String, u32)>,
+    /// Functions to outline in the highlight color
+    highlighted_functions: HashSet<String>,
 
     // Graphviz elements to visualize our graph
     graph : graphviz_rust::dot_structures::Graph,
@@ -26,49 +275,130 @@ pub struct CallGraph {
 }
 
 impl CallGraph {
-    fn setup_graph() -> Graph{
+    fn setup_graph(theme: &GraphTheme) -> Graph{
+        let rankdir = Self::quote(&theme.rankdir);
+        let splines = Self::quote(&theme.splines);
+        let fontname = Self::quote(&theme.fontname);
+        let fontsize = theme.fontsize.to_string();
+        let edge_fontsize = theme.fontsize.saturating_sub(2).to_string();
+
         let mut graph = graph!(di id!("CallGraph"));
-        graph.add_stmt(attr!("rankdir", "TB").into());
-        graph.add_stmt(attr!("splines", "ortho").into());
+        graph.add_stmt(attr!("rankdir", rankdir).into());
+        graph.add_stmt(attr!("splines", splines).into());
         graph.add_stmt(attr!("nodesep", "0.8").into());
         graph.add_stmt(attr!("ranksep", "0.8").into());
-        graph.add_stmt(attr!("fontname", "\"Helvetica\"").into());
-        graph.add_stmt(GraphAttributes::new("node",vec![
-                attr!("shape", "box"),
-                attr!("fontname", "\"Helvetica\""),
-                attr!("fontsize", "10")
-        ]).into());
-        graph.add_stmt(GraphAttributes::new("edge",vec![
-                attr!("fontsize", "8")
-        ]).into());
+        graph.add_stmt(attr!("fontname", fontname).into());
+        if let Some(background) = &theme.background {
+            let background = Self::quote(background);
+            graph.add_stmt(attr!("bgcolor", background).into());
+        }
+
+        let mut node_attributes = vec![
+            attr!("shape", "box"),
+            attr!("fontname", fontname),
+            attr!("fontsize", fontsize)
+        ];
+        let mut edge_attributes = vec![
+            attr!("fontsize", edge_fontsize)
+        ];
+        if let Some(font_color) = &theme.font_color {
+            let font_color = Self::quote(font_color);
+            graph.add_stmt(attr!("fontcolor", font_color).into());
+            graph.add_stmt(attr!("color", font_color).into());
+            node_attributes.push(attr!("fontcolor", font_color));
+            node_attributes.push(attr!("color", font_color));
+            edge_attributes.push(attr!("fontcolor", font_color));
+        }
+        graph.add_stmt(GraphAttributes::new("node", node_attributes).into());
+        graph.add_stmt(GraphAttributes::new("edge", edge_attributes).into());
 
         graph
     }
 
     pub fn build(db: &FunctionDatabase, entry_point: &str) -> Result<Self> {
-        let mut nodes = HashMap::new();
-        let mut visited = HashSet::new();
-        let mut queue = VecDeque::new();
+        Self::build_from_roots(db, entry_point, &[entry_point.to_string()], &BuildOptions::default())
+    }
+
+    /// Build a single graph covering everything reachable from any of `roots`,
+    /// labelled `name` in summaries, within the limits of `options`
+    pub fn build_from_roots(db: &FunctionDatabase, name: &str, roots: &[String], options: &BuildOptions) -> Result<Self> {
+        let roots = roots.iter()
+            .map(|root| Ok((root.clone(), db.find_function(root)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Self::build_from_resolved(db, name, roots, options)
+    }
+
+    /// Like `build_from_roots`, from definitions already chosen among those
+    /// sharing a name, as `FILE:FUNCTION` entry points pick a static
+    pub fn build_from_definitions(db: &FunctionDatabase, name: &str, roots: &[Arc<Definition>], options: &BuildOptions) -> Result<Self> {
+        let roots = roots.iter()
+            .map(|root| (root.signature.name.clone(), Some(Arc::clone(root))))
+            .collect();
+        Self::build_from_resolved(db, name, roots, options)
+    }
 
-        queue.push_back(entry_point.to_string());
+    fn build_from_resolved(
+        db: &FunctionDatabase,
+        name: &str,
+        roots: Vec<(String, Option<Arc<Definition>>)>,
+        options: &BuildOptions,
+    ) -> Result<Self> {
+        let mut nodes: BTreeMap<String, CallGraphNode> = BTreeMap::new();
+        let mut visited = HashSet::new();
+        // Each function is queued with the file of the caller reaching it, which
+        // decides between statics of the same name
+        let mut queue: VecDeque<(String, usize, Option<PathBuf>)> = VecDeque::new();
+        for (root, _) in &roots {
+            queue.push_back((root.clone(), 0, None));
+        }
+        let root_definition = |name: &str| roots.iter().find(|(root, _)| root == name).map(|(_, def)| def.clone());
+        let mut frontier = Vec::new();
 
-        while let Some(func_name) = queue.pop_front() {
+        while let Some((func_name, depth, caller_file)) = queue.pop_front() {
             if visited.contains(&func_name) {
+                if let Some(file) = &caller_file
+                    && let (Some(node), Some(def)) = (nodes.get(&func_name), db.resolve_call(&func_name, file))
+                    && !Arc::ptr_eq(&node.function, &def)
+                {
+                    tracing::warn!(
+                        "static functions named {} in {} and {} are both reachable; the graph only shows the first",
+                        func_name,
+                        node.function.source_file.display(),
+                        def.source_file.display()
+                    );
+                }
                 continue;
             }
             visited.insert(func_name.clone());
 
-            if let Some(def) = db.get_function_definition(&func_name) {
-                // Queue callees for processing
-                for call in &def.calls {
-                    if !visited.contains(&call.function_name) {
-                        queue.push_back(call.function_name.clone());
+            let def = match &caller_file {
+                Some(file) => db.resolve_call(&func_name, file),
+                None => root_definition(&func_name).flatten(),
+            };
+            if let Some(def) = def {
+                let at_limit = options.max_depth.is_some_and(|max| depth >= max);
+                let filtered = root_definition(&func_name).is_none() && !options.filter.follows(&def);
+                if filtered {
+                    nodes.insert(func_name.clone(), CallGraphNode {
+                        function: Arc::clone(&def),
+                        calls: vec![],
+                        truncated: !def.calls.is_empty(),
+                    });
+                    continue;
+                }
+                if at_limit {
+                    frontier.push(func_name.clone());
+                } else {
+                    // Queue callees for processing
+                    for call in &def.calls {
+                        queue.push_back((call.function_name.clone(), depth + 1, Some(def.source_file.clone())));
                     }
                 }
 
                 nodes.insert(func_name.clone(), CallGraphNode {
                     function: Arc::clone(&def),
                     calls: def.calls.clone(),
+                    truncated: false,
                 });
             } else {
                 // External function - no definition available
@@ -82,11 +412,22 @@ impl CallGraph {
                         ..Default::default()
                     }),
                     calls: vec![],
+                    truncated: false,
                 });
             }
         }
 
-        let graph = Self::setup_graph();
+        // Calls out of the depth limit are dropped, those back into the graph kept
+        let included: HashSet<String> = nodes.keys().cloned().collect();
+        for name in frontier {
+            if let Some(node) = nodes.get_mut(&name) {
+                let before = node.calls.len();
+                node.calls.retain(|call| included.contains(&call.function_name));
+                node.truncated = node.calls.len() < before;
+            }
+        }
+
+        let graph = Self::setup_graph(&GraphTheme::default());
         let mut printer_ctx = PrinterContext::default();
 
         printer_ctx
@@ -96,12 +437,235 @@ impl CallGraph {
 
         Ok(CallGraph {
             nodes,
-            entry_point: entry_point.to_string(),
+            entry_point: name.to_string(),
+            roots: roots.into_iter().map(|(root, _)| root).collect(),
+            source_links: None,
+            highlighted: HashSet::new(),
+            highlighted_functions: HashSet::new(),
             graph,
             printer_ctx
         })
     }
 
+    /// Add a node for every function-like macro of the project the graph's
+    /// functions invoke, and route the calls each macro makes through its
+    /// node. Returns the number of macros added.
+    pub fn show_macros(&mut self, db: &FunctionDatabase) -> usize {
+        let functions: HashSet<String> = self.nodes.keys().cloned().collect();
+        let mut macros: BTreeMap<String, CallGraphNode> = BTreeMap::new();
+        for node in self.nodes.values_mut() {
+            for expansion in &node.function.macro_expansions {
+                let Some(definition) = db.macros_named(&expansion.name).first() else {
+                    continue;
+                };
+                if functions.contains(&expansion.name) {
+                    continue;
+                }
+                macros.entry(expansion.name.clone()).or_insert_with(|| CallGraphNode {
+                    function: Arc::new(Definition {
+                        signature: crate::parser::function_db::Signature {
+                            name: definition.name.clone(),
+                            return_type: "macro".to_string(),
+                            ..Default::default()
+                        },
+                        body: definition.to_c(),
+                        source_file: definition.source_file.clone(),
+                        line: definition.line,
+                        ..Default::default()
+                    }),
+                    calls: vec![],
+                    truncated: false,
+                });
+            }
+
+            // One edge per invocation, whatever number of calls it makes
+            let mut invocations = HashSet::new();
+            node.calls.retain_mut(|call| {
+                let Some(macro_node) = call.macro_call.as_ref().and_then(|m| macros.get_mut(&m.name)) else {
+                    return true;
+                };
+                if !macro_node.calls.iter().any(|made| made.function_name == call.function_name) {
+                    macro_node.calls.push(CallInfo {
+                        order: macro_node.calls.len() as u32 + 1,
+                        context: CallContext::Sequential,
+                        context_depth: 0,
+                        macro_call: None,
+                        ..call.clone()
+                    });
+                }
+                call.function_name = macro_node.function.signature.name.clone();
+                invocations.insert((call.line, call.column))
+            });
+        }
+
+        let added = macros.len();
+        self.nodes.extend(macros);
+        added
+    }
+
+    /// Contract every edge through a pass-through wrapper into a direct edge
+    /// to the function it wraps, and drop the wrappers. Roots are kept.
+    /// Returns the number of wrappers removed.
+    pub fn collapse_wrappers(&mut self) -> usize {
+        let wrapped: HashMap<String, String> = self.nodes.iter()
+            .filter(|(name, _)| !self.roots.contains(name))
+            .filter_map(|(name, node)| Some((name.clone(), node.function.as_wrapper()?.callee)))
+            .collect();
+
+        // Follow chains of wrappers, stopping if they loop back on themselves
+        let resolve = |name: &str| {
+            let mut target = name.to_string();
+            let mut seen = HashSet::new();
+            while let Some(next) = wrapped.get(&target) {
+                if !seen.insert(target.clone()) {
+                    break;
+                }
+                target = next.clone();
+            }
+            target
+        };
+
+        for node in self.nodes.values_mut() {
+            for call in &mut node.calls {
+                if wrapped.contains_key(&call.function_name) {
+                    call.function_name = resolve(&call.function_name);
+                }
+            }
+        }
+
+        let before = self.nodes.len();
+        self.nodes.retain(|name, _| !wrapped.contains_key(name) || resolve(name) == *name);
+        before - self.nodes.len()
+    }
+
+    /// Every call in the graph, indexed by callee
+    pub fn reverse_index(&self) -> BTreeMap<&str, Vec<(&str, &CallInfo)>> {
+        let mut index: BTreeMap<&str, Vec<(&str, &CallInfo)>> = BTreeMap::new();
+        for (name, node) in &self.nodes {
+            for call in &node.calls {
+                index.entry(call.function_name.as_str()).or_default().push((name, call));
+            }
+        }
+        index
+    }
+
+    /// Every function `name` reaches through a chain of calls, itself included
+    pub fn reachable_from(&self, name: &str) -> BTreeSet<String> {
+        let mut visited = BTreeSet::new();
+        let mut queue = VecDeque::from([name]);
+        while let Some(current) = queue.pop_front() {
+            let Some((current, node)) = self.nodes.get_key_value(current) else {
+                continue;
+            };
+            if visited.insert(current.clone()) {
+                queue.extend(node.calls.iter().map(|call| call.function_name.as_str()));
+            }
+        }
+        visited
+    }
+
+    /// Direct calls to `name`, as (caller, call) pairs
+    pub fn callers_of(&self, name: &str) -> Vec<(&str, &CallInfo)> {
+        self.reverse_index().remove(name).unwrap_or_default()
+    }
+
+    /// Every function that can reach `name` through a chain of calls, nearest first
+    pub fn transitive_callers(&self, name: &str) -> Vec<String> {
+        let index = self.reverse_index();
+        let mut found = Vec::new();
+        let mut visited = HashSet::from([name]);
+        let mut queue = VecDeque::from([name]);
+        while let Some(current) = queue.pop_front() {
+            for (caller, _) in index.get(current).into_iter().flatten() {
+                if visited.insert(caller) {
+                    found.push(caller.to_string());
+                    queue.push_back(caller);
+                }
+            }
+        }
+        found
+    }
+
+    /// Keep only `name` and the functions that can reach it, and the calls between them
+    pub fn retain_callers_of(&mut self, name: &str) {
+        let keep: HashSet<String> = self.transitive_callers(name)
+            .into_iter()
+            .chain(std::iter::once(name.to_string()))
+            .collect();
+        self.nodes.retain(|name, _| keep.contains(name));
+        for node in self.nodes.values_mut() {
+            node.calls.retain(|call| keep.contains(&call.function_name));
+        }
+    }
+
+    /// Call chains from `from` to `to` that visit no function twice, as
+    /// (caller, call) steps, at most `max_paths` of them
+    pub fn paths_between(&self, from: &str, to: &str, max_paths: usize) -> Vec<Vec<(&str, &CallInfo)>> {
+        let mut paths = Vec::new();
+        let Some((from, _)) = self.nodes.get_key_value(from) else {
+            return paths;
+        };
+        let mut current = Vec::new();
+        let mut on_path = HashSet::from([from.as_str()]);
+        self.collect_paths(from, to, max_paths, &mut current, &mut on_path, &mut paths);
+        paths
+    }
+
+    fn collect_paths<'a>(
+        &'a self,
+        name: &'a str,
+        to: &str,
+        max_paths: usize,
+        current: &mut Vec<(&'a str, &'a CallInfo)>,
+        on_path: &mut HashSet<&'a str>,
+        paths: &mut Vec<Vec<(&'a str, &'a CallInfo)>>,
+    ) {
+        let Some((name, node)) = self.nodes.get_key_value(name) else {
+            return;
+        };
+        for call in &node.calls {
+            if paths.len() >= max_paths {
+                return;
+            }
+            let callee = call.function_name.as_str();
+            current.push((name.as_str(), call));
+            if callee == to {
+                paths.push(current.clone());
+            } else if on_path.insert(callee) {
+                self.collect_paths(callee, to, max_paths, current, on_path, paths);
+                on_path.remove(callee);
+            }
+            current.pop();
+        }
+    }
+
+    /// Draw the call of `caller` with this order in bold red in the next `to_dot`
+    pub fn highlight_call(&mut self, caller: &str, order: u32) {
+        self.highlighted.insert((caller.to_string(), order));
+    }
+
+    /// Highlight every function and call on a chain from the roots to `name`
+    /// in the next `to_dot`, and return how many functions that covers
+    pub fn highlight_paths_to(&mut self, name: &str) -> usize {
+        if !self.nodes.contains_key(name) {
+            return 0;
+        }
+        let on_path: HashSet<String> = self.transitive_callers(name)
+            .into_iter()
+            .chain(std::iter::once(name.to_string()))
+            .collect();
+        for caller in &on_path {
+            for call in &self.nodes[caller].calls {
+                if on_path.contains(&call.function_name) {
+                    self.highlighted.insert((caller.clone(), call.order));
+                }
+            }
+        }
+        let count = on_path.len();
+        self.highlighted_functions.extend(on_path);
+        count
+    }
+
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
@@ -110,113 +674,598 @@ impl CallGraph {
         self.nodes.values().map(|n| n.calls.len()).sum()
     }
 
-    pub fn to_dot(&mut self) {
+    pub fn roots(&self) -> &[String] {
+        &self.roots
+    }
+
+    pub fn entry_point(&self) -> &str {
+        &self.entry_point
+    }
+
+    /// Attach a clickable URL to every defined node in the next `to_dot`
+    pub fn set_source_links(&mut self, links: SourceLinks) {
+        self.source_links = Some(links);
+    }
+
+    pub fn node(&self, name: &str) -> Option<&CallGraphNode> {
+        self.nodes.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CallGraphNode> + '_ {
+        self.nodes.values()
+    }
+
+    pub fn external_count(&self) -> usize {
+        self.nodes.values()
+            .filter(|n| n.function.signature.return_type == "extern")
+            .count()
+    }
+
+    pub fn static_count(&self) -> usize {
+        self.nodes.values()
+            .filter(|n| n.function.is_static)
+            .count()
+    }
+
+    /// Every strongly connected component of the graph, each sorted by name.
+    /// Components come callees first: no component calls into a later one.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<String>> {
+        let names: Vec<&String> = self.nodes.keys().collect();
+        let position: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+        let successors: Vec<Vec<usize>> = self.nodes.values()
+            .map(|node| node.calls.iter().filter_map(|call| position.get(call.function_name.as_str()).copied()).collect())
+            .collect();
+        scc::strongly_connected_components(&successors)
+            .into_iter()
+            .map(|component| {
+                let mut group: Vec<String> = component.into_iter().map(|i| names[i].clone()).collect();
+                group.sort();
+                group
+            })
+            .collect()
+    }
+
+    /// Groups of mutually recursive functions, self-recursive functions included
+    pub fn recursion_groups(&self) -> Vec<Vec<String>> {
+        self.strongly_connected_components()
+            .into_iter()
+            .filter(|group| {
+                group.len() > 1 || self.nodes.get(&group[0])
+                    .is_some_and(|n| n.calls.iter().any(|c| c.function_name == group[0]))
+            })
+            .collect()
+    }
+
+    /// Which functions gate access to which others from the roots
+    pub fn dominator_tree(&self) -> DominatorTree {
+        DominatorTree::build(self)
+    }
+
+    /// Calls from one member of `group` to another, as (caller, call) pairs.
+    /// These are the call sites closing the cycle.
+    pub fn cycle_call_sites<'a>(&'a self, group: &[String]) -> Vec<(&'a str, &'a CallInfo)> {
+        group.iter()
+            .filter_map(|name| self.nodes.get_key_value(name))
+            .flat_map(|(name, node)| node.calls.iter()
+                .filter(|call| group.contains(&call.function_name))
+                .map(move |call| (name.as_str(), call)))
+            .collect()
+    }
+
+    /// Total body size of every defined function reachable from the entry point
+    pub fn original_size(&self) -> usize {
+        self.nodes.values().map(|n| n.function.body_size()).sum()
+    }
+
+    /// Estimated size of the roots once every defined callee is flattened
+    /// into them. Each call site contributes a full copy of its callee, and calls
+    /// between the functions of a recursion cycle only count their own call
+    /// site, as the bottom-up inliner leaves them.
+    pub fn estimate_flattened_size(&self) -> usize {
+        let mut group_of = HashMap::new();
+        for (index, group) in self.strongly_connected_components().into_iter().enumerate() {
+            group_of.extend(group.into_iter().map(|name| (name, index)));
+        }
+        let graph_callees = |def: &Definition, stack: &[(String, PathBuf)]| {
+            let Some(node) = self.nodes.get(&def.signature.name) else {
+                return Vec::new();
+            };
+            let group = group_of.get(&def.signature.name);
+            node.calls.iter()
+                .filter(|call| !stack.iter().any(|(name, _)| *name == call.function_name) && group_of.get(&call.function_name) != group)
+                .filter_map(|call| self.nodes.get(&call.function_name))
+                .map(|callee| Arc::clone(&callee.function))
+                .collect()
+        };
+        self.roots.iter()
+            .filter_map(|root| self.nodes.get(root))
+            .map(|root| flattened_size(&root.function, &[], graph_callees))
+            .fold(0, usize::saturating_add)
+    }
+
+    pub fn to_dot(&mut self, options: &DotOptions) {
+        const EXTERNALS_ID: &str = "external_functions__";
+        let theme = &options.theme;
+        self.graph = Self::setup_graph(theme);
+
+        let external_count = self.external_count();
+        let external_color = Self::quote(&theme.nodes.external);
+        if options.externals == ExternalDisplay::Collapse && external_count > 0 {
+            let label = format!("\"{} external functions\"", external_count);
+            self.graph.add_stmt(Node::new(node_id!(EXTERNALS_ID), vec![
+                attr!("label", label.to_string()),
+                attr!("fillcolor", external_color),
+                attr!("style", "\"filled,dashed\""),
+            ]).into());
+        }
+
+        let mut clusters: BTreeMap<String, Vec<Stmt>> = BTreeMap::new();
         for (name, node) in &self.nodes {
             let node_id = Self::sanitize_id(name);
             let is_external = node.function.signature.return_type == "extern";
-            let is_entry = name == &self.entry_point;
+            let is_entry = self.roots.contains(name);
+            if is_external && options.externals != ExternalDisplay::Show {
+                continue;
+            }
 
+            let is_macro = node.function.signature.return_type == "macro";
             let label = if is_external {
                 format!("\"{}\\n(external)\"", name)
+            } else if is_macro {
+                format!("\"{}()\\n(macro)\"", name)
             } else {
                 let source = node.function.source_file
                     .file_name()
                     .and_then(|f| f.to_str())
                     .unwrap_or("?");
-                format!("\"{}\\n{}\"", name, source)
+                let metrics = &node.function.metrics;
+                let mut lines = vec![name.clone()];
+                if theme.labels.show_file {
+                    lines.push(source.to_string());
+                }
+                if theme.labels.show_metrics {
+                    lines.push(format!("{} lines, CC {}", metrics.lines, metrics.cyclomatic_complexity()));
+                }
+                format!("\"{}\"", lines.join("\\n"))
             };
 
             let (fillcolor, style) = if is_entry {
-                ("\"#90EE90\"", "filled")
+                (&theme.nodes.entry, "filled")
             } else if is_external {
-                ("\"#D3D3D3\"", "\"filled,dashed\"")
+                (&theme.nodes.external, "\"filled,dashed\"")
             } else if node.function.is_static {
-                ("\"#FFFACD\"", "filled")
+                (&theme.nodes.static_function, "filled")
             } else {
-                ("\"#E6F3FF\"", "filled")
+                (&theme.nodes.defined, "filled")
             };
+            let fillcolor = Self::quote(fillcolor);
 
-            self.graph.add_stmt(
-                node!(node_id.to_string();
-                    attr!("label", label.to_string()),
-                    attr!("fillcolor", fillcolor),
-                    attr!("style", style))
-                .into());
+            let mut attributes = vec![
+                attr!("label", label.to_string()),
+                attr!("fillcolor", fillcolor),
+                attr!("style", style),
+            ];
+            if is_macro {
+                attributes.push(attr!("shape", "hexagon"));
+            }
+            // A double border marks nodes whose calls were left out
+            if node.truncated {
+                attributes.push(attr!("peripheries", "2"));
+            }
+            if self.highlighted_functions.contains(name) {
+                let color = Self::quote(&theme.edges.highlight.color);
+                attributes.push(attr!("color", color));
+                attributes.push(attr!("penwidth", "3"));
+            }
+
+            if !is_external {
+                // Hovering a node in the SVG shows its full signature and where it is defined
+                let source_file = std::path::absolute(&node.function.source_file)
+                    .unwrap_or_else(|_| node.function.source_file.clone());
+                let signature = if is_macro {
+                    node.function.body.clone()
+                } else {
+                    format!("{}{}", if node.function.is_static { "static " } else { "" }, node.function.signature.to_c())
+                };
+                let brief = node.function.brief()
+                    .map(|brief| format!("\\n{}", Self::escape(&brief)))
+                    .unwrap_or_default();
+                let tooltip = format!(
+                    "\"{}\\n{}:{}{}\"",
+                    Self::escape(&signature),
+                    Self::escape(&source_file.display().to_string()),
+                    node.function.line,
+                    brief
+                );
+                attributes.push(attr!("tooltip", tooltip.to_string()));
+
+                if let Some(links) = &self.source_links {
+                    let url = Self::quote(&links.url(&node.function));
+                    attributes.push(attr!("URL", url.to_string()));
+                    attributes.push(attr!("target", "\"_blank\""));
+                }
+            }
+
+            let stmt = Node::new(node_id!(node_id), attributes).into();
+            match (!is_external).then(|| options.cluster_of(&node.function)).flatten() {
+                Some(cluster) => clusters.entry(cluster).or_default().push(stmt),
+                None => self.graph.add_stmt(stmt),
+            }
         }
 
+        for (index, (cluster, mut stmts)) in clusters.into_iter().enumerate() {
+            let label = Self::quote(&cluster);
+            stmts.insert(0, attr!("label", label.to_string()).into());
+            stmts.insert(1, attr!("style", "rounded").into());
+            self.graph.add_stmt(Subgraph { id: Id::Plain(format!("cluster_{}", index)), stmts }.into());
+        }
 
         // Add edges with order labels and context-based styling
         for (name, node) in &self.nodes {
             let from_id = Self::sanitize_id(name);
+            let mut external_calls = 0;
+            let mut by_callee: Vec<(&str, Vec<&CallInfo>)> = Vec::new();
 
             for call in &node.calls {
                 let to_id = Self::sanitize_id(&call.function_name);
-
-                match &call.context {
-                    CallContext::Sequential => {
-                        let label = format!("\"{}\"",call.order);
-                        self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id),
-                        vec![
-                            attr!("color", "\"#333333\""),
-                            attr!("label", label.to_string()),
-                        ]).into());
+                let is_external = self.nodes.get(&call.function_name)
+                    .is_some_and(|callee| callee.function.signature.return_type == "extern");
+                if is_external && options.externals != ExternalDisplay::Show {
+                    external_calls += 1;
+                    continue;
+                }
+                if options.weighted_edges {
+                    match by_callee.iter_mut().find(|(callee, _)| *callee == call.function_name) {
+                        Some((_, calls)) => calls.push(call),
+                        None => by_callee.push((&call.function_name, vec![call])),
                     }
+                    continue;
+                }
+
+                let (edge_style, label) = match &call.context {
+                    CallContext::Sequential => (&theme.edges.sequential, format!("\"{}\"", call.order)),
                     CallContext::Conditional { branch_id } => {
-                        let label = format!("\"{}:if{}\"",call.order, branch_id);
-                        self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id),
-                        vec![
-                            attr!("color", "\"#333333\""),
-                            attr!("style", "dashed"),
-                            attr!("label", label.to_string()),
-                        ]).into());
-                    }
-                    CallContext::Loop => {
-                        let label = format!("\"{}:loop\"",call.order);
-                        self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id),
-                        vec![
-                            attr!("color", "\"#4ECDC4\""),
-                            attr!("style", "bold"),
-                            attr!("label", label.to_string()),
-                        ]).into());
-                    }
-                    CallContext::Switch { case_id } => {
-                        let label = format!("\"{}:case{}\"",call.order, case_id);
-                        self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id),
-                        vec![
-                            attr!("color", "\"#9B59B6\""),
-                            attr!("label", label.to_string()),
-                        ]).into());
+                        (&theme.edges.conditional, format!("\"{}:if{}\"", call.order, branch_id))
                     }
+                    CallContext::Loop => (&theme.edges.in_loop, format!("\"{}:loop\"", call.order)),
+                    CallContext::Switch { case_id, value } => (&theme.edges.switch, format!("\"{}:{}\"", call.order, Self::case_label(*case_id, *value))),
+                };
+                let label = match &call.macro_call {
+                    Some(macro_call) => format!("{}\\n{}\"", label.trim_end_matches('"'), macro_call.label()),
+                    None => label,
                 };
 
+                let highlighted = self.highlighted.contains(&(name.clone(), call.order));
+                let color = Self::quote(if highlighted { &theme.edges.highlight.color } else { &edge_style.color });
+                let mut attributes = vec![
+                    attr!("color", color),
+                    attr!("label", label.to_string()),
+                ];
+                if let Some(style) = &edge_style.style {
+                    let style = Self::quote(style);
+                    attributes.push(attr!("style", style));
+                }
+                if highlighted {
+                    attributes.push(attr!("penwidth", "3"));
+                }
+                self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id), attributes).into());
+
+            }
+
+            // Calls made from a loop are drawn in the loop color, and the edge
+            // thickens with each call site up to a limit
+            for (callee, calls) in by_callee {
+                let to_id = Self::sanitize_id(callee);
+                let in_loop = calls.iter().any(|call| call.context == CallContext::Loop);
+                let highlighted = calls.iter().any(|call| self.highlighted.contains(&(name.clone(), call.order)));
+                let edge_style: &EdgeStyle = if in_loop { &theme.edges.in_loop } else { &theme.edges.sequential };
+                let color = Self::quote(if highlighted { &theme.edges.highlight.color } else { &edge_style.color });
+                let penwidth = format!("\"{:.1}\"", (1.0 + (calls.len() - 1) as f64 * 0.75).min(6.0));
+                let label = if in_loop {
+                    format!("\"{}x loop\"", calls.len())
+                } else {
+                    format!("\"{}x\"", calls.len())
+                };
+                let mut attributes = vec![
+                    attr!("color", color),
+                    attr!("penwidth", penwidth.to_string()),
+                    attr!("weight", calls.len()),
+                ];
+                if calls.len() > 1 || in_loop {
+                    attributes.push(attr!("label", label.to_string()));
+                }
+                if let Some(style) = &edge_style.style {
+                    let style = Self::quote(style);
+                    attributes.push(attr!("style", style));
+                }
+                self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id), attributes).into());
+            }
+
+            // One edge per caller into the collapsed node, labelled with its call count
+            if options.externals == ExternalDisplay::Collapse && external_calls > 0 {
+                let label = format!("\"{} calls\"", external_calls);
+                self.graph.add_stmt(edge!(node_id!(from_id) => node_id!(EXTERNALS_ID), vec![
+                    attr!("color", external_color),
+                    attr!("style", "dashed"),
+                    attr!("label", label.to_string()),
+                ]).into());
             }
         }
     }
 
-    /// Export the graph to a PNG file
-    pub fn export_png(&mut self, output_path: &Path) -> Result<()> {
-        graphviz_rust::exec(
-            &self.graph,
-            &mut self.printer_ctx,
-            vec![
-                CommandArg::Format(Format::Png),
-                CommandArg::Output(output_path.to_string_lossy().to_string()),
-            ],
-        ).map_err(|e| anyhow::anyhow!("Failed to generate PNG: {}", e))?;
+    /// The graph as JSON, nodes sorted by name and edges in call order
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.json_graph(false))?)
+    }
+
+    /// The graph as `to_json` writes it, as a value to embed in other JSON
+    pub fn json_value(&self) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(self.json_graph(false))?)
+    }
+
+    fn json_graph(&self, with_bodies: bool) -> JsonGraph<'_> {
+        let nodes = self.nodes.iter()
+            .map(|(name, node)| {
+                let is_external = node.function.signature.return_type == "extern";
+                let metrics = node.function.metrics;
+                let body = (with_bodies && !is_external).then(|| {
+                    let signature = node.function.signature.to_c();
+                    let body = crate::rewriter::render(&crate::rewriter::tokenize(&node.function.body));
+                    format!("{}{}\n{}", if node.function.is_static { "static " } else { "" }, signature, body)
+                });
+                JsonNode {
+                    name,
+                    file: (!is_external).then_some(node.function.source_file.as_path()),
+                    line: (!is_external).then_some(node.function.line),
+                    is_static: node.function.is_static,
+                    is_external,
+                    is_root: self.roots.contains(name),
+                    lines: (!is_external).then_some(metrics.lines),
+                    branches: (!is_external).then_some(metrics.branches),
+                    call_count: (!is_external).then_some(metrics.call_count),
+                    cyclomatic_complexity: (!is_external).then(|| metrics.cyclomatic_complexity()),
+                    body,
+                }
+            })
+            .collect();
+
+        let edges = self.nodes.iter()
+            .flat_map(|(name, node)| node.calls.iter().map(move |call| {
+                let (context, branch) = Self::context_name(&call.context);
+                JsonEdge {
+                    from: name,
+                    to: &call.function_name,
+                    order: call.order,
+                    line: call.line,
+                    column: call.column,
+                    context,
+                    branch,
+                }
+            }))
+            .collect();
+
+        JsonGraph {
+            entry_point: &self.entry_point,
+            roots: &self.roots,
+            nodes,
+            edges,
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// The graph as GraphML, readable by yEd and Gephi. Node and edge
+    /// attributes mirror the JSON export.
+    pub fn to_graphml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        for (id, target, kind) in [
+            ("label", "node", "string"),
+            ("file", "node", "string"),
+            ("line", "node", "int"),
+            ("static", "node", "boolean"),
+            ("external", "node", "boolean"),
+            ("root", "node", "boolean"),
+            ("order", "edge", "int"),
+            ("line", "edge", "int"),
+            ("context", "edge", "string"),
+            ("branch", "edge", "int"),
+        ] {
+            out.push_str(&format!(
+                "  <key id=\"{}_{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>\n",
+                target, id, target, id, kind
+            ));
+        }
+        out.push_str(&format!("  <graph id=\"{}\" edgedefault=\"directed\">\n", Self::xml_escape(&self.entry_point)));
+
+        let data = |out: &mut String, indent: &str, key: &str, value: &str| {
+            out.push_str(&format!("{}<data key=\"{}\">{}</data>\n", indent, key, Self::xml_escape(value)));
+        };
+        for (name, node) in &self.nodes {
+            let is_external = node.function.signature.return_type == "extern";
+            out.push_str(&format!("    <node id=\"{}\">\n", Self::xml_escape(name)));
+            data(&mut out, "      ", "node_label", name);
+            if !is_external {
+                data(&mut out, "      ", "node_file", &node.function.source_file.display().to_string());
+                data(&mut out, "      ", "node_line", &node.function.line.to_string());
+            }
+            data(&mut out, "      ", "node_static", &node.function.is_static.to_string());
+            data(&mut out, "      ", "node_external", &is_external.to_string());
+            data(&mut out, "      ", "node_root", &self.roots.contains(name).to_string());
+            out.push_str("    </node>\n");
+        }
+
+        for (name, node) in &self.nodes {
+            for call in &node.calls {
+                let (context, branch) = Self::context_name(&call.context);
+                out.push_str(&format!(
+                    "    <edge source=\"{}\" target=\"{}\">\n",
+                    Self::xml_escape(name),
+                    Self::xml_escape(&call.function_name)
+                ));
+                data(&mut out, "      ", "edge_order", &call.order.to_string());
+                data(&mut out, "      ", "edge_line", &call.line.to_string());
+                data(&mut out, "      ", "edge_context", context);
+                if let Some(branch) = branch {
+                    data(&mut out, "      ", "edge_branch", &branch.to_string());
+                }
+                out.push_str("    </edge>\n");
+            }
+        }
+
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+
+    pub fn save_graphml(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, self.to_graphml())?;
+        Ok(())
+    }
+
+    /// The graph as a Mermaid `graph TD` block, ready to paste into Markdown.
+    /// Nodes and edges are styled like the DOT output.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = String::from("graph TD\n");
+        out.push_str("    classDef entry fill:#90EE90\n");
+        out.push_str("    classDef external fill:#D3D3D3,stroke-dasharray:4 2\n");
+        out.push_str("    classDef static fill:#FFFACD\n");
+        out.push_str("    classDef defined fill:#E6F3FF\n");
+
+        // Prefixed so that names like `end` do not collide with Mermaid keywords
+        let id = |name: &str| format!("f_{}", Self::sanitize_id(name));
+        let escape = |text: &str| text.replace('"', "#quot;");
+
+        for (name, node) in &self.nodes {
+            let is_external = node.function.signature.return_type == "extern";
+            let label = if is_external {
+                format!("{}<br/>(external)", name)
+            } else {
+                let source = node.function.source_file
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .unwrap_or("?");
+                format!("{}<br/>{}", name, source)
+            };
+            let class = if self.roots.contains(name) {
+                "entry"
+            } else if is_external {
+                "external"
+            } else if node.function.is_static {
+                "static"
+            } else {
+                "defined"
+            };
+            out.push_str(&format!("    {}[\"{}\"]:::{}\n", id(name), escape(&label), class));
+        }
+
+        // linkStyle refers to edges by their position in the block
+        let mut link_styles = Vec::new();
+        let mut edge_index = 0;
+        for (name, node) in &self.nodes {
+            for call in &node.calls {
+                let (arrow, label, color) = match &call.context {
+                    CallContext::Sequential => ("-->", call.order.to_string(), None),
+                    CallContext::Conditional { branch_id } => ("-.->", format!("{}:if{}", call.order, branch_id), None),
+                    CallContext::Loop => ("==>", format!("{}:loop", call.order), Some("#4ECDC4")),
+                    CallContext::Switch { case_id, value } => ("-->", format!("{}:{}", call.order, Self::case_label(*case_id, *value)), Some("#9B59B6")),
+                };
+                let label = match &call.macro_call {
+                    Some(macro_call) => format!("{}<br/>{}", label, macro_call.label()),
+                    None => label,
+                };
+                out.push_str(&format!(
+                    "    {} {}|\"{}\"| {}\n",
+                    id(name),
+                    arrow,
+                    label,
+                    id(&call.function_name)
+                ));
+                if let Some(color) = color {
+                    link_styles.push(format!("    linkStyle {} stroke:{}\n", edge_index, color));
+                }
+                edge_index += 1;
+            }
+        }
+        out.extend(link_styles);
+        out
+    }
 
+    pub fn save_mermaid(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, self.to_mermaid())?;
         Ok(())
     }
 
-    pub fn export_svg(&mut self, output_path: &Path) -> Result<()> {
+    /// The graph in Callgrind's format, for KCachegrind. Each function costs
+    /// its body size in tokens, and each call site counts as one call costing
+    /// the callee's body size.
+    pub fn to_callgrind(&self) -> String {
+        let mut out = String::from("# callgrind format\nversion: 1\ncreator: code-inliner\n");
+        out.push_str(&format!("cmd: {}\n", self.entry_point));
+        out.push_str("positions: line\nevents: Tokens\n");
+
+        let file_of = |def: &Definition| {
+            if def.signature.return_type == "extern" {
+                "(external)".to_string()
+            } else {
+                def.source_file.display().to_string()
+            }
+        };
 
+        for (name, node) in &self.nodes {
+            out.push_str(&format!("\nfl={}\nfn={}\n", file_of(&node.function), name));
+            out.push_str(&format!("{} {}\n", node.function.line, node.function.body_size()));
+            for call in &node.calls {
+                let (callee_file, callee_line, callee_size) = match self.nodes.get(&call.function_name) {
+                    Some(callee) => (file_of(&callee.function), callee.function.line, callee.function.body_size()),
+                    None => ("(external)".to_string(), 0, 0),
+                };
+                out.push_str(&format!("cfl={}\ncfn={}\n", callee_file, call.function_name));
+                out.push_str(&format!("calls=1 {}\n", callee_line));
+                out.push_str(&format!("{} {}\n", call.line, callee_size));
+            }
+        }
+        out
+    }
+
+    pub fn save_callgrind(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, self.to_callgrind())?;
+        Ok(())
+    }
+
+    /// Write a standalone HTML viewer: the graph and function bodies are
+    /// embedded as JSON and shown as a collapsible tree from the roots, with
+    /// a search box and each body displayed on click
+    pub fn export_html(&self, output_path: &Path) -> Result<()> {
+        // Keep the data from closing the script element it sits in
+        let data = serde_json::to_string(&self.json_graph(true))?.replace("</", "<\\/");
+        let html = VIEWER_TEMPLATE
+            .replace("{{title}}", &Self::xml_escape(&self.entry_point))
+            .replace("{{graph}}", &data);
+        std::fs::write(output_path, html)?;
+        Ok(())
+    }
+
+    /// Render the graph with Graphviz in `format`. DOT is written as built,
+    /// without running a layout.
+    pub fn export(&mut self, output_path: &Path, format: ExportFormat) -> Result<()> {
+        let graphviz_format = match format {
+            ExportFormat::Dot => return self.save_dot(output_path),
+            ExportFormat::Png => Format::Png,
+            ExportFormat::Svg => Format::Svg,
+            ExportFormat::Pdf => Format::Pdf,
+            ExportFormat::Cmapx => Format::Cmapx,
+        };
         graphviz_rust::exec(
             &self.graph,
             &mut self.printer_ctx,
             vec![
-                CommandArg::Format(Format::Svg),
+                CommandArg::Format(graphviz_format),
                 CommandArg::Output(output_path.to_string_lossy().to_string()),
             ],
-        ).map_err(|e| anyhow::anyhow!("Failed to generate SVG: {}", e))?;
+        ).map_err(|e| anyhow::anyhow!("Failed to generate {}: {}", format.extension().to_uppercase(), e))?;
 
         Ok(())
     }
@@ -232,6 +1281,40 @@ impl CallGraph {
         Ok(())
     }
 
+    /// Name of a call context in exports, with its branch or case number
+    fn context_name(context: &CallContext) -> (&'static str, Option<u32>) {
+        match *context {
+            CallContext::Sequential => ("sequential", None),
+            CallContext::Conditional { branch_id } => ("conditional", Some(branch_id)),
+            CallContext::Loop => ("loop", None),
+            CallContext::Switch { case_id, .. } => ("switch", Some(case_id)),
+        }
+    }
+
+    /// `case 4` for a label of known value, else `case2` after the case number
+    fn case_label(case_id: u32, value: Option<i64>) -> String {
+        match value {
+            Some(value) => format!("case {}", value),
+            None => format!("case{}", case_id),
+        }
+    }
+
+    fn xml_escape(value: &str) -> String {
+        value.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    fn quote(value: &str) -> String {
+        format!("\"{}\"", Self::escape(value))
+    }
+
+    /// Escape a value for use inside a quoted DOT string
+    fn escape(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('"', "\\\"")
+    }
+
     fn sanitize_id(name: &str) -> String {
         name.replace(|c: char| !c.is_alphanumeric() && c != '_', "_")
     }
@@ -240,17 +1323,107 @@ impl CallGraph {
     pub fn print_summary(&self) {
         println!("Call Graph Summary:");
         println!("  Entry point: {}", self.entry_point);
+        if self.roots.len() > 1 {
+            println!("  Roots: {}", self.roots.len());
+        }
         println!("  Total nodes: {}", self.node_count());
         println!("  Total edges: {}", self.edge_count());
 
-        let external_count = self.nodes.values()
-            .filter(|n| n.function.signature.return_type == "extern")
-            .count();
-        let static_count = self.nodes.values()
-            .filter(|n| n.function.is_static)
-            .count();
+        println!("  External functions: {}", self.external_count());
+        println!("  Static functions: {}", self.static_count());
+    }
+}
+
+/// Estimated size in tokens of `root` once flattened into the functions of
+/// `outer`: each callee that `callees` gives for a function, while the
+/// functions of the stack are being flattened around it, adds a full copy
+/// of its own flattened body
+pub fn flattened_size(
+    root: &Definition,
+    outer: &[(String, PathBuf)],
+    mut callees: impl FnMut(&Definition, &[(String, PathBuf)]) -> Vec<Arc<Definition>>,
+) -> usize {
+    let mut stack = outer.to_vec();
+    stack.push((root.signature.name.clone(), root.source_file.clone()));
+    flattened_size_of(root, &mut stack, &mut HashMap::new(), &mut callees).0
+}
+
+/// Flattened size of `def`, and whether a call into the stack was cut below
+/// it. Such a size depends on what is being flattened around `def`, so only
+/// the others are kept in `memo` for the other calls of the same function.
+fn flattened_size_of(
+    def: &Definition,
+    stack: &mut Vec<(String, PathBuf)>,
+    memo: &mut HashMap<(String, PathBuf), usize>,
+    callees: &mut impl FnMut(&Definition, &[(String, PathBuf)]) -> Vec<Arc<Definition>>,
+) -> (usize, bool) {
+    let mut size = def.body_size();
+    let mut cut = def.calls.iter().any(|call| stack.iter().any(|(name, _)| *name == call.function_name));
+    for callee in callees(def, stack) {
+        let key = (callee.signature.name.clone(), callee.source_file.clone());
+        let callee_size = match memo.get(&key) {
+            Some(callee_size) => *callee_size,
+            None => {
+                stack.push(key.clone());
+                let (callee_size, callee_cut) = flattened_size_of(&callee, stack, memo, callees);
+                stack.pop();
+                if !callee_cut {
+                    memo.insert(key, callee_size);
+                }
+                cut |= callee_cut;
+                callee_size
+            }
+        };
+        size = size.saturating_add(callee_size);
+    }
+    (size, cut)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, size: usize, callees: &[&str]) -> Arc<Definition> {
+        Arc::new(Definition {
+            signature: crate::parser::function_db::Signature { name: name.to_string(), ..Default::default() },
+            body: vec!["x"; size].join(" "),
+            calls: callees.iter()
+                .map(|callee| CallInfo { function_name: callee.to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn cuts_cycles_from_where_they_are_entered() {
+        // a -> b -> c -> b, a -> c: from a, c flattens to c + b the second time
+        let functions: HashMap<String, Arc<Definition>> = [
+            function("a", 1, &["b", "c"]),
+            function("b", 10, &["c"]),
+            function("c", 100, &["b"]),
+        ].into_iter().map(|def| (def.signature.name.clone(), def)).collect();
+        let size = flattened_size(&functions["a"], &[], |def, stack| def.calls.iter()
+            .filter(|call| !stack.iter().any(|(name, _)| *name == call.function_name))
+            .filter_map(|call| functions.get(&call.function_name).cloned())
+            .collect());
+        assert_eq!(size, 1 + (10 + 100) + (100 + 10));
+    }
 
-        println!("  External functions: {}", external_count);
-        println!("  Static functions: {}", static_count);
+    #[test]
+    fn reuses_sizes_of_acyclic_callees() {
+        // d is reached twice and counted twice
+        let functions: HashMap<String, Arc<Definition>> = [
+            function("a", 1, &["b", "c"]),
+            function("b", 10, &["d"]),
+            function("c", 100, &["d"]),
+            function("d", 1000, &[]),
+        ].into_iter().map(|def| (def.signature.name.clone(), def)).collect();
+        let mut visits = 0;
+        let size = flattened_size(&functions["a"], &[], |def, _| {
+            visits += 1;
+            def.calls.iter().filter_map(|call| functions.get(&call.function_name).cloned()).collect()
+        });
+        assert_eq!(size, 1 + (10 + 1000) + (100 + 1000));
+        assert_eq!(visits, 4);
     }
 }
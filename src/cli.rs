@@ -1,18 +1,577 @@
-use std::path::PathBuf;
-use clap::Parser;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::{Parser, Subcommand};
+
+use expansion::call_graph::{Clustering, ExportFormat, ExternalDisplay};
+use expansion::coupling::CouplingSort;
+use expansion::parse_report::DiagnosticsFormat;
+use expansion::build_system::BuildSystem;
+use expansion::parser::types::DataModel;
+use expansion::inliner::{DynamicStackPolicy, ExpansionMode, ShortCircuitPolicy, VariadicPolicy, parse_variadic_override};
 
 #[derive(Parser, Debug)]
 #[command(name = "code-inliner")]
-#[command(about = "Inline function calls in main")]
+#[command(about = "Call graph analysis and inline expansion for C projects")]
 pub struct Args {
+    /// Directory of the C project, needed by every command but `db diff`
     #[arg(short, long, value_name="DIR")]
-    pub project_path : PathBuf,
+    pub project_path : Option<PathBuf>,
+
+    /// Also log what is being parsed and decided (-v), or every step (-vv)
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose : u8,
+
+    /// Only print warnings, errors and results, without progress lines
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet : bool,
+
+    /// Keep what each translation unit contributed in FILE, and on the next
+    /// run only parse again those whose source, included headers or compile
+    /// flags changed. JSON when FILE ends in `.json`, compact binary otherwise
+    #[arg(long, value_name="FILE", global = true)]
+    pub db_cache : Option<PathBuf>,
+
+    /// Parse up to N translation units at once, each in a process of its
+    /// own; defaults to the number of CPUs
+    #[arg(short, long, value_name="N", global = true)]
+    pub jobs : Option<usize>,
+
+    /// Arguments passed to clang after those of each compile command, e.g.
+    /// `--extra-clang-args "-std=c11 -DNDEBUG"`; split on spaces, repeatable
+    #[arg(long, value_name="ARGS", global = true, allow_hyphen_values = true, value_delimiter = ' ')]
+    pub extra_clang_args : Vec<String>,
+
+    /// Target triple to parse for, e.g. `arm-none-eabi`, so type sizes and
+    /// built-in defines are the target's. Taken from the compiler of each
+    /// command when it is a GCC cross compiler like `arm-none-eabi-gcc`
+    #[arg(long, value_name="TRIPLE", global = true)]
+    pub target : Option<String>,
+
+    /// Root of the target's headers and libraries
+    #[arg(long, value_name="DIR", global = true)]
+    pub sysroot : Option<PathBuf>,
+
+    /// System include directory of the toolchain, searched before those a
+    /// GCC cross compiler reports (repeatable)
+    #[arg(long, value_name="DIR", global = true)]
+    pub toolchain_include : Vec<PathBuf>,
+
+    /// Directory to look in first for headers a compile command cannot
+    /// find, relative to the project (repeatable). The project is searched
+    /// by file name after these
+    #[arg(long, value_name="DIR", global = true)]
+    pub fallback_include : Vec<PathBuf>,
+
+    /// Header to precompile once per set of compile flags, as spelled in
+    /// `#include`, e.g. `stm32f4xx_hal.h` (repeatable). Used for the files
+    /// including every such header, which must not depend on macros the
+    /// files define before including them
+    #[arg(long, value_name="HEADER", global = true)]
+    pub pch : Vec<String>,
+
+    /// Directory of the precompiled headers of the parent process
+    #[arg(long, value_name="DIR", global = true, hide = true)]
+    pub pch_dir : Option<PathBuf>,
+
+    /// Report the warnings and errors clang gave for each translation unit
+    /// and the bodies they may have cut short: `text` prints them, `json`
+    /// saves parse_report.json
+    #[arg(long, value_name="FORMAT", global = true)]
+    pub diagnostics : Option<DiagnosticsFormat>,
+
+    /// Size locals and inlined parameters for this ABI rather than the one
+    /// clang parsed for: ilp32, lp64, llp64, i386, msp430 or avr
+    #[arg(long, value_name="MODEL", global = true)]
+    pub data_model : Option<DataModel>,
+
+    /// Pass ARG to make when generating compile_commands.json from a
+    /// Makefile, e.g. a target or `CC=arm-none-eabi-gcc`; repeatable
+    #[arg(long = "make-arg", value_name="ARG", allow_hyphen_values = true, global = true)]
+    pub make_args : Vec<String>,
+
+    /// Pass ARG to cmake when configuring a CMake project without a
+    /// compile_commands.json, e.g. `-DCMAKE_TOOLCHAIN_FILE=arm.cmake`; repeatable
+    #[arg(long = "cmake-arg", value_name="ARG", allow_hyphen_values = true, global = true)]
+    pub cmake_args : Vec<String>,
+
+    /// Get compile commands this way rather than by the files at the
+    /// project root: compdb, flags, cmake, make, ninja, meson, eclipse, keil
+    /// or iar
+    #[arg(long, value_name="KIND", global = true)]
+    pub build_system : Option<BuildSystem>,
+
+    /// Configuration to parse: of the compile commands of a file compiled
+    /// more than once, as for Debug and Release, the one whose flags or
+    /// directory mention NAME; for Eclipse, Keil and IAR projects, the
+    /// configuration or target named NAME
+    #[arg(long, value_name="NAME", global = true)]
+    pub config : Option<String>,
+
+    /// Read compile_commands.json from DIR rather than the project path,
+    /// e.g. an existing build directory
+    #[arg(long, value_name="DIR", global = true)]
+    pub database_dir : Option<PathBuf>,
+
+    /// Only parse the translation units defining the entry points and,
+    /// transitively, their callees, found by a quick scan of every unit
+    #[arg(long, global = true, conflicts_with = "load_db")]
+    pub on_demand : bool,
+
+    /// Use a database written by `db --save` instead of parsing the project
+    #[arg(long, value_name="FILE", global = true, conflicts_with = "db_cache")]
+    pub load_db : Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command : Command,
+}
+
+/// Where a graph starts: the given entry points, or every exported function
+#[derive(clap::Args, Debug)]
+pub struct RootArgs {
+    /// Function to start from, as `FUNCTION` or `FILE:FUNCTION` to pick a static
+    /// function; repeatable, comma-separated, or `@LIST` naming one per line.
+    /// `auto-isr` adds every interrupt handler found in the project
+    #[arg(short, long, visible_aliases = ["entry-point", "entries"], value_name="ENTRY", value_delimiter = ',', required_unless_present = "library")]
+    pub entry : Vec<String>,
+
+    /// Treat every externally visible function as a root instead of the entry points
+    #[arg(long, conflicts_with = "entry")]
+    pub library : bool,
+
+    /// Contract edges through pass-through wrappers
+    #[arg(long)]
+    pub collapse_wrappers : bool,
+
+    /// Only follow calls this many levels deep from the roots
+    #[arg(long, value_name="N")]
+    pub max_depth : Option<usize>,
+
+    /// Only follow functions defined in files matching this glob, relative
+    /// to the project, e.g. `Core/Src/*.c` (repeatable)
+    #[arg(long, value_name="GLOB")]
+    pub only : Vec<glob::Pattern>,
+
+    /// Do not follow functions defined in files matching this glob, e.g.
+    /// `Drivers/**` (repeatable)
+    #[arg(long, value_name="GLOB")]
+    pub exclude : Vec<glob::Pattern>,
+
+    /// Only follow functions whose name matches this regex (repeatable)
+    #[arg(long, value_name="REGEX")]
+    pub only_functions : Vec<regex::Regex>,
+
+    /// Do not follow functions whose name matches this regex (repeatable)
+    #[arg(long, value_name="REGEX")]
+    pub exclude_functions : Vec<regex::Regex>,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(subcommand_negates_reqs = true)]
+pub struct GraphArgs {
+    #[command(flatten)]
+    pub roots : RootArgs,
+
+    #[command(subcommand)]
+    pub action : Option<GraphCommand>,
+
+    /// Only keep the functions that can reach this one, and list their call sites
+    #[arg(long, value_name="FUNCTION")]
+    pub callers : Option<String>,
+
+    /// Print the call chains from the roots to this function
+    #[arg(long, value_name="FUNCTION")]
+    pub path_to : Option<String>,
+
+    /// Maximum number of chains printed per root with --path-to
+    #[arg(long, value_name="N", default_value_t = 10, requires = "path_to")]
+    pub max_paths : usize,
+
+    /// Draw the chains found by --path-to in bold red in the DOT output
+    #[arg(long, requires = "path_to")]
+    pub highlight_paths : bool,
+
+    /// Color every function and call on a chain from the roots to this function
+    #[arg(long, value_name="FUNCTION")]
+    pub highlight : Option<String>,
+
+    /// How to draw functions defined outside the project: show, collapse
+    /// (into a single node) or hide
+    #[arg(long, value_name="MODE", default_value = "show")]
+    pub externals : ExternalDisplay,
+
+    /// Group functions into boxes by source file or top-level directory: none, file or directory
+    #[arg(long, value_name="GROUPING", default_value = "none")]
+    pub cluster : Clustering,
+
+    /// Merge the calls between two functions into one edge, thicker with each call site
+    #[arg(long)]
+    pub weighted_edges : bool,
+
+    /// Formats rendered with Graphviz next to the DOT file: png, svg, pdf or dot
+    #[arg(long, value_name="FORMAT", value_delimiter = ',', default_value = "png,svg")]
+    pub format : Vec<ExportFormat>,
+
+    /// DOT colors and fonts: a preset (light or dark) or a TOML theme file
+    #[arg(long, value_name="THEME", default_value = "light")]
+    pub theme : String,
+
+    /// Print the dominator tree and save it as call_graph_dominators.dot
+    #[arg(long)]
+    pub dominators : bool,
+
+    /// Link graph nodes to their definitions, e.g. `https://github.com/org/repo/blob/main/{file}#L{line}`
+    #[arg(long, value_name="TEMPLATE")]
+    pub url_template : Option<String>,
+
+    /// Show the project's function-like macros the functions invoke as
+    /// nodes of their own, with the calls they make
+    #[arg(long)]
+    pub show_macros : bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum GraphCommand {
+    /// Compare the call graph with the one of another source tree, or compare
+    /// two saved JSON graphs, and report the functions and calls that changed
+    Diff(DiffArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Source tree to compare the project against, as the old revision; its
+    /// graph is built from the same roots
+    #[arg(long, value_name="DIR", required_unless_present = "old", conflicts_with = "old")]
+    pub against : Option<PathBuf>,
+
+    /// Graph saved by `graph` as call_graph.json, for the old revision
+    #[arg(long, value_name="JSON", requires = "new")]
+    pub old : Option<PathBuf>,
+
+    /// Graph saved by `graph` as call_graph.json, for the new revision
+    #[arg(long, value_name="JSON", requires = "old")]
+    pub new : Option<PathBuf>,
+
+    /// Also write call_graph_diff.dot with the changes color-coded
+    #[arg(long)]
+    pub dot : bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InlineArgs {
+    /// Function to expand, as `FUNCTION` or `FILE:FUNCTION` to pick a static
+    /// function; repeatable, comma-separated, or `@LIST` naming one per line.
+    /// `auto-isr` adds every interrupt handler found in the project
+    #[arg(short, long, visible_aliases = ["entry-point", "entries"], value_name="ENTRY", value_delimiter = ',', required = true)]
+    pub entry : Vec<String>,
+
+    /// How deep to expand: direct (the entry point's calls only), bottom-up
+    /// (every callee expanded once, recursion groups kept as calls) or
+    /// fixpoint (inline until nothing is left, down each call path)
+    #[arg(long, value_name="MODE", default_value = "bottom-up")]
+    pub mode : ExpansionMode,
+
+    /// With --mode fixpoint, stop after inlining this many levels of calls
+    #[arg(long, value_name="N")]
+    pub max_passes : Option<usize>,
+
+    /// Expand the invocations of the project's function-like macros left in
+    /// the output, each after a comment with the invocation it replaces
+    #[arg(long)]
+    pub expand_macros : bool,
+
+    /// Keep the #if chains of the inlined bodies, copying back the branches
+    /// that were not compiled under the current flags
+    #[arg(long)]
+    pub keep_inactive_branches : bool,
+
+    /// Compile the expansion with the entry file's original command, directory and environment
+    #[arg(long, conflicts_with = "dry_run")]
+    pub verify : bool,
+
+    /// Also write a single self-contained .c file with the types, globals and
+    /// remaining callees the expansion needs
+    #[arg(long, conflicts_with = "dry_run")]
+    pub amalgamate : bool,
 
-    #[arg(short, long, value_name="ENTRY_FILE")]
-    pub entry_file : PathBuf,
+    /// Report what would be inlined and why, without writing any files
+    #[arg(long)]
+    pub dry_run : bool,
+
+    /// Never inline calls to this function (repeatable)
+    #[arg(long, value_name="FUNCTION")]
+    pub exclude : Vec<String>,
+
+    /// Abort when the estimated expanded size exceeds the entry point's size by this factor
+    #[arg(long, value_name="FACTOR")]
+    pub max_expansion_factor : Option<f64>,
+
+    /// Only warn when --max-expansion-factor is exceeded
+    #[arg(long)]
+    pub warn_on_size_limit : bool,
+
+    /// Refuse to inline functions with static locals instead of hoisting them to file scope
+    #[arg(long)]
+    pub strict : bool,
+
+    /// Follow each expanded block with a `/* was: ... */` comment holding the original call
+    #[arg(long)]
+    pub annotate_calls : bool,
+
+    /// Open each expanded block with the doc comment of the function it came from
+    #[arg(long)]
+    pub doc_comments : bool,
+
+    /// Replace calls to pass-through wrappers by the call they forward, wherever they appear
+    #[arg(long)]
+    pub collapse_wrappers : bool,
+
+    /// How to handle calls to defined variadic functions: skip, external or error
+    #[arg(long, value_name="POLICY", default_value = "skip")]
+    pub variadic_policy : VariadicPolicy,
+
+    /// Per-function variadic policy, e.g. `log_printf=error` (repeatable)
+    #[arg(long, value_name="FUNCTION=POLICY", value_parser = parse_variadic_override)]
+    pub variadic : Vec<(String, VariadicPolicy)>,
+
+    /// How to handle calls guarded by &&, || or ?: : skip them with a warning,
+    /// or rewrite the guards into if-statements and inline
+    #[arg(long, value_name="POLICY", default_value = "skip")]
+    pub short_circuit : ShortCircuitPolicy,
+
+    /// How to handle callees using alloca or variable-length arrays: allow, warn or skip
+    #[arg(long, value_name="POLICY", default_value = "warn")]
+    pub dynamic_stack : DynamicStackPolicy,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AnalyzeArgs {
+    #[command(flatten)]
+    pub roots : RootArgs,
+
+    #[command(subcommand)]
+    pub action : Option<AnalyzeCommand>,
+
+    /// Write metrics.json and a summary badge
+    #[arg(long)]
+    pub metrics : bool,
+
+    /// List the defined functions the roots never reach, by file, and save dead_code.json
+    #[arg(long)]
+    pub dead_code : bool,
+
+    /// Exit with an error when --dead-code finds unreachable functions
+    #[arg(long, requires = "dead_code")]
+    pub fail_on_dead_code : bool,
+
+    /// List the globals each function reads and writes, directly and through
+    /// its callees, and save them as globals.json
+    #[arg(long)]
+    pub globals : bool,
+
+    /// Report the functions and globals reachable from both an interrupt
+    /// handler and another root, and save them as concurrency.json
+    #[arg(long)]
+    pub concurrency : bool,
+
+    /// Print the deepest call chain from the roots with each frame's call site,
+    /// and save it as call_depth.json
+    #[arg(long)]
+    pub call_depth : bool,
+
+    /// With --call-depth, how many times a recursion group is assumed to nest
+    #[arg(long, value_name="N", default_value_t = 1, requires = "call_depth")]
+    pub recursion_depth : usize,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AnalyzeCommand {
+    /// Print the fan-in and fan-out of every function, and save them as
+    /// coupling.json and coupling.csv
+    Metrics(CouplingArgs),
+
+    /// Estimate the worst-case stack depth of each root from the sizes of the
+    /// locals along its heaviest call chain, and save it as stack_usage.json
+    Stack(StackArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CouplingArgs {
+    /// Order of the table: name, fan-in, fan-out or call-sites
+    #[arg(long, value_name="KEY", default_value = "fan-in")]
+    pub sort : CouplingSort,
+
+    /// Only print the first N functions; the saved files keep all of them
+    #[arg(long, value_name="N")]
+    pub top : Option<usize>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct StackArgs {
+    /// How many times a recursion group is assumed to nest
+    #[arg(long, value_name="N", default_value_t = 1)]
+    pub recursion_depth : usize,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub action : Option<DbCommand>,
+
+    /// Show the definition and calls of this function instead of listing all of them
+    #[arg(long, value_name="FUNCTION")]
+    pub function : Option<String>,
+
+    /// List the prototypes whose types differ from those of the definition
+    /// they link to
+    #[arg(long, conflicts_with = "function")]
+    pub mismatches : bool,
+
+    /// Write the whole database to FILE, for --load-db or other tools; JSON
+    /// when FILE ends in `.json`, compact binary otherwise
+    #[arg(long, value_name="FILE")]
+    pub save : Option<PathBuf>,
+
+    /// Show which definition each weak or aliased function links to
+    #[arg(long, conflicts_with_all = ["function", "mismatches"])]
+    pub symbols : bool,
+
+    /// List the function-like macros of the project with their definitions
+    #[arg(long, conflicts_with_all = ["function", "mismatches", "symbols"])]
+    pub macros : bool,
+
+    /// Show what the typedef NAME stands for and where it is declared
+    #[arg(long, value_name="NAME", conflicts_with_all = ["function", "mismatches", "symbols", "macros"])]
+    pub typedef : Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DbCommand {
+    /// Compare two databases saved with `db --save` and list the functions
+    /// added, removed or whose code changed
+    Diff {
+        /// Database of the old revision
+        old : PathBuf,
+
+        /// Database of the new revision
+        new : PathBuf,
+    },
+}
+
+/// An entry point, optionally qualified by the file defining it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntrySpec {
+    pub file : Option<PathBuf>,
+    pub function : String,
+}
+
+impl FromStr for EntrySpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (file, function) = match s.rsplit_once(':') {
+            Some((file, function)) => (Some(PathBuf::from(file)), function),
+            None => (None, s),
+        };
+        let is_identifier = function.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && function.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !is_identifier {
+            return Err(format!("'{}' is not a function name", function));
+        }
+        Ok(EntrySpec { file, function: function.to_string() })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Export the call graph as DOT, JSON, GraphML, Mermaid, HTML, Callgrind and the --format images
+    Graph(Box<GraphArgs>),
+
+    /// Expand the entry points with their callees inlined
+    Inline(InlineArgs),
+
+    /// Report on the structure of the call graph
+    Analyze(AnalyzeArgs),
+
+    /// Inspect the function database
+    Db(DbArgs),
+
+    /// Rename a function and every reference to it across the project
+    Rename {
+        old : String,
+        new : String,
+
+        /// File defining the static function to rename, when several share the name
+        #[arg(long, value_name="FILE")]
+        file : Option<PathBuf>,
+
+        /// List the files that would change without writing them
+        #[arg(long)]
+        dry_run : bool,
+    },
+
+    /// Write a compile_commands.json at the project root for later runs, from
+    /// the build system found or given, and report the files that still do
+    /// not parse with it
+    Init {
+        /// Guess the compile commands from the source tree instead: every C
+        /// file compiled with every directory holding headers as an include
+        /// path and the defines the tree suggests
+        #[arg(long)]
+        scan : bool,
+
+        /// Replace a compile_commands.json already there
+        #[arg(long)]
+        force : bool,
+    },
+
+    /// Keep the database in memory and answer JSON-RPC 2.0 queries on stdin
+    /// for editor plugins: the callers, callees, expanded body or call graph
+    /// of a function. Requests come one per line or after a Content-Length
+    /// header, as in LSP
+    Serve,
+
+    /// Run a build command, e.g. `intercept -- make all`, and write the C
+    /// compiles it runs to the project's compile_commands.json. Compilers
+    /// are intercepted through PATH and CC, so a build running one by
+    /// absolute path is only seen when CC names it
+    Intercept {
+        /// The build command and its arguments
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command : Vec<String>,
+    },
+
+    /// Parse some compile commands for a parent process running with --jobs
+    #[command(hide = true)]
+    ParseWorker {
+        /// Indices of the commands in compile_commands.json
+        #[arg(long, value_delimiter = ',')]
+        commands : Vec<usize>,
+
+        /// File to write the parsed translation units to
+        #[arg(long)]
+        output : PathBuf,
+    },
+}
+
+impl Args {
+    /// The project directory, which `main` checks was given before running
+    /// a command that needs it
+    pub fn project(&self) -> &Path {
+        self.project_path.as_deref().expect("--project-path is checked before use")
+    }
+}
 
-    #[arg(short, long, value_name="ENTRY_POINT")]
-    pub entry_point : String
+impl Command {
+    /// The entry points given to a command that starts from some
+    pub fn entries(&self) -> Option<&[String]> {
+        let entries = match self {
+            Command::Graph(graph_args) => &graph_args.roots.entry,
+            Command::Inline(inline_args) => &inline_args.entry,
+            Command::Analyze(analyze_args) => &analyze_args.roots.entry,
+            _ => return None,
+        };
+        Some(entries.as_slice()).filter(|entries| !entries.is_empty())
+    }
 }
 
 
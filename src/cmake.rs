@@ -0,0 +1,42 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::build_system;
+
+/// Whether `directory` is the root of a CMake project
+pub fn is_cmake_project(directory: &Path) -> bool {
+    directory.join("CMakeLists.txt").is_file()
+}
+
+/// Configure the CMake project in `source` in a build directory of its own,
+/// exporting compile_commands.json, and return that directory. `cache_args`
+/// go to cmake, e.g. `-DCMAKE_TOOLCHAIN_FILE=arm.cmake`.
+pub fn configure(source: &Path, cache_args: &[String]) -> Result<PathBuf> {
+    let source = source.canonicalize()?;
+    let build = build_system::build_dir(&source, "cmake");
+
+    tracing::info!("Configuring {} with CMake in {}", source.display(), build.display());
+    let output = Command::new("cmake")
+        .arg("-S").arg(&source)
+        .arg("-B").arg(&build)
+        .arg("-DCMAKE_EXPORT_COMPILE_COMMANDS=ON")
+        .args(cache_args)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run cmake: {}", e))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "cmake failed to configure {}: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    if !build.join("compile_commands.json").is_file() {
+        anyhow::bail!(
+            "cmake configured {} without writing compile_commands.json; the generator may not support it, try -G Ninja or -G \"Unix Makefiles\"",
+            source.display()
+        );
+    }
+    Ok(build)
+}
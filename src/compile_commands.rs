@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// One entry of a `compile_commands.json`, as generated for projects that do
+/// not export one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompileEntry {
+    pub directory: PathBuf,
+    pub file: PathBuf,
+    /// Full compiler invocation, compiler first
+    pub arguments: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// The variables of `CAPTURED_ENVIRONMENT` the compiler ran with, when
+    /// the build was seen running it. Clang rejects unknown keys in
+    /// compile_commands.json, so `write` puts them in a file of their own.
+    #[serde(skip)]
+    pub environment: BTreeMap<String, String>,
+}
+
+/// Where `write` records the environment of the commands it writes, next
+/// to their compile_commands.json
+const ENVIRONMENT_FILE: &str = "compile_environment.json";
+
+/// The environment one file was compiled in, as `ENVIRONMENT_FILE` lists it
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedEnvironment {
+    file: PathBuf,
+    environment: BTreeMap<String, String>,
+}
+
+/// Options whose value is the next argument and never a source file
+const OPTIONS_WITH_VALUE: &[&str] = &["-o", "-MF", "-MT", "-MQ", "-include", "-imacros", "-x", "-I", "-D", "-U", "-isystem", "-iquote", "-idirafter"];
+
+impl CompileEntry {
+    /// An entry compiling `file` in `directory` with `compiler` and `flags`,
+    /// for projects whose build settings are read rather than run
+    pub fn new(directory: &Path, compiler: &str, flags: &[String], file: PathBuf) -> CompileEntry {
+        CompileEntry {
+            directory: directory.to_path_buf(),
+            arguments: std::iter::once(compiler.to_string())
+                .chain(flags.iter().cloned())
+                .chain(["-c".to_string(), file.display().to_string()])
+                .collect(),
+            file,
+            output: None,
+            environment: BTreeMap::new(),
+        }
+    }
+
+    /// An entry for every C file `arguments` compiles, run in `directory`.
+    /// Each keeps the other flags but only its own file, as clang expects.
+    /// None for invocations that only preprocess or link.
+    pub fn from_invocation(arguments: &[String], directory: &Path) -> Vec<CompileEntry> {
+        let arguments = match arguments.first() {
+            // ccache and distcc run the compiler given after them
+            Some(wrapper) if matches!(program_name(wrapper).as_str(), "ccache" | "distcc" | "sccache") => &arguments[1..],
+            _ => arguments,
+        };
+        if !arguments.first().is_some_and(|program| is_compiler(program))
+            || arguments.iter().any(|argument| matches!(argument.as_str(), "-E" | "-M" | "-MM"))
+        {
+            return Vec::new();
+        }
+
+        let mut sources = Vec::new();
+        let mut output = None;
+        for (index, argument) in arguments.iter().enumerate().skip(1) {
+            let previous = arguments[index - 1].as_str();
+            if previous == "-o" {
+                output = Some(directory.join(argument));
+            } else if !OPTIONS_WITH_VALUE.contains(&previous) && !argument.starts_with('-') && argument.ends_with(".c") {
+                sources.push(index);
+            }
+        }
+        sources.iter()
+            .map(|&source| CompileEntry {
+                directory: directory.to_path_buf(),
+                file: directory.join(&arguments[source]),
+                arguments: arguments.iter()
+                    .enumerate()
+                    .filter(|(index, _)| *index == source || !sources.contains(index))
+                    .map(|(_, argument)| argument.clone())
+                    .collect(),
+                output: output.clone(),
+                environment: BTreeMap::new(),
+            })
+            .collect()
+    }
+}
+
+/// Whether `program` is a C compiler: GCC, clang or a cross compiler named
+/// after one, e.g. `arm-none-eabi-gcc` or `/usr/bin/clang-15`
+pub fn is_compiler(program: &str) -> bool {
+    let name = program_name(program);
+    // Versioned names like gcc-12
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let name = name.strip_suffix('-').unwrap_or(name);
+    matches!(name, "cc" | "gcc" | "clang" | "tcc" | "armcc" | "armclang" | "iccarm")
+        || ["-gcc", "-cc", "-clang"].iter().any(|suffix| name.ends_with(suffix))
+}
+
+/// File name of a program, without directory or `.exe`
+fn program_name(program: &str) -> String {
+    let name = Path::new(program).file_name().and_then(|name| name.to_str()).unwrap_or(program);
+    name.strip_suffix(".exe").unwrap_or(name).to_string()
+}
+
+/// Split a shell command line into words, with quotes and backslashes
+/// removed. `;`, `&&`, `||` and `|` outside quotes come out as words of
+/// their own.
+pub fn split_command_line(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                in_word = true;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' if chars.peek().is_some_and(|next| matches!(next, '"' | '\\' | '$' | '`')) => {
+                            word.extend(chars.next());
+                        }
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.extend(chars.next());
+            }
+            ';' | '&' | '|' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                let mut operator = c.to_string();
+                if chars.peek() == Some(&c) && c != ';' {
+                    operator.push(c);
+                    chars.next();
+                }
+                words.push(operator);
+            }
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// Entries compiling every C file under `directory` with the flags of its
+/// clangd-style compile_flags.txt, one per line. Hidden directories, such
+/// as `.git`, are left out.
+pub fn from_compile_flags(directory: &Path) -> Result<Vec<CompileEntry>> {
+    let path = directory.join("compile_flags.txt");
+    let flags: Vec<String> = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .lines()
+        .map(str::trim)
+        .filter(|flag| !flag.is_empty())
+        .map(str::to_string)
+        .collect();
+    let pattern = format!("{}/**/*.c", glob::Pattern::escape(&directory.display().to_string()));
+    Ok(glob::glob(&pattern)?
+        .flatten()
+        .filter(|file| !file.strip_prefix(directory).is_ok_and(|relative| {
+            relative.components().any(|component| component.as_os_str().to_string_lossy().starts_with('.'))
+        }))
+        .map(|file| CompileEntry::new(directory, "clang", &flags, file))
+        .collect())
+}
+
+/// Write `entries` as a `compile_commands.json` at `path`
+pub fn write(entries: &[CompileEntry], path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(entries)?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    // Along with the environment of the commands, when known
+    let recorded: Vec<RecordedEnvironment> = entries.iter()
+        .filter(|entry| !entry.environment.is_empty())
+        .map(|entry| RecordedEnvironment { file: entry.directory.join(&entry.file), environment: entry.environment.clone() })
+        .collect();
+    let environment_path = path.with_file_name(ENVIRONMENT_FILE);
+    if recorded.is_empty() {
+        let _ = std::fs::remove_file(&environment_path);
+        return Ok(());
+    }
+    std::fs::write(&environment_path, serde_json::to_string_pretty(&recorded)?)
+        .with_context(|| format!("Failed to write {}", environment_path.display()))
+}
+
+/// The environment each file of the compile_commands.json in `directory`
+/// was compiled in, by canonical path, as `write` recorded it. Empty for
+/// databases written by the build system itself.
+pub fn read_environment(directory: &Path) -> BTreeMap<PathBuf, BTreeMap<String, String>> {
+    let path = directory.join(ENVIRONMENT_FILE);
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return BTreeMap::new();
+    };
+    let recorded: Vec<RecordedEnvironment> = match serde_json::from_str(&text) {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            tracing::warn!("Ignoring {}: {}", path.display(), e);
+            return BTreeMap::new();
+        }
+    };
+    recorded.into_iter()
+        .map(|recorded| (recorded.file.canonicalize().unwrap_or(recorded.file), recorded.environment))
+        .collect()
+}
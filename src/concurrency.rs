@@ -0,0 +1,138 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+use crate::parser::function_db::FunctionDatabase;
+
+/// A function both the main context and an interrupt handler can run
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedFunction {
+    pub name: String,
+    pub file: PathBuf,
+    /// Interrupt handlers reaching it
+    pub interrupts: Vec<String>,
+}
+
+/// A global variable touched from both contexts
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedGlobal {
+    pub name: String,
+    pub file: PathBuf,
+    pub is_volatile: bool,
+    /// Functions using it on the main side
+    pub main_users: Vec<String>,
+    /// Functions using it on the interrupt side
+    pub interrupt_users: Vec<String>,
+    /// Functions assigning it, on either side
+    pub writers: Vec<String>,
+}
+
+/// Functions and globals reachable both from an interrupt handler and from the
+/// main context, where an interrupt may preempt the code using them. Globals
+/// both sides only read are left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConcurrencyReport {
+    pub main_roots: Vec<String>,
+    pub interrupt_roots: Vec<String>,
+    pub shared_functions: Vec<SharedFunction>,
+    pub shared_globals: Vec<SharedGlobal>,
+}
+
+impl ConcurrencyReport {
+    /// The roots that are interrupt handlers form the interrupt context, the
+    /// others the main context
+    pub fn build(db: &FunctionDatabase, call_graph: &CallGraph) -> Result<Self> {
+        let handlers: BTreeSet<String> = db.interrupt_handlers().into_iter().collect();
+        let (interrupt_roots, main_roots): (Vec<String>, Vec<String>) = call_graph.roots()
+            .iter()
+            .cloned()
+            .partition(|root| handlers.contains(root));
+        if interrupt_roots.is_empty() || main_roots.is_empty() {
+            anyhow::bail!("Needs both interrupt handlers and other roots, e.g. --entry main,auto-isr");
+        }
+
+        let main_reach: BTreeSet<String> = main_roots.iter()
+            .flat_map(|root| call_graph.reachable_from(root))
+            .collect();
+        // Which handlers reach each function on the interrupt side
+        let mut interrupt_reach: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for root in &interrupt_roots {
+            for name in call_graph.reachable_from(root) {
+                interrupt_reach.entry(name).or_default().push(root.clone());
+            }
+        }
+
+        let shared_functions = interrupt_reach.iter()
+            .filter(|(name, _)| main_reach.contains(*name))
+            .filter_map(|(name, interrupts)| {
+                let node = call_graph.node(name)?;
+                (node.function.signature.return_type != "extern").then(|| SharedFunction {
+                    name: name.clone(),
+                    file: node.function.source_file.clone(),
+                    interrupts: interrupts.clone(),
+                })
+            })
+            .collect();
+
+        let mut globals: BTreeMap<&str, SharedGlobal> = BTreeMap::new();
+        for node in call_graph.iter() {
+            let name = &node.function.signature.name;
+            let on_main = main_reach.contains(name);
+            let on_interrupt = interrupt_reach.contains_key(name);
+            for global in &node.function.globals {
+                let entry = globals.entry(&global.name).or_insert_with(|| SharedGlobal {
+                    name: global.name.clone(),
+                    file: global.source_file.clone(),
+                    is_volatile: global.is_volatile,
+                    main_users: Vec::new(),
+                    interrupt_users: Vec::new(),
+                    writers: Vec::new(),
+                });
+                if on_main {
+                    entry.main_users.push(name.clone());
+                }
+                if on_interrupt {
+                    entry.interrupt_users.push(name.clone());
+                }
+                if (on_main || on_interrupt) && node.function.global_writes.contains(&global.name) {
+                    entry.writers.push(name.clone());
+                }
+            }
+        }
+        let shared_globals = globals.into_values()
+            .filter(|global| !global.main_users.is_empty() && !global.interrupt_users.is_empty() && !global.writers.is_empty())
+            .collect();
+
+        Ok(ConcurrencyReport { main_roots, interrupt_roots, shared_functions, shared_globals })
+    }
+
+    pub fn print(&self) {
+        println!("Concurrency Hazards:");
+        println!("  Main context: {}", self.main_roots.join(", "));
+        println!("  Interrupt context: {}", self.interrupt_roots.join(", "));
+        println!("  Functions run from both contexts: {}", self.shared_functions.len());
+        for function in &self.shared_functions {
+            println!("    {} ({}) from {}", function.name, function.file.display(), function.interrupts.join(", "));
+        }
+        println!("  Globals used from both contexts: {}", self.shared_globals.len());
+        for global in &self.shared_globals {
+            println!(
+                "    {}{} ({})",
+                global.name,
+                if global.is_volatile { " volatile" } else { "" },
+                global.file.display()
+            );
+            println!("      main: {}", global.main_users.join(", "));
+            println!("      interrupt: {}", global.interrupt_users.join(", "));
+            println!("      written by: {}", global.writers.join(", "));
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+
+/// How `CouplingReport` orders its functions; counts sort highest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CouplingSort {
+    Name,
+    #[default]
+    FanIn,
+    FanOut,
+    CallSites,
+}
+
+impl FromStr for CouplingSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(CouplingSort::Name),
+            "fan-in" => Ok(CouplingSort::FanIn),
+            "fan-out" => Ok(CouplingSort::FanOut),
+            "call-sites" => Ok(CouplingSort::CallSites),
+            _ => Err(format!("unknown sort key '{}', expected name, fan-in, fan-out or call-sites", s)),
+        }
+    }
+}
+
+/// Callers and callees of one function in the call graph
+#[derive(Debug, Clone, Serialize)]
+pub struct FunctionCoupling {
+    pub name: String,
+    pub file: String,
+    pub external: bool,
+    /// Distinct functions calling this one
+    pub fan_in: usize,
+    /// Distinct functions this one calls
+    pub fan_out: usize,
+    /// Call sites targeting this function
+    pub calls_in: usize,
+    /// Call sites in this function's body
+    pub calls_out: usize,
+}
+
+/// Fan-in and fan-out of every function of a call graph
+#[derive(Debug, Clone, Serialize)]
+pub struct CouplingReport {
+    pub roots: Vec<String>,
+    pub functions: Vec<FunctionCoupling>,
+}
+
+impl CouplingReport {
+    pub fn build(call_graph: &CallGraph, sort: CouplingSort) -> Self {
+        let index = call_graph.reverse_index();
+        let mut functions: Vec<FunctionCoupling> = call_graph.iter()
+            .map(|node| {
+                let name = node.function.signature.name.as_str();
+                let callers = index.get(name).map(Vec::as_slice).unwrap_or_default();
+                let callees: BTreeSet<&str> = node.calls.iter().map(|call| call.function_name.as_str()).collect();
+                let is_external = node.function.signature.return_type == "extern";
+                FunctionCoupling {
+                    name: name.to_string(),
+                    file: if is_external { String::new() } else { node.function.source_file.display().to_string() },
+                    external: is_external,
+                    fan_in: callers.iter().map(|(caller, _)| *caller).collect::<BTreeSet<_>>().len(),
+                    fan_out: callees.len(),
+                    calls_in: callers.len(),
+                    calls_out: node.calls.len(),
+                }
+            })
+            .collect();
+
+        // The nodes come sorted by name, so ties keep that order
+        match sort {
+            CouplingSort::Name => {}
+            CouplingSort::FanIn => functions.sort_by_key(|f| std::cmp::Reverse(f.fan_in)),
+            CouplingSort::FanOut => functions.sort_by_key(|f| std::cmp::Reverse(f.fan_out)),
+            CouplingSort::CallSites => functions.sort_by_key(|f| std::cmp::Reverse(f.calls_in + f.calls_out)),
+        }
+
+        CouplingReport {
+            roots: call_graph.roots().to_vec(),
+            functions,
+        }
+    }
+
+    /// Print the first `limit` functions, or all of them
+    pub fn print(&self, limit: Option<usize>) {
+        println!("Fan-in / Fan-out:");
+        let name_width = self.functions.iter().map(|f| f.name.len()).max().unwrap_or(0).max("function".len());
+        println!("  {:<name_width$}  {:>6}  {:>7}  {:>8}  {:>9}", "function", "fan-in", "fan-out", "calls in", "calls out");
+        for function in self.functions.iter().take(limit.unwrap_or(usize::MAX)) {
+            println!(
+                "  {:<name_width$}  {:>6}  {:>7}  {:>8}  {:>9}{}",
+                function.name,
+                function.fan_in,
+                function.fan_out,
+                function.calls_in,
+                function.calls_out,
+                if function.external { "  (external)" } else { "" }
+            );
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn save_csv(&self, output_path: &Path) -> Result<()> {
+        let mut csv = String::from("function,file,external,fan_in,fan_out,calls_in,calls_out\n");
+        for function in &self.functions {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{}\n",
+                function.name,
+                Self::csv_field(&function.file),
+                function.external,
+                function.fan_in,
+                function.fan_out,
+                function.calls_in,
+                function.calls_out
+            ));
+        }
+        std::fs::write(output_path, csv)?;
+        Ok(())
+    }
+
+    fn csv_field(value: &str) -> String {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+}
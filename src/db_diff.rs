@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::parser::function_db::{Definition, FunctionDatabase};
+
+/// A function present in both databases whose code differs
+#[derive(Debug, Clone)]
+pub struct ChangedFunction {
+    pub key: String,
+    pub old: Arc<Definition>,
+    pub new: Arc<Definition>,
+}
+
+impl ChangedFunction {
+    pub fn signature_changed(&self) -> bool {
+        self.old.signature.to_c() != self.new.signature.to_c()
+    }
+}
+
+/// The functions added, removed or changed between two saved databases, by
+/// the hash of their signature and body
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseDiff {
+    pub added: Vec<Arc<Definition>>,
+    pub removed: Vec<Arc<Definition>>,
+    pub changed: Vec<ChangedFunction>,
+}
+
+impl DatabaseDiff {
+    pub fn compute(old: &FunctionDatabase, new: &FunctionDatabase) -> Self {
+        let old_functions = Self::by_key(old);
+        let mut new_functions = Self::by_key(new);
+        let mut diff = DatabaseDiff::default();
+        for (key, old_def) in old_functions {
+            match new_functions.remove(&key) {
+                Some(new_def) if new_def.body_hash != old_def.body_hash => {
+                    diff.changed.push(ChangedFunction { key, old: old_def, new: new_def });
+                }
+                Some(_) => {}
+                None => diff.removed.push(old_def),
+            }
+        }
+        diff.added = new_functions.into_values().collect();
+        diff
+    }
+
+    /// Defined functions by name, static ones as `FILE:NAME` since several
+    /// files may define their own
+    fn by_key(db: &FunctionDatabase) -> BTreeMap<String, Arc<Definition>> {
+        db.iter()
+            .filter(|def| !def.body.is_empty())
+            .map(|def| {
+                let key = if def.is_static {
+                    format!("{}:{}", def.source_file.display(), def.signature.name)
+                } else {
+                    def.signature.name.clone()
+                };
+                (key, def)
+            })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Function Database Diff:");
+        println!(
+            "  Functions: {} added, {} removed, {} changed",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        );
+        for def in &self.added {
+            println!("  + {} ({}:{})", def.signature.name, def.source_file.display(), def.line);
+        }
+        for def in &self.removed {
+            println!("  - {} ({}:{})", def.signature.name, def.source_file.display(), def.line);
+        }
+        for change in &self.changed {
+            let what = if change.signature_changed() { "signature and body" } else { "body" };
+            println!("  ~ {} ({}:{}), {} changed", change.key, change.new.source_file.display(), change.new.line, what);
+        }
+    }
+}
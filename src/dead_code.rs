@@ -0,0 +1,73 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+use crate::parser::function_db::FunctionDatabase;
+
+/// Defined functions the call graph never reaches, by the file defining them
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadCodeReport {
+    pub roots: Vec<String>,
+    pub files: BTreeMap<PathBuf, Vec<String>>,
+    /// Unreachable functions whose address is taken somewhere, which may still
+    /// be called through a pointer the parser could not resolve
+    pub address_taken: Vec<String>,
+}
+
+impl DeadCodeReport {
+    pub fn build(db: &FunctionDatabase, call_graph: &CallGraph) -> Self {
+        let reachable: BTreeSet<&str> = call_graph.iter()
+            .map(|node| node.function.signature.name.as_str())
+            .collect();
+        let address_taken = db.address_taken_functions();
+
+        let mut files: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+        let mut maybe_called = Vec::new();
+        for def in db.iter().filter(|def| !def.body.is_empty()) {
+            let name = &def.signature.name;
+            if reachable.contains(name.as_str()) {
+                continue;
+            }
+            if address_taken.contains(name.as_str()) {
+                maybe_called.push(name.clone());
+            } else {
+                files.entry(def.source_file.clone()).or_default().push(name.clone());
+            }
+        }
+
+        DeadCodeReport {
+            roots: call_graph.roots().to_vec(),
+            files,
+            address_taken: maybe_called,
+        }
+    }
+
+    pub fn dead_count(&self) -> usize {
+        self.files.values().map(Vec::len).sum()
+    }
+
+    pub fn print(&self) {
+        println!("Dead Code Report:");
+        println!("  Unreachable functions: {}", self.dead_count());
+        for (file, functions) in &self.files {
+            println!("    {}", file.display());
+            for name in functions {
+                println!("      {}", name);
+            }
+        }
+        if !self.address_taken.is_empty() {
+            println!("  Unreachable but address taken, may be called through a pointer:");
+            for name in &self.address_taken {
+                println!("      {}", name);
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::inliner::{Decision, Expansion};
+use crate::parser::function_db::{FunctionDatabase, GlobalVariable, Signature};
+use crate::parser::types::Type;
+
+/// Header declaring what an expansion refers to but does not define: the
+/// globals used by the entry point and the bodies inlined into it, and the
+/// functions still called. The project headers declaring the typedefs of
+/// those functions' signatures are included; system types such as
+/// `uint32_t` and the types of globals come as the parser spelled them, so
+/// the headers defining them must be included first. Anonymous structs and
+/// unions the signatures use are defined under their synthetic tags.
+pub fn companion_header(db: &FunctionDatabase, expansion: &Expansion) -> String {
+    let guard = format!("{}_DECLS_H", expansion.entry_point.to_uppercase());
+    let mut out = format!(
+        "/* Declarations needed by the expansion of {} */\n#ifndef {}\n#define {}\n",
+        expansion.entry_point, guard, guard
+    );
+
+    let called: BTreeMap<&str, _> = expansion.reports.iter()
+        .filter(|report| report.decision != Decision::Inline)
+        .map(|report| (report.callee.as_str(), db.get_function_definition(&report.callee)))
+        .collect();
+    let signatures: Vec<&Signature> = called.iter()
+        .filter_map(|(name, def)| match def {
+            Some(def) => Some(&def.signature),
+            None => db.declarations_of(name).iter()
+                .find(|declaration| declaration.has_prototype)
+                .map(|declaration| &declaration.signature),
+        })
+        .collect();
+    let types: Vec<&Type> = signatures.iter()
+        .flat_map(|signature| std::iter::once(&signature.return_ty).chain(signature.args.iter().map(|arg| &arg.ty)))
+        .collect();
+    let mut typedefs = BTreeSet::new();
+    let mut records = Vec::new();
+    let mut enums = Vec::new();
+    for ty in &types {
+        ty.typedef_names(&mut typedefs);
+        ty.anonymous_types(&mut records, &mut enums);
+    }
+    let mut headers: BTreeMap<PathBuf, Vec<&str>> = BTreeMap::new();
+    let mut ambiguous = Vec::new();
+    for name in &typedefs {
+        match db.type_table().get(name) {
+            Some(typedef) if !typedef.is_system => headers.entry(typedef.source_file.clone()).or_default().push(name),
+            Some(_) => {}
+            None if !db.type_table().typedefs_named(name).is_empty() => ambiguous.push(name),
+            None => {}
+        }
+    }
+    if !headers.is_empty() || !ambiguous.is_empty() || !records.is_empty() || !enums.is_empty() {
+        out.push_str("\n/* Types */\n");
+    }
+    for (header, names) in &headers {
+        out.push_str(&format!("#include \"{}\" /* {} */\n", header.display(), names.join(", ")));
+    }
+    for name in ambiguous {
+        out.push_str(&format!("/* {} is declared differently in several headers */\n", name));
+    }
+    // Anonymous types are spelled with the tag the parser made up for them
+    for enumeration in enums {
+        out.push_str(&format!("{};\n", enumeration.to_c_definition()));
+    }
+    for record in records {
+        match record.to_c_definition() {
+            Some(definition) => out.push_str(&format!("{};\n", definition)),
+            None => out.push_str(&format!("{} {};\n", if record.is_union { "union" } else { "struct" }, record.name)),
+        }
+    }
+
+    let sources = std::iter::once(expansion.entry_point.as_str())
+        .chain(expansion.reports.iter()
+            .filter(|report| report.decision == Decision::Inline)
+            .map(|report| report.callee.as_str()));
+    let mut globals: BTreeMap<String, GlobalVariable> = BTreeMap::new();
+    for def in sources.filter_map(|name| db.get_function_definition(name)) {
+        for global in &def.globals {
+            globals.entry(global.name.clone()).or_insert_with(|| global.clone());
+        }
+    }
+
+    if !globals.is_empty() {
+        out.push_str("\n/* Globals */\n");
+    }
+    for global in globals.values() {
+        if global.is_static {
+            out.push_str(&format!(
+                "/* {} is static in {} */\n",
+                global.name,
+                global.source_file.display()
+            ));
+        } else {
+            out.push_str(&global.to_extern());
+            out.push('\n');
+        }
+    }
+
+    if !called.is_empty() {
+        out.push_str("\n/* Functions still called */\n");
+    }
+    for (name, def) in called {
+        match def {
+            Some(def) if def.is_static && def.in_header() => out.push_str(&format!(
+                "#include \"{}\" /* {} */\n",
+                def.source_file.display(),
+                name
+            )),
+            Some(def) if def.is_static => out.push_str(&format!(
+                "/* {} is static in {} */\n",
+                name,
+                def.source_file.display()
+            )),
+            Some(def) => {
+                out.push_str(&def.signature.to_c_declaration());
+                out.push_str(";\n");
+            }
+            None => match db.declarations_of(name).iter().find(|declaration| declaration.has_prototype) {
+                Some(declaration) => {
+                    out.push_str(&declaration.signature.to_c());
+                    out.push_str(&format!("; /* declared in {} */\n", declaration.source_file.display()));
+                }
+                None => out.push_str(&format!("/* {} has no definition in the project */\n", name)),
+            },
+        }
+    }
+
+    out.push_str(&format!("\n#endif /* {} */\n", guard));
+    out
+}
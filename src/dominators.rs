@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Result;
+use graphviz_rust::dot_generator::*;
+use graphviz_rust::dot_structures::*;
+use graphviz_rust::printer::PrinterContext;
+
+use crate::call_graph::CallGraph;
+
+/// Immediate dominators of a call graph: every call chain from a root to a
+/// function goes through its dominators. A function dominating others gates
+/// all access to them, so flattening it does not duplicate them.
+#[derive(Debug, Clone, Default)]
+pub struct DominatorTree {
+    /// Immediate dominator of each reachable function. None for the roots, and
+    /// for functions reached from several roots with no function in common.
+    idom: BTreeMap<String, Option<String>>,
+}
+
+impl DominatorTree {
+    /// Computed with the iterative algorithm of Cooper, Harvey and Kennedy,
+    /// from a virtual node calling every root
+    pub fn build(graph: &CallGraph) -> Self {
+        // Index 0 is the virtual root, the others follow reverse postorder
+        let mut order: Vec<&str> = Vec::new();
+        let mut visited = HashSet::new();
+        for root in graph.roots() {
+            Self::postorder(graph, root, &mut visited, &mut order);
+        }
+        order.reverse();
+        let mut names = vec![""];
+        names.extend(order);
+        let index: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (*name, i)).collect();
+
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); names.len()];
+        for root in graph.roots() {
+            if let Some(&i) = index.get(root.as_str()) {
+                predecessors[i].push(0);
+            }
+        }
+        for node in graph.iter() {
+            let Some(&from) = index.get(node.function.signature.name.as_str()) else {
+                continue;
+            };
+            for call in &node.calls {
+                if let Some(&to) = index.get(call.function_name.as_str()) {
+                    predecessors[to].push(from);
+                }
+            }
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; names.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..names.len() {
+                let mut processed = predecessors[b].iter().copied().filter(|&p| idom[p].is_some());
+                let Some(first) = processed.next() else {
+                    continue;
+                };
+                let new_idom = processed.fold(first, |a, p| Self::intersect(&idom, a, p));
+                if idom[b] != Some(new_idom) {
+                    idom[b] = Some(new_idom);
+                    changed = true;
+                }
+            }
+        }
+
+        let idom = (1..names.len())
+            .map(|b| {
+                let dominator = idom[b].filter(|&d| d != 0).map(|d| names[d].to_string());
+                (names[b].to_string(), dominator)
+            })
+            .collect();
+        DominatorTree { idom }
+    }
+
+    fn postorder<'a>(graph: &'a CallGraph, name: &'a str, visited: &mut HashSet<&'a str>, order: &mut Vec<&'a str>) {
+        let Some(node) = graph.node(name) else {
+            return;
+        };
+        let name = node.function.signature.name.as_str();
+        if !visited.insert(name) {
+            return;
+        }
+        for call in &node.calls {
+            Self::postorder(graph, &call.function_name, visited, order);
+        }
+        order.push(name);
+    }
+
+    /// Walk both fingers up the tree to their nearest common dominator, using
+    /// reverse postorder numbers
+    fn intersect(idom: &[Option<usize>], mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            while a > b {
+                a = idom[a].unwrap_or(0);
+            }
+            while b > a {
+                b = idom[b].unwrap_or(0);
+            }
+        }
+        a
+    }
+
+    /// Functions `name` immediately dominates, sorted by name
+    pub fn children(&self, name: &str) -> Vec<&str> {
+        self.idom.iter()
+            .filter(|(_, dominator)| dominator.as_deref() == Some(name))
+            .map(|(child, _)| child.as_str())
+            .collect()
+    }
+
+    pub fn print(&self) {
+        println!("Dominator Tree:");
+        for (name, dominator) in &self.idom {
+            if dominator.is_none() {
+                self.print_subtree(name, 1);
+            }
+        }
+    }
+
+    fn print_subtree(&self, name: &str, depth: usize) {
+        println!("{}{}", "  ".repeat(depth), name);
+        for child in self.children(name) {
+            self.print_subtree(child, depth + 1);
+        }
+    }
+
+    pub fn save_dot(&self, output_path: &Path) -> Result<()> {
+        let mut graph = graph!(di id!("DominatorTree"));
+        graph.add_stmt(attr!("rankdir", "TB").into());
+        graph.add_stmt(GraphAttributes::new("node", vec![
+            attr!("shape", "box"),
+            attr!("fontname", "\"Helvetica\""),
+            attr!("fontsize", "10"),
+        ]).into());
+
+        for (name, dominator) in &self.idom {
+            let node_id = Self::sanitize_id(name);
+            let label = format!("\"{}\"", name);
+            graph.add_stmt(Node::new(node_id!(node_id), vec![attr!("label", label.to_string())]).into());
+            if let Some(dominator) = dominator {
+                let from_id = Self::sanitize_id(dominator);
+                graph.add_stmt(edge!(node_id!(from_id) => node_id!(node_id), vec![]).into());
+            }
+        }
+
+        let mut printer_ctx = PrinterContext::default();
+        printer_ctx.with_semi().with_indent_step(4);
+        std::fs::write(output_path, graphviz_rust::print(&graph, &mut printer_ctx))?;
+        Ok(())
+    }
+
+    fn sanitize_id(name: &str) -> String {
+        name.replace(|c: char| !c.is_alphanumeric() && c != '_', "_")
+    }
+}
@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::compile_commands::CompileEntry;
+use crate::project_xml::{self, Element};
+
+/// Whether `directory` holds an Eclipse CDT project, as STM32CubeIDE makes
+pub fn is_eclipse_project(directory: &Path) -> bool {
+    directory.join(".cproject").is_file()
+}
+
+/// The C compiler settings of one build configuration of a .cproject
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Configuration {
+    /// e.g. `Debug`
+    pub name: String,
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    /// Folders holding sources, relative to the project; empty for all of it
+    pub source_folders: Vec<String>,
+    /// Paths left out of the build, relative to the project
+    pub excluded: Vec<String>,
+    /// GCC cross compiler the toolchain uses, when recognized
+    pub compiler: Option<String>,
+}
+
+/// Compile commands for the C files of the Eclipse project in `directory`,
+/// built with the include paths and defines of its configuration named
+/// `configuration`, else of its Debug one, else of its first
+pub fn generate(directory: &Path, configuration: Option<&str>) -> Result<Vec<CompileEntry>> {
+    let configurations = read_configurations(directory)?;
+    let chosen = configurations.iter()
+        .find(|candidate| configuration.is_some_and(|name| candidate.name == name))
+        .or_else(|| configurations.iter().find(|candidate| configuration.is_none() && candidate.name == "Debug"))
+        .or_else(|| configurations.first().filter(|_| configuration.is_none()))
+        .ok_or_else(|| anyhow::anyhow!(
+            "No {} configuration in {}, it has {}",
+            configuration.unwrap_or("C build"),
+            directory.join(".cproject").display(),
+            configurations.iter().map(|candidate| candidate.name.as_str()).collect::<Vec<_>>().join(", ")
+        ))?;
+    tracing::info!("Using the {} configuration of {}", chosen.name, directory.join(".cproject").display());
+
+    let compiler = chosen.compiler.clone().unwrap_or_else(|| "gcc".to_string());
+    let flags: Vec<String> = chosen.defines.iter()
+        .map(|define| format!("-D{}", define))
+        .chain(chosen.include_dirs.iter().map(|dir| format!("-I{}", dir.display())))
+        .collect();
+    Ok(source_files(directory, chosen)
+        .into_iter()
+        .map(|file| CompileEntry::new(directory, &compiler, &flags, file))
+        .collect())
+}
+
+/// The build configurations of the .cproject in `directory`
+pub fn read_configurations(directory: &Path) -> Result<Vec<Configuration>> {
+    let path = directory.join(".cproject");
+    let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let root = project_xml::parse(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let project_name = project_name(directory);
+    Ok(root.descendants_named("configuration")
+        .into_iter()
+        .filter(|configuration| configuration.attribute("name").is_some())
+        .filter(|configuration| !configuration.descendants_named("tool").is_empty())
+        .map(|configuration| read_configuration(configuration, directory, project_name.as_deref()))
+        .collect())
+}
+
+fn read_configuration(configuration: &Element, directory: &Path, project_name: Option<&str>) -> Configuration {
+    let name = configuration.attribute("name").unwrap_or_default().to_string();
+    // Relative paths in options are from the build directory, named after
+    // the configuration
+    let build_dir = directory.join(&name);
+    let mut result = Configuration { name, ..Default::default() };
+    let toolchain = configuration.descendants_named("toolChain")
+        .first()
+        .and_then(|toolchain| toolchain.attribute("superClass").or(toolchain.attribute("id")))
+        .unwrap_or_default()
+        .to_lowercase();
+    if toolchain.contains("stm32") || toolchain.contains("arm") {
+        result.compiler = Some("arm-none-eabi-gcc".to_string());
+    }
+
+    let c_tools = configuration.descendants_named("tool")
+        .into_iter()
+        .filter(|tool| {
+            let kind = tool.attribute("superClass").or(tool.attribute("id")).unwrap_or_default();
+            kind.contains("c.compiler") && !kind.contains("cpp")
+        });
+    for tool in c_tools {
+        for option in tool.children_named("option") {
+            let values = option.children_named("listOptionValue")
+                .filter(|value| value.attribute("builtIn") != Some("true"))
+                .filter_map(|value| value.attribute("value"));
+            match option.attribute("valueType") {
+                Some("includePath") => result.include_dirs.extend(values.map(|value| {
+                    let value = expand_variables(value, directory, project_name);
+                    build_dir.join(value.trim_matches('"'))
+                })),
+                Some("definedSymbols") => result.defines.extend(values.map(str::to_string)),
+                _ => {}
+            }
+        }
+    }
+
+    for entry in configuration.descendants_named("entry").into_iter().filter(|entry| entry.attribute("kind") == Some("sourcePath")) {
+        let folder = entry.attribute("name").unwrap_or_default().to_string();
+        if let Some(excluding) = entry.attribute("excluding") {
+            result.excluded.extend(excluding.split('|')
+                .filter(|path| !path.is_empty())
+                .map(|path| if folder.is_empty() { path.to_string() } else { format!("{}/{}", folder, path) }));
+        }
+        result.source_folders.push(folder);
+    }
+    result
+}
+
+/// The project name from the .project file next to the .cproject
+fn project_name(directory: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(directory.join(".project")).ok()?;
+    Some(project_xml::parse(&text).ok()?.child_text("name")?.to_string())
+}
+
+/// `value` with the Eclipse variables naming the project directory replaced,
+/// `${ProjDirPath}` and `${workspace_loc:/${ProjName}/...}`
+fn expand_variables(value: &str, directory: &Path, project_name: Option<&str>) -> String {
+    let directory = directory.display().to_string();
+    let mut value = value.replace("${ProjDirPath}", &directory);
+    if let Some(name) = project_name {
+        value = value.replace("${ProjName}", name);
+    }
+    if let Some(start) = value.find("${workspace_loc:")
+        && let Some(length) = value[start..].find('}')
+    {
+        let path = value[start + "${workspace_loc:".len()..start + length].trim_start_matches('/');
+        // The first component is the project, which is `directory`
+        let inside = path.split_once('/').map_or("", |(_, inside)| inside);
+        value = format!("{}{}/{}{}", &value[..start], directory, inside, &value[start + length + 1..]);
+    }
+    value
+}
+
+/// The C files of the configuration's source folders that are not excluded
+fn source_files(directory: &Path, configuration: &Configuration) -> Vec<PathBuf> {
+    let folders: Vec<&str> = if configuration.source_folders.is_empty() {
+        vec![""]
+    } else {
+        configuration.source_folders.iter().map(String::as_str).collect()
+    };
+    let mut files: Vec<PathBuf> = folders.iter()
+        .flat_map(|folder| {
+            let pattern = format!("{}/**/*.c", glob::Pattern::escape(&directory.join(folder).display().to_string()));
+            glob::glob(&pattern).into_iter().flatten().flatten()
+        })
+        .filter(|file| {
+            let relative = file.strip_prefix(directory).unwrap_or(file).to_string_lossy().replace('\\', "/");
+            !configuration.excluded.iter().any(|excluded| relative == *excluded || relative.starts_with(&format!("{}/", excluded)))
+        })
+        .collect();
+    files.sort();
+    files.dedup();
+    files
+}
@@ -0,0 +1,74 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+
+/// The globals one function reads and writes
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FunctionGlobals {
+    pub file: PathBuf,
+    pub reads: BTreeSet<String>,
+    pub writes: BTreeSet<String>,
+    /// Reads of the function and everything it calls, as in its flattened form
+    pub reads_with_callees: BTreeSet<String>,
+    /// Writes of the function and everything it calls
+    pub writes_with_callees: BTreeSet<String>,
+}
+
+/// Which functions of a call graph read and write which file-scope variables
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalAccessReport {
+    pub functions: BTreeMap<String, FunctionGlobals>,
+}
+
+impl GlobalAccessReport {
+    pub fn build(call_graph: &CallGraph) -> Self {
+        let functions = call_graph.iter()
+            .filter(|node| node.function.signature.return_type != "extern")
+            .map(|node| {
+                let name = &node.function.signature.name;
+                let mut globals = FunctionGlobals {
+                    file: node.function.source_file.clone(),
+                    reads: node.function.global_reads.clone(),
+                    writes: node.function.global_writes.clone(),
+                    ..Default::default()
+                };
+                for callee in call_graph.reachable_from(name).iter().filter_map(|callee| call_graph.node(callee)) {
+                    globals.reads_with_callees.extend(callee.function.global_reads.iter().cloned());
+                    globals.writes_with_callees.extend(callee.function.global_writes.iter().cloned());
+                }
+                (name.clone(), globals)
+            })
+            .collect();
+        GlobalAccessReport { functions }
+    }
+
+    pub fn print(&self) {
+        println!("Global Variable Accesses:");
+        for (name, globals) in &self.functions {
+            if globals.reads_with_callees.is_empty() && globals.writes_with_callees.is_empty() {
+                continue;
+            }
+            println!("  {} ({})", name, globals.file.display());
+            let list = |names: &BTreeSet<String>| if names.is_empty() {
+                "-".to_string()
+            } else {
+                names.iter().cloned().collect::<Vec<_>>().join(", ")
+            };
+            println!("    reads: {}", list(&globals.reads));
+            println!("    writes: {}", list(&globals.writes));
+            if globals.reads_with_callees != globals.reads || globals.writes_with_callees != globals.writes {
+                println!("    with callees, reads: {}", list(&globals.reads_with_callees));
+                println!("    with callees, writes: {}", list(&globals.writes_with_callees));
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::Result;
+use graphviz_rust::dot_generator::*;
+use graphviz_rust::dot_structures::*;
+use graphviz_rust::printer::PrinterContext;
+use serde::Deserialize;
+
+use crate::call_graph::CallGraph;
+
+/// The functions and caller/callee pairs of a call graph, without call sites
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GraphSnapshot {
+    pub functions: BTreeSet<String>,
+    pub edges: BTreeSet<(String, String)>,
+}
+
+/// The parts of a JSON export a snapshot needs
+#[derive(Deserialize)]
+struct JsonGraph {
+    nodes: Vec<JsonNode>,
+    edges: Vec<JsonEdge>,
+}
+
+#[derive(Deserialize)]
+struct JsonNode {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct JsonEdge {
+    from: String,
+    to: String,
+}
+
+impl GraphSnapshot {
+    pub fn from_graph(graph: &CallGraph) -> Self {
+        GraphSnapshot {
+            functions: graph.iter().map(|node| node.function.signature.name.clone()).collect(),
+            edges: graph.iter()
+                .flat_map(|node| node.calls.iter()
+                    .map(|call| (node.function.signature.name.clone(), call.function_name.clone())))
+                .collect(),
+        }
+    }
+
+    /// Read a graph saved by `CallGraph::save_json`
+    pub fn load_json(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+        let graph: JsonGraph = serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("{} is not a call graph export: {}", path.display(), e))?;
+        Ok(GraphSnapshot {
+            functions: graph.nodes.into_iter().map(|node| node.name).collect(),
+            edges: graph.edges.into_iter().map(|edge| (edge.from, edge.to)).collect(),
+        })
+    }
+}
+
+/// What changed between two call graphs
+#[derive(Debug, Clone, Default)]
+pub struct GraphDiff {
+    pub added_functions: BTreeSet<String>,
+    pub removed_functions: BTreeSet<String>,
+    pub added_edges: BTreeSet<(String, String)>,
+    pub removed_edges: BTreeSet<(String, String)>,
+    old: GraphSnapshot,
+    new: GraphSnapshot,
+}
+
+impl GraphDiff {
+    pub fn compute(old: GraphSnapshot, new: GraphSnapshot) -> Self {
+        GraphDiff {
+            added_functions: new.functions.difference(&old.functions).cloned().collect(),
+            removed_functions: old.functions.difference(&new.functions).cloned().collect(),
+            added_edges: new.edges.difference(&old.edges).cloned().collect(),
+            removed_edges: old.edges.difference(&new.edges).cloned().collect(),
+            old,
+            new,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_functions.is_empty()
+            && self.removed_functions.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+
+    pub fn print(&self) {
+        println!("Call Graph Diff:");
+        println!("  Functions: {} added, {} removed", self.added_functions.len(), self.removed_functions.len());
+        println!("  Calls: {} added, {} removed", self.added_edges.len(), self.removed_edges.len());
+        for name in &self.added_functions {
+            println!("  + {}", name);
+        }
+        for name in &self.removed_functions {
+            println!("  - {}", name);
+        }
+        for (from, to) in &self.added_edges {
+            println!("  + {} -> {}", from, to);
+        }
+        for (from, to) in &self.removed_edges {
+            println!("  - {} -> {}", from, to);
+        }
+    }
+
+    /// Both graphs merged into one, with additions in green and removals in
+    /// red. Unchanged parts are drawn in grey to keep the changes readable.
+    pub fn save_dot(&self, output_path: &Path) -> Result<()> {
+        let mut graph = graph!(di id!("CallGraphDiff"));
+        graph.add_stmt(attr!("rankdir", "TB").into());
+        graph.add_stmt(GraphAttributes::new("node", vec![
+            attr!("shape", "box"),
+            attr!("style", "filled"),
+            attr!("fontname", "\"Helvetica\""),
+            attr!("fontsize", "10"),
+        ]).into());
+
+        for name in self.old.functions.union(&self.new.functions) {
+            let fillcolor = if self.added_functions.contains(name) {
+                "\"#90EE90\""
+            } else if self.removed_functions.contains(name) {
+                "\"#F5B7B1\""
+            } else {
+                "\"#EEEEEE\""
+            };
+            let node_id = Self::sanitize_id(name);
+            let label = format!("\"{}\"", name);
+            graph.add_stmt(Node::new(node_id!(node_id), vec![
+                attr!("label", label.to_string()),
+                attr!("fillcolor", fillcolor),
+            ]).into());
+        }
+
+        for (from, to) in self.old.edges.union(&self.new.edges) {
+            let edge = (from.clone(), to.clone());
+            let (color, style) = if self.added_edges.contains(&edge) {
+                ("\"#27AE60\"", "bold")
+            } else if self.removed_edges.contains(&edge) {
+                ("\"#E74C3C\"", "dashed")
+            } else {
+                ("\"#AAAAAA\"", "solid")
+            };
+            let from_id = Self::sanitize_id(from);
+            let to_id = Self::sanitize_id(to);
+            graph.add_stmt(edge!(node_id!(from_id) => node_id!(to_id), vec![
+                attr!("color", color),
+                attr!("style", style),
+            ]).into());
+        }
+
+        let mut printer_ctx = PrinterContext::default();
+        printer_ctx.with_semi().with_indent_step(4);
+        std::fs::write(output_path, graphviz_rust::print(&graph, &mut printer_ctx))?;
+        Ok(())
+    }
+
+    fn sanitize_id(name: &str) -> String {
+        name.replace(|c: char| !c.is_alphanumeric() && c != '_', "_")
+    }
+}
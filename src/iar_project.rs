@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::compile_commands::CompileEntry;
+use crate::project_xml::{self, Element};
+
+/// The IAR Embedded Workbench project at the root of `directory`, or in the
+/// EWARM directory STM32CubeMX generates it in
+pub fn find_project(directory: &Path) -> Option<PathBuf> {
+    [directory.to_path_buf(), directory.join("EWARM")].iter().find_map(|directory| project_in(directory))
+}
+
+fn project_in(directory: &Path) -> Option<PathBuf> {
+    let mut projects: Vec<PathBuf> = std::fs::read_dir(directory).ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ewp"))
+        .collect();
+    projects.sort();
+    projects.into_iter().next()
+}
+
+/// A build configuration of an IAR project
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Configuration {
+    /// e.g. `Debug`
+    pub name: String,
+    /// Target architecture, e.g. `ARM` or `RISCV`
+    pub toolchain: String,
+    pub groups: Vec<Group>,
+}
+
+/// A group of source files and the settings they build with in a
+/// configuration: the project's, unless the group or one holding it
+/// overrides them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Group {
+    /// Path of the group in the workspace tree, e.g. `Drivers/HAL`
+    pub name: String,
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    /// C files of the group not excluded from the configuration
+    pub files: Vec<PathBuf>,
+}
+
+/// Compile commands for the C files of the IAR project `project_file`, built
+/// with the settings of its configuration named `configuration`, else of its
+/// Debug one, else of its first
+pub fn generate(project_file: &Path, configuration: Option<&str>) -> Result<Vec<CompileEntry>> {
+    let directory = project_file.parent().unwrap_or(Path::new("."));
+    let configurations = read_configurations(project_file)?;
+    let chosen = match configuration {
+        Some(name) => configurations.iter().find(|candidate| candidate.name == name),
+        None => configurations.iter().find(|candidate| candidate.name == "Debug").or(configurations.first()),
+    };
+    let chosen = chosen.ok_or_else(|| anyhow::anyhow!(
+        "No {} configuration in {}, it has {}",
+        configuration.unwrap_or("build"),
+        project_file.display(),
+        configurations.iter().map(|candidate| candidate.name.as_str()).collect::<Vec<_>>().join(", ")
+    ))?;
+    tracing::info!("Using the {} configuration of {}", chosen.name, project_file.display());
+
+    // iccarm, iccriscv, ...; only Arm has a target clang is told about
+    let compiler = format!("icc{}", chosen.toolchain.to_lowercase());
+    let target = (chosen.toolchain == "ARM").then(|| "--target=arm-none-eabi".to_string());
+    let mut entries = Vec::new();
+    for group in &chosen.groups {
+        tracing::debug!("Group {}: {} C files", group.name, group.files.len());
+        let flags: Vec<String> = target.iter()
+            .cloned()
+            .chain(group.defines.iter().map(|define| format!("-D{}", define)))
+            .chain(group.include_dirs.iter().map(|dir| format!("-I{}", dir.display())))
+            .collect();
+        entries.extend(group.files.iter().map(|file| CompileEntry::new(directory, &compiler, &flags, file.clone())));
+    }
+    Ok(entries)
+}
+
+/// The build configurations of the IAR project `project_file`
+pub fn read_configurations(project_file: &Path) -> Result<Vec<Configuration>> {
+    let directory = project_file.parent().unwrap_or(Path::new("."));
+    let text = std::fs::read_to_string(project_file).with_context(|| format!("Failed to read {}", project_file.display()))?;
+    let root = project_xml::parse(&text).with_context(|| format!("Failed to parse {}", project_file.display()))?;
+    Ok(root.children_named("configuration")
+        .map(|configuration| {
+            let name = configuration.child_text("name").unwrap_or_default().to_string();
+            let settings = Settings::default().overridden(configuration, directory);
+            let mut groups = Vec::new();
+            // Files may sit at the top of the tree as well as in groups
+            read_group(&root, "", &name, &settings, directory, &mut groups);
+            Configuration {
+                toolchain: configuration.child("toolchain").and_then(|toolchain| toolchain.child_text("name")).unwrap_or("ARM").to_string(),
+                name,
+                groups,
+            }
+        })
+        .collect())
+}
+
+/// Add `group` and the groups it holds to `groups`, as built in the
+/// configuration named `configuration` with `inherited` settings
+fn read_group(group: &Element, path: &str, configuration: &str, inherited: &Settings, directory: &Path, groups: &mut Vec<Group>) {
+    let settings = match group.children_named("configuration").find(|candidate| candidate.child_text("name") == Some(configuration)) {
+        Some(own) => inherited.overridden(own, directory),
+        None => inherited.clone(),
+    };
+    let files: Vec<PathBuf> = group.children_named("file")
+        .filter(|file| !is_excluded(file, configuration))
+        .filter_map(|file| file.child_text("name"))
+        .filter(|name| name.ends_with(".c"))
+        .filter_map(|name| expand_variables(name, directory))
+        .collect();
+    if !files.is_empty() {
+        groups.push(Group {
+            name: path.to_string(),
+            include_dirs: settings.include_dirs.clone(),
+            defines: settings.defines.clone(),
+            files,
+        });
+    }
+    for child in group.children_named("group").filter(|child| !is_excluded(child, configuration)) {
+        let name = child.child_text("name").unwrap_or_default();
+        let path = if path.is_empty() { name.to_string() } else { format!("{}/{}", path, name) };
+        read_group(child, &path, configuration, &settings, directory, groups);
+    }
+}
+
+/// Whether the group or file `element` is excluded from the build of
+/// `configuration`
+fn is_excluded(element: &Element, configuration: &str) -> bool {
+    element.child("excluded")
+        .is_some_and(|excluded| excluded.children_named("configuration").any(|name| name.text == configuration))
+}
+
+/// The path `value` names, with `$PROJ_DIR$` replaced by `directory`. None
+/// for paths in the IAR installation or workspace, which are not known here.
+fn expand_variables(value: &str, directory: &Path) -> Option<PathBuf> {
+    let value = value.trim().trim_matches('"').replace('\\', "/");
+    let value = value.replace("$PROJ_DIR$", &directory.display().to_string());
+    if value.contains('$') {
+        tracing::debug!("Skipping {}, which names a directory outside the project", value);
+        return None;
+    }
+    Some(directory.join(value))
+}
+
+/// The include paths and defines of the C compiler settings, `ICCARM` or
+/// the like for other targets
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<String>,
+}
+
+impl Settings {
+    /// These settings with the options `configuration`, a project
+    /// configuration or a group's override of one, sets in their place
+    fn overridden(&self, configuration: &Element, directory: &Path) -> Settings {
+        let mut result = self.clone();
+        let options = configuration.children_named("settings")
+            .filter(|settings| settings.child_text("name").is_some_and(|name| name.starts_with("ICC")))
+            .filter_map(|settings| settings.child("data"))
+            .flat_map(|data| data.children_named("option"));
+        for option in options {
+            let states = option.children_named("state").map(|state| state.text.as_str()).filter(|state| !state.is_empty());
+            match option.child_text("name") {
+                Some("CCDefines") => result.defines = states.map(str::to_string).collect(),
+                Some("CCIncludePath2" | "CCIncludePath") => {
+                    result.include_dirs = states.filter_map(|state| expand_variables(state, directory)).collect()
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,1685 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph;
+use crate::logging::Progress;
+use crate::parser::function_db::{CallContext, CallInfo, Definition, ForwardedArgument, FunctionDatabase, MacroCall, MacroDefinition};
+use crate::parser::types::DataModel;
+use crate::rewriter::{self, CallPosition, Token};
+
+/// Why a call site was left as a call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    External,
+    Variadic,
+    Recursive,
+    Excluded,
+    LoopCondition,
+    ShortCircuit,
+    ArgumentMismatch,
+    /// The callee keeps state in `static` locals and `--strict` forbids hoisting them
+    StaticLocals(Vec<String>),
+    /// The call comes from a macro that expands to more than just the call
+    MacroBody,
+    /// The call could not be matched to a token in the caller's body
+    NotFound,
+    /// The call is nested deeper than `--max-passes` allows
+    PassLimit,
+    /// The callee, or the frame it would land in, uses `setjmp`/`longjmp`
+    NonLocalJump,
+    /// The callee sizes its stack at run time and `--dynamic-stack skip` is set
+    DynamicStack,
+    /// The callee is `noreturn`, `weak` or `noinline`
+    Attribute(&'static str),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::External => write!(f, "external"),
+            SkipReason::Variadic => write!(f, "variadic"),
+            SkipReason::Recursive => write!(f, "recursive"),
+            SkipReason::Excluded => write!(f, "excluded"),
+            SkipReason::LoopCondition => write!(f, "loop condition"),
+            SkipReason::ShortCircuit => write!(f, "short-circuit operand"),
+            SkipReason::ArgumentMismatch => write!(f, "argument count mismatch"),
+            SkipReason::StaticLocals(names) => write!(f, "static locals: {}", names.join(", ")),
+            SkipReason::MacroBody => write!(f, "macro expands to more than the call"),
+            SkipReason::NotFound => write!(f, "call not found in body"),
+            SkipReason::PassLimit => write!(f, "pass limit reached"),
+            SkipReason::NonLocalJump => write!(f, "setjmp/longjmp"),
+            SkipReason::DynamicStack => write!(f, "alloca or variable-length array"),
+            SkipReason::Attribute(name) => write!(f, "{} attribute", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Inline,
+    Skip(SkipReason),
+}
+
+#[derive(Debug, Clone)]
+pub struct CallSiteReport {
+    pub caller: String,
+    pub callee: String,
+    pub file: PathBuf,
+    pub line: u32,
+    pub column: u32,
+    /// How the call is made when a macro invocation makes it, e.g.
+    /// `via macro FOO()`; `line` and `column` are then the invocation's
+    pub via_macro: Option<String>,
+    /// Nesting level in the expansion tree, 0 for calls made by the entry point
+    pub depth: usize,
+    pub decision: Decision,
+    /// Tokens added to the caller by expanding this call
+    pub size_growth: usize,
+    /// Bytes the callee's locals and parameter copies add to the caller's frame
+    pub stack_growth: usize,
+    /// Line of the expansion source where the call, or the block replacing
+    /// it, ends up; none when a later expansion removed it
+    pub output_line: Option<usize>,
+}
+
+/// A call site report as written to the JSON expansion report
+#[derive(Serialize)]
+struct ReportRecord<'a> {
+    caller: &'a str,
+    callee: &'a str,
+    file: &'a Path,
+    line: u32,
+    column: u32,
+    via_macro: Option<&'a str>,
+    depth: usize,
+    output_file: &'a Path,
+    output_line: Option<usize>,
+    decision: &'static str,
+    reason: Option<String>,
+    size_growth: usize,
+    stack_growth: usize,
+}
+
+/// Functions and builtins that save or restore a stack frame
+const NONLOCAL_JUMPS: [&str; 10] = [
+    "setjmp", "_setjmp", "__setjmp", "sigsetjmp", "__sigsetjmp", "__builtin_setjmp",
+    "longjmp", "_longjmp", "siglongjmp", "__builtin_longjmp",
+];
+
+/// What to do with callees using `alloca` or variable-length arrays, whose
+/// stack use moves to the caller's frame: `alloca` memory is then only released
+/// when the caller returns, so inlining into a loop can exhaust the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DynamicStackPolicy {
+    /// Inline them like any other callee
+    Allow,
+    /// Inline them with a warning
+    #[default]
+    Warn,
+    /// Keep the call
+    Skip,
+}
+
+impl FromStr for DynamicStackPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(DynamicStackPolicy::Allow),
+            "warn" => Ok(DynamicStackPolicy::Warn),
+            "skip" => Ok(DynamicStackPolicy::Skip),
+            _ => Err(format!("unknown dynamic stack policy '{}', expected allow, warn or skip", s)),
+        }
+    }
+}
+
+/// What to do with calls that only run depending on `&&`, `||` or `?:`,
+/// which cannot be hoisted in front of their statement as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShortCircuitPolicy {
+    /// Keep the call, with a warning
+    #[default]
+    Skip,
+    /// Rewrite the guarding operators into if-statements, then inline
+    Rewrite,
+}
+
+impl FromStr for ShortCircuitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ShortCircuitPolicy::Skip),
+            "rewrite" => Ok(ShortCircuitPolicy::Rewrite),
+            _ => Err(format!("unknown short-circuit policy '{}', expected skip or rewrite", s)),
+        }
+    }
+}
+
+/// How far expansion goes below the entry point's own calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpansionMode {
+    /// Inline the entry point's calls with the callee bodies as written
+    Direct,
+    /// Expand callees before their callers, each once, in the call graph's
+    /// topological order; calls within a recursion group stay calls
+    #[default]
+    BottomUp,
+    /// Keep inlining the calls brought in by inlined bodies until none can be,
+    /// following each call path down until it would re-enter a function
+    Fixpoint,
+}
+
+impl FromStr for ExpansionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(ExpansionMode::Direct),
+            "bottom-up" => Ok(ExpansionMode::BottomUp),
+            "fixpoint" => Ok(ExpansionMode::Fixpoint),
+            _ => Err(format!("unknown expansion mode '{}', expected direct, bottom-up or fixpoint", s)),
+        }
+    }
+}
+
+/// A callee expanded once in bottom-up mode, ready to be copied to each of its
+/// call sites. Its reports are indexed from 0 and its `inl_<id>_` names are
+/// numbered from 0 up to `ids`.
+struct ExpandedBody {
+    tokens: Vec<Token>,
+    reports: Vec<CallSiteReport>,
+    ids: usize,
+}
+
+/// A call located in its caller's tokens, with the statement it can be
+/// hoisted before, its arguments and the definition it reaches
+struct CallSite {
+    statement: rewriter::Statement,
+    index: usize,
+    close: usize,
+    arguments: Vec<Vec<Token>>,
+    callee: Arc<Definition>,
+}
+
+/// What to do with calls to defined variadic functions, which cannot be
+/// expanded since their `va_arg` accesses have no parameter to bind to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VariadicPolicy {
+    /// Keep the call and mark it with a comment in the expanded source
+    #[default]
+    SkipWithComment,
+    /// Keep the call silently, as if the callee had no definition
+    TreatAsExternal,
+    /// Abort the expansion
+    Error,
+}
+
+impl FromStr for VariadicPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "skip" | "skip-with-comment" => Ok(VariadicPolicy::SkipWithComment),
+            "external" | "treat-as-external" => Ok(VariadicPolicy::TreatAsExternal),
+            "error" => Ok(VariadicPolicy::Error),
+            _ => Err(format!("unknown variadic policy '{}', expected skip, external or error", s)),
+        }
+    }
+}
+
+/// Parse a `FUNCTION=POLICY` override
+pub fn parse_variadic_override(s: &str) -> std::result::Result<(String, VariadicPolicy), String> {
+    let (name, policy) = s.split_once('=')
+        .ok_or_else(|| format!("expected FUNCTION=POLICY, got '{}'", s))?;
+    Ok((name.to_string(), policy.parse()?))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InlineOptions {
+    /// Functions that are never expanded
+    pub exclude: HashSet<String>,
+    /// Refuse to expand when the estimated flattened size exceeds the entry
+    /// point's own size by more than this factor
+    pub max_expansion_factor: Option<f64>,
+    /// Only warn when `max_expansion_factor` is exceeded
+    pub warn_on_size_limit: bool,
+    /// Refuse to inline callees with `static` locals instead of hoisting them
+    pub strict: bool,
+    /// Follow each expanded block with a comment holding the call it replaces
+    pub annotate_calls: bool,
+    /// Replace calls to pass-through wrappers by the call they forward to,
+    /// wherever they appear
+    pub collapse_wrappers: bool,
+    /// Policy for variadic callees without a per-function override
+    pub variadic_policy: VariadicPolicy,
+    pub variadic_overrides: HashMap<String, VariadicPolicy>,
+    pub mode: ExpansionMode,
+    pub short_circuit: ShortCircuitPolicy,
+    pub dynamic_stack: DynamicStackPolicy,
+    /// In fixpoint mode, stop after inlining this many levels of calls
+    pub max_passes: Option<usize>,
+    /// Replace the invocations of the project's function-like macros left in
+    /// the expansion by their replacement, after a comment with the invocation
+    pub expand_macros: bool,
+    /// Keep the `#if` chains of the bodies, with the branches that were not
+    /// compiled copied back as written
+    pub keep_inactive_branches: bool,
+    /// Open each expanded block with the callee's doc comment
+    pub doc_comments: bool,
+    /// ABI to size stack growth for, the parsed target's when unset
+    pub data_model: Option<DataModel>,
+}
+
+pub struct Expansion {
+    pub entry_point: String,
+    pub source: String,
+    pub original_size: usize,
+    pub expanded_size: usize,
+    pub reports: Vec<CallSiteReport>,
+}
+
+impl Expansion {
+    pub fn inlined_count(&self) -> usize {
+        self.reports.iter().filter(|r| r.decision == Decision::Inline).count()
+    }
+
+    /// Put `text` before the source, keeping output lines in step
+    pub fn prepend(&mut self, text: &str) {
+        let added = text.matches('\n').count();
+        self.source.insert_str(0, text);
+        for report in &mut self.reports {
+            if let Some(line) = &mut report.output_line {
+                *line += added;
+            }
+        }
+    }
+
+    /// Write one JSON record per call site, for auditing and regression tests
+    pub fn save_report(&self, output_path: &Path, output_file: &Path) -> Result<()> {
+        let records: Vec<ReportRecord> = self.reports.iter()
+            .map(|report| ReportRecord {
+                caller: &report.caller,
+                callee: &report.callee,
+                file: &report.file,
+                line: report.line,
+                column: report.column,
+                via_macro: report.via_macro.as_deref(),
+                depth: report.depth,
+                output_file,
+                output_line: report.output_line,
+                decision: match report.decision {
+                    Decision::Inline => "inlined",
+                    Decision::Skip(_) => "skipped",
+                },
+                reason: match &report.decision {
+                    Decision::Inline => None,
+                    Decision::Skip(reason) => Some(reason.to_string()),
+                },
+                size_growth: report.size_growth,
+                stack_growth: report.stack_growth,
+            })
+            .collect();
+        std::fs::write(output_path, serde_json::to_string_pretty(&records)?)?;
+        Ok(())
+    }
+}
+
+/// The call sites expanding an entry point would inline and skip, as
+/// `--dry-run` reports them without rewriting anything
+pub struct ExpansionPlan {
+    pub entry_point: String,
+    pub original_size: usize,
+    pub estimated_size: usize,
+    pub reports: Vec<CallSiteReport>,
+}
+
+impl ExpansionPlan {
+    /// Print what would be inlined and why, per call site
+    pub fn print(&self) {
+        println!("Expansion plan for {}:", self.entry_point);
+        for report in &self.reports {
+            let location = format!(
+                "{}:{}:{}",
+                report.file.file_name().and_then(|f| f.to_str()).unwrap_or("?"),
+                report.line,
+                report.column
+            );
+            let decision = match &report.decision {
+                Decision::Inline => format!("inline (+{} tokens, +{} bytes of stack)", report.size_growth, report.stack_growth),
+                Decision::Skip(reason) => format!("skip ({})", reason),
+            };
+            let via_macro = report.via_macro.as_ref().map(|via| format!(" ({})", via)).unwrap_or_default();
+            println!(
+                "  {}{} -> {}{} at {}: {}",
+                "  ".repeat(report.depth),
+                report.caller,
+                report.callee,
+                via_macro,
+                location,
+                decision
+            );
+        }
+        let inlined = self.reports.iter().filter(|r| r.decision == Decision::Inline).count();
+        println!("Would inline {} call sites and skip {}", inlined, self.reports.len() - inlined);
+        println!("Estimated size: {} -> {} tokens", self.original_size, self.estimated_size);
+    }
+}
+
+pub struct Inliner<'a> {
+    db: &'a FunctionDatabase,
+    options: InlineOptions,
+    next_id: usize,
+    reports: Vec<CallSiteReport>,
+    /// File-scope declarations of hoisted `static` locals, by hoisted name,
+    /// along with the file of the function they come from
+    hoisted: BTreeMap<String, (PathBuf, Vec<Token>)>,
+    /// Recursion group of every function in one, by name and defining
+    /// file, for bottom-up mode
+    groups: HashMap<(String, PathBuf), usize>,
+    /// Bodies already expanded in bottom-up mode, by function name and
+    /// defining file
+    expanded: HashMap<(String, PathBuf), ExpandedBody>,
+    /// Call sites decided so far for the entry point being expanded
+    progress: Option<Progress>,
+}
+
+impl<'a> Inliner<'a> {
+    pub fn new(db: &'a FunctionDatabase, options: InlineOptions) -> Self {
+        Inliner {
+            db,
+            options,
+            next_id: 0,
+            reports: Vec::new(),
+            hoisted: BTreeMap::new(),
+            groups: HashMap::new(),
+            expanded: HashMap::new(),
+            progress: None,
+        }
+    }
+
+    /// Expand every inlinable call reachable from `entry_point` into its body
+    pub fn expand(&mut self, entry_point: &str) -> Result<Expansion> {
+        let def = self.db.find_function(entry_point)?
+            .ok_or_else(|| anyhow::anyhow!("No definition found for entry point {}", entry_point))?;
+        self.expand_definition(&def)
+    }
+
+    /// Like `expand`, from a definition already chosen among those sharing
+    /// its name, as `FILE:FUNCTION` entry points pick a static
+    pub fn expand_definition(&mut self, def: &Definition) -> Result<Expansion> {
+        let entry_point = def.signature.name.as_str();
+        self.next_id = 0;
+        self.reports.clear();
+        self.hoisted.clear();
+        self.expanded.clear();
+        self.load_groups();
+        self.check_size_limit(def)?;
+
+        let mut stack = vec![frame(def)];
+        self.progress = Some(Progress::unbounded(format!("Expanding {}", entry_point)));
+        let body = self.expand_function(def, &mut stack, 0);
+        self.progress = None;
+        let mut body = body?;
+        if self.options.expand_macros {
+            body = self.expand_macros(body, &mut Vec::new());
+        }
+
+        let mut source = format!("/* {} with its callees expanded inline */\n", entry_point);
+        for (_, declaration) in self.hoisted.values() {
+            source.push_str(&rewriter::render(declaration));
+        }
+        if !self.hoisted.is_empty() {
+            source.push('\n');
+        }
+        if def.is_static {
+            source.push_str("static ");
+        }
+        source.push_str(&def.signature.to_c_definition());
+        source.push('\n');
+        let body_start = source.matches('\n').count() + 1;
+        let (rendered, lines) = rewriter::render_with_lines(&body);
+        source.push_str(&rendered);
+        for (token, line) in body.iter().zip(lines) {
+            if let Some(report) = token.report.and_then(|r| self.reports.get_mut(r)) {
+                report.output_line.get_or_insert(body_start + line);
+            }
+        }
+
+        Ok(Expansion {
+            entry_point: entry_point.to_string(),
+            source,
+            original_size: def.body_size(),
+            expanded_size: body.len(),
+            reports: std::mem::take(&mut self.reports),
+        })
+    }
+
+    /// What expanding `def` would inline and skip. Each caller is rewritten
+    /// as expansion would, call site by call site, but with the callee bodies
+    /// as written rather than expanded, so sizes are estimates.
+    pub fn plan(&mut self, def: &Definition) -> Result<ExpansionPlan> {
+        self.next_id = 0;
+        self.reports.clear();
+        self.load_groups();
+        let mut stack = vec![frame(def)];
+        self.plan_calls(def, &mut stack, 0)?;
+        Ok(ExpansionPlan {
+            entry_point: def.signature.name.clone(),
+            original_size: def.body_size(),
+            estimated_size: self.estimate_size(def),
+            reports: std::mem::take(&mut self.reports),
+        })
+    }
+
+    fn plan_calls(&mut self, def: &Definition, stack: &mut Vec<Frame>, depth: usize) -> Result<()> {
+        let mut tokens = self.caller_tokens(def);
+        for (site, call) in def.calls.iter().enumerate() {
+            let (call, report_index, decision, index) = self.decide_site(&mut tokens, site, call, def, stack, depth)?;
+            let Some(index) = index.filter(|_| decision == Decision::Inline) else {
+                self.reports[report_index].decision = decision;
+                continue;
+            };
+            let site = match self.prepare_call_site(&mut tokens, index, &call, report_index)? {
+                Ok(site) => site,
+                Err(reason) => {
+                    self.reports[report_index].decision = Decision::Skip(reason);
+                    continue;
+                }
+            };
+
+            let callee = site.callee.clone();
+            self.reports[report_index].size_growth = self.estimate_size_within(&callee, stack);
+            self.reports[report_index].stack_growth = self.stack_growth(&callee);
+            self.splice_call_site(&mut tokens, site, &call, rewriter::tokenize(&callee.body), report_index);
+            match self.options.mode {
+                ExpansionMode::Direct => {}
+                ExpansionMode::BottomUp => self.plan_calls(&callee, &mut vec![frame(&callee)], depth + 1)?,
+                ExpansionMode::Fixpoint => {
+                    stack.push(frame(&callee));
+                    let planned = self.plan_calls(&callee, stack, depth + 1);
+                    stack.pop();
+                    planned?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Index the recursion groups bottom-up mode never inlines within
+    fn load_groups(&mut self) {
+        self.groups.clear();
+        if self.options.mode == ExpansionMode::BottomUp {
+            for (index, group) in self.db.recursion_groups().iter().enumerate() {
+                self.groups.extend(group.iter().map(|def| ((def.signature.name.clone(), def.source_file.clone()), index)));
+            }
+        }
+    }
+
+    /// Estimated size in tokens of `def` once flattened: every call that would
+    /// be inlined contributes a full copy of its own flattened callee
+    pub fn estimate_size(&self, def: &Definition) -> usize {
+        self.estimate_size_within(def, &[])
+    }
+
+    /// Estimated size of `def` once flattened into the functions of `stack`
+    fn estimate_size_within(&self, def: &Definition, stack: &[Frame]) -> usize {
+        call_graph::flattened_size(def, stack, |caller, stack| caller.calls.iter()
+            .filter(|call| self.decide(call, caller, stack) == Decision::Inline)
+            .filter_map(|call| self.callee(caller, &call.function_name))
+            .collect())
+    }
+
+    fn check_size_limit(&self, def: &Definition) -> Result<()> {
+        let Some(max_factor) = self.options.max_expansion_factor else {
+            return Ok(());
+        };
+        let original = def.body_size().max(1);
+        let estimated = self.estimate_size(def);
+        let factor = estimated as f64 / original as f64;
+        if factor <= max_factor {
+            return Ok(());
+        }
+
+        let message = format!(
+            "Expanding {} would grow it from {} to about {} tokens ({:.1}x, limit {:.1}x)",
+            def.signature.name, original, estimated, factor, max_factor
+        );
+        if self.options.warn_on_size_limit {
+            tracing::warn!("{}", message);
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!("{}; raise --max-expansion-factor or exclude large callees", message))
+        }
+    }
+
+    /// The definition a call from `caller` reaches, the caller's own statics first
+    fn callee(&self, caller: &Definition, name: &str) -> Option<Arc<Definition>> {
+        self.db.resolve_call(name, &caller.source_file)
+    }
+
+    fn decide(&self, call: &CallInfo, caller: &Definition, stack: &[Frame]) -> Decision {
+        let name = &call.function_name;
+        if self.options.exclude.contains(name) {
+            return Decision::Skip(SkipReason::Excluded);
+        }
+        if call.macro_call.as_ref().is_some_and(|m| m.arguments.is_none()) {
+            return Decision::Skip(SkipReason::MacroBody);
+        }
+        if call.via.as_deref().is_some_and(|slot| self.db.pointer_target(slot).is_none()) {
+            // Indirect call through a pointer that may hold several functions
+            return Decision::Skip(SkipReason::External);
+        }
+        let Some(callee) = self.callee(caller, name) else {
+            return Decision::Skip(SkipReason::External);
+        };
+        if callee.body.is_empty() {
+            return Decision::Skip(SkipReason::External);
+        }
+        // A noreturn body would leave the caller's code after the call
+        // reachable to the compiler, a weak one may be replaced at link time
+        for (set, attribute) in [
+            (callee.attributes.noreturn, "noreturn"),
+            (callee.attributes.weak, "weak"),
+            (callee.attributes.noinline, "noinline"),
+        ] {
+            if set {
+                return Decision::Skip(SkipReason::Attribute(attribute));
+            }
+        }
+        if callee.signature.is_variadic {
+            return match self.variadic_policy(name) {
+                VariadicPolicy::TreatAsExternal => Decision::Skip(SkipReason::External),
+                _ => Decision::Skip(SkipReason::Variadic),
+            };
+        }
+        // Inlining moves the callee into the caller's frame, which changes what
+        // a `setjmp` saves and what a `longjmp` unwinds
+        let uses_jumps = |(name, file): &Frame| self.db.resolve_call(name, file)
+            .is_some_and(|def| Self::uses_nonlocal_jumps(&def));
+        if Self::uses_nonlocal_jumps(&callee) || stack.iter().any(uses_jumps) {
+            return Decision::Skip(SkipReason::NonLocalJump);
+        }
+        if self.options.dynamic_stack == DynamicStackPolicy::Skip && (callee.uses_vla || callee.uses_alloca) {
+            return Decision::Skip(SkipReason::DynamicStack);
+        }
+        if stack.contains(&frame(&callee)) || self.same_group(caller, &callee) {
+            return Decision::Skip(SkipReason::Recursive);
+        }
+        Decision::Inline
+    }
+
+    /// Bytes inlining `callee` adds to its caller's frame: its locals, and
+    /// the copies of its parameters when the data model can size them
+    fn stack_growth(&self, callee: &Definition) -> usize {
+        let parameters: u64 = self.options.data_model
+            .map(|model| callee.signature.args.iter()
+                .filter_map(|arg| arg.ty.size_and_align(model))
+                .map(|(size, align)| size.next_multiple_of(align))
+                .sum())
+            .unwrap_or(0);
+        callee.frame_size(self.options.data_model) + parameters as usize
+    }
+
+    /// Whether `def` calls `setjmp`, `longjmp` or one of their variants,
+    /// directly or through a macro
+    fn uses_nonlocal_jumps(def: &Definition) -> bool {
+        def.calls.iter().any(|call| NONLOCAL_JUMPS.contains(&call.function_name.as_str()))
+            || def.body.split_whitespace().any(|token| NONLOCAL_JUMPS.contains(&token))
+    }
+
+    /// Whether `caller` and `callee` call each other, directly or not
+    fn same_group(&self, caller: &Definition, callee: &Definition) -> bool {
+        let group = |def: &Definition| self.groups.get(&(def.signature.name.clone(), def.source_file.clone()));
+        group(caller).is_some_and(|caller| group(callee) == Some(caller))
+    }
+
+    fn variadic_policy(&self, name: &str) -> VariadicPolicy {
+        self.options.variadic_overrides.get(name)
+            .copied()
+            .unwrap_or(self.options.variadic_policy)
+    }
+
+    /// Put the `#if` chains of `def` back among its body tokens, each
+    /// directive as one token followed by the source of its branch when that
+    /// branch was not compiled
+    fn insert_conditionals(tokens: &mut Vec<Token>, def: &Definition) {
+        let mut directives = Vec::new();
+        for conditional in &def.conditionals {
+            for branch in &conditional.branches {
+                let text = if branch.active {
+                    branch.directive.clone()
+                } else {
+                    format!("{}\n{}", branch.directive, branch.text)
+                };
+                directives.push((branch.offset, text));
+            }
+            directives.push((conditional.end, "#endif".to_string()));
+        }
+        // From the back, so the earlier positions stay valid; a stable sort
+        // keeps the directives at one offset in source order
+        directives.sort_by_key(|(offset, _)| *offset);
+        for (offset, text) in directives.into_iter().rev() {
+            let index = rewriter::tokenize(def.body.get(..offset).unwrap_or(&def.body)).len();
+            tokens.insert(index.min(tokens.len()), Token::new(text));
+        }
+    }
+
+    /// Expand the calls of `def`, returning its body (braces included)
+    fn expand_function(&mut self, def: &Definition, stack: &mut Vec<Frame>, depth: usize) -> Result<Vec<Token>> {
+        let mut tokens = self.caller_tokens(def);
+        for (site, call) in def.calls.iter().enumerate() {
+            let (call, report_index, decision, index) = self.decide_site(&mut tokens, site, call, def, stack, depth)?;
+            let call = &call;
+            tracing::trace!("{} -> {} at {}:{}: {:?}", def.signature.name, call.function_name, call.line, call.column, decision);
+            if let Some(progress) = &mut self.progress {
+                progress.tick(&call.function_name);
+            }
+            let Some(index) = index.filter(|_| decision == Decision::Inline) else {
+                self.reports[report_index].decision = decision;
+                continue;
+            };
+            self.warn_prototype_mismatch(def, call);
+
+            match self.expand_call_site(&mut tokens, index, call, stack, depth, report_index)? {
+                Ok(size_growth) => {
+                    self.reports[report_index].size_growth = size_growth;
+                    self.reports[report_index].stack_growth = self.callee(def, &call.function_name)
+                        .map_or(0, |callee| self.stack_growth(&callee));
+                    self.warn_dynamic_stack(def, call);
+                }
+                Err(reason) => {
+                    if reason == SkipReason::ShortCircuit {
+                        tracing::warn!(
+                            "call to {} in {} at {}:{}:{} only runs under &&, || or ?: and was not inlined",
+                            call.function_name, def.signature.name, def.source_file.display(), call.line, call.column
+                        );
+                    }
+                    self.reports[report_index].decision = Decision::Skip(reason);
+                }
+            }
+        }
+
+        for token in &mut tokens {
+            token.site = None;
+        }
+        Ok(tokens)
+    }
+
+    /// The body of `def` with its call sites tagged, as expanding it starts from
+    fn caller_tokens(&self, def: &Definition) -> Vec<Token> {
+        let mut tokens = rewriter::tokenize(&def.body);
+        Self::tag_call_sites(&mut tokens, &def.calls);
+        if self.options.keep_inactive_branches {
+            Self::insert_conditionals(&mut tokens, def);
+        }
+        tokens
+    }
+
+    /// Collapse the wrappers at call site `site` of `def` and report the call
+    /// left in their place, returning that call, its report, whether to inline
+    /// it and where it starts in `tokens`
+    fn decide_site(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        site: usize,
+        call: &CallInfo,
+        def: &Definition,
+        stack: &[Frame],
+        depth: usize,
+    ) -> Result<(CallInfo, usize, Decision, Option<usize>)> {
+        let mut call = call.clone();
+        // A collapsed wrapper leaves the call it forwards to in its place
+        while let Some(forwarded) = self.collapse_wrapper(tokens, site, &call, def, stack, depth) {
+            call = forwarded;
+        }
+
+        let report_index = self.reports.len();
+        self.reports.push(CallSiteReport {
+            caller: def.signature.name.clone(),
+            callee: call.function_name.clone(),
+            file: def.source_file.clone(),
+            line: call.line,
+            column: call.column,
+            via_macro: call.macro_call.as_ref().map(MacroCall::label),
+            depth,
+            decision: Decision::Inline,
+            size_growth: 0,
+            stack_growth: 0,
+            output_line: None,
+        });
+
+        let mut decision = self.decide(&call, def, stack);
+        if decision == Decision::Inline
+            && self.options.mode == ExpansionMode::Fixpoint
+            && self.options.max_passes.is_some_and(|max| depth >= max)
+        {
+            decision = Decision::Skip(SkipReason::PassLimit);
+        }
+        let index = tokens.iter().position(|t| t.site == Some(site));
+        if decision == Decision::Inline && index.is_none() {
+            decision = Decision::Skip(SkipReason::NotFound);
+        }
+        if decision == Decision::Skip(SkipReason::Variadic) {
+            if self.variadic_policy(&call.function_name) == VariadicPolicy::Error {
+                anyhow::bail!(
+                    "Call to variadic function {} in {} at {}:{}:{}",
+                    call.function_name, def.signature.name, def.source_file.display(), call.line, call.column
+                );
+            }
+            if let Some(index) = index {
+                tokens.insert(index, Token::new("/* variadic call not inlined */"));
+            }
+        }
+        if let Some(index) = index {
+            tokens[index].report = Some(report_index);
+        }
+        Ok((call, report_index, decision, index))
+    }
+
+    /// Replace every invocation of a function-like macro of the project by
+    /// its replacement list, rescanned for further macros as the preprocessor
+    /// would. `active` holds the macros being expanded, which are left alone
+    /// inside their own replacement. Macros defined differently in different
+    /// places are kept.
+    fn expand_macros(&self, tokens: Vec<Token>, active: &mut Vec<String>) -> Vec<Token> {
+        let mut out = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            let is_member = i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is("->"));
+            let expansion = (!is_member && !active.contains(&tokens[i].text) && tokens.get(i + 1).is_some_and(|t| t.is("(")))
+                .then(|| self.db.macro_definition(&tokens[i].text))
+                .flatten()
+                .and_then(|definition| {
+                    let close = rewriter::matching_close(&tokens, i + 1)?;
+                    Some((definition, close, Self::substitute_macro(definition, &tokens[i + 2..close])?))
+                });
+            let Some((definition, close, replacement)) = expansion else {
+                out.push(tokens[i].clone());
+                i += 1;
+                continue;
+            };
+
+            let invocation: Vec<&str> = tokens[i..=close].iter().map(|t| t.text.as_str()).collect();
+            let mut comment = Token::new(format!("/* expanded from macro {} */", invocation.join(" ").replace("*/", "* /")));
+            comment.report = tokens[i].report;
+            out.push(comment);
+            active.push(definition.name.clone());
+            out.extend(self.expand_macros(replacement, active));
+            active.pop();
+            i = close + 1;
+        }
+        out
+    }
+
+    /// The replacement list of `definition` with its parameters replaced by
+    /// the tokens of `arguments`, applying `#` and `##`. None when the number
+    /// of arguments does not fit the macro.
+    fn substitute_macro(definition: &MacroDefinition, arguments: &[Token]) -> Option<Vec<Token>> {
+        let arguments = rewriter::split_top_level(arguments, ",");
+        let named = definition.parameters.len() - usize::from(definition.is_variadic());
+        let fits = if definition.is_variadic() {
+            arguments.len() >= named
+        } else {
+            arguments.len() == named || (named == 1 && arguments.is_empty())
+        };
+        if !fits {
+            return None;
+        }
+
+        let value = |name: &str| -> Option<Vec<Token>> {
+            if name == "__VA_ARGS__" && definition.is_variadic() {
+                let rest = arguments.get(named..).unwrap_or_default();
+                return Some(rest.join(&Token::new(",")));
+            }
+            let index = definition.parameters[..named].iter().position(|parameter| parameter == name)?;
+            Some(arguments.get(index).map(|argument| argument.to_vec()).unwrap_or_default())
+        };
+
+        let body = rewriter::tokenize(&definition.replacement);
+        let mut out: Vec<Token> = Vec::new();
+        let mut k = 0;
+        while k < body.len() {
+            if body[k].is("#") && let Some(argument) = body.get(k + 1).and_then(|t| value(&t.text)) {
+                let text: Vec<&str> = argument.iter().map(|t| t.text.as_str()).collect();
+                out.push(Token::new(format!("\"{}\"", text.join(" ").replace('\\', "\\\\").replace('"', "\\\""))));
+                k += 2;
+                continue;
+            }
+            if body[k].is("##") && let (Some(left), Some(next)) = (out.pop(), body.get(k + 1)) {
+                let right = value(&next.text).unwrap_or_else(|| vec![next.clone()]);
+                // `, ## __VA_ARGS__` only drops the comma when there are no
+                // variadic arguments
+                let comma_before_va_args = left.is(",") && next.is("__VA_ARGS__");
+                match right.split_first() {
+                    Some(_) if comma_before_va_args => {
+                        out.push(left);
+                        out.extend(right);
+                    }
+                    Some((first, rest)) => {
+                        out.push(Token::new(format!("{}{}", left.text, first.text)));
+                        out.extend(rest.iter().cloned());
+                    }
+                    None if comma_before_va_args => {}
+                    None => out.push(left),
+                }
+                k += 2;
+                continue;
+            }
+            match value(&body[k].text) {
+                Some(argument) => out.extend(argument),
+                None => out.push(body[k].clone()),
+            }
+            k += 1;
+        }
+        Some(out)
+    }
+
+    /// Replace a call to a pass-through wrapper by the call the wrapper makes,
+    /// with the wrapper's parameters substituted by the call's arguments.
+    /// Unlike a full expansion this needs no statement to hoist into, so it
+    /// also applies in loop conditions and short-circuit operands.
+    fn collapse_wrapper(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        site: usize,
+        call: &CallInfo,
+        caller: &Definition,
+        stack: &[Frame],
+        depth: usize,
+    ) -> Option<CallInfo> {
+        if !self.options.collapse_wrappers || call.macro_call.is_some() || self.decide(call, caller, stack) != Decision::Inline {
+            return None;
+        }
+        let index = tokens.iter().position(|t| t.site == Some(site))?;
+        let wrapper_def = self.callee(caller, &call.function_name)?;
+        let wrapper = wrapper_def.as_wrapper()?;
+        let wrapped = self.db.resolve_call(&wrapper.callee, &wrapper_def.source_file);
+        if wrapped.is_some_and(|wrapped| stack.contains(&frame(&wrapped))) || wrapper.callee == call.function_name {
+            return None;
+        }
+
+        let open = index + Self::callee_tokens(call).len();
+        let close = rewriter::matching_close(tokens, open)?;
+        let arguments = rewriter::split_top_level(&tokens[open + 1..close], ",");
+        if arguments.len() != wrapper_def.signature.args.len() {
+            return None;
+        }
+
+        let mut forwarded_call = vec![Token::new(wrapper.callee.clone()), Token::new("(")];
+        for (position, forwarded) in wrapper.arguments.iter().enumerate() {
+            if position > 0 {
+                forwarded_call.push(Token::new(","));
+            }
+            match forwarded {
+                ForwardedArgument::Parameter(param_index) => {
+                    let param = &wrapper_def.signature.args[*param_index];
+                    let argument_type = call.arg_types.get(*param_index).map(String::as_str);
+                    // The argument now goes to the wrapped function's parameter instead
+                    if Self::converts(argument_type, &param.canonical_type) {
+                        forwarded_call.push(Token::new("("));
+                        forwarded_call.extend(rewriter::tokenize(&param.param_type));
+                        forwarded_call.extend(rewriter::tokenize(") ("));
+                        forwarded_call.extend(arguments[*param_index].iter().cloned());
+                        forwarded_call.push(Token::new(")"));
+                    } else {
+                        forwarded_call.extend(arguments[*param_index].iter().cloned());
+                    }
+                }
+                ForwardedArgument::Constant(text) => forwarded_call.push(Token::new(text.clone())),
+            }
+        }
+        forwarded_call.push(Token::new(")"));
+        forwarded_call[0].site = Some(site);
+        forwarded_call[0].report = Some(self.reports.len());
+
+        // Keep the wrapper's return type when the wrapped function's differs
+        let return_type = &wrapper_def.signature.return_type;
+        let needs_cast = !wrapper_def.signature.return_ty.is_void()
+            && self.db.prototype(&wrapper.callee, &wrapper_def.source_file)
+                .is_none_or(|callee| callee.return_type != *return_type);
+        let replacement = if needs_cast {
+            let mut cast = vec![Token::new("(")];
+            cast.extend(rewriter::tokenize(&wrapper_def.signature.return_ty.to_c_string(None)));
+            cast.extend(rewriter::tokenize(") ("));
+            cast.extend(forwarded_call);
+            cast.push(Token::new(")"));
+            cast
+        } else {
+            forwarded_call
+        };
+
+        self.reports.push(CallSiteReport {
+            caller: caller.signature.name.clone(),
+            callee: call.function_name.clone(),
+            file: caller.source_file.clone(),
+            line: call.line,
+            column: call.column,
+            via_macro: call.macro_call.as_ref().map(MacroCall::label),
+            depth,
+            decision: Decision::Inline,
+            size_growth: replacement.len().saturating_sub(close + 1 - index),
+            stack_growth: 0,
+            output_line: None,
+        });
+        tokens.splice(index..=close, replacement);
+
+        let inner = wrapper_def.calls.first()?;
+        Some(CallInfo {
+            line: call.line,
+            column: call.column,
+            order: call.order,
+            context: call.context.clone(),
+            context_depth: call.context_depth,
+            ..inner.clone()
+        })
+    }
+
+    /// Splice the callee of the call named at `index` into `tokens`, returning
+    /// the number of tokens added, or why the call has to stay
+    fn expand_call_site(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        index: usize,
+        call: &CallInfo,
+        stack: &mut Vec<Frame>,
+        depth: usize,
+        report: usize,
+    ) -> Result<std::result::Result<usize, SkipReason>> {
+        let original_len = tokens.len();
+        let site = match self.prepare_call_site(tokens, index, call, report)? {
+            Ok(site) => site,
+            Err(reason) => return Ok(Err(reason)),
+        };
+        let callee = site.callee.clone();
+        let call_size = site.close + 1 - site.index;
+
+        let mut body = match self.options.mode {
+            ExpansionMode::Direct => rewriter::tokenize(&callee.body),
+            ExpansionMode::BottomUp => self.expanded_body(&callee, depth + 1)?,
+            ExpansionMode::Fixpoint => {
+                stack.push(frame(&callee));
+                let body = self.expand_function(&callee, stack, depth + 1);
+                stack.pop();
+                body?
+            }
+        };
+        self.hoist_static_locals(&callee, &mut body);
+        self.splice_call_site(tokens, site, call, body, report);
+        Ok(Ok(tokens.len().saturating_sub(original_len) + call_size))
+    }
+
+    /// Lower the statement around the call named at `index` until the call
+    /// can be hoisted, then locate its arguments and callee, or tell why the
+    /// call has to stay
+    fn prepare_call_site(
+        &mut self,
+        tokens: &mut Vec<Token>,
+        mut index: usize,
+        call: &CallInfo,
+        report: usize,
+    ) -> Result<std::result::Result<CallSite, SkipReason>> {
+        let mut position = rewriter::classify_call(tokens, index);
+        if self.can_lower(&position)
+            && let Some((lowered, lowered_index)) = self.lower_call_position(tokens, index)
+        {
+            *tokens = lowered;
+            index = lowered_index;
+            position = rewriter::classify_call(tokens, index);
+        }
+        let statement = match position {
+            CallPosition::Hoistable(statement) => statement,
+            CallPosition::LoopCondition => return Ok(Err(SkipReason::LoopCondition)),
+            CallPosition::ShortCircuit => return Ok(Err(SkipReason::ShortCircuit)),
+            CallPosition::Unknown => return Ok(Err(SkipReason::NotFound)),
+        };
+        let (close, arguments): (usize, Vec<Vec<Token>>) = match &call.macro_call {
+            // The invocation is replaced as a whole, by the call it expands to
+            Some(macro_call) => (
+                index + Self::callee_tokens(call).len() - 1,
+                macro_call.arguments.iter().flatten().map(|arg| rewriter::tokenize(arg)).collect(),
+            ),
+            None => {
+                let open = index + Self::callee_tokens(call).len();
+                let Some(close) = rewriter::matching_close(tokens, open) else {
+                    return Ok(Err(SkipReason::NotFound));
+                };
+                let arguments = rewriter::split_top_level(&tokens[open + 1..close], ",")
+                    .into_iter()
+                    .map(|arg| arg.to_vec())
+                    .collect();
+                (close, arguments)
+            }
+        };
+        // The report records the caller's file, which picks among same-named statics
+        let callee = self.db.resolve_call(&call.function_name, &self.reports[report].file)
+            .ok_or_else(|| anyhow::anyhow!("Lost definition of {}", call.function_name))?;
+
+        if arguments.len() != callee.signature.args.len() {
+            return Ok(Err(SkipReason::ArgumentMismatch));
+        }
+
+        let statics = rewriter::static_local_declarations(&rewriter::tokenize(&callee.body));
+        if self.options.strict && !statics.is_empty() {
+            let names = statics.into_iter().flat_map(|d| d.names).collect();
+            return Ok(Err(SkipReason::StaticLocals(names)));
+        }
+        Ok(Ok(CallSite { statement, index, close, arguments, callee }))
+    }
+
+    /// Replace the call at `site` by `body` bound to its arguments, hoisted
+    /// before its statement when the call is only part of it
+    fn splice_call_site(&mut self, tokens: &mut Vec<Token>, site: CallSite, call: &CallInfo, mut body: Vec<Token>, report: usize) {
+        let CallSite { statement, index, close, arguments, callee } = site;
+        let id = self.next_id;
+        self.next_id += 1;
+
+        // Every copy of the body needs its own labels
+        for label in rewriter::label_names(&rewriter::tokenize(&callee.body)) {
+            rewriter::rename_label(&mut body, &label, &format!("inl_{}_lbl_{}", id, label));
+        }
+
+        let is_sole_call = statement.start == index && statement.end == close + 1;
+        let returns_value = !callee.signature.return_ty.is_void();
+        let result = (returns_value && !is_sole_call).then(|| format!("inl_{}_ret", id));
+
+        let mut block = Self::bind(&callee, call, arguments, body, id, result.as_deref());
+        block[0].report = Some(report);
+        if self.options.doc_comments && !callee.doc_lines().is_empty() {
+            let text = format!("{}: {}", callee.signature.name, callee.doc_lines().join("\n * "));
+            block.insert(1, Token::new(format!("/* {} */", text.replace("*/", "* /"))));
+        }
+        if let Some(macro_call) = &call.macro_call {
+            block.insert(1, Token::new(format!("/* expanded from macro {} */", macro_call.name)));
+        }
+        if self.options.annotate_calls {
+            let mut original = rewriter::render(&tokens[index..=close]).trim().to_string();
+            if is_sole_call {
+                original.push(';');
+            }
+            block.push(Token::new(format!("/* was: {} */", original.replace("*/", "* /"))));
+        }
+
+        let mut spliced: Vec<Token> = tokens[..statement.start].to_vec();
+        if is_sole_call {
+            spliced.extend(block.iter().cloned());
+        } else {
+            let wrap = !statement.is_declaration;
+            if wrap {
+                spliced.push(Token::new("{"));
+            }
+            if let Some(result) = &result {
+                let declaration = callee.signature.return_ty.to_c_string(Some(result));
+                spliced.extend(rewriter::tokenize(&declaration));
+                spliced.push(Token::new(";"));
+            }
+            spliced.extend(block.iter().cloned());
+            spliced.extend(tokens[statement.start..index].iter().cloned());
+            match &result {
+                Some(result) => spliced.push(Token::new(result.clone())),
+                None => spliced.extend(rewriter::tokenize("( ( void ) 0 )")),
+            }
+            spliced.extend(tokens[close + 1..=statement.end].iter().cloned());
+            if wrap {
+                spliced.push(Token::new("}"));
+            }
+        }
+        spliced.extend(tokens[statement.end + 1..].iter().cloned());
+
+        *tokens = spliced;
+    }
+
+    /// Warn when the call was compiled against a prototype incompatible with
+    /// the definition another file provides: the inlined body then receives
+    /// arguments converted for other types than it declares, which the
+    /// compiler could not see across translation units
+    fn warn_prototype_mismatch(&self, caller: &Definition, call: &CallInfo) {
+        let Some(callee) = self.callee(caller, &call.function_name) else {
+            return;
+        };
+        if callee.source_file == caller.source_file {
+            return;
+        }
+        for declaration in self.db.visible_prototypes(&call.function_name, &caller.source_file) {
+            if !declaration.signature.is_compatible(&callee.signature) {
+                tracing::warn!(
+                    "call to {} in {} at {}:{}:{} uses the prototype {} from {}:{}, incompatible with the definition {} at {}:{}",
+                    call.function_name,
+                    caller.signature.name,
+                    caller.source_file.display(),
+                    call.line,
+                    call.column,
+                    declaration.signature.to_c(),
+                    declaration.source_file.display(),
+                    declaration.line,
+                    callee.signature.to_c(),
+                    callee.source_file.display(),
+                    callee.line
+                );
+            }
+        }
+    }
+
+    fn warn_dynamic_stack(&self, caller: &Definition, call: &CallInfo) {
+        if self.options.dynamic_stack != DynamicStackPolicy::Warn {
+            return;
+        }
+        let Some(callee) = self.callee(caller, &call.function_name) else {
+            return;
+        };
+        let what = match (callee.uses_alloca, callee.uses_vla) {
+            (true, true) => "alloca and variable-length arrays",
+            (true, false) => "alloca",
+            (false, true) => "variable-length arrays",
+            (false, false) => return,
+        };
+        tracing::warn!(
+            "inlined {} into {} at {}:{}:{}{}; its {} now live in the caller's frame",
+            call.function_name,
+            caller.signature.name,
+            caller.source_file.display(),
+            call.line,
+            call.column,
+            if call.context == CallContext::Loop { " inside a loop" } else { "" },
+            what
+        );
+    }
+
+    /// Whether the statement around a call in this position can be rewritten
+    /// so that the call becomes hoistable
+    fn can_lower(&self, position: &CallPosition) -> bool {
+        match position {
+            CallPosition::LoopCondition => true,
+            CallPosition::ShortCircuit => self.options.short_circuit == ShortCircuitPolicy::Rewrite,
+            _ => false,
+        }
+    }
+
+    /// Rewrite the loop headers, `&&`, `||` and `?:` around the call at
+    /// `index` into plain statements until the call can be hoisted, returning
+    /// the rewritten tokens and the call's new position
+    fn lower_call_position(&mut self, tokens: &[Token], index: usize) -> Option<(Vec<Token>, usize)> {
+        let site = tokens[index].site?;
+        let mut lowered = tokens.to_vec();
+        let mut index = index;
+        let mut ids = 0;
+        loop {
+            let position = rewriter::classify_call(&lowered, index);
+            if !self.can_lower(&position) {
+                break;
+            }
+            let id = self.next_id + ids;
+            lowered = match position {
+                CallPosition::LoopCondition => rewriter::lower_loop_condition(&lowered, index, &format!("inl_{}_next", id))?,
+                _ => rewriter::lower_short_circuit(&lowered, index, &format!("inl_{}_cond", id))?,
+            };
+            let prefix = format!("inl_{}_", id);
+            if lowered.iter().any(|t| t.text.starts_with(&prefix)) {
+                ids += 1;
+            }
+            index = lowered.iter().position(|t| t.site == Some(site))?;
+        }
+        self.next_id += ids;
+        Some((lowered, index))
+    }
+
+    /// A copy of `callee` with its own calls expanded, for a call site at
+    /// `depth`. The callee is expanded on first use only; the reports and
+    /// names of each copy are renumbered after those already in use.
+    fn expanded_body(&mut self, callee: &Definition, depth: usize) -> Result<Vec<Token>> {
+        let name = &callee.signature.name;
+        let key = (name.clone(), callee.source_file.clone());
+        if !self.expanded.contains_key(&key) {
+            let next_id = std::mem::replace(&mut self.next_id, 0);
+            let reports = std::mem::take(&mut self.reports);
+            let tokens = self.expand_function(callee, &mut vec![frame(callee)], 0);
+            let ids = std::mem::replace(&mut self.next_id, next_id);
+            let expanded_reports = std::mem::replace(&mut self.reports, reports);
+            self.expanded.insert(key.clone(), ExpandedBody { tokens: tokens?, reports: expanded_reports, ids });
+        }
+
+        let expanded = &self.expanded[&key];
+        let first_report = self.reports.len();
+        let mut tokens = expanded.tokens.clone();
+        for token in &mut tokens {
+            if let Some(report) = &mut token.report {
+                *report += first_report;
+            }
+        }
+        Self::shift_ids(&mut tokens, self.next_id);
+        self.next_id += expanded.ids;
+        self.reports.extend(expanded.reports.iter().cloned().map(|mut report| {
+            report.depth += depth;
+            report
+        }));
+        Ok(tokens)
+    }
+
+    /// Add `base` to the id of every `inl_<id>_` name, in comments too
+    fn shift_ids(tokens: &mut [Token], base: usize) {
+        if base == 0 {
+            return;
+        }
+        for token in tokens.iter_mut().filter(|t| t.text.contains("inl_")) {
+            let mut text = String::with_capacity(token.text.len());
+            let mut rest = token.text.as_str();
+            while let Some(pos) = rest.find("inl_") {
+                let starts_name = !rest[..pos].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+                let (head, tail) = rest.split_at(pos + 4);
+                text.push_str(head);
+                let digits = tail.len() - tail.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                rest = tail;
+                if starts_name && digits > 0 && tail[digits..].starts_with('_')
+                    && let Ok(id) = tail[..digits].parse::<usize>()
+                {
+                    text.push_str(&(id + base).to_string());
+                    rest = &tail[digits..];
+                }
+            }
+            text.push_str(rest);
+            token.text = text;
+        }
+    }
+
+    /// Move the `static` locals of an inlined callee to file scope, so that all
+    /// of its copies share one instance just like calls to the original did
+    fn hoist_static_locals(&mut self, callee: &Definition, body: &mut Vec<Token>) {
+        for declaration in rewriter::static_local_declarations(body).into_iter().rev() {
+            let mut hoisted: Vec<Token> = body.drain(declaration.start..=declaration.end).collect();
+            let Some(first) = declaration.names.first() else {
+                continue;
+            };
+            let prefix = self.hoisted_prefix(callee, first);
+            for name in &declaration.names {
+                let hoisted_name = format!("{}{}", prefix, name);
+                rewriter::rename_identifier(&mut hoisted, name, &hoisted_name);
+                rewriter::rename_identifier(body, name, &hoisted_name);
+            }
+            self.hoisted.entry(format!("{}{}", prefix, first)).or_insert((callee.source_file.clone(), hoisted));
+        }
+    }
+
+    /// Start of the file-scope names of the `static` locals of `callee`
+    /// declared along with `first`. The stem of its file keeps them apart
+    /// from those of same-named statics elsewhere, with a number when the
+    /// stems are alike too.
+    fn hoisted_prefix(&self, callee: &Definition, first: &str) -> String {
+        let stem: String = callee.source_file.file_stem()
+            .map(|stem| stem.to_string_lossy().chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect())
+            .unwrap_or_default();
+        (1..)
+            .map(|n| match n {
+                1 => format!("inl_static_{}_{}_", stem, callee.signature.name),
+                _ => format!("inl_static_{}{}_{}_", stem, n, callee.signature.name),
+            })
+            .find(|prefix| self.hoisted.get(&format!("{}{}", prefix, first)).is_none_or(|(file, _)| *file == callee.source_file))
+            .unwrap_or_default()
+    }
+
+    /// Build the block replacing a call: parameter temporaries bound to the
+    /// arguments, followed by the callee body with its returns rewritten
+    fn bind(
+        callee: &Definition,
+        call: &CallInfo,
+        arguments: Vec<Vec<Token>>,
+        body: Vec<Token>,
+        id: usize,
+        result: Option<&str>,
+    ) -> Vec<Token> {
+        let mut block = vec![Token::new("{")];
+        let mut inner: Vec<Token> = body[1..body.len().saturating_sub(1)].to_vec();
+
+        for (position, (param, argument)) in callee.signature.args.iter().zip(arguments).enumerate() {
+            match &param.name {
+                Some(name) => {
+                    let temp = format!("inl_{}_{}", id, name);
+                    block.extend(rewriter::tokenize(&param.ty.to_c_string(Some(&temp))));
+                    block.push(Token::new("="));
+                    let argument_type = call.arg_types.get(position).map(String::as_str);
+                    if Self::needs_cast(argument_type, &param.canonical_type) {
+                        // Spell out the conversion the call performed, which
+                        // the initialization would not
+                        block.push(Token::new("("));
+                        block.extend(rewriter::tokenize(&param.ty.to_c_string(None)));
+                        block.extend(rewriter::tokenize(") ("));
+                        block.extend(argument);
+                        block.push(Token::new(")"));
+                    } else {
+                        block.extend(argument);
+                    }
+                    block.push(Token::new(";"));
+                    rewriter::rename_identifier(&mut inner, name, &temp);
+                }
+                None => {
+                    // Unnamed parameter: keep the argument's side effects
+                    block.extend(rewriter::tokenize("( void ) ("));
+                    block.extend(argument);
+                    block.extend(rewriter::tokenize(") ;"));
+                }
+            }
+        }
+
+        block.extend(Self::rewrite_returns(&inner, result, &format!("inl_{}_exit", id)));
+        block.push(Token::new("}"));
+        block
+    }
+
+    /// Whether binding an argument of `argument_type` to a parameter of
+    /// `parameter_type` converts it: the types differ once their own
+    /// qualifiers are dropped. Aggregates can't be cast and never convert.
+    fn converts(argument_type: Option<&str>, parameter_type: &str) -> bool {
+        let Some(argument_type) = argument_type else {
+            return false;
+        };
+        let is_aggregate = |t: &str| t.starts_with("struct ") || t.starts_with("union ");
+        argument_type != "unknown"
+            && parameter_type != "unknown"
+            && unqualified(argument_type) != unqualified(parameter_type)
+            && !is_aggregate(argument_type)
+            && !is_aggregate(parameter_type)
+    }
+
+    /// Whether initializing the copy of a parameter of `parameter_type` with
+    /// an argument of `argument_type` needs a cast to convert it as the call
+    /// did: arithmetic values and compatible or `void` pointers convert on
+    /// their own, while the call's other conversions must be spelled out
+    fn needs_cast(argument_type: Option<&str>, parameter_type: &str) -> bool {
+        if !Self::converts(argument_type, parameter_type) {
+            return false;
+        }
+        let argument = unqualified(argument_type.unwrap_or_default());
+        let parameter = unqualified(parameter_type);
+        let implicit = match (pointee(&argument), pointee(&parameter)) {
+            (Some(from), Some(to)) => {
+                let from = without_qualifiers(from);
+                let to = without_qualifiers(to);
+                from == "void" || to == "void" || from == to
+            }
+            (Some(_), None) => parameter == "_Bool",
+            (None, None) => is_arithmetic(&argument) && is_arithmetic(&parameter),
+            (None, Some(_)) => false,
+        };
+        !implicit
+    }
+
+    /// Turn `return` statements into assignments to `result` followed by a jump
+    /// to the end of the expanded block
+    fn rewrite_returns(tokens: &[Token], result: Option<&str>, exit_label: &str) -> Vec<Token> {
+        let final_return = Self::final_return_index(tokens);
+        let mut out = Vec::with_capacity(tokens.len());
+        let mut jumps = 0;
+        let mut i = 0;
+
+        while i < tokens.len() {
+            if !tokens[i].is("return") {
+                out.push(tokens[i].clone());
+                i += 1;
+                continue;
+            }
+            let end = rewriter::statement_end(tokens, i).unwrap_or(tokens.len() - 1);
+            let expression = &tokens[i + 1..end];
+            let is_final = final_return == Some(i);
+
+            let mut replacement = Vec::new();
+            if !expression.is_empty() {
+                match result {
+                    Some(result) => {
+                        replacement.push(Token::new(result));
+                        replacement.push(Token::new("="));
+                        replacement.extend(expression.iter().cloned());
+                    }
+                    None => {
+                        replacement.extend(rewriter::tokenize("( void ) ("));
+                        replacement.extend(expression.iter().cloned());
+                        replacement.push(Token::new(")"));
+                    }
+                }
+                replacement.push(Token::new(";"));
+            }
+            if !is_final {
+                jumps += 1;
+                let wrap = !replacement.is_empty();
+                if wrap {
+                    replacement.insert(0, Token::new("{"));
+                }
+                replacement.extend(rewriter::tokenize(&format!("goto {} ;", exit_label)));
+                if wrap {
+                    replacement.push(Token::new("}"));
+                }
+            }
+
+            out.extend(replacement);
+            i = end + 1;
+        }
+
+        if jumps > 0 {
+            out.extend(rewriter::tokenize(&format!("{} : ;", exit_label)));
+        }
+        out
+    }
+
+    /// Index of a `return` ending the body at its top level, which can fall
+    /// through instead of jumping
+    fn final_return_index(tokens: &[Token]) -> Option<usize> {
+        if !tokens.last()?.is(";") {
+            return None;
+        }
+        let start = rewriter::statement_start(tokens, tokens.len() - 1).ok()?;
+        let is_top_level = start == 0
+            || matches!(tokens[start - 1].text.as_str(), ";" | "{" | "}" | ":")
+            || rewriter::is_asm_block(tokens, start - 1);
+        (is_top_level && tokens[start].is("return")).then_some(start)
+    }
+
+    /// Mark the token naming each call, matching the n-th call to a function
+    /// with its n-th spelling in the body
+    fn tag_call_sites(tokens: &mut [Token], calls: &[CallInfo]) {
+        let mut seen: HashMap<String, usize> = HashMap::new();
+        for (site, call) in calls.iter().enumerate() {
+            let callee = Self::callee_tokens(call);
+            let occurrence = seen.entry(callee.join(" ")).or_insert(0);
+            let found = (0..tokens.len())
+                .filter(|&i| {
+                    tokens.get(i..i + callee.len()).is_some_and(|window| {
+                        window.iter().zip(&callee).all(|(token, text)| token.is(text))
+                    })
+                        && (call.macro_call.is_some() || tokens.get(i + callee.len()).is_some_and(|t| t.is("(")))
+                        && !(i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is("->")))
+                })
+                .nth(*occurrence);
+            *occurrence += 1;
+            if let Some(i) = found {
+                tokens[i].site = Some(site);
+            }
+        }
+    }
+
+    /// Tokens naming the callee at a call site: the function name, the
+    /// pointer expression of a resolved indirect call, or the whole macro
+    /// invocation the call comes from
+    fn callee_tokens(call: &CallInfo) -> Vec<String> {
+        let spelling = match (&call.macro_call, &call.callee_expr) {
+            (Some(macro_call), _) => &macro_call.invocation,
+            (None, Some(expr)) => expr,
+            (None, None) => return vec![call.function_name.clone()],
+        };
+        rewriter::tokenize(spelling).into_iter().map(|t| t.text).collect()
+    }
+}
+
+/// A function being expanded, by name and defining file
+type Frame = (String, PathBuf);
+
+fn frame(def: &Definition) -> Frame {
+    (def.signature.name.clone(), def.source_file.clone())
+}
+
+const TYPE_QUALIFIERS: [&str; 4] = ["const", "volatile", "restrict", "__restrict"];
+const ARITHMETIC_WORDS: [&str; 12] = [
+    "char", "short", "int", "long", "float", "double", "signed", "unsigned",
+    "_Bool", "_Complex", "__int128", "_Float16",
+];
+
+/// `ty` without the qualifiers it has itself, as `char *` for
+/// `char *const`, keeping those of what it points to. Function and array
+/// types are kept as written.
+fn unqualified(ty: &str) -> String {
+    if ty.contains(['(', '[']) {
+        return ty.to_string();
+    }
+    match ty.rfind('*') {
+        Some(star) => ty[..=star].trim().to_string(),
+        None => without_qualifiers(ty),
+    }
+}
+
+fn without_qualifiers(ty: &str) -> String {
+    ty.split_whitespace().filter(|word| !TYPE_QUALIFIERS.contains(word)).collect::<Vec<_>>().join(" ")
+}
+
+/// What an unqualified pointer or array type points to once decayed
+fn pointee(ty: &str) -> Option<&str> {
+    if ty.contains('(') {
+        return None;
+    }
+    ty.strip_suffix('*').or_else(|| ty.find('[').map(|bracket| &ty[..bracket])).map(str::trim)
+}
+
+fn is_arithmetic(ty: &str) -> bool {
+    ty.starts_with("enum ") || ty.split_whitespace().all(|word| ARITHMETIC_WORDS.contains(&word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::function_db::Signature;
+
+    fn function(name: &str, body: &str, callees: &[&str]) -> Arc<Definition> {
+        function_in("a.c", false, name, body, callees)
+    }
+
+    fn function_in(file: &str, is_static: bool, name: &str, body: &str, callees: &[&str]) -> Arc<Definition> {
+        Arc::new(Definition {
+            signature: Signature { name: name.to_string(), return_type: "void".to_string(), ..Default::default() },
+            body: body.to_string(),
+            source_file: PathBuf::from(file),
+            is_static,
+            calls: callees.iter()
+                .map(|callee| CallInfo { function_name: callee.to_string(), ..Default::default() })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// `main` in a.c calls the static `init` of a.c, then `run` in b.c,
+    /// which calls the static `init` of b.c; each `init` counts its calls
+    fn same_named_statics() -> FunctionDatabase {
+        let mut db = FunctionDatabase::new();
+        db.add_function(function_in("a.c", false, "main", "{ init ( ) ; run ( ) ; }", &["init", "run"]));
+        db.add_function(function_in("a.c", true, "init", "{ static int calls = 0 ; calls ++ ; a_ready ( ) ; }", &[]));
+        db.add_function(function_in("b.c", false, "run", "{ init ( ) ; }", &["init"]));
+        db.add_function(function_in("b.c", true, "init", "{ static int calls = 1 ; calls ++ ; b_ready ( ) ; }", &[]));
+        db
+    }
+
+    #[test]
+    fn keeps_same_named_statics_apart() {
+        let db = same_named_statics();
+        let main = db.get_function_definition("main").unwrap();
+        let source = Inliner::new(&db, InlineOptions::default()).expand_definition(&main).unwrap().source;
+        assert!(source.contains("a_ready") && source.contains("b_ready"), "{}", source);
+        assert!(source.contains("static int inl_static_a_init_calls = 0;"), "{}", source);
+        assert!(source.contains("static int inl_static_b_init_calls = 1;"), "{}", source);
+    }
+
+    #[test]
+    fn follows_same_named_statics_of_other_files() {
+        // a.c's static setup calls run in b.c, which calls b.c's own setup
+        let mut db = FunctionDatabase::new();
+        db.add_function(function_in("a.c", true, "setup", "{ run ( ) ; }", &["run"]));
+        db.add_function(function_in("b.c", false, "run", "{ setup ( ) ; }", &["setup"]));
+        db.add_function(function_in("b.c", true, "setup", "{ b_ready ( ) ; }", &[]));
+        let options = InlineOptions { mode: ExpansionMode::Fixpoint, ..Default::default() };
+        let setup = db.definition_in("setup", Path::new("a.c")).unwrap();
+        let expansion = Inliner::new(&db, options).expand_definition(&setup).unwrap();
+        assert_eq!(expansion.inlined_count(), 2, "{}", expansion.source);
+        assert!(expansion.source.contains("b_ready"));
+    }
+
+    #[test]
+    fn plans_without_rewriting() {
+        let mut db = FunctionDatabase::new();
+        db.add_function(function("main", "{ step ( ) ; puts ( \"\" ) ; }", &["step", "puts"]));
+        db.add_function(function("step", "{ tick ( ) ; }", &["tick"]));
+        db.add_function(function("tick", "{ count ++ ; }", &[]));
+        let plan = Inliner::new(&db, InlineOptions::default()).plan(&db.get_function_definition("main").unwrap()).unwrap();
+
+        let decisions: Vec<(&str, usize, &Decision)> = plan.reports.iter()
+            .map(|report| (report.callee.as_str(), report.depth, &report.decision))
+            .collect();
+        assert_eq!(decisions, [
+            ("step", 0, &Decision::Inline),
+            ("tick", 1, &Decision::Inline),
+            ("puts", 0, &Decision::Skip(SkipReason::External)),
+        ]);
+        assert_eq!(plan.reports[0].size_growth, 6 + 5);
+        assert_eq!(plan.estimated_size, 11 + 6 + 5);
+    }
+
+    #[test]
+    fn plans_what_expansion_does() {
+        let mut db = FunctionDatabase::new();
+        db.add_function(function(
+            "main",
+            "{ while ( more ( ) ) { step ( ) ; } x = a && tick ( ) ; relay ( ) ; puts ( \"\" ) ; }",
+            &["more", "step", "tick", "relay", "puts", "ghost"],
+        ));
+        db.add_function(function("more", "{ return ready ; }", &[]));
+        db.add_function(function("step", "{ tick ( ) ; if ( done ) tick ( ) ; }", &["tick", "tick"]));
+        db.add_function(function("tick", "{ count ++ ; }", &[]));
+        db.add_function(function("relay", "{ tick ( ) ; }", &["tick"]));
+        db.add_function(function("ghost", "{ }", &[]));
+        let main = db.get_function_definition("main").unwrap();
+
+        for mode in [ExpansionMode::Direct, ExpansionMode::BottomUp, ExpansionMode::Fixpoint] {
+            let options = InlineOptions { mode, collapse_wrappers: true, ..Default::default() };
+            let decisions = |reports: &[CallSiteReport]| -> Vec<(String, usize, Decision)> {
+                reports.iter().map(|report| (report.callee.clone(), report.depth, report.decision.clone())).collect()
+            };
+            let planned = decisions(&Inliner::new(&db, options.clone()).plan(&main).unwrap().reports);
+            let expanded = decisions(&Inliner::new(&db, options).expand_definition(&main).unwrap().reports);
+            assert_eq!(planned, expanded, "{:?}", mode);
+            assert!(planned.iter().any(|(_, _, decision)| *decision == Decision::Skip(SkipReason::ShortCircuit)));
+            assert!(planned.iter().any(|(_, _, decision)| *decision == Decision::Skip(SkipReason::NotFound)));
+            assert!(planned.iter().any(|(callee, _, _)| callee == "relay"));
+        }
+    }
+
+    #[test]
+    fn ignores_qualifiers_of_the_argument_itself() {
+        assert!(!Inliner::needs_cast(Some("const int"), "int"));
+        assert!(!Inliner::needs_cast(Some("char *const"), "char *"));
+        assert!(!Inliner::converts(Some("volatile unsigned int"), "unsigned int"));
+        assert!(Inliner::converts(Some("const char *"), "char *"));
+    }
+
+    #[test]
+    fn casts_only_what_initialization_would_not_convert() {
+        assert!(!Inliner::needs_cast(Some("int"), "double"));
+        assert!(!Inliner::needs_cast(Some("enum mode"), "unsigned char"));
+        assert!(!Inliner::needs_cast(Some("char *"), "const char *"));
+        assert!(!Inliner::needs_cast(Some("int *"), "void *"));
+        assert!(!Inliner::needs_cast(Some("void *"), "struct node *"));
+        assert!(!Inliner::needs_cast(Some("char [16]"), "char *"));
+        assert!(!Inliner::needs_cast(Some("int *"), "_Bool"));
+        assert!(Inliner::needs_cast(Some("long"), "char *"));
+        assert!(Inliner::needs_cast(Some("int *"), "long"));
+        assert!(Inliner::needs_cast(Some("unsigned char *"), "char *"));
+    }
+}
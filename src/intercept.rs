@@ -0,0 +1,188 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::build_system;
+use crate::compile_commands::{self, CompileEntry};
+use crate::parser::function_db::CAPTURED_ENVIRONMENT;
+
+/// Directory the shims record invocations in, set for the intercepted build
+const RECORDS_VAR: &str = "CODE_INLINER_INTERCEPT_RECORDS";
+/// PATH as it was before the shims were put first
+const PATH_VAR: &str = "CODE_INLINER_INTERCEPT_PATH";
+/// CC as it was, when the build was given one
+const CC_VAR: &str = "CODE_INLINER_INTERCEPT_CC";
+
+/// Names shimmed even when PATH has no compiler by that name, as builds
+/// default to them
+const DEFAULT_COMPILERS: [&str; 3] = ["cc", "gcc", "clang"];
+
+/// A compiler run a shim saw
+#[derive(Debug, Serialize, Deserialize)]
+struct Invocation {
+    directory: PathBuf,
+    /// The real compiler first
+    arguments: Vec<String>,
+    /// The variables of `CAPTURED_ENVIRONMENT` it ran with
+    #[serde(default)]
+    environment: BTreeMap<String, String>,
+}
+
+/// Run `command` in `project` with every C compiler it may run replaced by
+/// a shim recording the invocation, and write what they compiled as the
+/// project's compile_commands.json. Compilers are found through PATH and
+/// CC, so a build running one by absolute path is only seen when CC names
+/// it. Returns the path written.
+pub fn run(project: &Path, command: &[String]) -> Result<PathBuf> {
+    let (program, arguments) = command.split_first().ok_or_else(|| anyhow::anyhow!("No build command to run"))?;
+    let project = project.canonicalize()?;
+    let session = build_system::build_dir(&project, "intercept");
+    let shims = session.join("bin");
+    let records = session.join("records");
+    let _ = std::fs::remove_dir_all(&session);
+    std::fs::create_dir_all(&shims)?;
+    std::fs::create_dir_all(&records)?;
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let cc = std::env::var_os("CC").filter(|cc| !cc.is_empty());
+    let cc_name = cc.as_ref()
+        .and_then(|cc| Path::new(cc).file_name())
+        .map_or_else(|| "cc".to_string(), |name| name.to_string_lossy().into_owned());
+    let mut names: BTreeSet<String> = DEFAULT_COMPILERS.iter().map(|name| name.to_string()).collect();
+    names.insert(cc_name.clone());
+    names.extend(std::env::split_paths(&path)
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| compile_commands::is_compiler(name)));
+    let executable = std::env::current_exe()?;
+    for name in &names {
+        install_shim(&executable, &shims.join(name))
+            .with_context(|| format!("Failed to install a {} shim in {}", name, shims.display()))?;
+    }
+    tracing::info!("Intercepting {} compilers while running {}", names.len(), command.join(" "));
+
+    let mut build = Command::new(program);
+    build.args(arguments)
+        .current_dir(&project)
+        .env("PATH", std::env::join_paths(std::iter::once(shims.clone()).chain(std::env::split_paths(&path)))?)
+        .env(PATH_VAR, &path)
+        .env(RECORDS_VAR, &records)
+        .env("CC", shims.join(&cc_name));
+    if let Some(cc) = &cc {
+        build.env(CC_VAR, cc);
+    }
+    let status = build.status().map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+
+    let entries = read_records(&records)?;
+    let _ = std::fs::remove_dir_all(&session);
+    if !status.success() {
+        tracing::warn!("{} failed ({}); only the commands it ran before are recorded", command.join(" "), status);
+    }
+    if entries.is_empty() {
+        anyhow::bail!("{} ran no C compiler", command.join(" "));
+    }
+    let output = project.join("compile_commands.json");
+    compile_commands::write(&entries, &output)?;
+    Ok(output)
+}
+
+/// The compile commands of the invocations recorded in `records`, in the
+/// order they ran, without repeats
+fn read_records(records: &Path) -> Result<Vec<CompileEntry>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(records)?.flatten().map(|entry| entry.path()).collect();
+    files.sort();
+    let mut entries: Vec<CompileEntry> = Vec::new();
+    for file in files {
+        let text = std::fs::read_to_string(&file)?;
+        let invocation: Invocation = serde_json::from_str(&text).with_context(|| format!("Failed to read {}", file.display()))?;
+        for mut entry in CompileEntry::from_invocation(&invocation.arguments, &invocation.directory) {
+            entry.environment = invocation.environment.clone();
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// When this process is a shim an intercepted build ran as its compiler,
+/// record the invocation, run the real compiler and return its exit code.
+/// None when the program was run as itself.
+pub fn as_compiler() -> Option<i32> {
+    let records = std::env::var_os(RECORDS_VAR)?;
+    let mut arguments = std::env::args_os();
+    let invoked = PathBuf::from(arguments.next()?);
+    let name = invoked.file_name()?.to_string_lossy().into_owned();
+    if !compile_commands::is_compiler(&name) {
+        return None;
+    }
+    let arguments: Vec<OsString> = arguments.collect();
+    let Some(real) = real_compiler(&name) else {
+        eprintln!("{}: command not found", name);
+        return Some(127);
+    };
+    // A failure to record must not fail the build
+    if let Err(e) = record(Path::new(&records), &real, &arguments) {
+        eprintln!("code-inliner: failed to record a {} invocation: {}", name, e);
+    }
+    match Command::new(&real).args(&arguments).status() {
+        Ok(status) => Some(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("{}: {}", real.display(), e);
+            Some(126)
+        }
+    }
+}
+
+/// The compiler a shim named `name` stands for: CC when it was named so,
+/// else the first `name` on the PATH from before the shims
+fn real_compiler(name: &str) -> Option<PathBuf> {
+    if let Some(cc) = std::env::var_os(CC_VAR).map(PathBuf::from)
+        && cc.components().count() > 1
+        && cc.file_name().is_some_and(|cc_name| cc_name == name)
+    {
+        return Some(cc);
+    }
+    std::env::split_paths(&std::env::var_os(PATH_VAR)?)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Save the invocation of `real` with `arguments` in a file of its own in
+/// `records`, named so that the files sort in the order they were written
+fn record(records: &Path, real: &Path, arguments: &[OsString]) -> Result<()> {
+    // PATH as the build had it, without the shims
+    let environment = CAPTURED_ENVIRONMENT.iter()
+        .filter_map(|name| {
+            let value = if *name == "PATH" { std::env::var(PATH_VAR) } else { std::env::var(name) };
+            Some((name.to_string(), value.ok()?))
+        })
+        .collect();
+    let invocation = Invocation {
+        directory: std::env::current_dir()?,
+        arguments: std::iter::once(real.display().to_string())
+            .chain(arguments.iter().map(|argument| argument.to_string_lossy().into_owned()))
+            .collect(),
+        environment,
+    };
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let path = records.join(format!("{:020}-{}.json", time, std::process::id()));
+    std::fs::write(path, serde_json::to_string(&invocation)?)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn install_shim(executable: &Path, shim: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(executable, shim)
+}
+
+#[cfg(not(unix))]
+fn install_shim(executable: &Path, shim: &Path) -> std::io::Result<()> {
+    std::fs::copy(executable, format!("{}.exe", shim.display())).map(|_| ())
+}
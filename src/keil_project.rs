@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::compile_commands::CompileEntry;
+use crate::project_xml::{self, Element};
+
+/// The Keil uVision project at the root of `directory`, or in the MDK-ARM
+/// directory STM32CubeMX generates it in: a .uvprojx, or the .uvproj of
+/// uVision 4, which has the same layout
+pub fn find_project(directory: &Path) -> Option<PathBuf> {
+    [directory.to_path_buf(), directory.join("MDK-ARM")].iter().find_map(|directory| project_in(directory))
+}
+
+fn project_in(directory: &Path) -> Option<PathBuf> {
+    let mut projects: Vec<PathBuf> = std::fs::read_dir(directory).ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "uvprojx" || extension == "uvproj"))
+        .collect();
+    // Where both are there, the .uvprojx is the one uVision 5 maintains
+    projects.sort_by_key(|path| (path.extension() != Some("uvprojx".as_ref()), path.clone()));
+    projects.into_iter().next()
+}
+
+/// A target of a Keil project, what it builds with
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    /// e.g. `Target 1`
+    pub name: String,
+    /// `armclang` for Arm Compiler 6, else `armcc`
+    pub compiler: String,
+    pub groups: Vec<Group>,
+}
+
+/// A group of source files and the settings they build with: the target's
+/// with the group's own added, its include paths searched first
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Group {
+    pub name: String,
+    pub include_dirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    pub undefines: Vec<String>,
+    /// C files of the group included in the build
+    pub files: Vec<PathBuf>,
+}
+
+/// Compile commands for the C files of the Keil project `project_file`, as
+/// its target named `target`, else its first, builds them
+pub fn generate(project_file: &Path, target: Option<&str>) -> Result<Vec<CompileEntry>> {
+    let directory = project_file.parent().unwrap_or(Path::new("."));
+    let targets = read_targets(project_file)?;
+    let chosen = match target {
+        Some(name) => targets.iter().find(|candidate| candidate.name == name),
+        None => targets.first(),
+    };
+    let chosen = chosen.ok_or_else(|| anyhow::anyhow!(
+        "No target {} in {}, it has {}",
+        target.unwrap_or("for Arm"),
+        project_file.display(),
+        targets.iter().map(|candidate| candidate.name.as_str()).collect::<Vec<_>>().join(", ")
+    ))?;
+    tracing::info!("Using target {} of {}", chosen.name, project_file.display());
+
+    let mut entries = Vec::new();
+    for group in &chosen.groups {
+        tracing::debug!("Group {}: {} C files", group.name, group.files.len());
+        // Keil compiles for Arm, which clang must be told to target
+        let flags: Vec<String> = std::iter::once("--target=arm-none-eabi".to_string())
+            .chain(group.defines.iter().map(|define| format!("-D{}", define)))
+            .chain(group.undefines.iter().map(|undefine| format!("-U{}", undefine)))
+            .chain(group.include_dirs.iter().map(|dir| format!("-I{}", dir.display())))
+            .collect();
+        entries.extend(group.files.iter().map(|file| CompileEntry::new(directory, &chosen.compiler, &flags, file.clone())));
+    }
+    Ok(entries)
+}
+
+/// The Arm targets of the Keil project `project_file`
+pub fn read_targets(project_file: &Path) -> Result<Vec<Target>> {
+    let directory = project_file.parent().unwrap_or(Path::new("."));
+    let text = std::fs::read_to_string(project_file).with_context(|| format!("Failed to read {}", project_file.display()))?;
+    let root = project_xml::parse(&text).with_context(|| format!("Failed to parse {}", project_file.display()))?;
+    Ok(root.descendants_named("Target")
+        .into_iter()
+        // 8051 targets have C51 settings rather than Arm ones
+        .filter(|target| !target.descendants_named("TargetArmAds").is_empty())
+        .map(|target| read_target(target, directory))
+        .collect())
+}
+
+fn read_target(target: &Element, directory: &Path) -> Target {
+    let name = target.child_text("TargetName").unwrap_or_default().to_string();
+    let compiler = if target.child_text("uAC6") == Some("1") { "armclang" } else { "armcc" };
+    let mut settings = target.child("TargetOption")
+        .map(|option| Settings::read(option, directory))
+        .unwrap_or_default();
+    // Where uVision puts RTE_Components.h for the target's software packs
+    let rte = directory.join("RTE").join(format!("_{}", name.replace(' ', "_")));
+    if rte.is_dir() {
+        settings.include_dirs.push(rte);
+        settings.defines.push("_RTE_".to_string());
+    }
+
+    let groups = target.descendants_named("Group")
+        .into_iter()
+        .filter(|group| included_in_build(group.child("GroupOption")))
+        .map(|group| {
+            let own = group.child("GroupOption")
+                .map(|option| Settings::read(option, directory))
+                .unwrap_or_default();
+            let files = group.descendants_named("File")
+                .into_iter()
+                .filter(|file| included_in_build(file.child("FileOption")))
+                .filter_map(|file| file.child_text("FilePath"))
+                .filter(|path| path.ends_with(".c"))
+                .map(|path| directory.join(path.replace('\\', "/")))
+                .collect();
+            Group {
+                name: group.child_text("GroupName").unwrap_or_default().to_string(),
+                include_dirs: own.include_dirs.into_iter().chain(settings.include_dirs.iter().cloned()).collect(),
+                defines: settings.defines.iter().cloned().chain(own.defines).collect(),
+                undefines: settings.undefines.iter().cloned().chain(own.undefines).collect(),
+                files,
+            }
+        })
+        .collect();
+    Target { name, compiler: compiler.to_string(), groups }
+}
+
+/// Whether the target, group or file whose options are `option` is built,
+/// `IncludeInBuild` being 0 when it is not
+fn included_in_build(option: Option<&Element>) -> bool {
+    option.and_then(|option| option.child("CommonProperty"))
+        .and_then(|common| common.child_text("IncludeInBuild"))
+        != Some("0")
+}
+
+/// The C compiler's `VariousControls` of a target or group
+#[derive(Debug, Clone, Default)]
+struct Settings {
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<String>,
+    undefines: Vec<String>,
+}
+
+impl Settings {
+    /// The settings under `option`, a `TargetOption` or `GroupOption`. The
+    /// assembler has `VariousControls` of its own, under `Aads`.
+    fn read(option: &Element, directory: &Path) -> Settings {
+        let Some(controls) = option.descendants_named("Cads").first().and_then(|cads| cads.child("VariousControls")) else {
+            return Settings::default();
+        };
+        // Defines are separated by commas or spaces, include paths by semicolons
+        let symbols = |name: &str| -> Vec<String> {
+            controls.child_text(name)
+                .unwrap_or_default()
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|symbol| !symbol.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        Settings {
+            include_dirs: controls.child_text("IncludePath")
+                .unwrap_or_default()
+                .split(';')
+                .map(|path| path.trim().trim_matches('"'))
+                .filter(|path| !path.is_empty())
+                .map(|path| directory.join(path.replace('\\', "/")))
+                .collect(),
+            defines: symbols("Define"),
+            undefines: symbols("Undefine"),
+        }
+    }
+}
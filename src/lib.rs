@@ -0,0 +1,53 @@
+//! Call graph analysis and inline expansion for C projects, parsed with
+//! libclang.
+//!
+//! [`Project`] is the way in: it parses a project and builds the call graph
+//! of an entry point, which can then be expanded with its callees inlined.
+//! The parser, function database, call graph and inliner it is built on
+//! are exported too, for finer control. The other modules are what the
+//! `code-inliner` binary is made of, and may change from one release to
+//! the next.
+
+pub mod call_graph;
+pub mod inliner;
+pub mod parser;
+mod project;
+pub mod rewriter;
+mod scc;
+
+#[doc(hidden)] pub mod amalgamate;
+#[doc(hidden)] pub mod build_system;
+#[doc(hidden)] pub mod call_depth;
+#[doc(hidden)] pub mod cmake;
+#[doc(hidden)] pub mod compile_commands;
+#[doc(hidden)] pub mod concurrency;
+#[doc(hidden)] pub mod coupling;
+#[doc(hidden)] pub mod db_diff;
+#[doc(hidden)] pub mod dead_code;
+#[doc(hidden)] pub mod declarations;
+#[doc(hidden)] pub mod dominators;
+#[doc(hidden)] pub mod eclipse_project;
+#[doc(hidden)] pub mod global_access;
+#[doc(hidden)] pub mod graph_diff;
+#[doc(hidden)] pub mod iar_project;
+#[doc(hidden)] pub mod intercept;
+#[doc(hidden)] pub mod keil_project;
+#[doc(hidden)] pub mod library;
+#[doc(hidden)] pub mod logging;
+#[doc(hidden)] pub mod makefile_parser;
+#[doc(hidden)] pub mod metrics;
+#[doc(hidden)] pub mod ninja_parser;
+#[doc(hidden)] pub mod parse_report;
+#[doc(hidden)] pub mod project_xml;
+#[doc(hidden)] pub mod rename;
+#[doc(hidden)] pub mod serve;
+#[doc(hidden)] pub mod source_scan;
+#[doc(hidden)] pub mod stack_usage;
+#[doc(hidden)] pub mod theme;
+#[doc(hidden)] pub mod verify;
+
+pub use call_graph::CallGraph;
+pub use inliner::{Expansion, ExpansionPlan, InlineOptions, Inliner};
+pub use parser::ast::AstParser;
+pub use parser::function_db::FunctionDatabase;
+pub use project::{EntryGraph, Project};
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+use crate::parser::function_db::FunctionDatabase;
+
+/// Call graph summary of one exported function
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiSummary {
+    pub name: String,
+    pub source_file: PathBuf,
+    pub reachable_internal: usize,
+    pub reachable_exported: usize,
+    pub external_calls: usize,
+}
+
+/// A `static` function and the exported functions that can reach it
+#[derive(Debug, Clone, Serialize)]
+pub struct InternalUsage {
+    pub name: String,
+    pub source_file: PathBuf,
+    pub used_by: Vec<String>,
+}
+
+/// Internal vs exported breakdown of a library, where every externally
+/// visible function is treated as a root
+#[derive(Debug, Clone, Serialize)]
+pub struct LibraryReport {
+    pub exported: Vec<ApiSummary>,
+    pub internal: Vec<InternalUsage>,
+}
+
+/// Every defined function with external linkage, sorted by name
+pub fn exported_functions(db: &FunctionDatabase) -> Vec<String> {
+    let mut exported: Vec<String> = db.iter()
+        .filter(|def| !def.is_static && !def.body.is_empty())
+        .map(|def| def.signature.name.clone())
+        .collect();
+    exported.sort();
+    exported
+}
+
+impl LibraryReport {
+    /// Build one call graph per exported function and cross-reference them
+    pub fn build(db: &FunctionDatabase) -> Result<(Self, Vec<CallGraph>)> {
+        let exported = exported_functions(db);
+        let mut graphs = Vec::with_capacity(exported.len());
+        let mut used_by: BTreeMap<String, Vec<String>> = db.iter()
+            .filter(|def| def.is_static)
+            .map(|def| (def.signature.name.clone(), Vec::new()))
+            .collect();
+        let mut summaries = Vec::with_capacity(exported.len());
+
+        for api in &exported {
+            let graph = CallGraph::build(db, api)?;
+            let mut summary = ApiSummary {
+                name: api.clone(),
+                source_file: db.get_function_definition(api)
+                    .map(|def| def.source_file.clone())
+                    .unwrap_or_default(),
+                reachable_internal: 0,
+                reachable_exported: 0,
+                external_calls: graph.external_count(),
+            };
+
+            for node in graph.iter() {
+                let name = &node.function.signature.name;
+                if name == api || node.function.signature.return_type == "extern" {
+                    continue;
+                }
+                if node.function.is_static {
+                    summary.reachable_internal += 1;
+                    if let Some(apis) = used_by.get_mut(name) {
+                        apis.push(api.clone());
+                    }
+                } else {
+                    summary.reachable_exported += 1;
+                }
+            }
+
+            summaries.push(summary);
+            graphs.push(graph);
+        }
+
+        let internal = used_by.into_iter()
+            .map(|(name, used_by)| InternalUsage {
+                source_file: db.get_function_definition(&name)
+                    .map(|def| def.source_file.clone())
+                    .unwrap_or_default(),
+                name,
+                used_by,
+            })
+            .collect();
+
+        Ok((LibraryReport { exported: summaries, internal }, graphs))
+    }
+
+    pub fn print(&self) {
+        println!("Library Report:");
+        println!("  Exported functions: {}", self.exported.len());
+        for api in &self.exported {
+            println!(
+                "    {} - {} internal, {} exported, {} external",
+                api.name, api.reachable_internal, api.reachable_exported, api.external_calls
+            );
+        }
+
+        println!("  Internal functions: {}", self.internal.len());
+        for internal in &self.internal {
+            if internal.used_by.is_empty() {
+                println!("    {} - unreachable from any exported function", internal.name);
+            } else {
+                println!("    {} - used by {}", internal.name, internal.used_by.join(", "));
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
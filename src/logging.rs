@@ -0,0 +1,118 @@
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use tracing::level_filters::LevelFilter;
+
+/// Clears the progress line on a terminal, so log lines do not run into it
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Time between two redraws of a progress line
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Send log lines to stderr: warnings and errors only when `quiet`, what is
+/// being parsed and decided from one `verbose`, every step from two
+pub fn init(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::WARN,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(|| LogWriter { cleared: false })
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
+/// The flags giving a parse worker the log level of this process
+pub fn worker_flags() -> Vec<&'static str> {
+    match LevelFilter::current() {
+        LevelFilter::TRACE => vec!["-vv"],
+        LevelFilter::DEBUG => vec!["-v"],
+        LevelFilter::INFO => vec![],
+        _ => vec!["--quiet"],
+    }
+}
+
+/// Progress lines are only drawn on a terminal, and not with `--quiet`
+fn shows_progress() -> bool {
+    std::io::stderr().is_terminal() && LevelFilter::current() >= LevelFilter::INFO
+}
+
+/// Writes one log line to stderr, first clearing a progress line drawn
+/// there by this process or a parse worker
+struct LogWriter {
+    cleared: bool,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut stderr = std::io::stderr().lock();
+        if !self.cleared && shows_progress() {
+            stderr.write_all(CLEAR_LINE.as_bytes())?;
+            self.cleared = true;
+        }
+        stderr.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// A line on stderr redrawn in place as the items of a long task are done,
+/// cleared when dropped
+pub struct Progress {
+    label: String,
+    /// Number of items, unknown when the task discovers them as it goes
+    total: Option<usize>,
+    done: usize,
+    visible: bool,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, total: usize) -> Self {
+        Self::with_total(label.into(), Some(total))
+    }
+
+    pub fn unbounded(label: impl Into<String>) -> Self {
+        Self::with_total(label.into(), None)
+    }
+
+    fn with_total(label: String, total: Option<usize>) -> Self {
+        Progress {
+            label,
+            total,
+            done: 0,
+            visible: shows_progress(),
+            last_draw: None,
+        }
+    }
+
+    /// Count one more item done, `item` being what it was
+    pub fn tick(&mut self, item: &str) {
+        self.done += 1;
+        if !self.visible || self.last_draw.is_some_and(|last| last.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        let count = match self.total {
+            Some(total) => format!("{}/{}", self.done, total),
+            None => self.done.to_string(),
+        };
+        let item: String = item.chars().take(60).collect();
+        eprint!("{}{} [{}] {}", CLEAR_LINE, self.label, count, item);
+        self.last_draw = Some(Instant::now());
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.last_draw.is_some() {
+            eprint!("{}", CLEAR_LINE);
+        }
+    }
+}
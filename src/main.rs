@@ -1,64 +1,921 @@
-use crate::{cli::Args, parser::{ast::AstParser}, call_graph::CallGraph};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::cli::{Args, AnalyzeArgs, AnalyzeCommand, Command, CouplingArgs, StackArgs, DbArgs, DbCommand, DiffArgs, EntrySpec, GraphArgs, GraphCommand, InlineArgs, RootArgs};
+use expansion::{parser::{ast::AstParser}, call_graph::{BuildOptions, CallGraph, DotOptions, ExportFormat, NodeFilter, SourceLinks}, metrics::RunMetrics};
+use expansion::{compile_commands, intercept, logging, serve, source_scan};
+use expansion::inliner::{Inliner, InlineOptions};
+use expansion::rename::rename_function;
+use expansion::library::{LibraryReport, exported_functions};
+use expansion::verify::verify_expansion;
+use expansion::amalgamate::amalgamate;
+use expansion::declarations::companion_header;
+use expansion::graph_diff::{GraphDiff, GraphSnapshot};
+use expansion::db_diff::DatabaseDiff;
+use expansion::build_system::BuildSystem;
+use expansion::dead_code::DeadCodeReport;
+use expansion::coupling::CouplingReport;
+use expansion::call_depth::CallDepthReport;
+use expansion::stack_usage::StackReport;
+use expansion::concurrency::ConcurrencyReport;
+use expansion::global_access::GlobalAccessReport;
+use expansion::theme::GraphTheme;
+use expansion::parse_report::{DiagnosticsFormat, ParseReport};
+use expansion::parser::function_db::{Definition, FunctionDatabase};
+use expansion::parser::parse_cache::ParseCache;
+use expansion::parser::toolchain::Toolchain;
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap::error::ErrorKind;
 
 
-mod parser;
-mod inliner;
-mod rewriter;
 mod cli;
-mod call_graph;
 
 
 fn main() -> Result<()> {
+    // Run by a build under `intercept` as its compiler
+    if let Some(code) = intercept::as_compiler() {
+        std::process::exit(code);
+    }
     let args = Args::parse();
-    println!("Looking for compile_commands.json in {}", args.project_path.display());
-    let build_path = args.project_path.join("compile_commands.json");
-    if !build_path.exists() {
-        anyhow::bail!(
-            "Compile commands file not found at: {}",
-            build_path.display()
-        );
+    logging::init(args.verbose, args.quiet);
+    // Comparing saved databases needs no project
+    if let Command::Db(DbArgs { action: Some(DbCommand::Diff { old, new }), .. }) = &args.command {
+        return run_db_diff(old, new);
     }
-    if !args.entry_file.exists() {
-        anyhow::bail!(
-            "Cannot find entry point : {}",
-            args.entry_file.display()
-        );
+    if args.project_path.is_none() {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "the following required arguments were not provided:\n  --project-path <DIR>")
+            .exit();
+    }
+    // Nor does building it to record its compile commands
+    if let Command::Intercept { command } = &args.command {
+        let path = intercept::run(args.project(), command)?;
+        println!("Saved compile commands to: {}", path.display());
+        return Ok(());
     }
+    let database_dir = match &args.command {
+        Command::Init { scan, force } => run_init(&args, *scan, *force)?,
+        _ => compilation_database_dir(&args)?,
+    };
 
-    let parser = AstParser::new(&args.project_path)?;
+    let jobs = args.jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1));
+    let parser = AstParser::open(args.project(), &database_dir)?
+        .with_workers(jobs, Some(std::env::current_exe()?))
+        .with_extra_args(args.extra_clang_args.clone())
+        .with_config(args.config.clone())
+        .with_toolchain(Toolchain {
+            target: args.target.clone(),
+            sysroot: args.sysroot.clone(),
+            include_dirs: args.toolchain_include.clone(),
+        })
+        .with_fallback_includes(args.fallback_include.clone())
+        .with_pch(args.pch.clone(), args.pch_dir.clone());
+    if let Command::ParseWorker { commands, output } = &args.command {
+        return parser.parse_worker(commands, output);
+    }
+
+    let function_db = load_or_parse(&parser, &args)?;
+    tracing::info!("Found {} functions in database", function_db.iter().count());
 
-    println!("\nParsing all source files...");
-    let function_db = parser.parse_all_files(false)?;
-    println!("Found {} functions in database", function_db.iter().count());
+    match args.diagnostics {
+        Some(DiagnosticsFormat::Text) => ParseReport::build(&function_db).print(),
+        Some(DiagnosticsFormat::Json) => {
+            let report_path = output_dir()?.join("parse_report.json");
+            ParseReport::build(&function_db).save_json(&report_path)?;
+            println!("Saved parse report to: {}", report_path.display());
+        }
+        None => {}
+    }
 
-    let entry_func = "main";
-    let mut call_graph = CallGraph::build(&function_db, entry_func)?;
+    match &args.command {
+        Command::Graph(graph_args) => match &graph_args.action {
+            Some(GraphCommand::Diff(diff_args)) => run_graph_diff(&args, &parser, &function_db, &graph_args.roots, diff_args),
+            None => run_graph(&args, &function_db, graph_args),
+        },
+        Command::Inline(inline_args) => run_inline(&args, &function_db, inline_args),
+        Command::Analyze(analyze_args) => match &analyze_args.action {
+            Some(AnalyzeCommand::Metrics(coupling_args)) => run_coupling(&args, &function_db, &analyze_args.roots, coupling_args),
+            Some(AnalyzeCommand::Stack(stack_args)) => run_stack(&args, &function_db, &analyze_args.roots, stack_args),
+            None => run_analyze(&args, &function_db, analyze_args),
+        },
+        Command::Db(db_args) => run_db(&function_db, db_args),
+        Command::Rename { old, new, file, dry_run } => {
+            let summary = rename_function(&parser, &function_db, old, new, file.as_deref(), *dry_run)?;
+            for (path, count) in &summary.files {
+                println!("  {}: {} occurrence(s)", path.display(), count);
+            }
+            let verb = if *dry_run { "Would rename" } else { "Renamed" };
+            println!("{} {} occurrence(s) of {} in {} file(s)", verb, summary.total(), old, summary.files.len());
+            if summary.skipped > 0 {
+                tracing::warn!("{} occurrence(s) inside macro expansions were left untouched", summary.skipped);
+            }
+            Ok(())
+        }
+        Command::ParseWorker { .. } => unreachable!("parse workers return before the database is built"),
+        Command::Serve => serve::run(&function_db, std::io::stdin().lock(), std::io::stdout().lock()),
+        // What failed to parse is what is left to fix in the database
+        Command::Init { .. } => {
+            if args.diagnostics.is_none() {
+                ParseReport::build(&function_db).print();
+            }
+            Ok(())
+        }
+        Command::Intercept { .. } => unreachable!("intercept returns before the database is built"),
+    }
+}
 
-    call_graph.to_dot();
+/// Directory holding the compile_commands.json to parse with: the one given,
+/// else the one the project's build system has or generates
+fn compilation_database_dir(args: &Args) -> Result<PathBuf> {
+    if let Some(dir) = &args.database_dir {
+        return Ok(dir.clone());
+    }
+    let build_system = match args.build_system {
+        Some(build_system) => build_system,
+        None => BuildSystem::detect(args.project()).ok_or_else(|| anyhow::anyhow!(
+            "No compile_commands.json, build files or IDE project in {}; `init --scan` can guess the compile commands from the sources",
+            args.project().display()
+        ))?,
+    };
+    tracing::info!("Getting compile commands from {} in {}", build_system.name(), args.project().display());
+    build_system.database_dir(args.project(), &args.make_args, &args.cmake_args, args.config.as_deref())
+}
 
-    call_graph.print_summary();
+/// Write the project's compile_commands.json for `init`, guessed from the
+/// source tree with `scan`, and return the project directory holding it
+fn run_init(args: &Args, scan: bool, force: bool) -> Result<PathBuf> {
+    let project = args.project().canonicalize()?;
+    let path = project.join("compile_commands.json");
+    if path.exists() && !force {
+        anyhow::bail!("{} already exists, pass --force to replace it", path.display());
+    }
+    if scan {
+        let entries = source_scan::scan(&project)?;
+        if entries.is_empty() {
+            anyhow::bail!("No C files under {}", project.display());
+        }
+        compile_commands::write(&entries, &path)?;
+    } else {
+        let database_dir = compilation_database_dir(args)?.canonicalize()?;
+        if database_dir != project {
+            std::fs::copy(database_dir.join("compile_commands.json"), &path)?;
+        }
+    }
+    println!("Saved compile commands to: {}", path.display());
+    Ok(project)
+}
 
-    let original_dir = std::env::current_dir()?;
-    let output_dir = original_dir.join("call_graph_output");
+fn output_dir() -> Result<PathBuf> {
+    let output_dir = std::env::current_dir()?.join("call_graph_output");
     std::fs::create_dir_all(&output_dir)?;
+    Ok(output_dir)
+}
+
+/// Build the graph of everything reachable from the requested roots
+fn build_graph(project_path: &Path, db: &FunctionDatabase, roots: &RootArgs) -> Result<CallGraph> {
+    let options = BuildOptions {
+        max_depth: roots.max_depth,
+        filter: NodeFilter {
+            base_dir: project_path.canonicalize().unwrap_or_else(|_| project_path.to_path_buf()),
+            only_files: roots.only.clone(),
+            exclude_files: roots.exclude.clone(),
+            only_functions: roots.only_functions.clone(),
+            exclude_functions: roots.exclude_functions.clone(),
+        },
+    };
+    let mut call_graph = if roots.library {
+        let exported = exported_functions(db);
+        if exported.is_empty() {
+            anyhow::bail!("No externally visible functions found");
+        }
+        CallGraph::build_from_roots(db, "library", &exported, &options)?
+    } else {
+        let entry_points = entry_points(db, &roots.entry)?;
+        let name = entry_points.iter().map(|def| def.signature.name.as_str()).collect::<Vec<_>>().join(", ");
+        CallGraph::build_from_definitions(db, &name, &entry_points, &options)?
+    };
+
+    if roots.collapse_wrappers {
+        let collapsed = call_graph.collapse_wrappers();
+        println!("Collapsed {} wrapper functions", collapsed);
+    }
+    Ok(call_graph)
+}
+
+fn run_graph(args: &Args, db: &FunctionDatabase, graph_args: &GraphArgs) -> Result<()> {
+    let source_links = graph_args.url_template.as_ref().map(|url_template| SourceLinks {
+        url_template: url_template.clone(),
+        base_dir: args.project().canonicalize().unwrap_or_else(|_| args.project().to_path_buf()),
+    });
+
+    let mut call_graph = build_graph(args.project(), db, &graph_args.roots)?;
+    if graph_args.show_macros {
+        let shown = call_graph.show_macros(db);
+        println!("Added {} function-like macros to the graph", shown);
+    }
+    if let Some(target) = &graph_args.callers {
+        print_callers(&call_graph, target)?;
+        call_graph.retain_callers_of(target);
+    }
+    if let Some(target) = &graph_args.path_to {
+        let steps = print_paths(&call_graph, target, graph_args.max_paths);
+        if graph_args.highlight_paths {
+            for (caller, order) in steps {
+                call_graph.highlight_call(&caller, order);
+            }
+        }
+    }
+    if let Some(target) = &graph_args.highlight {
+        match call_graph.highlight_paths_to(target) {
+            0 => anyhow::bail!("{} is not reachable from {}", target, call_graph.entry_point()),
+            count => println!("Highlighted {} functions on the way to {}", count, target),
+        }
+    }
+    if let Some(links) = &source_links {
+        call_graph.set_source_links(links.clone());
+    }
+
+    let dot_options = DotOptions {
+        externals: graph_args.externals,
+        clusters: graph_args.cluster,
+        weighted_edges: graph_args.weighted_edges,
+        theme: GraphTheme::load(&graph_args.theme)?,
+        base_dir: args.project().canonicalize().unwrap_or_else(|_| args.project().to_path_buf()),
+    };
+    call_graph.to_dot(&dot_options);
+
+    call_graph.print_summary();
+
+    let output_dir = output_dir()?;
 
     let dot_path = output_dir.join("call_graph.dot");
     call_graph.save_dot(&dot_path)?;
     println!("\nSaved DOT file to: {}", dot_path.display());
 
-    let png_path = output_dir.join("call_graph.png");
-    match call_graph.export_png(&png_path) {
-        Ok(_) => println!("Saved PNG to: {}", png_path.display()),
-        Err(e) => eprintln!("Warning: Could not generate PNG: {}", e),
+    let json_path = output_dir.join("call_graph.json");
+    call_graph.save_json(&json_path)?;
+    println!("Saved JSON to: {}", json_path.display());
+
+    let graphml_path = output_dir.join("call_graph.graphml");
+    call_graph.save_graphml(&graphml_path)?;
+    println!("Saved GraphML to: {}", graphml_path.display());
+
+    let mermaid_path = output_dir.join("call_graph.mmd");
+    call_graph.save_mermaid(&mermaid_path)?;
+    println!("Saved Mermaid flowchart to: {}", mermaid_path.display());
+
+    let callgrind_path = output_dir.join("callgrind.out.call_graph");
+    call_graph.save_callgrind(&callgrind_path)?;
+    println!("Saved Callgrind profile to: {}", callgrind_path.display());
+
+    let html_path = output_dir.join("call_graph.html");
+    call_graph.export_html(&html_path)?;
+    println!("Saved interactive viewer to: {}", html_path.display());
+
+    if graph_args.dominators {
+        let tree = call_graph.dominator_tree();
+        tree.print();
+        let dominators_path = output_dir.join("call_graph_dominators.dot");
+        tree.save_dot(&dominators_path)?;
+        println!("Saved dominator tree to: {}", dominators_path.display());
+    }
+
+    // The DOT file is always written above
+    for format in graph_args.format.iter().filter(|format| **format != ExportFormat::Dot) {
+        let path = output_dir.join(format!("call_graph.{}", format.extension()));
+        match call_graph.export(&path, *format) {
+            Ok(_) => println!("Saved {} to: {}", format.extension().to_uppercase(), path.display()),
+            Err(e) => tracing::warn!("Could not generate {}: {}", format.extension().to_uppercase(), e),
+        }
+    }
+
+    if source_links.is_some() {
+        let map_path = output_dir.join("call_graph.map");
+        match call_graph.export(&map_path, ExportFormat::Cmapx) {
+            Ok(_) => println!("Saved image map to: {}", map_path.display()),
+            Err(e) => tracing::warn!("Could not generate image map: {}", e),
+        }
+    }
+
+    if graph_args.roots.library {
+        let api_dir = output_dir.join("api");
+        std::fs::create_dir_all(&api_dir)?;
+        let (_, graphs) = LibraryReport::build(db)?;
+        let count = graphs.len();
+        for mut graph in graphs {
+            if let Some(links) = &source_links {
+                graph.set_source_links(links.clone());
+            }
+            if graph_args.roots.collapse_wrappers {
+                graph.collapse_wrappers();
+            }
+            graph.to_dot(&dot_options);
+            graph.save_dot(&api_dir.join(format!("{}.dot", graph.entry_point())))?;
+        }
+        println!("Saved {} per-API graphs to: {}", count, api_dir.display());
+    }
+
+    Ok(())
+}
+
+fn run_graph_diff(args: &Args, parser: &AstParser, db: &FunctionDatabase, roots: &RootArgs, diff_args: &DiffArgs) -> Result<()> {
+    let (old, new) = match (&diff_args.against, &diff_args.old, &diff_args.new) {
+        (Some(against), _, _) => {
+            if roots.entry.is_empty() && !roots.library {
+                anyhow::bail!("graph diff --against needs --entry or --library");
+            }
+            tracing::info!("Parsing {}", against.display());
+            let old_db = parser.for_project(against)?.parse_all_files(false)?;
+            let old = GraphSnapshot::from_graph(&build_graph(against, &old_db, roots)?);
+            let new = GraphSnapshot::from_graph(&build_graph(args.project(), db, roots)?);
+            (old, new)
+        }
+        (None, Some(old), Some(new)) => (GraphSnapshot::load_json(old)?, GraphSnapshot::load_json(new)?),
+        _ => anyhow::bail!("graph diff needs --against DIR, or --old and --new JSON graphs"),
+    };
+
+    let diff = GraphDiff::compute(old, new);
+    diff.print();
+    if diff.is_empty() {
+        println!("  No changes");
+    }
+
+    if diff_args.dot {
+        let dot_path = output_dir()?.join("call_graph_diff.dot");
+        diff.save_dot(&dot_path)?;
+        println!("Saved diff DOT file to: {}", dot_path.display());
+    }
+    Ok(())
+}
+
+/// List every function reaching `target`, each with its calls on the way
+fn print_callers(call_graph: &CallGraph, target: &str) -> Result<()> {
+    if call_graph.node(target).is_none() {
+        anyhow::bail!("{} is not reachable from {}", target, call_graph.entry_point());
+    }
+
+    let callers = call_graph.transitive_callers(target);
+    println!("Functions reaching {}: {}", target, callers.len());
+    for callee in std::iter::once(target).chain(callers.iter().map(String::as_str)) {
+        for (caller, call) in call_graph.callers_of(callee) {
+            let file = call_graph.node(caller)
+                .map(|node| node.function.source_file.display().to_string())
+                .unwrap_or_default();
+            println!("  {} -> {} at {}:{}:{} ({:?})", caller, callee, file, call.line, call.column, call.context);
+        }
+    }
+    println!();
+    Ok(())
+}
+
+/// Print the call chains from each root to `target`, and return the calls
+/// they go through as (caller, order) pairs
+fn print_paths(call_graph: &CallGraph, target: &str, max_paths: usize) -> Vec<(String, u32)> {
+    let mut steps = Vec::new();
+    for root in call_graph.roots() {
+        let paths = call_graph.paths_between(root, target, max_paths);
+        println!("Call chains from {} to {}: {}", root, target, paths.len());
+        for path in &paths {
+            let chain: Vec<String> = path.iter()
+                .map(|(caller, call)| format!("{} -> {} at {}:{} ({:?})", caller, call.function_name, call.line, call.column, call.context))
+                .collect();
+            println!("  {}", chain.join("\n    "));
+            steps.extend(path.iter().map(|(caller, call)| (caller.to_string(), call.order)));
+        }
+        if paths.len() == max_paths {
+            println!("  (stopped after {} chains, see --max-paths)", max_paths);
+        }
+    }
+    println!();
+    steps
+}
+
+fn run_inline(args: &Args, db: &FunctionDatabase, inline_args: &InlineArgs) -> Result<()> {
+    let entry_points = entry_points(db, &inline_args.entry)?;
+    let inline_options = InlineOptions {
+        exclude: inline_args.exclude.iter().cloned().collect(),
+        max_expansion_factor: inline_args.max_expansion_factor,
+        warn_on_size_limit: inline_args.warn_on_size_limit,
+        strict: inline_args.strict,
+        annotate_calls: inline_args.annotate_calls,
+        collapse_wrappers: inline_args.collapse_wrappers,
+        variadic_policy: inline_args.variadic_policy,
+        variadic_overrides: inline_args.variadic.iter().cloned().collect(),
+        mode: inline_args.mode,
+        short_circuit: inline_args.short_circuit,
+        dynamic_stack: inline_args.dynamic_stack,
+        max_passes: inline_args.max_passes,
+        expand_macros: inline_args.expand_macros,
+        keep_inactive_branches: inline_args.keep_inactive_branches,
+        doc_comments: inline_args.doc_comments,
+        data_model: args.data_model,
+    };
+
+    let mut inliner = Inliner::new(db, inline_options);
+    if inline_args.dry_run {
+        for entry in &entry_points {
+            inliner.plan(entry)?.print();
+        }
+        return Ok(());
+    }
+
+    let output_dir = output_dir()?;
+    let output_names = output_names(&entry_points)?;
+    for (entry, entry_func) in entry_points.iter().zip(&output_names) {
+        let mut expansion = inliner.expand_definition(entry)?;
+        let header_name = format!("{}_expanded_decls.h", entry_func);
+        std::fs::write(output_dir.join(&header_name), companion_header(db, &expansion))?;
+        let expanded_path = output_dir.join(format!("{}_expanded.c", entry_func));
+        expansion.prepend(&format!("#include \"{}\"\n\n", header_name));
+        std::fs::write(&expanded_path, &expansion.source)?;
+        println!(
+            "Saved expansion ({} call sites inlined) to: {}",
+            expansion.inlined_count(),
+            expanded_path.display()
+        );
+        let report_path = output_dir.join(format!("{}_expansion_report.json", entry_func));
+        expansion.save_report(&report_path, &expanded_path)?;
+        println!("Saved expansion report to: {}", report_path.display());
+
+        if inline_args.verify {
+            let unit = db.unit_for(&entry.source_file)
+                .ok_or_else(|| anyhow::anyhow!("No compile command recorded for {}", entry.source_file.display()))?;
+            let verification = verify_expansion(unit, entry, &expansion, &output_dir)?;
+            if !verification.success {
+                eprint!("{}", verification.diagnostics);
+                anyhow::bail!("Expansion does not compile: {}", verification.source.display());
+            }
+            println!("Verified expansion compiles: {}", verification.source.display());
+        }
+
+        if inline_args.amalgamate {
+            let amalgamation = amalgamate(db, entry, &expansion, args.project())?;
+            let amalgamation_path = output_dir.join(format!("{}_amalgamation.c", entry_func));
+            std::fs::write(&amalgamation_path, &amalgamation.source)?;
+            println!(
+                "Saved amalgamation ({} files, {} remaining functions) to: {}",
+                amalgamation.files.len(),
+                amalgamation.functions.len(),
+                amalgamation_path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// What the outputs of each entry point are named after: its name, with
+/// the stem of its file when a same-named static is expanded too
+fn output_names(entry_points: &[Arc<Definition>]) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in entry_points {
+        let name = &entry.signature.name;
+        let shared = entry_points.iter().filter(|other| other.signature.name == *name).count() > 1;
+        let output_name = match entry.source_file.file_stem().and_then(|stem| stem.to_str()) {
+            Some(stem) if shared => format!("{}_{}", stem, name),
+            _ => name.clone(),
+        };
+        if names.contains(&output_name) {
+            anyhow::bail!(
+                "Entry points {} would overwrite each other's outputs, as their files share the name {}",
+                name,
+                entry.source_file.file_name().unwrap_or_default().to_string_lossy()
+            );
+        }
+        names.push(output_name);
+    }
+    Ok(names)
+}
+
+fn run_analyze(args: &Args, db: &FunctionDatabase, analyze_args: &AnalyzeArgs) -> Result<()> {
+    let call_graph = build_graph(args.project(), db, &analyze_args.roots)?;
+    call_graph.print_summary();
+
+    let recursion_groups = call_graph.recursion_groups();
+    if !recursion_groups.is_empty() {
+        println!("\nRecursion groups:");
+        for group in &recursion_groups {
+            println!("  {}", group.join(", "));
+            for (caller, call) in call_graph.cycle_call_sites(group) {
+                let file = call_graph.node(caller)
+                    .map(|node| node.function.source_file.display().to_string())
+                    .unwrap_or_default();
+                println!("    {} -> {} at {}:{}:{}", caller, call.function_name, file, call.line, call.column);
+            }
+        }
+    }
+
+    if analyze_args.roots.library {
+        let (report, _) = LibraryReport::build(db)?;
+        println!();
+        report.print();
+        let report_path = output_dir()?.join("library_report.json");
+        report.save_json(&report_path)?;
+        println!("Saved library report to: {}", report_path.display());
+    }
+
+    if analyze_args.metrics {
+        let output_dir = output_dir()?;
+        let metrics = RunMetrics::collect(&call_graph);
+
+        let metrics_path = output_dir.join("metrics.json");
+        metrics.save_json(&metrics_path)?;
+        println!("Saved metrics to: {}", metrics_path.display());
+
+        let badge_path = output_dir.join("badge.svg");
+        metrics.save_badge(&badge_path)?;
+        println!("Saved badge to: {}", badge_path.display());
+    }
+
+    if analyze_args.globals {
+        let report = GlobalAccessReport::build(&call_graph);
+        println!();
+        report.print();
+        let report_path = output_dir()?.join("globals.json");
+        report.save_json(&report_path)?;
+        println!("Saved global variable accesses to: {}", report_path.display());
+    }
+
+    if analyze_args.concurrency {
+        let report = ConcurrencyReport::build(db, &call_graph)?;
+        println!();
+        report.print();
+        let report_path = output_dir()?.join("concurrency.json");
+        report.save_json(&report_path)?;
+        println!("Saved concurrency report to: {}", report_path.display());
+    }
+
+    if analyze_args.call_depth {
+        let report = CallDepthReport::build(&call_graph, analyze_args.recursion_depth);
+        println!();
+        report.print();
+        let report_path = output_dir()?.join("call_depth.json");
+        report.save_json(&report_path)?;
+        println!("Saved call depth report to: {}", report_path.display());
     }
 
-    let svg_path = output_dir.join("call_graph.svg");
-    match call_graph.export_svg(&svg_path) {
-        Ok(_) => println!("Saved SVG to: {}", svg_path.display()),
-        Err(e) => eprintln!("Warning: Could not generate SVG: {}", e),
+    if analyze_args.dead_code {
+        let report = DeadCodeReport::build(db, &call_graph);
+        println!();
+        report.print();
+        let report_path = output_dir()?.join("dead_code.json");
+        report.save_json(&report_path)?;
+        println!("Saved dead code report to: {}", report_path.display());
+        if analyze_args.fail_on_dead_code && report.dead_count() > 0 {
+            anyhow::bail!("{} unreachable functions found", report.dead_count());
+        }
     }
 
     Ok(())
 }
+
+fn run_coupling(args: &Args, db: &FunctionDatabase, roots: &RootArgs, coupling_args: &CouplingArgs) -> Result<()> {
+    let call_graph = build_graph(args.project(), db, roots)?;
+    let report = CouplingReport::build(&call_graph, coupling_args.sort);
+    report.print(coupling_args.top);
+
+    let output_dir = output_dir()?;
+    let json_path = output_dir.join("coupling.json");
+    report.save_json(&json_path)?;
+    println!("\nSaved fan-in / fan-out to: {}", json_path.display());
+    let csv_path = output_dir.join("coupling.csv");
+    report.save_csv(&csv_path)?;
+    println!("Saved fan-in / fan-out to: {}", csv_path.display());
+    Ok(())
+}
+
+fn run_stack(args: &Args, db: &FunctionDatabase, roots: &RootArgs, stack_args: &StackArgs) -> Result<()> {
+    let call_graph = build_graph(args.project(), db, roots)?;
+    let report = StackReport::build(&call_graph, stack_args.recursion_depth, args.data_model);
+    report.print();
+
+    let report_path = output_dir()?.join("stack_usage.json");
+    report.save_json(&report_path)?;
+    println!("\nSaved stack usage to: {}", report_path.display());
+    Ok(())
+}
+
+/// The database saved with `db --save` when asked for, else a parse of the
+/// project, or of what the entry points reach with `--on-demand`, reusing
+/// the translation units of the cache that did not change
+fn load_or_parse(parser: &AstParser, args: &Args) -> Result<FunctionDatabase> {
+    if let Some(saved) = &args.load_db {
+        let db = FunctionDatabase::load(saved)?;
+        tracing::info!("Loaded function database from {}", saved.display());
+        let stale = db.stale_files();
+        if !stale.is_empty() {
+            tracing::warn!("{} source files changed since it was saved, e.g. {}", stale.len(), stale[0].display());
+        }
+        return Ok(db);
+    }
+
+    let mut cache = match &args.db_cache {
+        Some(cache_path) if cache_path.exists() => ParseCache::load(cache_path).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring {}: {}", cache_path.display(), e);
+            ParseCache::new()
+        }),
+        _ => ParseCache::new(),
+    };
+    let db = if args.on_demand {
+        let entries = args.command.entries()
+            .ok_or_else(|| anyhow::anyhow!("--on-demand needs a command given --entry"))?;
+        if entries.iter().any(|entry| entry == "auto-isr") {
+            anyhow::bail!("--on-demand cannot find interrupt handlers without parsing the whole project");
+        }
+        let names: Vec<String> = entry_specs(entries)?.into_iter().map(|spec| spec.function).collect();
+        tracing::info!("Parsing the source files reachable from {}", names.join(", "));
+        parser.parse_reachable_cached(&names, &mut cache)?
+    } else {
+        tracing::info!("Parsing {} source files", if args.db_cache.is_some() { "changed" } else { "all" });
+        parser.parse_all_files_cached(false, &mut cache)?
+    };
+    if let Some(cache_path) = &args.db_cache {
+        tracing::info!("Reused {} translation units, parsed {}", cache.hits, cache.misses);
+        cache.save(cache_path)?;
+    }
+    Ok(db)
+}
+
+fn run_db_diff(old: &Path, new: &Path) -> Result<()> {
+    let diff = DatabaseDiff::compute(&FunctionDatabase::load(old)?, &FunctionDatabase::load(new)?);
+    diff.print();
+    if diff.is_empty() {
+        println!("  No changes");
+    }
+    Ok(())
+}
+
+fn run_db(db: &FunctionDatabase, db_args: &DbArgs) -> Result<()> {
+    if let Some(name) = &db_args.function {
+        let definitions = db.definitions_named(name);
+        if definitions.is_empty() {
+            anyhow::bail!("No function named {} in the database", name);
+        }
+        // Several files may define their own static function of that name
+        for def in definitions {
+            println!("{}{}", if def.is_static { "static " } else { "" }, def.signature.to_c());
+            println!("  defined at {}:{}", def.source_file.display(), def.line);
+            for declaration in db.declarations_of(name) {
+                println!(
+                    "  declared at {}:{}{}",
+                    declaration.source_file.display(),
+                    declaration.line,
+                    if !declaration.has_prototype || declaration.signature.is_compatible(&def.signature) { "" } else { " with a different signature" }
+                );
+            }
+            println!("  body: {} tokens", def.body_size());
+            println!(
+                "  {} lines, {} branches, {} calls, cyclomatic complexity {}",
+                def.metrics.lines,
+                def.metrics.branches,
+                def.metrics.call_count,
+                def.metrics.cyclomatic_complexity()
+            );
+            println!("  locals: {} bytes", def.metrics.frame_size);
+            for local in &def.locals {
+                println!(
+                    "    {}{} at line {} ({} bytes)",
+                    if local.is_static { "static " } else { "" },
+                    expansion::rewriter::declarator(&local.var_type, &local.name),
+                    local.line,
+                    local.size
+                );
+            }
+            if !def.global_reads.is_empty() {
+                println!("  reads globals: {}", def.global_reads.iter().cloned().collect::<Vec<_>>().join(", "));
+            }
+            if !def.global_writes.is_empty() {
+                println!("  writes globals: {}", def.global_writes.iter().cloned().collect::<Vec<_>>().join(", "));
+            }
+            if db.interrupt_handlers().contains(&def.signature.name) {
+                println!("  interrupt handler");
+            }
+            if !def.attributes.names().is_empty() {
+                println!("  attributes: {}", def.attributes.names().join(", "));
+            }
+            let is_def = |member: &Arc<Definition>| member.signature.name == def.signature.name && member.source_file == def.source_file;
+            if let Some(group) = db.recursion_groups().iter().find(|group| group.iter().any(|member| is_def(member))) {
+                match group.as_slice() {
+                    [_] => println!("  recursive"),
+                    _ => println!("  mutually recursive with {}", group.iter()
+                        .filter(|&member| !is_def(member))
+                        .map(|member| member.signature.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")),
+                }
+            }
+            if let Some(brief) = def.brief() {
+                println!("  {}", brief);
+            }
+            if def.is_inline && def.in_header() {
+                println!("  inline in a header, compiled by every unit including it");
+            }
+            if def.contains_asm {
+                println!("  contains inline assembly");
+            }
+            if def.uses_vla || def.uses_alloca {
+                println!("  sizes its stack at run time (alloca or variable-length arrays)");
+            }
+            if let Some(wrapper) = def.as_wrapper() {
+                println!("  wraps {}", wrapper.callee);
+            }
+            for conditional in &def.conditionals {
+                let branches: Vec<String> = conditional.branches.iter()
+                    .map(|branch| format!("{} ({})", branch.directive, if branch.active { "compiled" } else { "not compiled" }))
+                    .collect();
+                println!("  line {}: {}", conditional.line, branches.join(", "));
+            }
+            println!("  calls:");
+            for call in &def.calls {
+                println!(
+                    "    {}({}) at {}:{} ({:?}, result {:?}){}",
+                    call.function_name,
+                    call.arguments.join(", "),
+                    call.line,
+                    call.column,
+                    call.context,
+                    call.result_usage,
+                    call.macro_call.as_ref().map(|m| format!(" {}", m.label())).unwrap_or_default()
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &db_args.save {
+        db.save(path)?;
+        println!("Saved function database to {}", path.display());
+        return Ok(());
+    }
+
+    if db_args.symbols {
+        for resolution in db.symbol_resolutions() {
+            let winner = match &resolution.winner {
+                Some(def) if def.signature.name != resolution.name => format!("{} in {}", def.signature.name, def.source_file.display()),
+                Some(def) => format!("{}{}", def.source_file.display(), if def.attributes.weak { " (weak default)" } else { "" }),
+                None => "defined outside the project".to_string(),
+            };
+            match &resolution.alias_of {
+                Some(target) => println!("{:<40} alias of {}, links to {}", resolution.name, target, winner),
+                None => println!("{:<40} links to {}", resolution.name, winner),
+            }
+            for file in &resolution.replaced {
+                println!("{:<40}   replaces {}", "", file.display());
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(name) = &db_args.typedef {
+        let typedefs = db.type_table().typedefs_named(name);
+        if typedefs.is_empty() {
+            anyhow::bail!("No typedef named {} in the database", name);
+        }
+        for typedef in typedefs {
+            println!("typedef {};", typedef.target.to_c_string(Some(name)));
+            println!("  declared at {}:{}{}", typedef.source_file.display(), typedef.line, if typedef.is_system { " (system header)" } else { "" });
+        }
+        match db.type_table().resolve(name) {
+            Some(resolved) => println!("  stands for {}", resolved.to_c_string(None)),
+            None => println!("  declared differently in several places"),
+        }
+        return Ok(());
+    }
+
+    if db_args.macros {
+        let mut count = 0;
+        for definition in db.macros() {
+            println!("{}:{}: {}", definition.source_file.display(), definition.line, definition.to_c());
+            count += 1;
+        }
+        println!("{} function-like macros", count);
+        return Ok(());
+    }
+
+    if db_args.mismatches {
+        let mismatches = db.signature_mismatches();
+        for mismatch in &mismatches {
+            println!("{}:{}: {};", mismatch.declaration.source_file.display(), mismatch.declaration.line, mismatch.declaration.signature.to_c());
+            println!(
+                "  defined at {}:{} as {}",
+                mismatch.definition.source_file.display(),
+                mismatch.definition.line,
+                mismatch.definition.signature.to_c()
+            );
+        }
+        println!("{} mismatched prototypes", mismatches.len());
+        return Ok(());
+    }
+
+    let mut functions: Vec<_> = db.iter().collect();
+    functions.sort_by(|a, b| a.signature.name.cmp(&b.signature.name));
+    for def in functions {
+        println!(
+            "{:<40} {}:{}{} ({} calls)",
+            def.signature.name,
+            def.source_file.display(),
+            def.line,
+            if def.is_static { " static" } else { "" },
+            def.calls.len()
+        );
+    }
+    Ok(())
+}
+
+/// Entry points given on the command line, where `@LIST` stands for the
+/// entries listed in the file LIST, one per line
+fn entry_specs(arguments: &[String]) -> Result<Vec<EntrySpec>> {
+    let mut entries = Vec::new();
+    for argument in arguments {
+        match argument.strip_prefix('@') {
+            Some(list) => {
+                let contents = std::fs::read_to_string(list)
+                    .map_err(|e| anyhow::anyhow!("Cannot read entry point list {}: {}", list, e))?;
+                entries.extend(contents.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(String::from));
+            }
+            None => entries.push(argument.clone()),
+        }
+    }
+    if entries.is_empty() {
+        anyhow::bail!("No entry point given");
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    entries.retain(|entry| seen.insert(entry.clone()));
+    entries.iter()
+        .map(|entry| entry.parse::<EntrySpec>().map_err(|e| anyhow::anyhow!("Invalid entry point: {}", e)))
+        .collect()
+}
+
+/// Definition an entry point designates: the one in the requested file,
+/// which picks among statics sharing a name
+fn resolve_entry(db: &FunctionDatabase, spec: &EntrySpec) -> Result<Arc<Definition>> {
+    let Some(file) = &spec.file else {
+        return db.find_function(&spec.function)?
+            .filter(|def| !def.body.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("No definition found for entry point {}", spec.function));
+    };
+
+    if let Some(def) = db.definition_in(&spec.function, file).filter(|def| !def.body.is_empty()) {
+        return Ok(def);
+    }
+    let files: Vec<String> = db.definitions_named(&spec.function)
+        .iter()
+        .filter(|def| !def.body.is_empty())
+        .map(|def| def.source_file.display().to_string())
+        .collect();
+    if files.is_empty() {
+        anyhow::bail!("No definition found for entry point {}", spec.function);
+    }
+    anyhow::bail!(
+        "{} is defined in {}, not {}",
+        spec.function,
+        files.join(", "),
+        file.display()
+    );
+}
+
+/// Resolve the entry points given on the command line to their definitions.
+/// `auto-isr` stands for every interrupt handler found in the project.
+fn entry_points(db: &FunctionDatabase, arguments: &[String]) -> Result<Vec<Arc<Definition>>> {
+    const AUTO_ISR: &str = "auto-isr";
+    let (auto_isr, arguments): (Vec<String>, Vec<String>) = arguments.iter()
+        .cloned()
+        .partition(|argument| argument == AUTO_ISR);
+
+    let mut names = Vec::new();
+    if !arguments.is_empty() || auto_isr.is_empty() {
+        let specs = entry_specs(&arguments)?;
+        for spec in &specs {
+            if let Some(file) = &spec.file && !file.exists() {
+                anyhow::bail!(
+                    "Cannot find entry point : {}",
+                    file.display()
+                );
+            }
+        }
+        names = specs.iter()
+            .map(|spec| resolve_entry(db, spec))
+            .collect::<Result<Vec<_>>>()?;
+    }
+
+    if !auto_isr.is_empty() {
+        let handlers = db.interrupt_handlers();
+        if handlers.is_empty() {
+            anyhow::bail!("No interrupt handlers found for {}", AUTO_ISR);
+        }
+        println!("Found {} interrupt handlers: {}", handlers.len(), handlers.join(", "));
+        // Each file's static handler of a name is an entry point of its own
+        for def in db.iter().filter(|def| !def.body.is_empty() && handlers.contains(&def.signature.name)) {
+            if !names.iter().any(|entry| Arc::ptr_eq(entry, &def)) {
+                names.push(def);
+            }
+        }
+    }
+    Ok(names)
+}
@@ -0,0 +1,101 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::compile_commands::{self, CompileEntry};
+use crate::parser::function_db::TranslationUnitInfo;
+
+/// The makefile `make` reads in `directory` without `-f`, if any
+pub fn find_makefile(directory: &Path) -> Option<PathBuf> {
+    ["GNUmakefile", "makefile", "Makefile"].iter()
+        .map(|name| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Compile commands of a Makefile project, from the commands `make -nB`
+/// prints without running them. `make_args` go to make, for targets or
+/// variables such as `CC=arm-none-eabi-gcc`. Make runs them in the
+/// environment it inherits from here, which they are recorded with.
+pub fn generate(directory: &Path, make_args: &[String]) -> Result<Vec<CompileEntry>> {
+    let output = Command::new("make")
+        // Every target, as if out of date; directory changes reported
+        .args(["-n", "-B", "-k", "-w"])
+        .args(make_args)
+        .current_dir(directory)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run make in {}: {}", directory.display(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stdout.trim().is_empty() {
+            anyhow::bail!("make -n failed in {}: {}", directory.display(), stderr.trim());
+        }
+        tracing::warn!("make -n reported errors in {}, some commands may be missing: {}", directory.display(), stderr.trim());
+    }
+    let environment = TranslationUnitInfo::current_environment();
+    let mut entries = parse_dry_run(&stdout, directory);
+    for entry in &mut entries {
+        entry.environment = environment.clone();
+    }
+    Ok(entries)
+}
+
+/// Compile commands in the output of `make -nw` run in `directory`,
+/// following the directories make enters and those of `cd dir && ...`
+pub fn parse_dry_run(output: &str, directory: &Path) -> Vec<CompileEntry> {
+    let mut directories = vec![directory.to_path_buf()];
+    let mut entries = Vec::new();
+    let mut pending = String::new();
+    for line in output.lines() {
+        // A recipe line continued with a backslash
+        if let Some(start) = line.strip_suffix('\\') {
+            pending.push_str(start);
+            pending.push(' ');
+            continue;
+        }
+        let line = std::mem::take(&mut pending) + line;
+        if let Some(entered) = directory_change(&line, "Entering directory") {
+            directories.push(entered);
+            continue;
+        }
+        if directory_change(&line, "Leaving directory").is_some() {
+            if directories.len() > 1 {
+                directories.pop();
+            }
+            continue;
+        }
+        let mut current = directories.last().cloned().unwrap_or_else(|| directory.to_path_buf());
+        let words = compile_commands::split_command_line(&line);
+        for command in words.split(|word| matches!(word.as_str(), ";" | "&&" | "||" | "|")) {
+            // Variable assignments prefixing the command
+            let command: Vec<String> = command.iter()
+                .skip_while(|word| word.contains('=') && !word.starts_with('-'))
+                .cloned()
+                .collect();
+            match command.first().map(String::as_str) {
+                Some("cd") => {
+                    if let Some(target) = command.get(1) {
+                        current = current.join(target);
+                    }
+                }
+                Some(_) => entries.extend(CompileEntry::from_invocation(&command, &current)),
+                None => {}
+            }
+        }
+    }
+    entries
+}
+
+/// The directory in `make[1]: Entering directory '/src/lib'` and the like,
+/// when `line` is such a message for `action`
+fn directory_change(line: &str, action: &str) -> Option<PathBuf> {
+    let (program, message) = line.split_once(": ")?;
+    if !program.starts_with("make") {
+        return None;
+    }
+    let path = message.strip_prefix(action)?.trim();
+    // Quoted 'dir' by recent versions, `dir' by older ones
+    let path = path.trim_start_matches(['\'', '`']).trim_end_matches('\'');
+    Some(PathBuf::from(path))
+}
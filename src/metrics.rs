@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_graph::CallGraph;
+
+/// Machine-readable summary of a run, meant to be published as a CI artifact
+/// and compared across runs to track structural drift.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunMetrics {
+    pub entry_point: String,
+    pub reachable_functions: usize,
+    pub external_functions: usize,
+    pub static_functions: usize,
+    pub call_sites: usize,
+    pub recursion_groups: usize,
+    pub original_size: usize,
+    pub flattened_size: usize,
+    pub expansion_size_delta: i64,
+}
+
+impl RunMetrics {
+    pub fn collect(call_graph: &CallGraph) -> Self {
+        let original_size = call_graph.original_size();
+        let flattened_size = call_graph.estimate_flattened_size();
+
+        RunMetrics {
+            entry_point: call_graph.entry_point().to_string(),
+            reachable_functions: call_graph.node_count() - call_graph.external_count(),
+            external_functions: call_graph.external_count(),
+            static_functions: call_graph.static_count(),
+            call_sites: call_graph.edge_count(),
+            recursion_groups: call_graph.recursion_groups().len(),
+            original_size,
+            flattened_size,
+            expansion_size_delta: flattened_size as i64 - original_size as i64,
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Save a shields-style SVG badge summarizing the run
+    pub fn save_badge(&self, output_path: &Path) -> Result<()> {
+        let label = "call graph";
+        let message = format!(
+            "{} fn | {} ext | {:+} tok",
+            self.reachable_functions, self.external_functions, self.expansion_size_delta
+        );
+        let color = if self.recursion_groups == 0 { "#4c1" } else { "#dfb317" };
+
+        std::fs::write(output_path, Self::render_badge(label, &message, color))?;
+        Ok(())
+    }
+
+    fn render_badge(label: &str, message: &str, color: &str) -> String {
+        // Rough Verdana 11px advance, good enough to size the two halves
+        let text_width = |text: &str| text.chars().count() * 7 + 10;
+        let label_width = text_width(label);
+        let message_width = text_width(message);
+        let total_width = label_width + message_width;
+
+        format!(
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="{total}" height="20" role="img" aria-label="{label}: {message}">
+  <title>{label}: {message}</title>
+  <linearGradient id="s" x2="0" y2="100%">
+    <stop offset="0" stop-color="#bbb" stop-opacity=".1"/>
+    <stop offset="1" stop-opacity=".1"/>
+  </linearGradient>
+  <clipPath id="r"><rect width="{total}" height="20" rx="3" fill="#fff"/></clipPath>
+  <g clip-path="url(#r)">
+    <rect width="{lw}" height="20" fill="#555"/>
+    <rect x="{lw}" width="{mw}" height="20" fill="{color}"/>
+    <rect width="{total}" height="20" fill="url(#s)"/>
+  </g>
+  <g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+    <text x="{lx}" y="14">{label}</text>
+    <text x="{mx}" y="14">{message}</text>
+  </g>
+</svg>
+"##,
+            total = total_width,
+            lw = label_width,
+            mw = message_width,
+            lx = label_width / 2,
+            mx = label_width + message_width / 2,
+            label = label,
+            message = message,
+            color = color,
+        )
+    }
+}
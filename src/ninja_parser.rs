@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+use crate::compile_commands::{self, CompileEntry};
+use crate::parser::function_db::TranslationUnitInfo;
+
+/// The C compile commands of the Ninja build in `directory`, as
+/// `ninja -t compdb` lists them, else as read from build.ninja when ninja
+/// cannot be run. Ninja runs them in the environment it inherits from
+/// here, which they are recorded with.
+pub fn generate(directory: &Path) -> Result<Vec<CompileEntry>> {
+    let mut entries = match compdb(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("{}; reading build.ninja instead", e);
+            parse(&directory.join("build.ninja"))?
+        }
+    };
+    let environment = TranslationUnitInfo::current_environment();
+    for entry in &mut entries {
+        entry.environment = environment.clone();
+    }
+    Ok(entries)
+}
+
+/// The C compile commands ninja lists for every rule
+fn compdb(directory: &Path) -> Result<Vec<CompileEntry>> {
+    let output = Command::new("ninja")
+        .args(["-t", "compdb"])
+        .current_dir(directory)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run ninja in {}: {}", directory.display(), e))?;
+    if !output.status.success() {
+        anyhow::bail!("ninja -t compdb failed in {}: {}", directory.display(), String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let listed: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)?;
+    // Link and other rules come too, and with a command line rather than
+    // arguments
+    Ok(listed.iter()
+        .filter_map(|entry| {
+            let command = entry.get("command")?.as_str()?;
+            let command_directory = directory.join(entry.get("directory")?.as_str()?);
+            Some(CompileEntry::from_invocation(&compile_commands::split_command_line(command), &command_directory))
+        })
+        .flatten()
+        .collect())
+}
+
+/// A `build` statement, with the variables bound under it
+struct Build {
+    rule: String,
+    outputs: Vec<String>,
+    inputs: Vec<String>,
+    variables: HashMap<String, String>,
+}
+
+/// What a Ninja manifest declares, files it includes read in
+#[derive(Default)]
+struct Manifest {
+    variables: HashMap<String, String>,
+    /// Each rule's variables, `command` among them, unexpanded
+    rules: HashMap<String, HashMap<String, String>>,
+    builds: Vec<Build>,
+}
+
+/// The C compile commands of the build statements of `manifest`, with the
+/// rules and variables of the files it includes, such as the rules.ninja
+/// CMake generates. Commands run in the manifest's directory.
+pub fn parse(manifest: &Path) -> Result<Vec<CompileEntry>> {
+    let directory = manifest.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let mut parsed = Manifest::default();
+    parsed.read(manifest, &directory)?;
+
+    let mut entries = Vec::new();
+    for build in &parsed.builds {
+        let Some(rule) = parsed.rules.get(&build.rule) else {
+            continue;
+        };
+        let Some(command) = rule.get("command") else {
+            continue;
+        };
+        let mut scope = build.variables.clone();
+        // Quoted as ninja does for paths with spaces
+        let paths = |paths: &[String]| paths.iter()
+            .map(|path| if path.contains(' ') { format!("'{}'", path) } else { path.clone() })
+            .collect::<Vec<_>>()
+            .join(" ");
+        scope.insert("in".to_string(), paths(&build.inputs));
+        scope.insert("out".to_string(), paths(&build.outputs));
+        let lookup = |name: &str| -> String {
+            if let Some(value) = scope.get(name) {
+                return value.clone();
+            }
+            // A rule variable is expanded in the scope of the build using it
+            if let Some(value) = rule.get(name) {
+                return expand(value, &|inner: &str| scope.get(inner).or(parsed.variables.get(inner)).cloned().unwrap_or_default());
+            }
+            parsed.variables.get(name).cloned().unwrap_or_default()
+        };
+        let command = expand(command, &lookup);
+        entries.extend(CompileEntry::from_invocation(&compile_commands::split_command_line(&command), &directory));
+    }
+    Ok(entries)
+}
+
+impl Manifest {
+    fn read(&mut self, path: &Path, directory: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let lines = logical_lines(&text);
+        let mut index = 0;
+        while index < lines.len() {
+            let line = &lines[index];
+            index += 1;
+            // Bindings indented under the statement they belong to
+            let mut bindings = HashMap::new();
+            while index < lines.len() && lines[index].starts_with([' ', '\t']) {
+                if let Some((name, value)) = lines[index].trim().split_once('=') {
+                    bindings.insert(name.trim().to_string(), value.trim_start().to_string());
+                }
+                index += 1;
+            }
+
+            if let Some(name) = line.strip_prefix("rule ") {
+                self.rules.insert(name.trim().to_string(), bindings);
+            } else if let Some(statement) = line.strip_prefix("build ") {
+                if let Some(build) = self.build(statement, bindings) {
+                    self.builds.push(build);
+                }
+            } else if let Some(file) = line.strip_prefix("include ").or_else(|| line.strip_prefix("subninja ")) {
+                let file = expand(file.trim(), &|name: &str| self.variables.get(name).cloned().unwrap_or_default());
+                self.read(&directory.join(file), directory)?;
+            } else if let Some((name, value)) = line.split_once('=')
+                && !line.starts_with(|c: char| c == '#' || c.is_whitespace())
+            {
+                let value = expand(value.trim_start(), &|name: &str| self.variables.get(name).cloned().unwrap_or_default());
+                self.variables.insert(name.trim().to_string(), value);
+            }
+        }
+        Ok(())
+    }
+
+    /// `outputs | implicit: rule inputs | implicit || order-only`
+    fn build(&self, statement: &str, bindings: HashMap<String, String>) -> Option<Build> {
+        let paths = split_paths(statement);
+        let colon = paths.iter().position(|path| path.ends_with(':') && !path.ends_with("$:"))?;
+        let mut outputs: Vec<String> = paths[..=colon].to_vec();
+        if let Some(last) = outputs.last_mut() {
+            last.pop();
+        }
+        let outputs = outputs.into_iter().filter(|output| !output.is_empty()).take_while(|output| output != "|").collect();
+        let rule = paths.get(colon + 1)?.clone();
+        let inputs = paths[colon + 2..].iter().take_while(|path| !path.starts_with('|')).cloned().collect();
+        let lookup = |name: &str| self.variables.get(name).cloned().unwrap_or_default();
+        let variables = bindings.into_iter().map(|(name, value)| (name, expand(&value, &lookup))).collect();
+        let unescape = |paths: Vec<String>| paths.iter().map(|path| expand(path, &lookup)).collect();
+        Some(Build { rule, outputs: unescape(outputs), inputs: unescape(inputs), variables })
+    }
+}
+
+/// Lines of a manifest with `$` continuations joined and comments dropped
+fn logical_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pending = String::new();
+    for line in text.lines() {
+        if pending.is_empty() && line.trim_start().starts_with('#') {
+            continue;
+        }
+        let continued = line.ends_with('$') && !line.ends_with("$$");
+        if continued {
+            pending.push_str(&line[..line.len() - 1]);
+        } else {
+            // A continuation's leading whitespace is not part of the value
+            pending.push_str(if pending.is_empty() { line } else { line.trim_start() });
+            let line = std::mem::take(&mut pending);
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    lines
+}
+
+/// The words of a `build` line, split at spaces not escaped with `$`
+fn split_paths(statement: &str) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut path = String::new();
+    let mut chars = statement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                path.push('$');
+                path.extend(chars.next());
+            }
+            ' ' | '\t' => {
+                if !path.is_empty() {
+                    paths.push(std::mem::take(&mut path));
+                }
+            }
+            c => path.push(c),
+        }
+    }
+    if !path.is_empty() {
+        paths.push(path);
+    }
+    paths
+}
+
+/// `value` with `$name`, `${name}` and the `$` escapes replaced
+fn expand(value: &str, lookup: &dyn Fn(&str) -> String) -> String {
+    let mut out = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('{') => {
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                out.push_str(&lookup(&name));
+            }
+            Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let mut name = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_' || next == '-') {
+                        break;
+                    }
+                    name.push(next);
+                    chars.next();
+                }
+                out.push_str(&lookup(&name));
+            }
+            // `$$`, `$ ` and `$:` stand for the character itself
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::parser::function_db::{DiagnosticSeverity, FunctionDatabase, ParseDiagnostic, ParseMode};
+
+/// How `--diagnostics` shows the parse report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticsFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for DiagnosticsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(DiagnosticsFormat::Text),
+            "json" => Ok(DiagnosticsFormat::Json),
+            _ => Err(format!("unknown diagnostics format '{}', expected text or json", s)),
+        }
+    }
+}
+
+/// How one translation unit parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct UnitReport {
+    pub file: PathBuf,
+    pub parse_mode: ParseMode,
+    pub errors: usize,
+    pub warnings: usize,
+    /// Functions defined in this unit with an error inside their body, which
+    /// may then miss statements and calls
+    pub incomplete_functions: Vec<String>,
+    pub diagnostics: Vec<ParseDiagnostic>,
+}
+
+/// What clang reported for every translation unit, and which bodies the
+/// errors may have cut short
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseReport {
+    pub units: Vec<UnitReport>,
+}
+
+impl ParseReport {
+    pub fn build(db: &FunctionDatabase) -> Self {
+        let units = db.units()
+            .iter()
+            .map(|unit| {
+                let is_error = |d: &&ParseDiagnostic| d.severity >= DiagnosticSeverity::Error;
+                let errors: Vec<&ParseDiagnostic> = unit.diagnostics.iter().filter(is_error).collect();
+                let incomplete_functions = db.iter()
+                    .filter(|def| !def.body.is_empty())
+                    .filter(|def| errors.iter().any(|error| {
+                        let lines = def.line..def.line + def.metrics.lines;
+                        error.file.as_ref() == Some(&def.source_file) && lines.contains(&error.line)
+                    }))
+                    .map(|def| def.signature.name.clone())
+                    .collect();
+                UnitReport {
+                    file: unit.file.clone(),
+                    parse_mode: unit.parse_mode.clone(),
+                    errors: errors.len(),
+                    warnings: unit.diagnostics.len() - errors.len(),
+                    incomplete_functions,
+                    diagnostics: unit.diagnostics.clone(),
+                }
+            })
+            .collect();
+        ParseReport { units }
+    }
+
+    /// Print the units that needed a fallback or have errors, with their
+    /// errors; warnings are only counted
+    pub fn print(&self) {
+        let clean = self.units.iter().filter(|unit| unit.errors == 0 && !unit.parse_mode.is_fallback()).count();
+        println!("Parse Diagnostics: {} of {} translation units parsed cleanly", clean, self.units.len());
+        for unit in &self.units {
+            if unit.errors == 0 && !unit.parse_mode.is_fallback() {
+                continue;
+            }
+            println!("  {}: {} errors, {} warnings", unit.file.display(), unit.errors, unit.warnings);
+            if unit.parse_mode.is_fallback() {
+                println!("    {}", unit.parse_mode);
+            }
+            for diagnostic in unit.diagnostics.iter().filter(|d| d.severity >= DiagnosticSeverity::Error) {
+                let location = match &diagnostic.file {
+                    Some(file) => format!("{}:{}:{}", file.display(), diagnostic.line, diagnostic.column),
+                    None => "<command line>".to_string(),
+                };
+                println!("    {}: {}", location, diagnostic.message);
+            }
+            if !unit.incomplete_functions.is_empty() {
+                println!("    Bodies may be incomplete: {}", unit.incomplete_functions.join(", "));
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
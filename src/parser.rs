@@ -1,2 +1,5 @@
 pub mod ast;
 pub mod function_db;
+pub mod parse_cache;
+pub mod toolchain;
+pub mod types;
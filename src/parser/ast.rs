@@ -1,10 +1,53 @@
-use std::{collections::HashSet, path::{Path, PathBuf}};
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet, HashMap}, io::{BufRead, BufReader}, ops::Range, path::{Path, PathBuf}, rc::Rc};
 
-use clang::{Clang, CompilationDatabase, CompileCommand, Entity, EntityKind, Index, StorageClass};
+use clang::diagnostic::Severity;
+use clang::token::TokenKind;
+use clang::{Clang, CompilationDatabase, CompileCommand, Entity, EntityKind, EvaluationResult, Index, StorageClass, TranslationUnit, Type, TypeKind};
 use anyhow::Result;
 
 
-use super::function_db::{FunctionDatabase, Definition, Signature, Parameter, CallInfo, CallContext};
+use super::parse_cache::{CachedUnit, ParseCache};
+use super::toolchain::Toolchain;
+use super::types::{self, ArrayLength, EnumType, Field, FloatKind, FunctionType, IntegerKind, RecordType, TypedefDefinition};
+use super::function_db::{normalized_hash, read_serialized, write_serialized, FunctionDatabase, Definition, Declaration, Signature, Parameter, CallInfo, CallContext, ConditionalBranch, MacroCall, MacroDefinition, MacroExpansion, PreprocessorConditional, TranslationUnitInfo, ParseMode, ParseDiagnostic, DiagnosticSeverity, GlobalVariable, LocalVar, FunctionMetrics, FunctionAttributes, ResultUsage};
+use crate::compile_commands;
+use crate::logging::{self, Progress};
+use crate::rewriter;
+
+/// Functions allocating on the caller's stack frame
+const ALLOCA_FUNCTIONS: [&str; 4] = ["alloca", "_alloca", "__builtin_alloca", "__builtin_alloca_with_align"];
+
+/// Times a translation unit is parsed again with the include directories
+/// found for its missing headers
+const MAX_INCLUDE_PASSES: usize = 8;
+
+/// Flags dropped along with the argument following them: outputs, dependency
+/// files and options for the linker or assembler
+const DROPPED_WITH_VALUE: [&str; 7] = ["-o", "-MF", "-MT", "-MQ", "-Xlinker", "-Xassembler", "-T"];
+
+/// Flags clang rejects or that only matter to code generation, linking or
+/// dependency files. An entry ending in `*` matches by prefix.
+const DROPPED_FLAGS: &[&str] = &[
+    "-c", "-S", "-E", "-M", "-MM", "-MD", "-MMD", "-MP", "-MG", "-pipe", "-save-temps*",
+    "-Wl,*", "-Wa,*", "-Wp,*", "-specs=*", "--specs=*",
+    "-fstack-usage", "-fcallgraph-info*", "-fcyclomatic-complexity", "-fdump-*", "-fanalyzer",
+    "-ftree-*", "-fno-tree-*", "-fipa-*", "-fno-ipa-*", "-fmax-errors=*", "-fconserve-stack",
+    "-fno-reorder-functions", "-fsingle-precision-constant", "-fcompare-debug*",
+    "-mpoke-function-name", "-mthumb-interwork", "-mno-thumb-interwork",
+];
+
+/// Flags naming a file or directory, resolved against the command's
+/// directory. They take their path joined, as a separate argument, or after
+/// `=` for `--sysroot`.
+const PATH_FLAGS: [&str; 8] = ["-isystem", "-iquote", "-idirafter", "-include", "-imacros", "-isysroot", "--sysroot", "-I"];
+
+/// How an expression uses the variable it designates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
 
 /// Tracks the current context while traversing the AST
 #[derive(Debug, Clone, Default)]
@@ -38,195 +81,1091 @@ impl CallCollector {
         self.context_stack.push(CallContext::Loop);
     }
 
-    fn push_switch_case(&mut self) {
+    fn push_switch_case(&mut self, value : Option<i64>) {
         self.case_counter += 1;
-        self.context_stack.push(CallContext::Switch { case_id: self.case_counter });
+        self.context_stack.push(CallContext::Switch { case_id: self.case_counter, value });
     }
 
     fn pop_context(&mut self) {
         self.context_stack.pop();
     }
 
-    fn add_call(&mut self, function_name: String, line: u32, column: u32) {
+    fn add_call(&mut self, function_name: String, line: u32, column: u32, arg_types: Vec<String>) {
         self.order_counter += 1;
         self.calls.push(CallInfo {
             function_name,
             line,
             column,
+            arg_types,
+            arguments: Vec::new(),
+            result_usage: ResultUsage::Used,
             order: self.order_counter,
             context: self.current_context(),
             context_depth: self.depth(),
+            via: None,
+            callee_expr: None,
+            macro_call: None,
+        });
+    }
+
+    fn add_macro_call(&mut self, function_name: String, macro_call: MacroCall, line: u32, column: u32, arg_types: Vec<String>) {
+        self.add_call(function_name, line, column, arg_types);
+        if let Some(call) = self.calls.last_mut() {
+            call.macro_call = Some(macro_call);
+        }
+    }
+
+    fn add_indirect_call(&mut self, pointer_name: String, slot: String, callee_expr: String, line: u32, column: u32, arg_types: Vec<String>) {
+        self.add_call(pointer_name, line, column, arg_types);
+        if let Some(call) = self.calls.last_mut() {
+            call.via = Some(slot);
+            call.callee_expr = Some(callee_expr);
+        }
+    }
+}
+
+/// A declaration of or reference to a function, at its spelling location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolReference {
+    /// Clang's unified symbol resolution, which tells statics in different files apart
+    pub usr : String,
+    pub file : PathBuf,
+    pub offset : usize,
+    pub line : u32,
+    pub column : u32,
+    pub is_static : bool,
+}
+
+/// A declaration of some other name in scope at a reference to a function,
+/// which that reference would refer to if renamed to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShadowingDeclaration {
+    /// The function referred to
+    pub usr : String,
+    pub reference_file : PathBuf,
+    pub reference_line : u32,
+    pub file : PathBuf,
+    pub line : u32,
+}
+
+/// Declarations of one name in a translation unit, by scope
+#[derive(Default)]
+struct NameScopes {
+    /// At file scope, seen from the whole unit
+    file_scope : Vec<(PathBuf, u32)>,
+    /// Locals and parameters, with the file and byte range of their function
+    locals : Vec<(PathBuf, Range<usize>, u32)>,
+}
+
+/// What `function_body` takes from a function's body
+#[derive(Default)]
+struct ExtractedBody {
+    text : String,
+    raw : String,
+    macro_expansions : Vec<MacroExpansion>,
+    conditionals : Vec<PreprocessorConditional>,
+}
+
+/// A preprocessor directive line inside a function body
+struct DirectiveLine {
+    /// Byte range in the file, continuation lines included
+    range : Range<usize>,
+    /// The directive with its continuation lines joined, e.g. `#ifdef DEBUG`
+    spelling : String,
+    /// `if`, `ifdef`, `else`, `endif`, `define`...
+    keyword : String,
+}
+
+/// The `#if` chains of a body, followed directive by directive
+#[derive(Default)]
+struct ConditionalTracker {
+    /// Chains still open, innermost last, with the file offset where the
+    /// code of their current branch starts
+    open : Vec<(PreprocessorConditional, usize)>,
+    /// Chains nested in a branch that was not compiled, only counted so that
+    /// their `#endif` is not taken for that of the enclosing chain
+    ignored : usize,
+    finished : Vec<PreprocessorConditional>,
+}
+
+impl ConditionalTracker {
+    /// Take in a directive met when `body_len` bytes of body text were written
+    fn directive(&mut self, directive : &DirectiveLine, contents : &str, skipped : &[Range<usize>], body_len : usize) {
+        let is_inactive = |offset : usize| skipped.iter().any(|range| range.start < offset && range.contains(&offset));
+        let in_inactive_branch = self.open.last().is_some_and(|(conditional, _)| {
+            conditional.branches.last().is_some_and(|branch| !branch.active)
         });
+        match directive.keyword.as_str() {
+            "if" | "ifdef" | "ifndef" if in_inactive_branch || self.ignored > 0 => self.ignored += 1,
+            "endif" if self.ignored > 0 => self.ignored -= 1,
+            _ if self.ignored > 0 => {}
+            "if" | "ifdef" | "ifndef" => {
+                let line = contents[..directive.range.start].matches('\n').count() as u32 + 1;
+                let conditional = PreprocessorConditional { line, ..Default::default() };
+                self.open.push((conditional, directive.range.end));
+                self.open_branch(directive, is_inactive(directive.range.end), body_len);
+            }
+            "elif" | "elifdef" | "elifndef" | "else" => {
+                self.close_branch(contents, directive.range.start);
+                self.open_branch(directive, is_inactive(directive.range.end), body_len);
+                if let Some((_, code_start)) = self.open.last_mut() {
+                    *code_start = directive.range.end;
+                }
+            }
+            "endif" => {
+                self.close_branch(contents, directive.range.start);
+                if let Some((mut conditional, _)) = self.open.pop() {
+                    conditional.end = body_len;
+                    self.finished.push(conditional);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open_branch(&mut self, directive : &DirectiveLine, inactive : bool, body_len : usize) {
+        if let Some((conditional, _)) = self.open.last_mut() {
+            conditional.branches.push(ConditionalBranch {
+                directive : directive.spelling.clone(),
+                active : !inactive,
+                text : String::new(),
+                offset : body_len,
+            });
+        }
+    }
+
+    /// Keep the source of the current branch when it was not compiled
+    fn close_branch(&mut self, contents : &str, end : usize) {
+        if let Some((conditional, code_start)) = self.open.last_mut()
+            && let Some(branch) = conditional.branches.last_mut()
+            && !branch.active
+        {
+            branch.text = contents.get(*code_start..end).unwrap_or_default().trim_end().to_string();
+        }
     }
 }
 
+
 pub struct AstParser{
-    clang : Clang,
+    /// Shared between parsers of different projects, as only one can exist at a time
+    clang : Rc<Clang>,
     compilation_db : CompilationDatabase,
     project_root : PathBuf,
+    /// Directory compile_commands.json was read from, the project root
+    /// unless it was generated elsewhere
+    database_dir : PathBuf,
+    /// Environment the build compiled each file in, by file, for the
+    /// commands whose environment was recorded
+    environments : BTreeMap<PathBuf, BTreeMap<String, String>>,
+    /// Translation units parsed at once, each in a process of its own
+    jobs : usize,
+    /// Program the worker processes run, which takes the arguments of
+    /// `code-inliner parse-worker`
+    worker : Option<PathBuf>,
+    /// Passed to clang after those of each compile command
+    extra_args : Vec<String>,
+    /// Picks among the commands of a file compiled more than once, by what
+    /// their flags or directory mention
+    config : Option<String>,
+    /// Target, sysroot and include directories given by the user
+    toolchain : Toolchain,
+    /// What each cross compiler met so far reported about itself
+    detected_toolchains : RefCell<HashMap<PathBuf, Option<Toolchain>>>,
+    /// Directories searched first for headers a command cannot find,
+    /// relative to the project root
+    fallback_includes : Vec<PathBuf>,
+    /// Project headers by file name, built on the first missing header
+    header_index : RefCell<Option<BTreeMap<String, Vec<PathBuf>>>>,
+    /// Headers, as spelled in `#include`, precompiled once per set of flags
+    /// for the files including them all
+    pch_headers : Vec<String>,
+    /// Where the precompiled headers go, shared with the parse workers
+    pch_dir : PathBuf,
+    /// Whether `pch_dir` was made for this parser and goes away with it
+    owns_pch_dir : bool,
+    /// Precompiled header built for each set of flags, none when it failed
+    pch_files : RefCell<HashMap<u64, Option<PathBuf>>>,
 }
 
 impl AstParser {
-    pub fn new(build_path : &Path) -> Result<Self> {
+    /// A parser for the sources under `project_root` whose compile_commands.json
+    /// lives in `database_dir`, such as a build directory CMake configured
+    pub fn open(project_root : &Path, database_dir : &Path) -> Result<Self> {
         let clang = Clang::new()
             .map_err(|e| anyhow::anyhow!("Failed to initialize Clang parser : {}", e))?;
+        Self::with_clang(Rc::new(clang), project_root, database_dir)
+    }
+
+    /// A parser for another project, reusing this parser's Clang instance
+    pub fn for_project(&self, build_path : &Path) -> Result<Self> {
+        Ok(Self::with_clang(Rc::clone(&self.clang), build_path, build_path)?
+            .with_extra_args(self.extra_args.clone())
+            .with_config(self.config.clone())
+            .with_toolchain(self.toolchain.clone())
+            .with_fallback_includes(self.fallback_includes.clone())
+            .with_pch(self.pch_headers.clone(), Some(self.pch_dir.clone()))
+            .with_workers(self.jobs, self.worker.clone()))
+    }
+
+    /// Precompile `headers` once per set of flags and have clang load them
+    /// instead of parsing them again for every file that includes them all.
+    /// They are then included ahead of the file's own lines, so this is only
+    /// right for headers not depending on what the files define before them.
+    /// Without `dir`, they are built in a directory removed with the parser.
+    pub fn with_pch(mut self, headers : Vec<String>, dir : Option<PathBuf>) -> Self {
+        self.pch_headers = headers;
+        self.owns_pch_dir = dir.is_none();
+        if let Some(dir) = dir {
+            self.pch_dir = dir;
+        }
+        self
+    }
+
+    /// Look for the headers a command cannot find in `dirs` before searching
+    /// the whole project
+    pub fn with_fallback_includes(mut self, dirs : Vec<PathBuf>) -> Self {
+        self.fallback_includes = dirs;
+        self
+    }
+
+    /// Parse for this target rather than the host. Settings left unset are
+    /// taken from the cross compiler of each command, when it is a GCC one.
+    pub fn with_toolchain(mut self, toolchain : Toolchain) -> Self {
+        self.toolchain = toolchain;
+        self
+    }
+
+    /// Pass `extra_args` to clang after the flags of every compile command,
+    /// so they override them
+    pub fn with_extra_args(mut self, extra_args : Vec<String>) -> Self {
+        self.extra_args = extra_args.into_iter().filter(|arg| !arg.is_empty()).collect();
+        self
+    }
+
+    /// Of the commands compiling a file more than once, as for Debug and
+    /// Release builds, parse the one whose flags or directory mention `config`
+    pub fn with_config(mut self, config : Option<String>) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Parse up to `jobs` translation units in parallel, each worker running
+    /// `worker parse-worker` with the options of `code-inliner`, usually the
+    /// `code-inliner` executable itself. Without one, units are parsed in turn.
+    pub fn with_workers(mut self, jobs : usize, worker : Option<PathBuf>) -> Self {
+        self.jobs = jobs.max(1);
+        self.worker = worker;
+        self
+    }
+
+    fn with_clang(clang : Rc<Clang>, build_path : &Path, database_dir : &Path) -> Result<Self> {
         let project_root = build_path.canonicalize()
             .map_err(|e| anyhow::anyhow!("Failed to canonicalize project path: {}", e))?;
-        let db = CompilationDatabase::from_directory(&project_root)
+        let database_dir = database_dir.canonicalize()
+            .map_err(|e| anyhow::anyhow!("Failed to canonicalize {}: {}", database_dir.display(), e))?;
+        let db = CompilationDatabase::from_directory(&database_dir)
             .map_err(|_| anyhow::anyhow!(format!( "Failed to load compile_commands.json from {}",
-                    project_root.display()
+                    database_dir.display()
             )))?;
 
-        Ok(AstParser {clang, compilation_db : db, project_root})
+        Ok(AstParser {
+            clang,
+            compilation_db : db,
+            project_root,
+            environments : compile_commands::read_environment(&database_dir),
+            database_dir,
+            jobs : 1,
+            worker : None,
+            extra_args : Vec::new(),
+            config : None,
+            toolchain : Toolchain::default(),
+            detected_toolchains : RefCell::new(HashMap::new()),
+            fallback_includes : Vec::new(),
+            header_index : RefCell::new(None),
+            pch_headers : Vec::new(),
+            pch_dir : std::env::temp_dir().join(format!("expansion-pch-{}", std::process::id())),
+            owns_pch_dir : true,
+            pch_files : RefCell::new(HashMap::new()),
+        })
+    }
+    /// Directory a compile command runs in. A relative one is taken from
+    /// where compile_commands.json lives.
+    fn command_directory(&self, command : &CompileCommand) -> PathBuf {
+        self.database_dir.join(command.get_directory())
     }
-    //pub fn parse_file(&self, file_path : &Path) -> Result<FunctionDatabase> {
-    //    // Make file path absolute before changing directory
-    //    let abs_file_path = if file_path.is_absolute() {
-    //        file_path.to_path_buf()
-    //    } else {
-    //        std::env::current_dir()?.join(file_path).canonicalize()
-    //            .map_err(|e| anyhow::anyhow!("Failed to resolve file path {}: {}", file_path.display(), e))?
-    //    };
-
-    //    // Change to project directory so relative include paths work
-    //    let original_dir = std::env::current_dir()?;
-    //    std::env::set_current_dir(&self.project_root)?;
 
-    //    let result = self.parse_file_impl(&abs_file_path);
+    /// The file a compile command compiles, as an absolute path
+    fn command_file(&self, command : &CompileCommand) -> PathBuf {
+        Self::normalize_path(&command.get_filename(), &self.command_directory(command))
+    }
 
-    //    // Restore original directory
-    //    std::env::set_current_dir(original_dir)?;
+    /// The user's toolchain settings, completed by what the command's compiler
+    /// reports about itself
+    fn toolchain_for(&self, command : &CompileCommand) -> Toolchain {
+        let Some(compiler) = command.get_arguments().into_iter().next() else {
+            return self.toolchain.clone();
+        };
+        // A bare name is looked up in PATH, a relative path from the command's directory
+        let compiler = if compiler.contains('/') {
+            self.command_directory(command).join(compiler)
+        } else {
+            PathBuf::from(compiler)
+        };
+        let mut detected = self.detected_toolchains.borrow_mut();
+        let detected = detected.entry(compiler)
+            .or_insert_with_key(|compiler| Toolchain::detect(compiler));
+        match detected {
+            Some(detected) => self.toolchain.or(detected),
+            None => self.toolchain.clone(),
+        }
+    }
 
-    //    result
-    //}
+    /// Key of everything that changes how a command parses
+    fn flags_hash(&self, command : &CompileCommand) -> u64 {
+        let arguments = [command.get_arguments(), self.toolchain_for(command).clang_arguments(), self.extra_args.clone()].concat();
+        ParseCache::flags_hash(&self.command_directory(command), &arguments)
+    }
 
-    fn parse_command_impl(&self, command : &CompileCommand, function_db : &mut FunctionDatabase, index : &Index) -> Result<()> {
-        let mut args = Self::extract_compatible_flags(&command.get_arguments()[1..]);
+    /// Everything clang is given to parse a command in `mode`
+    fn parse_arguments(&self, command : &CompileCommand, mode : &ParseMode) -> Vec<String> {
+        let mut args = self.clang_arguments(command, mode.reduced_flags);
+        args.extend(mode.added_includes.iter().map(|dir| format!("-I{}", dir.display())));
+        args.extend(self.toolchain_for(command).clang_arguments());
         args.push("-ferror-limit=0".to_string());
-        args.push("-Wno-everything".to_string());
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    fn parse_translation_unit<'i>(&self, command : &CompileCommand, index : &'i Index, mode : &ParseMode) -> Result<TranslationUnit<'i>> {
+        let file_path = self.command_file(command);
+        let arguments = self.parse_arguments(command, mode);
+        let parse = |arguments : &[String]| {
+            index.parser(&file_path)
+                .arguments(arguments)
+                .skip_function_bodies(false)
+                .detailed_preprocessing_record(true)
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", file_path.display(), e))
+        };
+
+        let Some((key, pch)) = self.precompiled_header(command, index, &arguments) else {
+            return parse(&arguments);
+        };
+        let with_pch = [arguments.clone(), vec!["-include-pch".to_string(), pch.display().to_string()]].concat();
+        let tu = parse(&with_pch)?;
+        // A header edited since it was precompiled makes the whole file fail
+        let rejected = tu.get_diagnostics().iter().any(|diagnostic| {
+            diagnostic.get_severity() == Severity::Fatal && diagnostic.get_text().contains(&pch.display().to_string())
+        });
+        if !rejected {
+            return Ok(tu);
+        }
+        tracing::warn!("Clang rejected {} for {}, parsing without it", pch.display(), file_path.display());
+        self.pch_files.borrow_mut().insert(key, None);
+        parse(&arguments)
+    }
+
+    /// The precompiled `pch_headers` for a command parsed with `arguments`,
+    /// with the key of its flags, built on first use. None when there are no
+    /// such headers, the file does not include them all or they fail to build.
+    fn precompiled_header(&self, command : &CompileCommand, index : &Index, arguments : &[String]) -> Option<(u64, PathBuf)> {
+        let file = self.command_file(command);
+        if self.pch_headers.is_empty() || !Self::includes_all(&file, &self.pch_headers) {
+            return None;
+        }
+        // Quoted includes are first looked for next to the file
+        let directory = file.parent().unwrap_or(&self.project_root);
+        let arguments = [arguments, &["-iquote".to_string(), directory.display().to_string()]].concat();
+        let key = ParseCache::flags_hash(directory, &[arguments.as_slice(), &self.pch_headers].concat());
+        let pch = self.pch_files.borrow_mut()
+            .entry(key)
+            .or_insert_with(|| {
+                let pch = self.pch_dir.join(format!("{:016x}.pch", key));
+                // Workers share the directory, and whoever comes first builds
+                if pch.exists() {
+                    return Some(pch);
+                }
+                self.build_precompiled_header(index, &arguments, &pch)
+                    .inspect_err(|e| tracing::warn!("Could not precompile {}: {}", self.pch_headers.join(", "), e))
+                    .ok()
+            })
+            .clone()?;
+        Some((key, pch))
+    }
 
-        let file_path = command.get_filename();
+    fn build_precompiled_header(&self, index : &Index, arguments : &[String], pch : &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.pch_dir)?;
+        let umbrella = pch.with_extension("h");
+        let includes : String = self.pch_headers.iter()
+            .map(|header| format!("#include \"{}\"\n", header))
+            .collect();
+        std::fs::write(&umbrella, includes)?;
 
-        let tu_result = index.parser(&file_path)
-            .arguments(&args)
-            .skip_function_bodies(false)
+        let arguments = [arguments, &["-x".to_string(), "c-header".to_string()]].concat();
+        let tu = index.parser(&umbrella)
+            .arguments(&arguments)
             .detailed_preprocessing_record(true)
-            .parse();
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Failed to parse {}", umbrella.display()))?;
+        // Written aside first, as a worker may be loading the final name
+        let partial = pch.with_extension(format!("{}.tmp", std::process::id()));
+        tu.save(&partial).map_err(|e| anyhow::anyhow!("{:?}", e))?;
+        std::fs::rename(&partial, pch)?;
+        tracing::debug!("Precompiled {} into {}", self.pch_headers.join(", "), pch.display());
+        Ok(pch.to_path_buf())
+    }
 
-        if let Ok(tu) = tu_result {
-            let _ = self.collect_functions(&tu.get_entity(), function_db);
-        } else {
-            eprintln!("Warning: Failed to parse {}", file_path.display());
-            return Err(anyhow::anyhow!("Failed to parse {}", file_path.display()));
+    /// Whether `file` has an `#include` of every header in `headers`, as spelled
+    fn includes_all(file : &Path, headers : &[String]) -> bool {
+        let Ok(source) = std::fs::read_to_string(file) else {
+            return false;
+        };
+        let included : Vec<&str> = source.lines()
+            .filter_map(|line| {
+                let rest = line.trim_start().strip_prefix('#')?.trim_start().strip_prefix("include")?.trim_start();
+                let close = match rest.chars().next()? {
+                    '"' => '"',
+                    '<' => '>',
+                    _ => return None,
+                };
+                let rest = &rest[1..];
+                Some(&rest[..rest.find(close)?])
+            })
+            .collect();
+        headers.iter().all(|header| included.contains(&header.as_str()))
+    }
+
+    /// Parse a command, keeping only its defines and include paths when clang
+    /// rejects the whole set, and adding include directories for the headers
+    /// it cannot find
+    fn parse_with_fallback<'i>(&self, command : &CompileCommand, index : &'i Index) -> Result<(TranslationUnit<'i>, ParseMode)> {
+        let mut mode = ParseMode::default();
+        let mut tu = match self.parse_translation_unit(command, index, &mode) {
+            Ok(tu) => tu,
+            Err(e) => {
+                tracing::debug!("{}, retrying with reduced flags", e);
+                mode.reduced_flags = true;
+                self.parse_translation_unit(command, index, &mode)?
+            }
+        };
+
+        // A missing header stops the preprocessor, so each pass may reveal
+        // another one behind it
+        for pass in 0.. {
+            let missing = Self::missing_headers(&tu);
+            let mut found = Vec::new();
+            for header in &missing {
+                if let Some(dir) = self.find_include_dir(header)
+                    && !mode.added_includes.contains(&dir)
+                    && !found.contains(&dir)
+                {
+                    found.push(dir);
+                }
+            }
+            if found.is_empty() || pass == MAX_INCLUDE_PASSES {
+                mode.missing_headers = missing;
+                break;
+            }
+            mode.added_includes.extend(found);
+            tu = self.parse_translation_unit(command, index, &mode)?;
         }
-        Ok(())
+        Ok((tu, mode))
     }
 
-    fn parse_file_impl(&self, file_path : &Path, function_db : &mut FunctionDatabase, index : &Index, one_command_per_file : bool) -> Result<()> {
-        // Skip non-C files (like assembly)
-        let ext = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
-        if ext != "c" && ext != "h" {
-            return Ok(());
+    /// The warnings and errors of a translation unit. Warnings from system
+    /// headers are left out, as nothing can be done about them.
+    fn diagnostics(tu : &TranslationUnit) -> Vec<ParseDiagnostic> {
+        tu.get_diagnostics()
+            .iter()
+            .filter_map(|diagnostic| {
+                let severity = match diagnostic.get_severity() {
+                    Severity::Warning => DiagnosticSeverity::Warning,
+                    Severity::Error => DiagnosticSeverity::Error,
+                    Severity::Fatal => DiagnosticSeverity::Fatal,
+                    Severity::Ignored | Severity::Note => return None,
+                };
+                let location = diagnostic.get_location();
+                if severity == DiagnosticSeverity::Warning && location.is_in_system_header() {
+                    return None;
+                }
+                let spelling = location.get_spelling_location();
+                Some(ParseDiagnostic {
+                    file: spelling.file.map(|file| file.get_path()),
+                    line: spelling.line,
+                    column: spelling.column,
+                    severity,
+                    message: diagnostic.get_text(),
+                })
+            })
+            .collect()
+    }
+
+    /// Headers an `#include` of the translation unit names but clang did not find
+    fn missing_headers(tu : &TranslationUnit) -> Vec<String> {
+        let mut missing = Vec::new();
+        for diagnostic in tu.get_diagnostics() {
+            if !matches!(diagnostic.get_severity(), Severity::Error | Severity::Fatal) {
+                continue;
+            }
+            let text = diagnostic.get_text();
+            if let Some(header) = text.strip_suffix("' file not found").and_then(|rest| rest.strip_prefix('\''))
+                && !missing.iter().any(|known| known == header)
+            {
+                missing.push(header.to_string());
+            }
         }
+        missing
+    }
 
-        let binding = self.compilation_db.get_compile_commands(&file_path)
-            .map_err(|_| anyhow::anyhow!("Cannot find commands for {}", file_path.display()))?;
-        let commands = binding.get_commands();
-        if commands.len() == 0 {
-            return Ok(());
+    /// Directory to add with `-I` for `#include "header"` to resolve: the
+    /// first fallback directory holding it, else a project directory found
+    /// by its file name
+    fn find_include_dir(&self, header : &str) -> Option<PathBuf> {
+        let found = self.fallback_includes.iter()
+            .map(|dir| self.project_root.join(dir))
+            .find(|dir| dir.join(header).is_file());
+        if found.is_some() {
+            return found;
         }
-        if one_command_per_file && commands.len() > 1{
-                return Err(anyhow::anyhow!(
-                        "One command per file set and we have multiple for {}",
-                        file_path.display()
-                        ));
+
+        let header_path = Path::new(header);
+        let name = header_path.file_name()?.to_str()?;
+        let depth = header_path.components().count();
+        let mut index = self.header_index.borrow_mut();
+        let index = index.get_or_insert_with(|| self.index_headers());
+        index.get(name)?
+            .iter()
+            .filter_map(|file| file.ancestors().nth(depth))
+            .find(|dir| dir.join(header).is_file())
+            .map(Path::to_path_buf)
+    }
+
+    /// Every header of the project by file name, in path order
+    fn index_headers(&self) -> BTreeMap<String, Vec<PathBuf>> {
+        let mut index : BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        let pattern = format!("{}/**/*.h", glob::Pattern::escape(&self.project_root.display().to_string()));
+        for file in glob::glob(&pattern).into_iter().flatten().flatten() {
+            if let Some(name) = file.file_name().and_then(|name| name.to_str()) {
+                index.entry(name.to_string()).or_default().push(file.clone());
+            }
         }
+        index
+    }
 
-        for command in commands {
-            self.parse_command_impl(&command, function_db, index)?;
+    /// Parse one compile command into a database of its own, with the hashes
+    /// of the files it was parsed from
+    fn parse_unit(&self, command : &CompileCommand, index : &Index, cache : &mut ParseCache) -> Result<CachedUnit> {
+        let file = self.command_file(command);
+        let directory = self.command_directory(command);
+        tracing::debug!("Parsing {}", file.display());
+        let (tu, parse_mode) = self.parse_with_fallback(command, index).inspect_err(|e| tracing::warn!("{}", e))?;
+        if parse_mode.is_fallback() {
+            tracing::warn!("{}: {}", file.display(), parse_mode);
         }
-        Ok(())
+        let mut database = FunctionDatabase::new();
+        let _ = self.collect_functions(&tu.get_entity(), &mut database);
+        let includes = Self::included_files(&tu.get_entity());
+        database.add_unit(TranslationUnitInfo {
+            file: file.clone(),
+            directory: directory.clone(),
+            arguments: command.get_arguments(),
+            environment: self.environments.get(&file).cloned().unwrap_or_default(),
+            parse_mode,
+            diagnostics: Self::diagnostics(&tu),
+            includes: includes.iter().cloned().collect(),
+        });
+
+        let file_hashes = std::iter::once(file.clone())
+            .chain(includes)
+            .filter_map(|dependency| Some((dependency.clone(), cache.file_hash(&dependency)?)))
+            .collect();
+        Ok(CachedUnit {
+            file,
+            flags_hash: self.flags_hash(command),
+            file_hashes,
+            database,
+        })
     }
 
-    /// Parse all source files in the compilation database to build a complete function database
-    pub fn parse_all_files(&self, parse_all_commands : bool) -> Result<FunctionDatabase> {
-        let original_dir = std::env::current_dir()?;
-        std::env::set_current_dir(&self.project_root)?;
+    /// Every file a translation unit includes, directly or not
+    fn included_files(tu : &Entity) -> Vec<PathBuf> {
+        tu.get_children()
+            .iter()
+            .filter(|child| child.get_kind() == EntityKind::InclusionDirective)
+            .filter_map(|child| child.get_file())
+            .map(|file| file.get_path())
+            .collect()
+    }
+
+    /// Find every declaration of and reference to the functions called `name`
+    /// across all translation units. References resolve through clang, so
+    /// shadowing locals and unrelated statics in other files are left out.
+    /// Along with them come the declarations of `other` in scope at any of
+    /// them: globals, functions and types of the same unit, and the locals
+    /// and parameters of the enclosing function.
+    pub fn find_symbol_references(&self, name : &str, other : &str) -> Result<(Vec<SymbolReference>, Vec<ShadowingDeclaration>)> {
+        let index = Index::new(&self.clang, true, true);
+        let mut references = Vec::new();
+        let mut shadowing = Vec::new();
+        for command in self.compilation_db.get_all_compile_commands().get_commands() {
+            let (tu, _) = self.parse_with_fallback(&command, &index)?;
+            let mut unit_references = Vec::new();
+            let mut scopes = NameScopes::default();
+            Self::collect_references(&tu.get_entity(), name, other, None, &mut unit_references, &mut scopes);
 
-        let result = if parse_all_commands {
-            self.parse_all_commands_impl()
-        }else {
-            self.parse_all_files_impl()
+            for reference in &unit_references {
+                let in_scope = scopes.file_scope.first().cloned().or_else(|| {
+                    scopes.locals.iter()
+                        .find(|(file, function, _)| *file == reference.file && function.contains(&reference.offset))
+                        .map(|(file, _, line)| (file.clone(), *line))
+                });
+                if let Some((file, line)) = in_scope {
+                    shadowing.push(ShadowingDeclaration {
+                        usr : reference.usr.clone(),
+                        reference_file : reference.file.clone(),
+                        reference_line : reference.line,
+                        file,
+                        line,
+                    });
+                }
+            }
+            references.extend(unit_references);
+        }
+
+        // Headers are seen once per including translation unit
+        references.sort_by(|a, b| (&a.file, a.offset).cmp(&(&b.file, b.offset)));
+        references.dedup_by(|a, b| a.file == b.file && a.offset == b.offset);
+        Ok((references, shadowing))
+    }
+
+    /// `function` is the file and byte range of the definition `entity` is in
+    fn collect_references(
+        entity : &Entity,
+        name : &str,
+        other : &str,
+        function : Option<&(PathBuf, Range<usize>)>,
+        references : &mut Vec<SymbolReference>,
+        scopes : &mut NameScopes,
+    ) {
+        if entity.get_location().is_some_and(|loc| loc.is_in_system_header()) {
+            return;
+        }
+
+        let target = match entity.get_kind() {
+            EntityKind::FunctionDecl => Some(*entity),
+            EntityKind::DeclRefExpr => entity.get_reference()
+                .filter(|r| r.get_kind() == EntityKind::FunctionDecl),
+            _ => None,
         };
+        let spelling = entity.get_location().map(|loc| loc.get_spelling_location());
+        if let Some(target) = target.filter(|t| t.get_name().as_deref() == Some(name)) {
+            if let (Some(usr), Some(spelling)) = (target.get_usr(), spelling)
+                && let Some(file) = spelling.file
+            {
+                references.push(SymbolReference {
+                    usr: usr.0,
+                    file: file.get_path(),
+                    offset: spelling.offset as usize,
+                    line: spelling.line,
+                    column: spelling.column,
+                    is_static: target.get_storage_class() == Some(StorageClass::Static),
+                });
+            }
+        }
 
-        std::env::set_current_dir(original_dir)?;
-        result
+        let is_ordinary_declaration = matches!(
+            entity.get_kind(),
+            EntityKind::VarDecl | EntityKind::ParmDecl | EntityKind::FunctionDecl
+                | EntityKind::TypedefDecl | EntityKind::EnumConstantDecl
+        );
+        if is_ordinary_declaration
+            && entity.get_name().as_deref() == Some(other)
+            && let Some(file) = spelling.and_then(|spelling| spelling.file)
+        {
+            let line = spelling.map_or(0, |spelling| spelling.line);
+            match function {
+                Some((function_file, range)) => scopes.locals.push((function_file.clone(), range.clone(), line)),
+                // Parameters of a prototype are only in scope inside it
+                None if entity.get_kind() == EntityKind::ParmDecl => {}
+                None => scopes.file_scope.push((file.get_path(), line)),
+            }
+        }
+
+        // Parameters and locals belong to the definition they are declared in
+        let definition = (function.is_none() && entity.get_kind() == EntityKind::FunctionDecl && entity.is_definition())
+            .then(|| entity.get_range())
+            .flatten()
+            .and_then(|range| {
+                let start = range.get_start().get_spelling_location();
+                let end = range.get_end().get_spelling_location();
+                Some((start.file?.get_path(), start.offset as usize..end.offset as usize))
+            });
+        for child in entity.get_children() {
+            Self::collect_references(&child, name, other, definition.as_ref().or(function), references, scopes);
+        }
     }
 
-    fn parse_all_commands_impl(&self) -> Result<FunctionDatabase> {
+    /// Parse all source files in the compilation database to build a complete function database
+    pub fn parse_all_files(&self, parse_all_commands : bool) -> Result<FunctionDatabase> {
+        self.parse_all_files_cached(parse_all_commands, &mut ParseCache::new())
+    }
+
+    /// Like `parse_all_files`, reusing the translation units of `cache` whose
+    /// source, headers and flags did not change, and storing the others in it
+    pub fn parse_all_files_cached(&self, parse_all_commands : bool, cache : &mut ParseCache) -> Result<FunctionDatabase> {
+        cache.start_run();
+        let all_commands = self.compilation_db.get_all_compile_commands();
+        let commands = all_commands.get_commands();
+        let selected = if parse_all_commands {
+            (0..commands.len()).collect()
+        } else {
+            self.one_command_per_file(&commands)
+        };
+
         let mut function_db = FunctionDatabase::new();
-        let index = Index::new(&self.clang, true, true);
+        self.parse_into(&commands, &selected, cache, &mut function_db)?;
+        function_db.resolve_symbols();
+        function_db.resolve_indirect_calls();
+        function_db.resolve_recursion();
+        Ok(function_db)
+    }
 
+    /// Parse only the translation units defining `entry_points` and, round
+    /// after round, those defining the functions they call that are still
+    /// missing or only weakly defined. Which unit defines what comes from a
+    /// quick scan of every unit that skips function bodies. Functions only
+    /// reached through pointers assigned in units left out are missed.
+    pub fn parse_reachable_cached(&self, entry_points : &[String], cache : &mut ParseCache) -> Result<FunctionDatabase> {
+        cache.start_run();
         let all_commands = self.compilation_db.get_all_compile_commands();
-        for command in all_commands.get_commands() {
-            match self.parse_command_impl(&command, &mut function_db, &index) {
-                Err(e) => {
-                    function_db.clear();
-                    return Err(e);
-                }
-                Ok(_) => {}
+        let commands = all_commands.get_commands();
+        let selected = self.one_command_per_file(&commands);
+        let symbols = self.symbol_index(&commands, &selected);
+
+        let mut function_db = FunctionDatabase::new();
+        let mut parsed = BTreeSet::new();
+        let mut wanted : BTreeSet<String> = entry_points.iter().cloned().collect();
+        loop {
+            let mut round : Vec<usize> = wanted.iter()
+                .flat_map(|name| symbols.get(name).into_iter().flatten())
+                .copied()
+                .filter(|i| !parsed.contains(i))
+                .collect();
+            round.sort_unstable();
+            round.dedup();
+            if round.is_empty() {
+                break;
             }
+            tracing::debug!("Parsing {} more translation units for {} functions", round.len(), wanted.len());
+            parsed.extend(round.iter().copied());
+            self.parse_into(&commands, &round, cache, &mut function_db)?;
+
+            let called : BTreeSet<String> = function_db.iter()
+                .flat_map(|def| def.calls.iter().map(|call| call.function_name.clone()).collect::<Vec<_>>())
+                .chain(function_db.address_taken_functions().into_iter().map(String::from))
+                .collect();
+            wanted = called.into_iter()
+                .filter(|name| function_db.get_function_definition(name).is_none_or(|def| def.attributes.weak))
+                .collect();
         }
+        tracing::info!("Parsed {} of {} translation units", parsed.len(), selected.len());
 
+        cache.keep_unclaimed();
+        function_db.resolve_symbols();
+        function_db.resolve_indirect_calls();
+        function_db.resolve_recursion();
         Ok(function_db)
     }
 
-    fn normalize_path(path : &PathBuf, base_dir : &PathBuf) -> PathBuf {
-        if path.is_absolute() {
-            path.canonicalize().unwrap_or_else(|_| path.clone())
-        }else{
-            base_dir.join(path).canonicalize().unwrap_or_else(|_| base_dir.join(path) )
+    /// Parse the commands at `indices`, or take them from the cache when they
+    /// did not change, and merge them into `function_db`
+    fn parse_into(&self, commands : &[CompileCommand], indices : &[usize], cache : &mut ParseCache, function_db : &mut FunctionDatabase) -> Result<()> {
+        let mut cached = Vec::with_capacity(indices.len());
+        let mut to_parse = Vec::new();
+        for &i in indices {
+            let command = &commands[i];
+            let file = self.command_file(command);
+            let unit = cache.take(&file, self.flags_hash(command));
+            match unit {
+                Some(_) => tracing::debug!("Reusing {}", file.display()),
+                None => to_parse.push(i),
+            }
+            cached.push(unit);
+        }
+        let mut parsed = self.parse_commands(commands, &to_parse, cache)?.into_iter();
+
+        // Merged in the order of the compilation database, wherever each unit
+        // was parsed, so the result does not depend on the number of jobs
+        for unit in cached {
+            let reused = unit.is_some();
+            let Some(unit) = unit.or_else(|| parsed.next()) else {
+                anyhow::bail!("A parse worker returned fewer translation units than asked");
+            };
+            function_db.merge(unit.database.clone());
+            cache.insert(unit, reused);
         }
+        Ok(())
     }
 
-    fn parse_all_files_impl(&self) -> Result<FunctionDatabase> {
-        let mut function_db = FunctionDatabase::new();
-        let index = Index::new(&self.clang, true, true);
+    /// The commands at `indices` that may define each function name
+    fn symbol_index(&self, commands : &[CompileCommand], indices : &[usize]) -> BTreeMap<String, Vec<usize>> {
+        let index = Index::new(&self.clang, true, false);
+        let mut progress = Progress::new("Indexing", indices.len());
+        let mut symbols : BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for &i in indices {
+            for name in self.scan_definitions(&commands[i], &index) {
+                symbols.entry(name).or_default().push(i);
+            }
+            progress.tick(&commands[i].get_filename().display().to_string());
+        }
+        symbols
+    }
+
+    /// Names of the functions declared in a command's own file, from a parse
+    /// that skips function bodies. Clang may not count a skipped body as a
+    /// definition, so prototypes in the file count as well: a unit parsed for
+    /// nothing only costs time.
+    fn scan_definitions(&self, command : &CompileCommand, index : &Index) -> Vec<String> {
+        let file_path = self.command_file(command);
+        let tu = index.parser(&file_path)
+            .arguments(&self.parse_arguments(command, &ParseMode::default()))
+            .skip_function_bodies(true)
+            .parse();
+        let Ok(tu) = tu else {
+            tracing::warn!("Could not scan {}", file_path.display());
+            return Vec::new();
+        };
+        tu.get_entity()
+            .get_children()
+            .into_iter()
+            .filter(|entity| entity.get_kind() == EntityKind::FunctionDecl && entity.is_in_main_file())
+            .filter_map(|entity| entity.get_name())
+            .collect()
+    }
 
-        let mut file_set : HashSet<PathBuf> = HashSet::new();
-        let curr_dir = std::env::current_dir()?;
+    /// Indices of the commands compiling C files, one per file. Of the
+    /// several commands a file may have, as in a database holding Debug and
+    /// Release builds, the first matching the config is taken, else the
+    /// first, with a warning about those left out.
+    fn one_command_per_file(&self, commands : &[CompileCommand]) -> Vec<usize> {
+        let mut by_file : BTreeMap<PathBuf, Vec<usize>> = BTreeMap::new();
+        for (i, command) in commands.iter().enumerate() {
+            by_file.entry(self.command_file(command)).or_default().push(i);
+        }
 
-        let all_commands = self.compilation_db.get_all_compile_commands();
-        let commands = all_commands.get_commands();
+        let mut selected = Vec::new();
+        let mut ignored = 0;
+        let mut unmatched = 0;
+        for (file_path, indices) in by_file {
+            // Skip non-C files (like assembly)
+            let ext = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            if ext != "c" && ext != "h" {
+                continue;
+            }
+            if indices.len() == 1 {
+                selected.extend(indices);
+                continue;
+            }
+            let matching : Vec<usize> = match &self.config {
+                Some(config) => indices.iter().copied().filter(|&i| self.command_matches(&commands[i], &file_path, config)).collect(),
+                None => Vec::new(),
+            };
+            let chosen = matching.first().copied().unwrap_or(indices[0]);
+            // The alternatives the config did not rule out
+            let others : Vec<usize> = if matching.is_empty() {
+                indices.iter().copied().filter(|&i| i != chosen).collect()
+            } else {
+                matching[1..].to_vec()
+            };
+            if matching.is_empty() && self.config.is_some() {
+                unmatched += 1;
+            } else if !others.is_empty() {
+                ignored += 1;
+            }
+            if !others.is_empty() {
+                tracing::debug!(
+                    "{} has {} compile commands, left out: {}",
+                    file_path.display(),
+                    indices.len(),
+                    others.iter().map(|&i| self.command_difference(&commands[chosen], &commands[i], &file_path)).collect::<Vec<_>>().join("; ")
+                );
+            }
+            selected.push(chosen);
+        }
+        let config = self.config.as_deref().unwrap_or_default();
+        if unmatched > 0 {
+            tracing::warn!("{} files have several compile commands, none mentioning --config {}; the first of each is parsed (-v lists the others)", unmatched, config);
+        }
+        if ignored > 0 && self.config.is_none() {
+            tracing::warn!("{} files have several compile commands, the first of each is parsed; pick one with --config (-v lists the others)", ignored);
+        } else if ignored > 0 {
+            tracing::warn!("{} files have several compile commands mentioning --config {}; the first of each is parsed (-v lists the others)", ignored, config);
+        }
+        selected
+    }
 
-        for command in commands {
-            let file_path = command.get_filename();
-            file_set.insert(AstParser::normalize_path(&file_path, &curr_dir));
+    /// Whether the directory of `command` or one of its arguments other than
+    /// the source file mentions `config`, e.g. `Release` in `build/Release`
+    /// or `-DNDEBUG` in its flags
+    fn command_matches(&self, command : &CompileCommand, file : &Path, config : &str) -> bool {
+        let directory = self.command_directory(command);
+        directory.to_string_lossy().contains(config)
+            || command.get_arguments().iter()
+                .filter(|argument| Self::normalize_path(&PathBuf::from(argument), &directory) != file)
+                .any(|argument| argument.contains(config))
+    }
+
+    /// What sets `other` apart from `chosen`, two commands compiling `file`:
+    /// its directory, when another, and the arguments only it has
+    fn command_difference(&self, chosen : &CompileCommand, other : &CompileCommand, file : &Path) -> String {
+        let directory = self.command_directory(other);
+        let mut parts = Vec::new();
+        if directory != self.command_directory(chosen) {
+            parts.push(format!("in {}", directory.display()));
         }
-        for file in file_set {
-            match self.parse_file_impl(&file, &mut function_db, &index, true) {
-                Err(e) => {
-                    function_db.clear();
-                    return Err(e);
-                }
-                Ok(_) => {}
+        let chosen_arguments = chosen.get_arguments();
+        let extra : Vec<String> = other.get_arguments().into_iter()
+            .filter(|argument| !chosen_arguments.contains(argument))
+            .filter(|argument| Self::normalize_path(&PathBuf::from(argument), &directory) != file)
+            .collect();
+        if !extra.is_empty() {
+            parts.push(format!("with {}", extra.join(" ")));
+        }
+        if parts.is_empty() {
+            "the same command again".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+
+    /// Parse the commands at `indices`, spread over worker processes when more
+    /// than one job is allowed. Units come back in the order of `indices`.
+    fn parse_commands(&self, commands : &[CompileCommand], indices : &[usize], cache : &mut ParseCache) -> Result<Vec<CachedUnit>> {
+        let workers = self.jobs.min(indices.len());
+        let mut progress = Progress::new("Parsing", indices.len());
+        let Some(exe) = self.worker.as_ref().filter(|_| workers > 1) else {
+            let index = Index::new(&self.clang, true, true);
+            return indices.iter()
+                .map(|&i| {
+                    let unit = self.parse_unit(&commands[i], &index, cache);
+                    progress.tick(&commands[i].get_filename().display().to_string());
+                    unit
+                })
+                .collect();
+        };
+
+        // Only one Clang instance may exist per process, so each worker is a
+        // process of its own running the hidden `parse-worker` command
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut children = Vec::new();
+        for (worker, chunk) in indices.chunks(indices.len().div_ceil(workers)).enumerate() {
+            let output = std::env::temp_dir().join(format!("expansion-parse-{}-{}.bin", std::process::id(), worker));
+            let list : Vec<String> = chunk.iter().map(usize::to_string).collect();
+            let mut child = std::process::Command::new(exe)
+                .arg("--project-path").arg(&self.project_root)
+                .arg(format!("--database-dir={}", self.database_dir.display()))
+                .args(logging::worker_flags())
+                .args(self.extra_args.iter().map(|arg| format!("--extra-clang-args={}", arg)))
+                .args(self.toolchain.target.iter().map(|target| format!("--target={}", target)))
+                .args(self.toolchain.sysroot.iter().map(|sysroot| format!("--sysroot={}", sysroot.display())))
+                .args(self.toolchain.include_dirs.iter().map(|dir| format!("--toolchain-include={}", dir.display())))
+                .args(self.fallback_includes.iter().map(|dir| format!("--fallback-include={}", dir.display())))
+                .args(self.pch_headers.iter().map(|header| format!("--pch={}", header)))
+                .arg(format!("--pch-dir={}", self.pch_dir.display()))
+                .arg("parse-worker")
+                .arg("--commands").arg(list.join(","))
+                .arg("--output").arg(&output)
+                .stdout(std::process::Stdio::piped())
+                .spawn()?;
+            tracing::debug!("Parse worker {} takes {} translation units", worker, chunk.len());
+
+            // Each worker prints the file of every unit it is done with
+            if let Some(stdout) = child.stdout.take() {
+                let sender = sender.clone();
+                std::thread::spawn(move || {
+                    for line in BufReader::new(stdout).lines().map_while(std::result::Result::ok) {
+                        let _ = sender.send(line);
+                    }
+                });
             }
+            children.push((child, output));
         }
+        drop(sender);
+        for file in receiver {
+            progress.tick(&file);
+        }
+        drop(progress);
 
-        Ok(function_db)
+        // Wait for every worker before reporting a failure
+        let mut results = Vec::new();
+        for (mut child, output) in children {
+            let result = match child.wait() {
+                Ok(status) if status.success() => read_serialized::<Vec<CachedUnit>>(&output),
+                Ok(status) => Err(anyhow::anyhow!("Parse worker failed with {}", status)),
+                Err(e) => Err(e.into()),
+            };
+            let _ = std::fs::remove_file(&output);
+            results.push(result);
+        }
+        let mut units = Vec::with_capacity(indices.len());
+        for result in results {
+            units.extend(result?);
+        }
+        Ok(units)
+    }
+
+    /// Parse the compile commands at `indices` and write their units to
+    /// `output`, for the process that spawned this one
+    pub fn parse_worker(&self, indices : &[usize], output : &Path) -> Result<()> {
+        let all_commands = self.compilation_db.get_all_compile_commands();
+        let commands = all_commands.get_commands();
+        let index = Index::new(&self.clang, true, true);
+        let mut hashes = ParseCache::new();
+        let units = indices.iter()
+            .map(|&i| {
+                let command = commands.get(i).ok_or_else(|| anyhow::anyhow!("No compile command {}", i))?;
+                let unit = self.parse_unit(command, &index, &mut hashes);
+                println!("{}", command.get_filename().display());
+                unit
+            })
+            .collect::<Result<Vec<_>>>()?;
+        write_serialized(output, &units)
+    }
+
+    fn normalize_path(path : &PathBuf, base_dir : &PathBuf) -> PathBuf {
+        if path.is_absolute() {
+            path.canonicalize().unwrap_or_else(|_| path.clone())
+        }else{
+            base_dir.join(path).canonicalize().unwrap_or_else(|_| base_dir.join(path) )
+        }
     }
 
     fn collect_functions(&self, entity : &Entity, db : &mut FunctionDatabase) -> Result<()>{
+        // Before skipping system headers, whose typedefs name the fixed-width
+        // and vendor types signatures use
+        if let Some(typedef) = Self::extract_typedef(entity) {
+            db.add_typedef(typedef);
+        }
         if let Some(location) = entity.get_location() {
-            if location.is_in_system_header() {
+            if location.is_in_system_header() && !self.is_project_inline(entity) {
                 return Ok(());
             }
         }
+        Self::collect_pointer_assignments(entity, db);
+        Self::collect_vector_table(entity, db);
+        if let Some(definition) = Self::extract_macro(entity) {
+            db.add_macro(definition);
+        }
         if entity.get_kind() == EntityKind::FunctionDecl {
             if entity.is_definition() {
                 if let Some(def) = self.extract_function_definition(entity)? {
                     db.add_function_ref(&def);
                 }
+            } else if let Some(declaration) = Self::extract_declaration(entity) {
+                if declaration.attributes.alias.is_some() {
+                    db.add_alias(declaration.clone());
+                }
+                db.add_declaration(declaration);
             }
         }
         for child in entity.get_children() {
@@ -235,15 +1174,123 @@ impl AstParser {
         Ok(())
     }
 
+    /// A `static inline` definition from a header of the project, such as
+    /// those of CMSIS, kept even when the header is included as a system one
+    fn is_project_inline(&self, entity : &Entity) -> bool {
+        entity.get_kind() == EntityKind::FunctionDecl
+            && entity.is_definition()
+            && entity.is_inline_function()
+            && entity.get_storage_class() == Some(StorageClass::Static)
+            && Self::source_location(entity).0.starts_with(&self.project_root)
+    }
+
     pub fn extract_function_definition(&self, entity : &Entity) -> Result<Option<Definition>> {
         if entity.get_kind() != EntityKind::FunctionDecl || !entity.is_definition() {
             ()
         }
-        let name = match entity.get_name() {
-            Some(n) => n,
-            None => return Ok(None)
+        let Some(signature) = Self::extract_signature(entity) else {
+            return Ok(None);
         };
-        let return_type = entity.get_result_type().map(|t| t.get_display_name()).unwrap_or_else(|| "void".to_string());
+        let (source_file, line) = Self::source_location(entity);
+        let extent = entity.get_range()
+            .map(|range| {
+                let start = range.get_start().get_spelling_location().offset as usize;
+                let end = range.get_end().get_spelling_location().offset as usize;
+                start..end
+            })
+            .unwrap_or_default();
+
+        let ExtractedBody { text: body, raw: raw_body, macro_expansions, conditionals } = Self::function_body(entity);
+        let is_static = entity.get_storage_class() == Some(StorageClass::Static);
+        let is_inline = entity.is_inline_function();
+        let doc_comment = entity.get_comment().or_else(|| Self::preceding_comment(entity));
+        let contains_asm = Self::contains_asm(entity);
+        let uses_vla = Self::declares_vla(entity);
+        let attributes = Self::function_attributes(entity);
+        let calls = self.collect_calls(entity);
+        let uses_alloca = calls.iter().any(|call| ALLOCA_FUNCTIONS.contains(&call.function_name.as_str()))
+            || body.split_whitespace().any(|token| ALLOCA_FUNCTIONS.contains(&token));
+        let mut locals = Vec::new();
+        Self::collect_locals(entity, &mut locals);
+        let mut globals = Vec::new();
+        Self::collect_globals(entity, &mut globals);
+        let mut global_reads = BTreeSet::new();
+        let mut global_writes = BTreeSet::new();
+        Self::collect_global_accesses(entity, Access::Read, &mut global_reads, &mut global_writes);
+        let metrics = FunctionMetrics {
+            lines: entity.get_range()
+                .map(|range| {
+                    let start = range.get_start().get_spelling_location().line;
+                    let end = range.get_end().get_spelling_location().line;
+                    end.saturating_sub(start) + 1
+                })
+                .unwrap_or(0),
+            branches: Self::count_branches(entity)
+                + body.split_whitespace().filter(|token| *token == "&&" || *token == "||").count() as u32,
+            call_count: calls.len() as u32,
+            frame_size: locals.iter().filter(|local| !local.is_static).map(|local| local.size).sum(),
+        };
+
+        let body_hash = normalized_hash(&signature, &body);
+        let is_recursive = calls.iter().any(|call| call.via.is_none() && call.function_name == signature.name);
+        Ok(Some(Definition {
+            signature,
+            body,
+            source_file,
+            line,
+            extent,
+            is_static,
+            is_inline,
+            contains_asm,
+            uses_vla,
+            uses_alloca,
+            attributes,
+            calls,
+            locals,
+            globals,
+            global_reads,
+            global_writes,
+            metrics,
+            macro_expansions,
+            raw_body,
+            conditionals,
+            doc_comment,
+            body_hash,
+            is_recursive,
+        }))
+
+    }
+
+    /// The comment block ending on the line above a definition, for the
+    /// plain comments clang does not attach to declarations
+    fn preceding_comment(entity : &Entity) -> Option<String> {
+        let start = entity.get_range()?.get_start().get_spelling_location();
+        let contents = start.file?.get_contents()?;
+        let before = contents.get(..start.offset as usize)?;
+        let before = before.trim_end_matches([' ', '\t']).strip_suffix('\n')?;
+        let before = before.strip_suffix('\r').unwrap_or(before).trim_end_matches([' ', '\t']);
+
+        if before.ends_with("*/") {
+            let open = before.rfind("/*")?;
+            // Only a comment starting its own line
+            let line_start = before[..open].rfind('\n').map_or(0, |i| i + 1);
+            return before[line_start..open].trim().is_empty().then(|| before[open..].to_string());
+        }
+        let lines : Vec<&str> = before.lines()
+            .rev()
+            .take_while(|line| line.trim_start().starts_with("//"))
+            .collect();
+        (!lines.is_empty()).then(|| lines.iter().rev().map(|line| line.trim()).collect::<Vec<_>>().join("\n"))
+    }
+
+    fn extract_signature(entity : &Entity) -> Option<Signature> {
+        let name = entity.get_name()?;
+        let result_type = entity.get_result_type();
+        let return_type = result_type.map(|t| t.get_display_name()).unwrap_or_else(|| "void".to_string());
+        let canonical_return_type = result_type
+            .map(|t| t.get_canonical_type().get_display_name())
+            .unwrap_or_else(|| "void".to_string());
+        let return_ty = result_type.map(|t| Self::typed(&t)).unwrap_or_default();
         let params = entity.get_arguments().unwrap_or_default();
         let args = params
             .iter()
@@ -252,74 +1299,288 @@ impl AstParser {
                 let param_type = arg.get_type()
                     .map(|t| t.get_display_name())
                     .unwrap_or_else(|| "unknown".to_string());
+                let canonical_type = arg.get_type()
+                    .map(|t| t.get_canonical_type().get_display_name())
+                    .unwrap_or_else(|| "unknown".to_string());
+                let ty = arg.get_type()
+                    .map(|t| Self::typed(&t))
+                    .unwrap_or_else(|| types::Type::new(types::TypeKind::Other(param_type.clone())));
                 Parameter {
                     name,
-                    param_type
+                    param_type,
+                    canonical_type,
+                    ty,
                 }
             })
             .collect();
         let is_variadic = entity.is_variadic();
+        // A K&R definition declares its parameters between the identifier
+        // list and the body; clang still lists them with their types
+        let is_old_style = entity.get_type().is_some_and(|t| t.get_kind() == TypeKind::FunctionNoPrototype);
 
-        let signature = Signature {
+        Some(Signature {
             name,
             return_type,
+            canonical_return_type,
+            return_ty,
             args,
-            is_variadic
+            is_variadic,
+            is_old_style,
+        })
+    }
+
+    /// The model of a clang type, typedefs kept with their target
+    fn typed(clang_type : &Type) -> types::Type {
+        Self::typed_at(clang_type, true)
+    }
+
+    /// Like `typed`; the members of records are only listed when `by_value`,
+    /// as those behind a pointer need no layout and may point back to the
+    /// record holding them
+    fn typed_at(clang_type : &Type, by_value : bool) -> types::Type {
+        use types::TypeKind as Kind;
+        let integer = |kind| Kind::Integer(kind);
+        let kind = match clang_type.get_kind() {
+            TypeKind::Void => Kind::Void,
+            TypeKind::Bool => Kind::Bool,
+            TypeKind::CharS | TypeKind::CharU => integer(IntegerKind::Char),
+            TypeKind::SChar => integer(IntegerKind::SignedChar),
+            TypeKind::UChar => integer(IntegerKind::UnsignedChar),
+            TypeKind::Short => integer(IntegerKind::Short),
+            TypeKind::UShort => integer(IntegerKind::UnsignedShort),
+            TypeKind::Int => integer(IntegerKind::Int),
+            TypeKind::UInt => integer(IntegerKind::UnsignedInt),
+            TypeKind::Long => integer(IntegerKind::Long),
+            TypeKind::ULong => integer(IntegerKind::UnsignedLong),
+            TypeKind::LongLong => integer(IntegerKind::LongLong),
+            TypeKind::ULongLong => integer(IntegerKind::UnsignedLongLong),
+            TypeKind::Int128 => integer(IntegerKind::Int128),
+            TypeKind::UInt128 => integer(IntegerKind::UnsignedInt128),
+            TypeKind::Float => Kind::Float(FloatKind::Float),
+            TypeKind::Double => Kind::Float(FloatKind::Double),
+            TypeKind::LongDouble => Kind::Float(FloatKind::LongDouble),
+            TypeKind::Complex => match clang_type.get_element_type().map(|element| element.get_kind()) {
+                Some(TypeKind::Float) => Kind::Complex(FloatKind::Float),
+                Some(TypeKind::Double) => Kind::Complex(FloatKind::Double),
+                Some(TypeKind::LongDouble) => Kind::Complex(FloatKind::LongDouble),
+                _ => Kind::Other(clang_type.get_display_name()),
+            },
+            TypeKind::Pointer => match clang_type.get_pointee_type() {
+                Some(pointee) => Kind::Pointer(Box::new(Self::typed_at(&pointee, false))),
+                None => Kind::Other(clang_type.get_display_name()),
+            },
+            TypeKind::ConstantArray | TypeKind::IncompleteArray | TypeKind::VariableArray | TypeKind::DependentSizedArray => {
+                let length = match (clang_type.get_kind(), clang_type.get_size()) {
+                    (TypeKind::ConstantArray, Some(size)) => ArrayLength::Fixed(size as u64),
+                    (TypeKind::VariableArray, _) => ArrayLength::Variable,
+                    _ => ArrayLength::Unknown,
+                };
+                match clang_type.get_element_type() {
+                    Some(element) => Kind::Array { element: Box::new(Self::typed_at(&element, by_value)), length },
+                    None => Kind::Other(clang_type.get_display_name()),
+                }
+            }
+            TypeKind::FunctionPrototype | TypeKind::FunctionNoPrototype => Kind::Function(FunctionType {
+                return_type: Box::new(clang_type.get_result_type().map(|t| Self::typed_at(&t, false)).unwrap_or_default()),
+                parameters: clang_type.get_argument_types().unwrap_or_default().iter().map(|t| Self::typed_at(t, false)).collect(),
+                is_variadic: clang_type.is_variadic(),
+                has_prototype: clang_type.get_kind() == TypeKind::FunctionPrototype,
+            }),
+            TypeKind::Record => Kind::Record(Self::record_type(clang_type, by_value)),
+            TypeKind::Enum => {
+                let declaration = clang_type.get_declaration();
+                let (name, is_anonymous) = Self::tag_name(declaration);
+                let constants = declaration.and_then(|d| d.get_definition())
+                    .map(|definition| definition.get_children())
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|constant| Some((constant.get_name()?, constant.get_enum_constant_value()?.0)))
+                    .collect();
+                Kind::Enum(EnumType { name, is_anonymous, constants })
+            }
+            TypeKind::Typedef => {
+                let declaration = clang_type.get_declaration();
+                let target = declaration.and_then(|d| d.get_typedef_underlying_type());
+                match (clang_type.get_typedef_name().or_else(|| declaration.and_then(|d| d.get_name())), target) {
+                    (Some(name), Some(target)) => Kind::Typedef { name, target: Box::new(Self::typed_at(&target, by_value)) },
+                    _ => Kind::Other(clang_type.get_display_name()),
+                }
+            }
+            // `struct s` and attributed types carry their qualifiers here and
+            // the type itself inside
+            TypeKind::Elaborated | TypeKind::Attributed => {
+                let inner = clang_type.get_elaborated_type().or_else(|| clang_type.get_modified_type());
+                match inner {
+                    Some(inner) => Self::typed_at(&inner, by_value).kind,
+                    None => Kind::Other(clang_type.get_display_name()),
+                }
+            }
+            _ => {
+                let canonical = clang_type.get_canonical_type();
+                if canonical.get_kind() != clang_type.get_kind() {
+                    Self::typed_at(&canonical, by_value).kind
+                } else {
+                    Self::atomic_type(&clang_type.get_display_name())
+                        .unwrap_or_else(|| Kind::Other(clang_type.get_display_name()))
+                }
+            }
         };
+        types::Type {
+            kind,
+            is_const: clang_type.is_const_qualified(),
+            is_volatile: clang_type.is_volatile_qualified(),
+            is_restrict: clang_type.is_restrict_qualified(),
+        }
+    }
+
+    /// The model of `_Atomic(T)`, which this libclang leaves unexposed: the
+    /// value type is only known by its spelling, so only builtin ones are
+    /// modelled and the rest kept as written
+    fn atomic_type(spelling : &str) -> Option<types::TypeKind> {
+        use types::TypeKind as Kind;
+        let value = spelling.trim().strip_prefix("_Atomic(")?.strip_suffix(')')?.trim();
+        let (is_const, value) = match value.strip_prefix("const ") {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        let kind = match value {
+            "_Bool" | "bool" => Kind::Bool,
+            "char" => Kind::Integer(IntegerKind::Char),
+            "signed char" => Kind::Integer(IntegerKind::SignedChar),
+            "unsigned char" => Kind::Integer(IntegerKind::UnsignedChar),
+            "short" => Kind::Integer(IntegerKind::Short),
+            "unsigned short" => Kind::Integer(IntegerKind::UnsignedShort),
+            "int" => Kind::Integer(IntegerKind::Int),
+            "unsigned int" => Kind::Integer(IntegerKind::UnsignedInt),
+            "long" => Kind::Integer(IntegerKind::Long),
+            "unsigned long" => Kind::Integer(IntegerKind::UnsignedLong),
+            "long long" => Kind::Integer(IntegerKind::LongLong),
+            "unsigned long long" => Kind::Integer(IntegerKind::UnsignedLongLong),
+            "float" => Kind::Float(FloatKind::Float),
+            "double" => Kind::Float(FloatKind::Double),
+            "long double" => Kind::Float(FloatKind::LongDouble),
+            _ => Kind::Other(value.to_string()),
+        };
+        Some(Kind::Atomic(Box::new(types::Type { kind, is_const, ..Default::default() })))
+    }
+
+    fn record_type(clang_type : &Type, by_value : bool) -> RecordType {
+        let declaration = clang_type.get_declaration();
+        let definition = declaration.and_then(|d| d.get_definition());
+        let attributes = definition.map(|d| d.get_children()).unwrap_or_default();
+        let fields = clang_type.get_fields()
+            .filter(|_| by_value && definition.is_some())
+            .map(|fields| fields.iter()
+                .map(|field| Field {
+                    name: field.get_name().filter(|name| !name.is_empty()),
+                    ty: field.get_type().map(|t| Self::typed_at(&t, true)).unwrap_or_default(),
+                    bit_width: field.get_bit_field_width().map(|width| width as u32),
+                })
+                .collect());
+        // The argument of `aligned` is not exposed, clang's alignment of the
+        // record stands for it
+        let alignment = attributes.iter()
+            .any(|attribute| attribute.get_kind() == EntityKind::AlignedAttr)
+            .then(|| clang_type.get_alignof().ok().map(|align| align as u64))
+            .flatten();
+        let (name, is_anonymous) = Self::tag_name(declaration);
+        RecordType {
+            is_union: declaration.is_some_and(|d| d.get_kind() == EntityKind::UnionDecl),
+            name,
+            is_anonymous,
+            fields,
+            is_packed: attributes.iter().any(|attribute| attribute.get_kind() == EntityKind::PackedAttr),
+            alignment,
+        }
+    }
+
+    /// The tag of a struct, union or enum, and whether it is anonymous.
+    /// Anonymous ones are named after the file and offset they are declared
+    /// at, e.g. `__anon_hal_def_h_1204`, so that every translation unit
+    /// including the header gives them the same name.
+    fn tag_name(declaration : Option<Entity>) -> (String, bool) {
+        if let Some(name) = declaration.and_then(|d| d.get_name()).filter(|name| Self::is_tag_name(name)) {
+            return (name, false);
+        }
+        let location = declaration.and_then(|d| d.get_location()).map(|location| location.get_file_location());
+        let file = location.and_then(|location| location.file)
+            .and_then(|file| file.get_path().file_name().map(|name| name.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let file : String = file.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+        (format!("__anon_{}_{}", file, location.map_or(0, |location| location.offset)), true)
+    }
 
+    /// Whether clang's name for a struct, union or enum is a tag, rather
+    /// than a description like `struct (unnamed at main.c:3:5)`
+    fn is_tag_name(name : &str) -> bool {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn source_location(entity : &Entity) -> (PathBuf, u32) {
         let source_file = entity.get_location()
             .and_then(|loc|{
                 loc.get_file_location().file.map(|f| f.get_path())
             })
             .unwrap_or_else( || std::path::PathBuf::from("<unknown>"));
+        let line = entity.get_location()
+            .map(|loc| loc.get_spelling_location().line)
+            .unwrap_or(0);
+        (source_file, line)
+    }
 
-        let body = self.extract_function_body(entity)?;
-        let is_static = entity.get_storage_class() == Some(StorageClass::Static);
-        let calls = self.collect_calls(entity);
-
-        Ok(Some(Definition {
+    /// A function declaration without a body, such as a prototype
+    fn extract_declaration(entity : &Entity) -> Option<Declaration> {
+        let signature = Self::extract_signature(entity)?;
+        let (source_file, line) = Self::source_location(entity);
+        Some(Declaration {
             signature,
-            body,
             source_file,
-            is_static,
-            calls,
-        }))
-
+            line,
+            is_static: entity.get_storage_class() == Some(StorageClass::Static),
+            attributes: Self::function_attributes(entity),
+            has_prototype: entity.get_type().is_some_and(|t| t.get_kind() == TypeKind::FunctionPrototype),
+        })
     }
 
     fn collect_calls(&self, entity: &Entity) -> Vec<CallInfo> {
         let mut collector = CallCollector::new();
-        self.collect_calls_recursive(entity, &mut collector);
+        self.collect_calls_recursive(entity, ResultUsage::Discarded, &mut collector);
         collector.calls
     }
 
-    fn collect_calls_recursive(&self, entity: &Entity, collector: &mut CallCollector) {
+    /// Collect the calls under `entity`, whose value is used as `usage`
+    fn collect_calls_recursive(&self, entity: &Entity, usage: ResultUsage, collector: &mut CallCollector) {
         let kind = entity.get_kind();
 
-        // Handle different control flow constructs
+        // Handle different control flow constructs. Their bodies are statements,
+        // whose value is discarded, and their conditions are used.
         match kind {
             EntityKind::IfStmt => {
                 let children: Vec<_> = entity.get_children();
                 // IfStmt has: condition, then-branch, [else-branch]
                 if let Some(condition) = children.get(0) {
-                    self.collect_calls_recursive(condition, collector);
+                    self.collect_calls_recursive(condition, ResultUsage::Used, collector);
                 }
                 if let Some(then_branch) = children.get(1) {
                     collector.push_conditional();
-                    self.collect_calls_recursive(then_branch, collector);
+                    self.collect_calls_recursive(then_branch, ResultUsage::Discarded, collector);
                     collector.pop_context();
                 }
                 if let Some(else_branch) = children.get(2) {
                     collector.push_conditional();
-                    self.collect_calls_recursive(else_branch, collector);
+                    self.collect_calls_recursive(else_branch, ResultUsage::Discarded, collector);
                     collector.pop_context();
                 }
                 return;
             }
             EntityKind::WhileStmt | EntityKind::ForStmt | EntityKind::DoStmt => {
+                let children = entity.get_children();
+                // The body comes last, or first in a do-while
+                let body = if kind == EntityKind::DoStmt { 0 } else { children.len().saturating_sub(1) };
                 collector.push_loop();
-                for child in entity.get_children() {
-                    self.collect_calls_recursive(&child, collector);
+                for (index, child) in children.iter().enumerate() {
+                    let usage = if index == body { ResultUsage::Discarded } else { ResultUsage::Used };
+                    self.collect_calls_recursive(child, usage, collector);
                 }
                 collector.pop_context();
                 return;
@@ -328,68 +1589,886 @@ impl AstParser {
                 let children: Vec<_> = entity.get_children();
                 // First child is the condition
                 if let Some(condition) = children.get(0) {
-                    self.collect_calls_recursive(condition, collector);
+                    self.collect_calls_recursive(condition, ResultUsage::Used, collector);
                 }
                 // Rest are case statements
                 for child in children.iter().skip(1) {
-                    self.collect_calls_recursive(child, collector);
+                    self.collect_calls_recursive(child, ResultUsage::Discarded, collector);
                 }
                 return;
             }
             EntityKind::CaseStmt | EntityKind::DefaultStmt => {
-                collector.push_switch_case();
-                for child in entity.get_children() {
-                    self.collect_calls_recursive(&child, collector);
+                let children = entity.get_children();
+                // Labels naming enum constants or macros evaluate like literals
+                let value = children.first()
+                    .filter(|_| entity.get_kind() == EntityKind::CaseStmt)
+                    .and_then(|label| label.evaluate())
+                    .and_then(|result| match result {
+                        EvaluationResult::SignedInteger(value) => Some(value),
+                        EvaluationResult::UnsignedInteger(value) => Some(value as i64),
+                        _ => None,
+                    });
+                collector.push_switch_case(value);
+                for (index, child) in children.iter().enumerate() {
+                    let usage = if index + 1 == children.len() { ResultUsage::Discarded } else { ResultUsage::Used };
+                    self.collect_calls_recursive(child, usage, collector);
                 }
                 collector.pop_context();
                 return;
             }
             EntityKind::CallExpr => {
-                if let Some(referenced) = entity.get_reference() {
-                    if let Some(name) = referenced.get_name() {
-                        let (line, column) = entity.get_location()
-                            .map(|loc| {
-                                let file_loc = loc.get_file_location();
-                                (file_loc.line, file_loc.column)
-                            })
-                            .unwrap_or((0, 0));
-                        collector.add_call(name, line, column);
+                let (line, column) = entity.get_location()
+                    .map(|loc| {
+                        let file_loc = loc.get_file_location();
+                        (file_loc.line, file_loc.column)
+                    })
+                    .unwrap_or((0, 0));
+                let arguments = entity.get_arguments().unwrap_or_default();
+                let arg_types = || arguments
+                    .iter()
+                    .map(Self::argument_type)
+                    .collect();
+
+                let before = collector.calls.len();
+                if let Some(referenced) = entity.get_reference()
+                    && let Some(name) = referenced.get_name()
+                {
+                    if referenced.get_kind() == EntityKind::FunctionDecl {
+                        match Self::macro_call(entity, &name) {
+                            Some((macro_call, line, column)) => collector.add_macro_call(name, macro_call, line, column, arg_types()),
+                            None => collector.add_call(name, line, column, arg_types()),
+                        }
+                    } else if let Some(callee) = entity.get_children().first()
+                        && let Some(slot) = Self::pointer_slot(callee)
+                    {
+                        collector.add_indirect_call(name, slot, Self::spelling(callee), line, column, arg_types());
                     }
                 }
+                if collector.calls.len() > before
+                    && let Some(call) = collector.calls.last_mut()
+                {
+                    call.arguments = arguments.iter().map(Self::spelling).collect();
+                    call.result_usage = usage.clone();
+                }
             }
             _ => {}
         }
 
+        let children = entity.get_children();
+        for (index, child) in children.iter().enumerate() {
+            let child_usage = match kind {
+                EntityKind::CompoundStmt | EntityKind::LabelStmt => ResultUsage::Discarded,
+                EntityKind::ParenExpr | EntityKind::UnexposedExpr => usage.clone(),
+                EntityKind::CStyleCastExpr if entity.get_type().is_some_and(|t| t.get_kind() == TypeKind::Void) => ResultUsage::Discarded,
+                EntityKind::CStyleCastExpr => usage.clone(),
+                EntityKind::ReturnStmt => ResultUsage::Returned,
+                EntityKind::VarDecl => ResultUsage::Initializes(entity.get_name().unwrap_or_default()),
+                EntityKind::BinaryOperator if index == 1
+                    && Self::binary_operator(entity, &children[0]).as_deref() == Some("=") =>
+                {
+                    ResultUsage::Assigned(Self::spelling(&children[0]))
+                }
+                _ => ResultUsage::Used,
+            };
+            self.collect_calls_recursive(child, child_usage, collector);
+        }
+    }
+
+    /// A file-scope `typedef`
+    fn extract_typedef(entity : &Entity) -> Option<TypedefDefinition> {
+        if entity.get_kind() != EntityKind::TypedefDecl
+            || entity.get_semantic_parent().is_none_or(|parent| parent.get_kind() != EntityKind::TranslationUnit)
+        {
+            return None;
+        }
+        let (source_file, line) = Self::source_location(entity);
+        Some(TypedefDefinition {
+            name: entity.get_name()?,
+            target: Self::typed(&entity.get_typedef_underlying_type()?),
+            source_file,
+            line,
+            is_system: entity.get_location().is_some_and(|location| location.is_in_system_header()),
+        })
+    }
+
+    /// The definition of a function-like macro, split into its parameters
+    /// and replacement list
+    fn extract_macro(entity : &Entity) -> Option<MacroDefinition> {
+        if entity.get_kind() != EntityKind::MacroDefinition || !entity.is_function_like_macro() || entity.is_builtin_macro() {
+            return None;
+        }
+        let tokens : Vec<String> = entity.get_range()?.tokenize().iter().map(|token| token.get_spelling()).collect();
+        let tokens = rewriter::tokenize(&tokens.join(" "));
+        let close = rewriter::matching_close(&tokens, 1)?;
+        let parameters = rewriter::split_top_level(&tokens[2..close], ",")
+            .iter()
+            .map(|parameter| parameter.iter().map(|token| token.text.as_str()).collect::<String>())
+            .collect();
+        let (source_file, line) = Self::source_location(entity);
+        Some(MacroDefinition {
+            name: entity.get_name()?,
+            parameters,
+            replacement: tokens[close + 1..].iter().map(|token| token.text.as_str()).collect::<Vec<_>>().join(" "),
+            source_file,
+            line,
+        })
+    }
+
+    /// Describe a call spelled inside a macro definition, along with the
+    /// position of the macro invocation it should be attributed to. Calls
+    /// spelled in a macro argument are ordinary calls and yield `None`.
+    fn macro_call(entity : &Entity, callee : &str) -> Option<(MacroCall, u32, u32)> {
+        let location = entity.get_location()?;
+        let expansion = location.get_expansion_location();
+        let spelling = location.get_spelling_location();
+        let expansion_file = expansion.file?;
+        let spelling_path = spelling.file?.get_path();
+        if expansion_file.get_path() == spelling_path && expansion.offset == spelling.offset {
+            return None;
+        }
+
+        let invocation = expansion_file.get_offset_location(expansion.offset)
+            .get_entity()
+            .filter(|e| e.get_kind() == EntityKind::MacroExpansion)?;
+        let invocation_range = invocation.get_range()?;
+        let invocation_start = invocation_range.get_start().get_spelling_location();
+        let invocation_end = invocation_range.get_end().get_spelling_location();
+        let in_invocation = invocation_start.file.is_some_and(|f| f.get_path() == spelling_path)
+            && (invocation_start.offset..=invocation_end.offset).contains(&spelling.offset);
+        if in_invocation {
+            return None;
+        }
+
+        let definition = invocation.get_reference()?;
+        let definition_range = definition.get_range()?;
+        let definition_start = definition_range.get_start().get_spelling_location();
+        let definition_end = definition_range.get_end().get_spelling_location();
+        let in_definition = definition_start.file.is_some_and(|f| f.get_path() == spelling_path)
+            && (definition_start.offset..=definition_end.offset).contains(&spelling.offset);
+
+        let spell = |range : clang::source::SourceRange| range.tokenize()
+            .iter()
+            .map(|token| token.get_spelling())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let definition_tokens = rewriter::tokenize(&spell(definition_range));
+        let invocation_tokens = rewriter::tokenize(&spell(invocation_range));
+        // A call made by a macro the invoked one expands to cannot be
+        // replaced wholesale, but still comes from the invocation
+        let arguments = if in_definition {
+            Self::macro_call_arguments(&definition_tokens, &invocation_tokens, definition.is_function_like_macro(), callee)
+        } else {
+            None
+        };
+
+        let macro_call = MacroCall {
+            name: invocation.get_name().unwrap_or_default(),
+            function_like: definition.is_function_like_macro(),
+            invocation: invocation_tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>().join(" "),
+            arguments,
+            definition_file: definition_start.file.map(|file| file.get_path()),
+            definition_line: definition_start.line,
+        };
+        Some((macro_call, expansion.line, expansion.column))
+    }
+
+    /// Arguments of the call a macro expands to, when its replacement list
+    /// is exactly one call to `callee` (possibly parenthesized), with the
+    /// macro's parameters replaced by the invocation's arguments
+    fn macro_call_arguments(definition : &[rewriter::Token], invocation : &[rewriter::Token], function_like : bool, callee : &str) -> Option<Vec<String>> {
+        // Skip the macro name, and the parameter list of a function-like macro
+        let (parameters, mut body) = if function_like {
+            let close = rewriter::matching_close(definition, 1)?;
+            let parameters: Vec<&str> = rewriter::split_top_level(&definition[2..close], ",")
+                .iter()
+                .map(|p| match p {
+                    [name] => Some(name.text.as_str()),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            (parameters, &definition[close + 1..])
+        } else {
+            (Vec::new(), definition.get(1..)?)
+        };
+        if parameters.contains(&"...") || body.iter().any(|t| t.is("#") || t.is("##")) {
+            return None;
+        }
+        while body.first().is_some_and(|t| t.is("(")) && rewriter::matching_close(body, 0) == Some(body.len() - 1) {
+            body = &body[1..body.len() - 1];
+        }
+        if body.len() < 3 || !body[0].is(callee) || !body[1].is("(") || rewriter::matching_close(body, 1) != Some(body.len() - 1) {
+            return None;
+        }
+
+        let values: Vec<&[rewriter::Token]> = if function_like {
+            let close = rewriter::matching_close(invocation, 1)?;
+            rewriter::split_top_level(&invocation[2..close], ",")
+        } else {
+            Vec::new()
+        };
+        if values.len() != parameters.len() && !(parameters.is_empty() && values.is_empty()) {
+            return None;
+        }
+
+        let arguments = rewriter::split_top_level(&body[2..body.len() - 1], ",")
+            .iter()
+            .map(|argument| {
+                argument.iter()
+                    .map(|token| match parameters.iter().position(|p| token.is(p)) {
+                        Some(index) => values[index].iter().map(|t| t.text.as_str()).collect::<Vec<_>>().join(" "),
+                        None => token.text.clone(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        Some(arguments)
+    }
+
+    /// Record which functions every function pointer variable or field is
+    /// assigned, through initializers (including callback tables) and plain
+    /// `=` assignments. A pointer whose address is taken may be assigned
+    /// anything through it, so it gets an unknown target.
+    fn collect_pointer_assignments(entity : &Entity, db : &mut FunctionDatabase) {
+        match entity.get_kind() {
+            EntityKind::VarDecl => {
+                let initializer = entity.get_children().into_iter()
+                    .rfind(|c| c.is_expression() || c.get_kind() == EntityKind::InitListExpr);
+                if let (Some(initializer), Some(var_type), Some(usr)) = (initializer, entity.get_type(), entity.get_usr()) {
+                    if initializer.get_kind() == EntityKind::InitListExpr {
+                        Self::collect_initializer_targets(&initializer, var_type, db);
+                    } else if Self::is_function_pointer(var_type) {
+                        Self::add_pointer_target(db, &usr.0, &initializer);
+                    }
+                }
+            }
+            EntityKind::BinaryOperator => {
+                if let [lhs, rhs] = entity.get_children().as_slice()
+                    && Self::binary_operator(entity, lhs).as_deref() == Some("=")
+                    && lhs.get_type().is_some_and(Self::is_function_pointer)
+                    && let Some(slot) = Self::pointer_slot(lhs)
+                {
+                    Self::add_pointer_target(db, &slot, rhs);
+                }
+            }
+            EntityKind::UnaryOperator => {
+                let operator = entity.get_range()
+                    .and_then(|range| range.tokenize().first().map(|token| token.get_spelling()));
+                if operator.as_deref() == Some("&")
+                    && let Some(operand) = entity.get_children().first()
+                    && operand.get_type().is_some_and(Self::holds_function_pointers)
+                    && let Some(slot) = Self::pointer_slot(operand)
+                {
+                    db.add_pointer_target(&slot, None);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the functions of a file-scope array that looks like an interrupt
+    /// vector table: named after vectors, or placed in a vector section
+    fn collect_vector_table(entity : &Entity, db : &mut FunctionDatabase) {
+        if entity.get_kind() != EntityKind::VarDecl
+            || entity.get_semantic_parent().is_none_or(|parent| parent.get_kind() != EntityKind::TranslationUnit)
+            || !entity.get_type().is_some_and(|t| matches!(
+                t.get_canonical_type().get_kind(),
+                TypeKind::ConstantArray | TypeKind::IncompleteArray
+            ))
+        {
+            return;
+        }
+        let named_vector = entity.get_name().is_some_and(|name| name.to_lowercase().contains("vector"));
+        let in_vector_section = entity.get_range().is_some_and(|range| {
+            let tokens: Vec<String> = range.tokenize().iter().map(|token| token.get_spelling()).collect();
+            tokens.windows(3).any(|window| window[0] == "section"
+                && window[1] == "("
+                && ["vector", "isr"].iter().any(|key| window[2].contains(key)))
+        });
+        if !named_vector && !in_vector_section {
+            return;
+        }
+        let Some(initializer) = entity.get_children().into_iter().rfind(|c| c.get_kind() == EntityKind::InitListExpr) else {
+            return;
+        };
+        let mut handlers = Vec::new();
+        Self::collect_function_references(&initializer, &mut handlers);
+        for handler in handlers {
+            db.add_vector_table_entry(&handler);
+        }
+    }
+
+    fn collect_function_references(entity : &Entity, names : &mut Vec<String>) {
+        if entity.get_kind() == EntityKind::DeclRefExpr
+            && let Some(reference) = entity.get_reference()
+            && reference.get_kind() == EntityKind::FunctionDecl
+            && let Some(name) = reference.get_name()
+        {
+            names.push(name);
+        }
         for child in entity.get_children() {
-            self.collect_calls_recursive(&child, collector);
+            Self::collect_function_references(&child, names);
+        }
+    }
+
+    /// Pair the elements of a brace initializer with the fields or array
+    /// elements they initialize
+    fn collect_initializer_targets(init_list : &Entity, init_type : Type, db : &mut FunctionDatabase) {
+        let init_type = init_type.get_canonical_type();
+        let fields = init_type.get_fields();
+        let element_type = init_type.get_element_type();
+
+        for (position, element) in init_list.get_children().iter().enumerate() {
+            // Designated initializers expose the field they name as a MemberRef
+            let (field, value) = match element.get_children().as_slice() {
+                [designator, .., value] if designator.get_kind() == EntityKind::MemberRef =>
+                    (designator.get_reference(), *value),
+                _ => (fields.as_ref().and_then(|f| f.get(position).copied()), *element),
+            };
+            let target_type = match (&field, element_type) {
+                (Some(field), _) => field.get_type(),
+                (None, element_type) => element_type,
+            };
+            let Some(target_type) = target_type else {
+                continue;
+            };
+
+            if value.get_kind() == EntityKind::InitListExpr {
+                Self::collect_initializer_targets(&value, target_type, db);
+            } else if Self::is_function_pointer(target_type)
+                && let Some(usr) = field.and_then(|f| f.get_usr())
+            {
+                Self::add_pointer_target(db, &usr.0, &value);
+            }
         }
     }
 
-    pub fn extract_function_body(&self, entity : &Entity) -> Result<String> {
+    fn add_pointer_target(db : &mut FunctionDatabase, slot : &str, value : &Entity) {
+        let value = Self::strip_expression(value);
+        let target = match value.get_kind() {
+            // NULL leaves the pointer unset rather than pointing elsewhere
+            EntityKind::IntegerLiteral | EntityKind::GNUNullExpr => return,
+            EntityKind::DeclRefExpr => value.get_reference()
+                .filter(|r| r.get_kind() == EntityKind::FunctionDecl)
+                .and_then(|r| r.get_name()),
+            _ => None,
+        };
+        db.add_pointer_target(slot, target);
+    }
+
+    /// USR of the variable or field an lvalue (or called pointer) designates,
+    /// with array elements standing for their whole array
+    fn pointer_slot(expr : &Entity) -> Option<String> {
+        let expr = Self::strip_expression(expr);
+        match expr.get_kind() {
+            EntityKind::DeclRefExpr | EntityKind::MemberRefExpr => expr.get_reference()
+                .filter(|r| matches!(r.get_kind(), EntityKind::VarDecl | EntityKind::FieldDecl))
+                .and_then(|r| r.get_usr())
+                .map(|usr| usr.0),
+            EntityKind::ArraySubscriptExpr => expr.get_children().first().and_then(Self::pointer_slot),
+            _ => None,
+        }
+    }
+
+    /// Look through implicit casts, parentheses, casts and `&`/`*`
+    fn strip_expression<'tu>(expr : &Entity<'tu>) -> Entity<'tu> {
+        let mut expr = *expr;
+        while matches!(
+            expr.get_kind(),
+            EntityKind::UnexposedExpr | EntityKind::ParenExpr | EntityKind::CStyleCastExpr | EntityKind::UnaryOperator
+        ) {
+            match expr.get_children().last() {
+                Some(inner) => expr = *inner,
+                None => break,
+            }
+        }
+        expr
+    }
+
+    fn is_function_pointer(pointer_type : Type) -> bool {
+        let pointer_type = pointer_type.get_canonical_type();
+        pointer_type.get_kind() == TypeKind::Pointer
+            && pointer_type.get_pointee_type().is_some_and(|pointee| {
+                matches!(pointee.get_kind(), TypeKind::FunctionPrototype | TypeKind::FunctionNoPrototype)
+            })
+    }
+
+    /// A function pointer, or an array of them
+    fn holds_function_pointers(value_type : Type) -> bool {
+        let value_type = value_type.get_canonical_type();
+        match value_type.get_kind() {
+            TypeKind::ConstantArray | TypeKind::IncompleteArray => value_type.get_element_type()
+                .is_some_and(Self::holds_function_pointers),
+            _ => Self::is_function_pointer(value_type),
+        }
+    }
+
+    /// Spelling of the operator of a binary expression, found right after its
+    /// left operand
+    fn binary_operator(expr : &Entity, lhs : &Entity) -> Option<String> {
+        let lhs_len = lhs.get_range()?.tokenize().len();
+        expr.get_range()?.tokenize().get(lhs_len).map(|token| token.get_spelling())
+    }
+
+    /// The file-scope variable a `DeclRefExpr` designates
+    fn global_variable<'tu>(entity : &Entity<'tu>) -> Option<Entity<'tu>> {
+        if entity.get_kind() != EntityKind::DeclRefExpr {
+            return None;
+        }
+        entity.get_reference()
+            .filter(|r| r.get_kind() == EntityKind::VarDecl)
+            .filter(|variable| variable.get_semantic_parent().is_some_and(|p| p.get_kind() == EntityKind::TranslationUnit)
+                || variable.get_storage_class() == Some(StorageClass::Extern))
+    }
+
+    /// File-scope variables referenced under `entity`, in order of first use
+    fn collect_globals(entity : &Entity, globals : &mut Vec<GlobalVariable>) {
+        if let Some(variable) = Self::global_variable(entity)
+            && let Some(name) = variable.get_name()
+            && !globals.iter().any(|g| g.name == name)
+        {
+            let declaration = variable.get_definition().unwrap_or(variable);
+            globals.push(GlobalVariable {
+                name,
+                var_type: declaration.get_type().map(|t| t.get_display_name()).unwrap_or_else(|| "int".to_string()),
+                is_static: declaration.get_storage_class() == Some(StorageClass::Static),
+                // An array is volatile through its elements
+                is_volatile: declaration.get_type().is_some_and(|t| t.is_volatile_qualified()
+                    || t.get_element_type().is_some_and(|e| e.is_volatile_qualified())),
+                source_file: declaration.get_location()
+                    .and_then(|loc| loc.get_file_location().file.map(|f| f.get_path()))
+                    .unwrap_or_else(|| PathBuf::from("<unknown>")),
+            });
+        }
+        for child in entity.get_children() {
+            Self::collect_globals(&child, globals);
+        }
+    }
+
+    /// Sort the globals referenced under `entity` into reads and writes. A
+    /// write through a field or an array element writes the whole variable,
+    /// one through a pointer only reads the pointer.
+    fn collect_global_accesses(entity : &Entity, access : Access, reads : &mut BTreeSet<String>, writes : &mut BTreeSet<String>) {
+        if let Some(variable) = Self::global_variable(entity) {
+            if let Some(name) = variable.get_name() {
+                if access != Access::Write {
+                    reads.insert(name.clone());
+                }
+                if access != Access::Read {
+                    writes.insert(name);
+                }
+            }
+            return;
+        }
+
+        let children = entity.get_children();
+        // Look through implicit conversions, an array decaying to a pointer stays an array
+        let is_pointer = |e : &Entity| {
+            let mut e = *e;
+            while matches!(e.get_kind(), EntityKind::UnexposedExpr | EntityKind::ParenExpr)
+                && let [inner] = e.get_children().as_slice()
+            {
+                e = *inner;
+            }
+            e.get_type().is_some_and(|t| t.get_canonical_type().get_kind() == TypeKind::Pointer)
+        };
+        match (entity.get_kind(), children.as_slice()) {
+            (EntityKind::BinaryOperator, [lhs, rhs]) if Self::binary_operator(entity, lhs).as_deref() == Some("=") => {
+                Self::collect_global_accesses(lhs, Access::Write, reads, writes);
+                Self::collect_global_accesses(rhs, Access::Read, reads, writes);
+            }
+            (EntityKind::CompoundAssignOperator, [lhs, rhs]) => {
+                Self::collect_global_accesses(lhs, Access::ReadWrite, reads, writes);
+                Self::collect_global_accesses(rhs, Access::Read, reads, writes);
+            }
+            (EntityKind::UnaryOperator, [operand]) => {
+                let tokens : Vec<String> = entity.get_range()
+                    .map(|range| range.tokenize().iter().map(|token| token.get_spelling()).collect())
+                    .unwrap_or_default();
+                let modifies = [tokens.first(), tokens.last()].into_iter()
+                    .flatten()
+                    .any(|token| token == "++" || token == "--")
+                    || tokens.first().is_some_and(|token| token == "&");
+                let access = if modifies { Access::ReadWrite } else { Access::Read };
+                Self::collect_global_accesses(operand, access, reads, writes);
+            }
+            (EntityKind::MemberRefExpr, [base]) => {
+                let access = if is_pointer(base) { Access::Read } else { access };
+                Self::collect_global_accesses(base, access, reads, writes);
+            }
+            (EntityKind::ArraySubscriptExpr, [base, index]) => {
+                let access = if is_pointer(base) { Access::Read } else { access };
+                Self::collect_global_accesses(base, access, reads, writes);
+                Self::collect_global_accesses(index, Access::Read, reads, writes);
+            }
+            (EntityKind::ParenExpr | EntityKind::UnexposedExpr | EntityKind::CStyleCastExpr, _) => {
+                for child in &children {
+                    Self::collect_global_accesses(child, access, reads, writes);
+                }
+            }
+            _ => {
+                for child in &children {
+                    Self::collect_global_accesses(child, Access::Read, reads, writes);
+                }
+            }
+        }
+    }
+
+    fn spelling(entity : &Entity) -> String {
+        entity.get_range()
+            .map(|range| range.tokenize().iter().map(|token| token.get_spelling()).collect::<Vec<_>>().join(" "))
+            .unwrap_or_default()
+    }
+
+    /// Canonical type of a call argument as written, before the implicit
+    /// conversion to the parameter type
+    fn argument_type(argument: &Entity) -> String {
+        let mut expr = *argument;
+        // Implicit casts are exposed as single-child UnexposedExpr nodes
+        while expr.get_kind() == EntityKind::UnexposedExpr {
+            match expr.get_children().as_slice() {
+                [inner] => expr = *inner,
+                _ => break,
+            }
+        }
+        expr.get_type()
+            .map(|t| t.get_canonical_type().get_display_name())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// The body as its tokens joined by spaces, except for inline assembly,
+    /// which is copied from the source as written. Preprocessor directives
+    /// and the branches of `#if` chains that were not compiled are left out
+    /// of the text and recorded on their own.
+    fn function_body(entity : &Entity) -> ExtractedBody {
+        let Some(body) = entity.get_children().into_iter().find(|child| child.get_kind() == EntityKind::CompoundStmt) else {
+            return ExtractedBody::default();
+        };
+        let Some(body_range) = body.get_range() else {
+            return ExtractedBody::default();
+        };
+        let start = body_range.get_start().get_spelling_location();
+        let end = body_range.get_end().get_spelling_location().offset as usize;
+        let contents = start.file.and_then(|file| file.get_contents()).unwrap_or_default();
+        let raw = contents.get(start.offset as usize..end).unwrap_or_default().to_string();
+        let directives = Self::directive_lines(&contents, start.offset as usize..end);
+        let skipped : Vec<Range<usize>> = start.file
+            .map(|file| file.get_skipped_ranges())
+            .unwrap_or_default()
+            .iter()
+            .map(|range| range.get_start().get_file_location().offset as usize..range.get_end().get_file_location().offset as usize)
+            .collect();
+
+        let mut asm_blocks = Vec::new();
+        Self::collect_asm_blocks(&body, &mut asm_blocks);
+        let mut extracted = ExtractedBody { raw, ..Default::default() };
+        let mut conditionals = ConditionalTracker::default();
+        let mut next_directive = directives.iter().peekable();
+        // Offset in the file where the last invocation seen ends
+        let mut expansion_end = 0;
+        for token in body_range.tokenize() {
+            let location = token.get_location();
+            let offset = location.get_spelling_location().offset as usize;
+            while let Some(directive) = next_directive.next_if(|directive| directive.range.start <= offset) {
+                conditionals.directive(directive, &contents, &skipped, extracted.text.len());
+            }
+            let in_directive = directives.iter().any(|directive| directive.range.contains(&offset));
+            let is_skipped = skipped.iter().any(|range| range.contains(&offset));
+            if in_directive || is_skipped {
+                continue;
+            }
+
+            let text = match asm_blocks.iter().find(|(range, _)| range.contains(&offset)) {
+                Some((range, raw)) if range.start == offset => raw.clone(),
+                Some(_) => continue,
+                None => token.get_spelling(),
+            };
+            if !extracted.text.is_empty() {
+                extracted.text.push(' ');
+            }
+            let start = extracted.text.len();
+            extracted.text.push_str(&text);
+
+            if offset < expansion_end && let Some(expansion) = extracted.macro_expansions.last_mut() {
+                expansion.span.end = extracted.text.len();
+            } else if token.get_kind() == TokenKind::Identifier
+                && let Some(invocation) = location.get_entity().filter(|e| e.get_kind() == EntityKind::MacroExpansion)
+                && let Some(range) = invocation.get_range()
+            {
+                let position = location.get_file_location();
+                expansion_end = range.get_end().get_spelling_location().offset as usize;
+                extracted.macro_expansions.push(MacroExpansion {
+                    name: text,
+                    line: position.line,
+                    column: position.column,
+                    span: start..extracted.text.len(),
+                });
+            }
+        }
+        for directive in next_directive {
+            conditionals.directive(directive, &contents, &skipped, extracted.text.len());
+        }
+        extracted.conditionals = conditionals.finished;
+        extracted
+    }
+
+    /// The preprocessor directives in `range` of a file's contents, each
+    /// spanning its continuation lines
+    fn directive_lines(contents : &str, range : Range<usize>) -> Vec<DirectiveLine> {
+        let mut directives = Vec::new();
+        let mut offset = range.start;
+        let text = contents.get(range.clone()).unwrap_or_default();
+        let mut lines = text.split_inclusive('\n');
+        while let Some(line) = lines.next() {
+            let start = offset;
+            offset += line.len();
+            let Some(rest) = line.trim_start().strip_prefix('#') else {
+                continue;
+            };
+            let mut spelling = rest.trim_end().to_string();
+            while spelling.ends_with('\\') && let Some(continued) = lines.next() {
+                offset += continued.len();
+                spelling.pop();
+                spelling.push(' ');
+                spelling.push_str(continued.trim());
+            }
+            let spelling = format!("#{}", spelling.trim());
+            let keyword = spelling[1..].split(|c : char| !c.is_ascii_alphanumeric() && c != '_').find(|word| !word.is_empty()).unwrap_or_default().to_string();
+            directives.push(DirectiveLine { range: start..offset.min(range.end), spelling, keyword });
+        }
+        directives
+    }
+
+    /// Source text and byte range of every inline assembly statement under
+    /// `entity` that is written out in the function rather than in a macro
+    fn collect_asm_blocks(entity : &Entity, blocks : &mut Vec<(Range<usize>, String)>) {
+        if matches!(entity.get_kind(), EntityKind::AsmStmt | EntityKind::MsAsmStmt) {
+            let is_direct = entity.get_location()
+                .is_some_and(|loc| loc.get_spelling_location() == loc.get_expansion_location());
+            if let Some(range) = entity.get_range().filter(|_| is_direct) {
+                let start = range.get_start().get_spelling_location();
+                let end = range.get_end().get_spelling_location().offset as usize;
+                let contents = start.file.and_then(|file| file.get_contents());
+                let raw = contents.as_deref().and_then(|text| text.get(start.offset as usize..end));
+                // Keep the clang tokens unless the text reads back as one statement
+                if let Some(raw) = raw.filter(|raw| rewriter::tokenize(raw).len() == 1) {
+                    blocks.push((start.offset as usize..end, raw.to_string()));
+                }
+            }
+            return;
+        }
         for child in entity.get_children() {
-            if child.get_kind() == EntityKind::CompoundStmt {
-                if let Some(body_range) = child.get_range() {
-                    let body = body_range.tokenize()
-                        .iter()
-                        .map(|token| {
-                            token.get_spelling()
-                        })
-                    .collect::<Vec<String>>()
-                        .join(" ");
-                    return Ok(body);
+            Self::collect_asm_blocks(&child, blocks);
+        }
+    }
+
+    /// Attributes of a function, from its definition and its first
+    /// declaration, which is where headers usually put them. Clang drops the
+    /// attributes the host target does not know, so the tokens are read.
+    fn function_attributes(entity : &Entity) -> FunctionAttributes {
+        let mut attributes = FunctionAttributes::default();
+        let canonical = entity.get_canonical_entity();
+        let declarations = if canonical == *entity { vec![*entity] } else { vec![canonical, *entity] };
+        for declaration in declarations {
+            let Some(range) = declaration.get_range() else {
+                continue;
+            };
+            let tokens: Vec<String> = range.tokenize().iter()
+                .map(|token| token.get_spelling())
+                .take_while(|token| token != "{")
+                .collect();
+            Self::parse_attributes(&tokens, &mut attributes);
+        }
+        attributes
+    }
+
+    /// Read `_Noreturn` and the entries of every `__attribute__((...))`
+    fn parse_attributes(tokens : &[String], attributes : &mut FunctionAttributes) {
+        for (start, token) in tokens.iter().enumerate() {
+            if token == "_Noreturn" {
+                attributes.noreturn = true;
+            }
+            // Macros from the CubeMX and CMSIS headers, as spelled in the source
+            if token == "__weak" || token == "__WEAK" {
+                attributes.weak = true;
+            }
+            if token != "__attribute__" && token != "__attribute" {
+                continue;
+            }
+            // Entries sit at depth 2, their arguments deeper
+            let mut depth = 0;
+            let mut entry: Vec<&str> = Vec::new();
+            for token in &tokens[start + 1..] {
+                match token.as_str() {
+                    "(" => {
+                        depth += 1;
+                        if depth > 2 {
+                            entry.push(token);
+                        }
+                    }
+                    ")" => {
+                        depth -= 1;
+                        if depth >= 2 {
+                            entry.push(token);
+                        } else {
+                            Self::apply_attribute(&entry, attributes);
+                            entry.clear();
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                    }
+                    "," if depth == 2 => {
+                        Self::apply_attribute(&entry, attributes);
+                        entry.clear();
+                    }
+                    _ if depth >= 2 => entry.push(token),
+                    _ => {}
                 }
             }
         }
-        Ok(String::new())
+    }
+
+    fn apply_attribute(entry : &[&str], attributes : &mut FunctionAttributes) {
+        let Some(name) = entry.first() else {
+            return;
+        };
+        match name.trim_matches('_') {
+            "noreturn" => attributes.noreturn = true,
+            "weak" => attributes.weak = true,
+            "always_inline" => attributes.always_inline = true,
+            "noinline" => attributes.noinline = true,
+            "interrupt" | "isr" | "interrupt_handler" => attributes.interrupt = true,
+            "section" => {
+                attributes.section = entry.iter()
+                    .find(|token| token.starts_with('"'))
+                    .map(|literal| literal.trim_matches('"').to_string());
+            }
+            "alias" => {
+                attributes.alias = entry.iter()
+                    .find(|token| token.starts_with('"'))
+                    .map(|literal| literal.trim_matches('"').to_string());
+            }
+            _ => {}
+        }
+    }
+
+    fn declares_vla(entity : &Entity) -> bool {
+        let is_vla = entity.get_kind() == EntityKind::VarDecl
+            && entity.get_type().is_some_and(|t| t.get_canonical_type().get_kind() == TypeKind::VariableArray);
+        is_vla || entity.get_children().iter().any(Self::declares_vla)
+    }
+
+    /// Variables declared in a function body. Variable-length arrays have a
+    /// size of zero, `uses_vla` flags them.
+    fn collect_locals(entity : &Entity, locals : &mut Vec<LocalVar>) {
+        if entity.get_kind() == EntityKind::VarDecl
+            && entity.get_storage_class() != Some(StorageClass::Extern)
+            && let Some(name) = entity.get_name()
+        {
+            let location = entity.get_location().map(|loc| loc.get_spelling_location());
+            locals.push(LocalVar {
+                name,
+                var_type: entity.get_type().map(|t| t.get_display_name()).unwrap_or_else(|| "int".to_string()),
+                is_static: entity.get_storage_class() == Some(StorageClass::Static),
+                line: location.as_ref().map(|loc| loc.line).unwrap_or(0),
+                column: location.as_ref().map(|loc| loc.column).unwrap_or(0),
+                size: entity.get_type()
+                    .map(|t| {
+                        let size = t.get_sizeof().unwrap_or(0);
+                        let align = t.get_alignof().unwrap_or(1).max(1);
+                        size.div_ceil(align) * align
+                    })
+                    .unwrap_or(0) as u32,
+                ty: entity.get_type().map(|t| Self::typed(&t)).unwrap_or_default(),
+            });
+        }
+        for child in entity.get_children() {
+            Self::collect_locals(&child, locals);
+        }
+    }
+
+    /// Statements and expressions that split the control flow, `&&` and `||` aside
+    fn count_branches(entity : &Entity) -> u32 {
+        let own = matches!(
+            entity.get_kind(),
+            EntityKind::IfStmt
+                | EntityKind::WhileStmt
+                | EntityKind::ForStmt
+                | EntityKind::DoStmt
+                | EntityKind::CaseStmt
+                | EntityKind::ConditionalOperator
+        );
+        own as u32 + entity.get_children().iter().map(Self::count_branches).sum::<u32>()
+    }
+
+    fn contains_asm(entity : &Entity) -> bool {
+        matches!(entity.get_kind(), EntityKind::AsmStmt | EntityKind::MsAsmStmt)
+            || entity.get_children().iter().any(Self::contains_asm)
     }
 
     /// Extract only -D (defines) and -I (includes) flags, which are the only ones
     /// that affect AST parsing. This avoids GCC/ARM-specific flag incompatibilities.
-    fn extract_compatible_flags(args: &[String]) -> Vec<String> {
-        args.iter()
-            .filter(|arg| arg.starts_with("-D") || arg.starts_with("-I"))
-            .map(|s| s.clone())
-            .collect()
+    /// The arguments of a command clang can use: all but the compiler, the
+    /// source file and `DROPPED_FLAGS`, with relative paths made absolute
+    /// against the command's directory so parsing does not depend on the
+    /// current directory. `reduced` keeps the defines and include paths only.
+    fn clang_arguments(&self, command : &CompileCommand, reduced : bool) -> Vec<String> {
+        let directory = self.command_directory(command);
+        let file = self.command_file(command);
+        let absolute = |path : &str| directory.join(path).display().to_string();
+
+        let mut arguments = Vec::new();
+        let mut args = command.get_arguments().into_iter().skip(1);
+        while let Some(arg) = args.next() {
+            if DROPPED_WITH_VALUE.contains(&arg.as_str()) {
+                args.next();
+                continue;
+            }
+            if Self::is_dropped(&arg) {
+                continue;
+            }
+            if !arg.starts_with('-') && Self::normalize_path(&PathBuf::from(&arg), &directory) == file {
+                continue;
+            }
+            if reduced && (arg == "-D" || arg == "-U") {
+                if let Some(value) = args.next() {
+                    arguments.push(arg);
+                    arguments.push(value);
+                }
+                continue;
+            }
+            if PATH_FLAGS.contains(&arg.as_str()) {
+                if let Some(path) = args.next() {
+                    arguments.push(arg);
+                    arguments.push(absolute(&path));
+                }
+                continue;
+            }
+            let joined = PATH_FLAGS.iter().find_map(|flag| Some((*flag, arg.strip_prefix(flag)?)));
+            match joined {
+                Some(("--sysroot", path)) if path.starts_with('=') => arguments.push(format!("--sysroot={}", absolute(&path[1..]))),
+                Some((flag, path)) if flag != "--sysroot" => arguments.push(format!("{}{}", flag, absolute(path))),
+                _ if !reduced || arg.starts_with("-D") || arg.starts_with("-U") => arguments.push(arg),
+                _ => {}
+            }
+        }
+        arguments
+    }
+
+    fn is_dropped(arg : &str) -> bool {
+        DROPPED_FLAGS.iter().any(|dropped| match dropped.strip_suffix('*') {
+            Some(prefix) => arg.starts_with(prefix),
+            None => arg == *dropped,
+        })
     }
 
 }
+
+impl Drop for AstParser {
+    fn drop(&mut self) {
+        if self.owns_pch_dir && self.pch_dir.exists() {
+            let _ = std::fs::remove_dir_all(&self.pch_dir);
+        }
+    }
+}
@@ -1,52 +1,723 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, path::{Path, PathBuf}};
+use std::ops::Range;
 use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::types::{DataModel, Type, TypeTable, TypedefDefinition};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Signature {
     pub name : String,
     pub return_type : String,
+    pub canonical_return_type : String,
+    /// The return type as a typed model; `return_type` is its spelling
+    pub return_ty : Type,
     pub args : Vec<Parameter>,
     pub is_variadic : bool,
+    /// Declared without a prototype, as K&R definitions like
+    /// `int f(a, b) int a; char *b; { ... }` are. Callers then pass every
+    /// argument with the default promotions.
+    pub is_old_style : bool,
+}
+
+impl Signature {
+    /// Render the signature as a C function declarator, without trailing `;`.
+    /// K&R definitions come out in prototype form.
+    pub fn to_c(&self) -> String {
+        let mut params: Vec<String> = self.args.iter()
+            .map(|arg| arg.ty.to_c_string(arg.name.as_deref()))
+            .collect();
+        if self.is_variadic {
+            params.push("...".to_string());
+        } else if params.is_empty() {
+            params.push("void".to_string());
+        }
+        self.return_ty.to_c_string(Some(&format!("{}({})", self.name, params.join(", "))))
+    }
+
+    /// The signature as a declaration other files can use: without
+    /// parameters for a K&R definition, since a prototype would change how
+    /// its callers pass small integers and `float`
+    pub fn to_c_declaration(&self) -> String {
+        if self.is_old_style {
+            self.return_ty.to_c_string(Some(&format!("{}()", self.name)))
+        } else {
+            self.to_c()
+        }
+    }
+
+    /// The signature heading a definition, K&R definitions kept in their
+    /// own form so that callers without a prototype stay correct
+    pub fn to_c_definition(&self) -> String {
+        if !self.is_old_style || self.args.is_empty() {
+            return self.to_c();
+        }
+        let names : Vec<&str> = self.args.iter().filter_map(|arg| arg.name.as_deref()).collect();
+        let mut out = self.return_ty.to_c_string(Some(&format!("{}({})", self.name, names.join(", "))));
+        for arg in &self.args {
+            if let Some(name) = &arg.name {
+                out.push_str(&format!("\n{};", arg.ty.to_c_string(Some(name))));
+            }
+        }
+        out
+    }
+
+    /// Whether two declarators of the same function have compatible return
+    /// and parameter types under the C rules of `Type::is_compatible_with`,
+    /// and agree on `...`. Parameter names do not matter. A prototype is
+    /// compared with a K&R definition's parameters after their default
+    /// argument promotions, so `int f(char);` does not match
+    /// `int f(c) char c;` but `int f(int);` does.
+    pub fn is_compatible(&self, other : &Signature) -> bool {
+        let compatible = |a : &Parameter, b : &Parameter| match (self.is_old_style, other.is_old_style) {
+            (true, false) => a.ty.promoted().is_compatible_parameter(&b.ty),
+            (false, true) => a.ty.is_compatible_parameter(&b.ty.promoted()),
+            _ => a.ty.is_compatible_parameter(&b.ty),
+        };
+        self.return_ty.is_compatible_with(&other.return_ty)
+            && self.is_variadic == other.is_variadic
+            && self.args.len() == other.args.len()
+            && self.args.iter().zip(&other.args).all(|(a, b)| compatible(a, b))
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Parameter {
     pub name : Option<String>,
     pub param_type : String,
+    pub canonical_type : String,
+    /// The type as a typed model; `param_type` is its spelling
+    pub ty : Type,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct Definition {
     pub signature : Signature,
     pub body : String,
     pub source_file : PathBuf,
+    pub line : u32,
+    /// Byte range of the whole definition in `source_file`, return type included
+    pub extent : Range<usize>,
     pub is_static : bool,
+    /// Declared `inline`, as the `static inline` functions of headers are
+    pub is_inline : bool,
+    /// The body holds inline assembly, kept verbatim as a single token
+    pub contains_asm : bool,
+    /// The body declares variable-length arrays
+    pub uses_vla : bool,
+    /// The body allocates with `alloca`, which lasts until the function returns
+    pub uses_alloca : bool,
+    pub attributes : FunctionAttributes,
     pub calls : Vec<CallInfo>,
+    /// Variables declared in the body, in order of declaration
+    pub locals : Vec<LocalVar>,
+    /// File-scope variables the body refers to, each listed once
+    pub globals : Vec<GlobalVariable>,
+    /// Names of the globals whose value the body reads
+    pub global_reads : BTreeSet<String>,
+    /// Names of the globals the body assigns, increments, or takes the
+    /// address of, through any field or array element
+    pub global_writes : BTreeSet<String>,
+    pub metrics : FunctionMetrics,
+    /// Macro invocations written in the body, in order
+    pub macro_expansions : Vec<MacroExpansion>,
+    /// The body as written in the source, directives and code that was not
+    /// compiled included
+    pub raw_body : String,
+    /// `#if` chains written in the body, in order
+    pub conditionals : Vec<PreprocessorConditional>,
+    /// The `/** ... */`, `/* ... */` or `//` comment block right above the
+    /// definition, as written
+    pub doc_comment : Option<String>,
+    /// Hash of the signature and body, tokens separated by single spaces, so
+    /// that only a change to the code itself changes it. Equal for the copies
+    /// of a header function seen by units compiling it under the same macros.
+    pub body_hash : u64,
+    /// The body calls the function itself directly
+    pub is_recursive : bool,
+}
+
+/// Attributes of a function that change how it may be inlined or placed,
+/// from `__attribute__((...))` and `_Noreturn`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct FunctionAttributes {
+    pub noreturn : bool,
+    /// A strong definition elsewhere replaces this one at link time
+    pub weak : bool,
+    pub always_inline : bool,
+    pub noinline : bool,
+    /// `interrupt`, `isr` or `interrupt_handler`, whatever the target
+    pub interrupt : bool,
+    /// Linker section the function is placed in
+    pub section : Option<String>,
+    /// Function this declaration is another name for
+    pub alias : Option<String>,
+}
+
+impl FunctionAttributes {
+    /// Names of the attributes set, in source spelling
+    pub fn names(&self) -> Vec<String> {
+        let flags = [
+            (self.noreturn, "noreturn"),
+            (self.weak, "weak"),
+            (self.always_inline, "always_inline"),
+            (self.noinline, "noinline"),
+            (self.interrupt, "interrupt"),
+        ];
+        let mut names: Vec<String> = flags.iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name.to_string())
+            .collect();
+        if let Some(section) = &self.section {
+            names.push(format!("section(\"{}\")", section));
+        }
+        if let Some(alias) = &self.alias {
+            names.push(format!("alias(\"{}\")", alias));
+        }
+        names
+    }
+}
+
+/// Size and complexity of a definition, measured while parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct FunctionMetrics {
+    /// Source lines of the whole definition, signature included
+    pub lines : u32,
+    /// Decision points: `if`, loops, `case` labels, `?:`, `&&` and `||`
+    pub branches : u32,
+    pub call_count : u32,
+    /// Bytes of the automatic local variables, each rounded up to its
+    /// alignment. Blocks are not assumed to share space, so this is an upper
+    /// bound of the frame without saved registers or spills.
+    pub frame_size : u32,
+}
+
+impl FunctionMetrics {
+    /// McCabe's cyclomatic complexity, one more than the decision points
+    pub fn cyclomatic_complexity(&self) -> u32 {
+        self.branches + 1
+    }
+}
+
+impl Definition {
+    /// Bytes of stack the locals take, under `model` when given and as clang
+    /// laid them out for the parsed target otherwise
+    pub fn frame_size(&self, model : Option<DataModel>) -> usize {
+        match model {
+            Some(model) => self.locals.iter()
+                .filter(|local| !local.is_static)
+                .map(|local| local.size_in(model) as usize)
+                .sum(),
+            None => self.metrics.frame_size as usize,
+        }
+    }
+
+    /// Whether the function is defined in a header, so that every translation
+    /// unit including it compiles its own copy
+    pub fn in_header(&self) -> bool {
+        self.source_file.extension().is_some_and(|extension| extension == "h")
+    }
+
+    /// The text of `doc_comment` without its comment markers, one entry per line
+    pub fn doc_lines(&self) -> Vec<String> {
+        let Some(comment) = &self.doc_comment else {
+            return Vec::new();
+        };
+        let lines : Vec<String> = comment.lines()
+            .map(|line| {
+                let line = line.trim();
+                let line = line.strip_prefix("/**").or_else(|| line.strip_prefix("/*!")).or_else(|| line.strip_prefix("/*"))
+                    .or_else(|| line.strip_prefix("///")).or_else(|| line.strip_prefix("//!")).or_else(|| line.strip_prefix("//"))
+                    .unwrap_or(line);
+                let line = line.strip_suffix("*/").unwrap_or(line).trim();
+                line.strip_prefix('*').unwrap_or(line).trim().to_string()
+            })
+            .collect();
+        let start = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+        let end = lines.iter().rposition(|line| !line.is_empty()).map_or(start, |end| end + 1);
+        lines[start..end].to_vec()
+    }
+
+    /// The first paragraph of the doc comment, on one line, without a
+    /// leading `@brief` or `\brief`
+    pub fn brief(&self) -> Option<String> {
+        let lines = self.doc_lines();
+        let paragraph : Vec<&str> = lines.iter()
+            .map(|line| line.trim_start_matches("@brief").trim_start_matches("\\brief").trim())
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| !line.is_empty() && !line.starts_with('@') && !line.starts_with('\\'))
+            .collect();
+        (!paragraph.is_empty()).then(|| paragraph.join(" "))
+    }
+
+    /// Approximate size of the body, in tokens. An inline assembly
+    /// statement counts as one.
+    pub fn body_size(&self) -> usize {
+        if self.contains_asm {
+            return crate::rewriter::tokenize(&self.body).len();
+        }
+        self.body.split_whitespace().count()
+    }
+
+    /// Whether this function only forwards its parameters to another one,
+    /// as in `{ return HAL_Foo ( handle , 0 ) ; }`. Every parameter has to be
+    /// passed on exactly once so the call can stand in for the wrapper.
+    pub fn as_wrapper(&self) -> Option<Wrapper> {
+        let [call] = self.calls.as_slice() else {
+            return None;
+        };
+        if call.via.is_some() || call.macro_call.is_some() || self.signature.is_variadic {
+            return None;
+        }
+
+        let tokens = crate::rewriter::tokenize(&self.body);
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        let inner = match texts.as_slice() {
+            ["{", "return", inner @ .., ";", "}"] | ["{", inner @ .., ";", "}"] => inner,
+            _ => return None,
+        };
+        let [name, "(", .., ")"] = inner else {
+            return None;
+        };
+        if *name != call.function_name {
+            return None;
+        }
+
+        let offset = texts.len() - inner.len() - 2;
+        let argument_tokens = &tokens[offset + 2..offset + inner.len() - 1];
+        let mut used = vec![false; self.signature.args.len()];
+        let mut arguments = Vec::new();
+        for argument in crate::rewriter::split_top_level(argument_tokens, ",") {
+            let [token] = argument else {
+                return None;
+            };
+            let parameter = self.signature.args.iter()
+                .position(|arg| arg.name.as_deref() == Some(token.text.as_str()));
+            match parameter {
+                Some(index) if used[index] => return None,
+                Some(index) => {
+                    used[index] = true;
+                    arguments.push(ForwardedArgument::Parameter(index));
+                }
+                None => arguments.push(ForwardedArgument::Constant(token.text.clone())),
+            }
+        }
+        if used.contains(&false) {
+            return None;
+        }
+
+        Some(Wrapper { callee: call.function_name.clone(), arguments })
+    }
+}
+
+/// A declaration of a function without its body, usually a prototype in a
+/// header
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Declaration {
+    pub signature : Signature,
+    pub source_file : PathBuf,
+    pub line : u32,
+    pub is_static : bool,
+    pub attributes : FunctionAttributes,
+    /// Unset for old-style declarations like `int f();`, which say nothing
+    /// about the parameters
+    pub has_prototype : bool,
+}
+
+/// Which definition a weak or aliased name ends up linked to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SymbolResolution {
+    pub name : String,
+    /// The definition calls to `name` reach, none when an alias points to a
+    /// function defined outside the project
+    pub winner : Option<Arc<Definition>>,
+    /// Files of the weak definitions or alias declarations that lost
+    pub replaced : Vec<PathBuf>,
+    /// Set when `name` was declared as an alias of this function
+    pub alias_of : Option<String>,
+}
+
+/// A declaration disagreeing with the definition it links to
+#[derive(Debug, Clone)]
+pub struct SignatureMismatch {
+    pub declaration : Declaration,
+    pub definition : Arc<Definition>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+/// A variable declared inside a function body
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct LocalVar {
+    pub name : String,
+    pub var_type : String,
+    /// Declared `static`, so it outlives the call and takes no stack
+    pub is_static : bool,
+    pub line : u32,
+    pub column : u32,
+    /// Layout size in bytes rounded up to the alignment, 0 when unknown or
+    /// sized at run time
+    pub size : u32,
+    pub ty : Type,
+}
+
+impl LocalVar {
+    /// `size` under another data model; clang's size when the type cannot
+    /// be laid out without the target
+    pub fn size_in(&self, model : DataModel) -> u32 {
+        self.ty.size_and_align(model)
+            .map_or(self.size, |(size, align)| size.next_multiple_of(align) as u32)
+    }
+}
+
+/// A variable declared at file scope, as seen from a function using it
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct GlobalVariable {
+    pub name : String,
+    pub var_type : String,
+    pub is_static : bool,
+    pub is_volatile : bool,
+    /// File holding the definition, or the declaration when none was seen
+    pub source_file : PathBuf,
+}
+
+impl GlobalVariable {
+    /// Declaration making the variable visible from another translation unit
+    pub fn to_extern(&self) -> String {
+        format!("extern {};", crate::rewriter::declarator(&self.var_type, &self.name))
+    }
+}
+
+/// What a wrapper passes on in each argument position of the call it makes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardedArgument {
+    /// The wrapper's own parameter at this index
+    Parameter(usize),
+    /// A constant or global, spelled as in the wrapper
+    Constant(String),
+}
+
+/// A function whose body is a single call forwarding its parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wrapper {
+    pub callee : String,
+    pub arguments : Vec<ForwardedArgument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum CallContext {
     #[default]
     Sequential,
     /// Inside an if/else-if condition or body, with branch index
     Conditional { branch_id: u32 },
     Loop,
-    Switch { case_id: u32 },
+    /// Under a case label, with the label's value when it is a constant
+    /// clang could evaluate, as it is for `default`
+    Switch { case_id: u32, value: Option<i64> },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+/// What the caller does with the value a call returns
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum ResultUsage {
+    /// The call is a statement of its own, or cast to `void`
+    Discarded,
+    /// Assigned with `=` to this lvalue
+    Assigned(String),
+    /// Initializes this variable
+    Initializes(String),
+    Returned,
+    /// Part of a larger expression: an operand, an argument or a condition
+    #[default]
+    Used,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct CallInfo {
     pub function_name : String,
     pub line : u32,
     pub column : u32,
+    /// Canonical types of the arguments as written, before conversion
+    pub arg_types : Vec<String>,
+    /// Argument expressions as written, tokens separated by spaces
+    pub arguments : Vec<String>,
+    pub result_usage : ResultUsage,
     pub order: u32,
     pub context: CallContext,
     pub context_depth: u32,
+    /// USR of the pointer variable or field an indirect call goes through
+    pub via : Option<String>,
+    /// Spelling of the callee expression of an indirect call, e.g. `ops -> run`
+    pub callee_expr : Option<String>,
+    /// Set when the call comes from the body of a macro rather than the source
+    pub macro_call : Option<MacroCall>,
+}
+
+/// A call produced by expanding a macro, like `HAL_GPIO_TOGGLE ( )`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct MacroCall {
+    /// The macro invoked in the caller's body. When it expands to another
+    /// macro making the call, this is still the outer one.
+    pub name : String,
+    pub function_like : bool,
+    /// Spelling of the whole invocation in the caller's body
+    pub invocation : String,
+    /// Arguments of the call with the macro's parameters substituted, when the
+    /// macro expands to exactly that one call and can be replaced wholesale
+    pub arguments : Option<Vec<String>>,
+    /// Where `name` is defined, which is where clang spells the call when the
+    /// macro makes it directly
+    pub definition_file : Option<PathBuf>,
+    pub definition_line : u32,
+}
+
+impl MacroCall {
+    /// How the call is made, e.g. `via macro HAL_GPIO_TOGGLE()`
+    pub fn label(&self) -> String {
+        format!("via macro {}{}", self.name, if self.function_like { "()" } else { "" })
+    }
+}
+
+/// A function-like macro of the project, from the preprocessing record
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct MacroDefinition {
+    pub name : String,
+    /// Parameter names, the last one `...` for a variadic macro
+    pub parameters : Vec<String>,
+    /// Replacement list, tokens joined by spaces
+    pub replacement : String,
+    pub source_file : PathBuf,
+    pub line : u32,
+}
+
+impl MacroDefinition {
+    pub fn is_variadic(&self) -> bool {
+        self.parameters.last().is_some_and(|parameter| parameter == "...")
+    }
+
+    /// The macro as a `#define` line
+    pub fn to_c(&self) -> String {
+        format!("#define {}({}) {}", self.name, self.parameters.join(", "), self.replacement)
+    }
+}
+
+/// A macro invocation in a function body
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct MacroExpansion {
+    pub name : String,
+    pub line : u32,
+    pub column : u32,
+    /// Byte range of the invocation, arguments included, in the body text
+    pub span : Range<usize>,
+}
+
+/// An `#if`/`#ifdef`/`#ifndef` chain in a function body, down to its `#endif`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct PreprocessorConditional {
+    pub line : u32,
+    pub branches : Vec<ConditionalBranch>,
+    /// Byte offset in the body text where the `#endif` stood
+    pub end : usize,
+}
+
+/// One branch of a preprocessor conditional
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct ConditionalBranch {
+    /// The directive opening the branch, e.g. `#ifdef DEBUG` or `#else`
+    pub directive : String,
+    /// Whether the branch was compiled under the flags of its unit
+    pub active : bool,
+    /// Source of the branch when it was not compiled, as it is absent from
+    /// the body text
+    pub text : String,
+    /// Byte offset in the body text where the directive stood
+    pub offset : usize,
+}
+
+/// Environment variables that affect how a compiler finds headers and tools
+pub const CAPTURED_ENVIRONMENT: &[&str] = &[
+    "PATH",
+    "CPATH",
+    "C_INCLUDE_PATH",
+    "CPLUS_INCLUDE_PATH",
+    "GCC_EXEC_PREFIX",
+    "COMPILER_PATH",
+    "SDKROOT",
+];
+
+/// How a translation unit was compiled, so generated code can be checked in
+/// the same context as the original build
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TranslationUnitInfo {
+    pub file : PathBuf,
+    pub directory : PathBuf,
+    /// Full compiler invocation, compiler first
+    pub arguments : Vec<String>,
+    /// The variables of `CAPTURED_ENVIRONMENT` the build compiled the unit
+    /// with, empty when the compile commands did not record them
+    pub environment : BTreeMap<String, String>,
+    pub parse_mode : ParseMode,
+    /// Warnings and errors clang reported, outside system headers for warnings
+    pub diagnostics : Vec<ParseDiagnostic>,
+    /// Every file the unit includes, directly or not
+    pub includes : BTreeSet<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    Warning,
+    Error,
+    Fatal,
+}
+
+/// A message clang reported while parsing a translation unit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseDiagnostic {
+    /// Unset for diagnostics about the command rather than the source
+    pub file : Option<PathBuf>,
+    pub line : u32,
+    pub column : u32,
+    pub severity : DiagnosticSeverity,
+    pub message : String,
+}
+
+/// What had to change from the compile command for a translation unit to
+/// parse; all empty when the command was enough
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ParseMode {
+    /// Clang rejected the command's flags, so only its defines and include
+    /// paths were kept
+    pub reduced_flags : bool,
+    /// Include directories added to find headers the command's do not
+    pub added_includes : Vec<PathBuf>,
+    /// Headers found nowhere; code depending on them parsed with errors
+    pub missing_headers : Vec<String>,
+}
+
+impl ParseMode {
+    pub fn is_fallback(&self) -> bool {
+        self.reduced_flags || !self.added_includes.is_empty() || !self.missing_headers.is_empty()
+    }
+}
+
+impl std::fmt::Display for ParseMode {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if self.reduced_flags {
+            parts.push("clang rejected the compile flags, parsed with its -D/-I only".to_string());
+        }
+        if !self.added_includes.is_empty() {
+            let dirs : Vec<String> = self.added_includes.iter().map(|dir| dir.display().to_string()).collect();
+            parts.push(format!("added include directories {}", dirs.join(", ")));
+        }
+        if !self.missing_headers.is_empty() {
+            parts.push(format!("headers not found: {}", self.missing_headers.join(", ")));
+        }
+        if parts.is_empty() {
+            parts.push("compile command".to_string());
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl TranslationUnitInfo {
+    /// The captured variables of this process's environment, which a build
+    /// it runs, as make and ninja are to list their commands, inherits
+    pub fn current_environment() -> BTreeMap<String, String> {
+        CAPTURED_ENVIRONMENT.iter()
+            .filter_map(|name| Some((name.to_string(), std::env::var(name).ok()?)))
+            .collect()
+    }
+}
+
+/// Bumped whenever the saved layout of the database changes
+const FORMAT_VERSION : u32 = 18;
+
+/// A database as written to disk, with a hash of every source file it was
+/// parsed from so a reader can tell whether it is still up to date
+#[derive(Serialize, Deserialize)]
+struct SavedDatabase<D> {
+    format_version : u32,
+    source_hashes : BTreeMap<PathBuf, u64>,
+    database : D,
+}
+
+/// Hash of a function's code as `Definition::body_hash` stores it
+pub fn normalized_hash(signature : &Signature, body : &str) -> u64 {
+    let tokens : Vec<String> = crate::rewriter::tokenize(body).into_iter().map(|token| token.text).collect();
+    content_hash(format!("{}\n{}", signature.to_c(), tokens.join(" ")).as_bytes())
+}
+
+/// 64-bit FNV-1a, stable across runs and toolchains unlike `DefaultHasher`
+pub fn content_hash(bytes : &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Write `value` to `path`, as JSON when the file name ends in `.json` and in
+/// a compact binary form otherwise
+pub fn write_serialized<T : Serialize>(path : &Path, value : &T) -> Result<()> {
+    let writer = std::io::BufWriter::new(std::fs::File::create(path)
+        .with_context(|| format!("Cannot create {}", path.display()))?);
+    if is_json(path) {
+        serde_json::to_writer(writer, value)?;
+    } else {
+        bincode::serialize_into(writer, value)?;
+    }
+    Ok(())
+}
+
+/// Read back what `write_serialized` wrote to `path`
+pub fn read_serialized<T : DeserializeOwned>(path : &Path) -> Result<T> {
+    let reader = std::io::BufReader::new(std::fs::File::open(path)
+        .with_context(|| format!("Cannot open {}", path.display()))?);
+    if is_json(path) {
+        Ok(serde_json::from_reader(reader)?)
+    } else {
+        Ok(bincode::deserialize_from(reader)?)
+    }
 }
 
-#[derive(Debug, Clone, Default)]
+fn is_json(path : &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "json")
+}
+
+/// Functions and pointer targets are kept sorted by name so that everything
+/// derived from the database comes out in the same order on every run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FunctionDatabase{
-    functions : HashMap<String, Arc<Definition>>,
+    /// Functions with external linkage, by name
+    functions : BTreeMap<String, Arc<Definition>>,
+    /// Static functions by name, then by defining file, since every
+    /// translation unit may define its own
+    statics : BTreeMap<String, BTreeMap<PathBuf, Arc<Definition>>>,
+    units : Vec<TranslationUnitInfo>,
+    /// Functions assigned to each function pointer variable or field, by USR.
+    /// `None` stands for a value that is not a known function.
+    pointer_targets : BTreeMap<String, BTreeSet<Option<String>>>,
+    /// Functions listed in an interrupt vector table
+    vector_table_entries : BTreeSet<String>,
+    /// Declarations without a body by function name, each listed once even
+    /// when its header is included by several translation units
+    declarations : BTreeMap<String, Vec<Declaration>>,
+    /// Weak definitions a strong one of the same name replaced
+    overridden : BTreeMap<String, Vec<Arc<Definition>>>,
+    /// Definitions with external linkage of a name `functions` holds from
+    /// another file, none replacing the other, as when the compile commands
+    /// build two programs. The name is then ambiguous.
+    conflicting : BTreeMap<String, Vec<Arc<Definition>>>,
+    /// Declarations making a name an alias of another function, by name
+    aliases : BTreeMap<String, Declaration>,
+    /// Outcome of `resolve_symbols`
+    resolutions : Vec<SymbolResolution>,
+    /// Function-like macros by name, one entry per distinct definition
+    macros : BTreeMap<String, Vec<MacroDefinition>>,
+    /// Outcome of `resolve_recursion`
+    recursion_groups : Vec<Vec<Arc<Definition>>>,
+    /// Typedefs of every translation unit
+    types : TypeTable,
+    /// Source hashes recorded when the database was saved, for a loaded one
+    #[serde(skip)]
+    saved_hashes : BTreeMap<PathBuf, u64>,
 }
 
 impl FunctionDatabase {
@@ -54,25 +725,562 @@ impl FunctionDatabase {
         Self::default()
     }
     pub fn add_function(&mut self, def : Arc<Definition>){
-        self.functions.insert(def.signature.name.clone(), def);
+        if def.is_static {
+            // A header function comes once per translation unit including it;
+            // the first copy is kept
+            self.statics.entry(def.signature.name.clone())
+                .or_default()
+                .entry(def.source_file.clone())
+                .or_insert(def);
+        } else {
+            let name = def.signature.name.clone();
+            // A strong definition replaces a weak one, as the linker would,
+            // whatever order the translation units come in
+            let replaced = match self.functions.get(&name) {
+                Some(existing) if existing.source_file == def.source_file => Vec::new(),
+                Some(existing) if def.attributes.weak && !existing.attributes.weak => vec![def],
+                Some(existing) if !def.attributes.weak && existing.attributes.weak => {
+                    // The weak ones that conflicted with each other too
+                    let mut replaced = self.conflicting.remove(&name).unwrap_or_default();
+                    replaced.extend(self.functions.insert(name.clone(), def));
+                    replaced
+                }
+                Some(_) => {
+                    let conflicting = self.conflicting.entry(name.clone()).or_default();
+                    if !conflicting.iter().any(|other| other.source_file == def.source_file) {
+                        conflicting.push(def);
+                    }
+                    Vec::new()
+                }
+                None => {
+                    self.functions.insert(name.clone(), def);
+                    Vec::new()
+                }
+            };
+            for weak in replaced {
+                let overridden = self.overridden.entry(name.clone()).or_default();
+                if !overridden.iter().any(|other| other.source_file == weak.source_file) {
+                    overridden.push(weak);
+                }
+            }
+        }
+    }
+
+    /// Add everything another database collected, as if its translation
+    /// units had been parsed into this one. Run `resolve_symbols` and
+    /// `resolve_indirect_calls` once all are merged.
+    pub fn merge(&mut self, other : FunctionDatabase) {
+        for def in other.functions.into_values()
+            .chain(other.overridden.into_values().flatten())
+            .chain(other.conflicting.into_values().flatten())
+            .chain(other.statics.into_values().flat_map(BTreeMap::into_values))
+        {
+            self.add_function(def);
+        }
+        for declaration in other.declarations.into_values().flatten() {
+            self.add_declaration(declaration);
+        }
+        for definition in other.macros.into_values().flatten() {
+            self.add_macro(definition);
+        }
+        self.types.merge(other.types);
+        self.aliases.extend(other.aliases);
+        self.units.extend(other.units);
+        for (slot, targets) in other.pointer_targets {
+            self.pointer_targets.entry(slot).or_default().extend(targets);
+        }
+        self.vector_table_entries.extend(other.vector_table_entries);
+    }
+
+    pub fn add_alias(&mut self, declaration : Declaration) {
+        self.aliases.insert(declaration.signature.name.clone(), declaration);
+    }
+
+    /// Record which definition every weak or aliased name links to. Run once
+    /// every translation unit has been collected.
+    pub fn resolve_symbols(&mut self) {
+        let mut resolutions = Vec::new();
+        for def in self.functions.values().filter(|def| def.attributes.weak) {
+            resolutions.push(SymbolResolution {
+                name : def.signature.name.clone(),
+                winner : Some(Arc::clone(def)),
+                replaced : Vec::new(),
+                alias_of : None,
+            });
+        }
+        for (name, weak) in &self.overridden {
+            resolutions.push(SymbolResolution {
+                name : name.clone(),
+                winner : self.functions.get(name).cloned(),
+                replaced : weak.iter().map(|def| def.source_file.clone()).collect(),
+                alias_of : None,
+            });
+        }
+        for (name, conflicting) in &self.conflicting {
+            let files : Vec<String> = self.functions.get(name).into_iter()
+                .chain(conflicting)
+                .map(|def| def.source_file.display().to_string())
+                .collect();
+            tracing::warn!("{} is defined in several files, none of which replaces the others: {}", name, files.join(", "));
+        }
+        for (name, declaration) in &self.aliases {
+            let overridden = self.functions.get(name).filter(|def| !def.attributes.weak);
+            resolutions.push(SymbolResolution {
+                name : name.clone(),
+                winner : overridden.cloned().or_else(|| self.get_function_definition(name)),
+                replaced : if overridden.is_some() { vec![declaration.source_file.clone()] } else { Vec::new() },
+                alias_of : declaration.attributes.alias.clone(),
+            });
+        }
+        resolutions.sort_by(|a, b| a.name.cmp(&b.name));
+        self.resolutions = resolutions;
+    }
+
+    /// Which definition each weak or aliased name links to
+    pub fn symbol_resolutions(&self) -> &[SymbolResolution] {
+        &self.resolutions
     }
     pub fn add_function_ref(&mut self, def : &Definition){
         self.add_function(Arc::new(def.clone()));
     }
 
+    /// The definition `name` designates without knowing the calling file: the
+    /// external one, else the target of an alias, else the only static of
+    /// that name. `None` when several files define their own static `name`
+    /// with different bodies, or their own external one.
     pub fn get_function_definition(&self, name : & str) -> Option<Arc<Definition>> {
-        self.functions.get(name).cloned()
+        if self.conflicting.contains_key(name) {
+            return None;
+        }
+        if let Some(def) = self.functions.get(name) {
+            return Some(Arc::clone(def));
+        }
+        if let Some(target) = self.aliases.get(name).and_then(|declaration| declaration.attributes.alias.as_deref()) {
+            return self.get_function_definition(target);
+        }
+        Self::single_body(self.statics.get(name)?.values()).cloned()
+    }
+
+    /// The first of `definitions` when they all have the same body, as do
+    /// copies of one header in several directories
+    fn single_body<'a>(definitions : impl IntoIterator<Item = &'a Arc<Definition>>) -> Option<&'a Arc<Definition>> {
+        let mut definitions = definitions.into_iter();
+        let first = definitions.next()?;
+        definitions.all(|def| def.body_hash == first.body_hash).then_some(first)
+    }
+
+    /// Like `get_function_definition`, but an error rather than `None` when
+    /// the name is ambiguous
+    pub fn find_function(&self, name : &str) -> Result<Option<Arc<Definition>>> {
+        let found = self.get_function_definition(name);
+        if let Some(conflicting) = self.conflicting.get(name) {
+            let files : Vec<String> = self.functions.get(name).into_iter()
+                .chain(conflicting)
+                .map(|def| def.source_file.display().to_string())
+                .collect();
+            anyhow::bail!("{} is defined in several files: {}", name, files.join(", "));
+        }
+        if found.is_none() && let Some(statics) = self.statics.get(name) {
+            let files : Vec<String> = statics.keys().map(|file| file.display().to_string()).collect();
+            anyhow::bail!("{} is defined static in several files: {}", name, files.join(", "));
+        }
+        Ok(found)
+    }
+
+    /// The definition a call to `name` from a function defined in `file`
+    /// reaches: the static of that file first, as the compiler would, then
+    /// one from a header the units compiling `file` include. Of external
+    /// definitions in several files, only that of `file` is reached.
+    pub fn resolve_call(&self, name : &str, file : &Path) -> Option<Arc<Definition>> {
+        let statics = self.statics.get(name);
+        if let Some(def) = statics.and_then(|statics| statics.get(file)) {
+            return Some(Arc::clone(def));
+        }
+        if let Some(conflicting) = self.conflicting.get(name) {
+            return self.functions.get(name).into_iter()
+                .chain(conflicting)
+                .find(|def| def.source_file == file)
+                .cloned();
+        }
+        let included = statics.into_iter()
+            .flat_map(|statics| statics.values())
+            .filter(|def| def.in_header() && self.sees_header(file, &def.source_file));
+        Self::single_body(included).cloned()
+            .or_else(|| self.get_function_definition(name))
+    }
+
+    /// Whether a unit compiling `file`, or including it, also includes `header`
+    fn sees_header(&self, file : &Path, header : &Path) -> bool {
+        self.units.iter().any(|unit| {
+            (unit.file == file || unit.includes.contains(file)) && unit.includes.contains(header)
+        })
+    }
+
+    /// The definition of `name` in `file`, static or not
+    pub fn definition_in(&self, name : &str, file : &Path) -> Option<Arc<Definition>> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let same_file = |def : &&Arc<Definition>| def.source_file.canonicalize().unwrap_or_else(|_| def.source_file.clone()) == file;
+        self.statics.get(name)
+            .and_then(|statics| statics.values().find(same_file))
+            .or_else(|| self.functions.get(name).into_iter().chain(self.conflicting.get(name).into_iter().flatten()).find(same_file))
+            .cloned()
+    }
+
+    /// Every definition named `name`: the external ones, the weak ones they
+    /// replaced and each file's static
+    pub fn definitions_named(&self, name : &str) -> Vec<Arc<Definition>> {
+        self.functions.get(name)
+            .into_iter()
+            .chain(self.conflicting.get(name).into_iter().flatten())
+            .chain(self.overridden.get(name).into_iter().flatten())
+            .chain(self.statics.get(name).into_iter().flat_map(|statics| statics.values()))
+            .cloned()
+            .collect()
+    }
+
+    pub fn add_declaration(&mut self, declaration : Declaration) {
+        let declarations = self.declarations.entry(declaration.signature.name.clone()).or_default();
+        if !declarations.contains(&declaration) {
+            declarations.push(declaration);
+        }
+    }
+
+    /// Record a function-like macro, once however many translation units
+    /// include its header
+    pub fn add_macro(&mut self, definition : MacroDefinition) {
+        let definitions = self.macros.entry(definition.name.clone()).or_default();
+        if !definitions.contains(&definition) {
+            definitions.push(definition);
+        }
+    }
+
+    /// Every definition of the function-like macro `name`
+    pub fn macros_named(&self, name : &str) -> &[MacroDefinition] {
+        self.macros.get(name).map(Vec::as_slice).unwrap_or_default()
     }
 
-    pub fn clear(&mut self) {
-        self.functions.clear();
-        self.functions.shrink_to(0);
+    /// The function-like macro `name`, when all its definitions agree on
+    /// what it expands to
+    pub fn macro_definition(&self, name : &str) -> Option<&MacroDefinition> {
+        let definitions = self.macros.get(name)?;
+        let first = definitions.first()?;
+        definitions.iter()
+            .all(|definition| definition.parameters == first.parameters && definition.replacement == first.replacement)
+            .then_some(first)
     }
 
+    pub fn macros(&self) -> impl Iterator<Item = &MacroDefinition> {
+        self.macros.values().flatten()
+    }
+
+    pub fn add_typedef(&mut self, typedef : TypedefDefinition) {
+        self.types.add(typedef);
+    }
+
+    pub fn type_table(&self) -> &TypeTable {
+        &self.types
+    }
+
+    /// Declarations of `name` without a body, in the order they were seen
+    pub fn declarations_of(&self, name : &str) -> &[Declaration] {
+        self.declarations.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Prototypes of `name` a function defined in `file` is compiled with:
+    /// those of the file itself and of the headers its units include
+    pub fn visible_prototypes(&self, name : &str, file : &Path) -> Vec<&Declaration> {
+        self.declarations_of(name).iter()
+            .filter(|declaration| declaration.has_prototype)
+            .filter(|declaration| declaration.source_file == file || self.sees_header(file, &declaration.source_file))
+            .collect()
+    }
+
+    /// The signature a call to `name` from `file` is compiled against: the
+    /// definition's, else that of a prototype. `None` when the function is
+    /// neither defined nor declared with its parameters.
+    pub fn prototype(&self, name : &str, file : &Path) -> Option<Signature> {
+        if let Some(def) = self.resolve_call(name, file) {
+            return Some(def.signature.clone());
+        }
+        self.declarations_of(name).iter()
+            .find(|declaration| declaration.has_prototype)
+            .map(|declaration| declaration.signature.clone())
+    }
+
+    /// Prototypes whose return or parameter types differ from those of the
+    /// definition they link to, which the compiler cannot see when they live
+    /// in different translation units
+    pub fn signature_mismatches(&self) -> Vec<SignatureMismatch> {
+        self.declarations.values()
+            .flatten()
+            .filter(|declaration| declaration.has_prototype)
+            .filter_map(|declaration| {
+                let definition = self.resolve_call(&declaration.signature.name, &declaration.source_file)?;
+                (!declaration.signature.is_compatible(&definition.signature)).then(|| SignatureMismatch {
+                    declaration : declaration.clone(),
+                    definition,
+                })
+            })
+            .collect()
+    }
+
+    pub fn add_unit(&mut self, unit : TranslationUnitInfo) {
+        self.units.push(unit);
+    }
+
+    pub fn units(&self) -> &[TranslationUnitInfo] {
+        &self.units
+    }
+
+    /// The first translation unit compiled from `file`
+    pub fn unit_for(&self, file : &Path) -> Option<&TranslationUnitInfo> {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        self.units.iter().find(|unit| unit.file == file)
+    }
+
+    pub fn add_pointer_target(&mut self, slot : &str, target : Option<String>) {
+        self.pointer_targets.entry(slot.to_string()).or_default().insert(target);
+    }
+
+    /// The single function ever assigned to `slot`, if there is exactly one
+    pub fn pointer_target(&self, slot : &str) -> Option<&str> {
+        match self.pointer_targets.get(slot)?.iter().collect::<Vec<_>>().as_slice() {
+            [Some(target)] => Some(target),
+            _ => None,
+        }
+    }
+
+    pub fn add_vector_table_entry(&mut self, name : &str) {
+        self.vector_table_entries.insert(name.to_string());
+    }
+
+    /// Defined functions that look like interrupt handlers: named
+    /// `*_IRQHandler`, declared with an interrupt attribute, or listed in a
+    /// vector table
+    pub fn interrupt_handlers(&self) -> Vec<String> {
+        self.iter()
+            .filter(|def| !def.body.is_empty())
+            .filter(|def| def.attributes.interrupt
+                || def.signature.name.ends_with("_IRQHandler")
+                || self.vector_table_entries.contains(&def.signature.name))
+            .map(|def| def.signature.name.clone())
+            .collect()
+    }
+
+    /// Functions ever stored in a function pointer variable or field
+    pub fn address_taken_functions(&self) -> BTreeSet<&str> {
+        self.pointer_targets.values()
+            .flatten()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Point indirect calls whose pointer only ever holds one function at
+    /// that function. Run once every translation unit has been collected.
+    pub fn resolve_indirect_calls(&mut self) {
+        let resolved: Vec<(Arc<Definition>, Vec<CallInfo>)> = self.iter()
+            .filter(|def| def.calls.iter().any(|c| c.via.is_some()))
+            .map(|def| {
+                let calls = def.calls.iter()
+                    .cloned()
+                    .map(|mut call| {
+                        if let Some(target) = call.via.as_deref().and_then(|slot| self.pointer_target(slot)) {
+                            call.function_name = target.to_string();
+                        }
+                        call
+                    })
+                    .collect();
+                (def, calls)
+            })
+            .collect();
+
+        for (def, calls) in resolved {
+            let mut def = Arc::unwrap_or_clone(def);
+            def.calls = calls;
+            self.replace_function(Arc::new(def));
+        }
+    }
+
+    /// Put `def` in place of the definition of the same name from the same file
+    fn replace_function(&mut self, def : Arc<Definition>) {
+        let name = &def.signature.name;
+        let slot = if def.is_static {
+            self.statics.get_mut(name).and_then(|statics| statics.get_mut(&def.source_file))
+        } else {
+            self.functions.get_mut(name).filter(|existing| existing.source_file == def.source_file)
+        };
+        match slot {
+            Some(slot) => *slot = def,
+            None => self.add_function(def),
+        }
+    }
+
+    /// Find the groups of functions calling each other, directly or not,
+    /// self-recursive ones included. Calls resolve as from the caller's
+    /// file, so statics sharing a name stay apart. Run after
+    /// `resolve_indirect_calls`.
+    pub fn resolve_recursion(&mut self) {
+        let definitions : Vec<Arc<Definition>> = self.iter().collect();
+        let position : HashMap<(&str, &Path), usize> = definitions.iter()
+            .enumerate()
+            .map(|(i, def)| ((def.signature.name.as_str(), def.source_file.as_path()), i))
+            .collect();
+        let successors : Vec<Vec<usize>> = definitions.iter()
+            .map(|def| def.calls.iter()
+                .filter_map(|call| self.resolve_call(&call.function_name, &def.source_file))
+                .filter_map(|callee| position.get(&(callee.signature.name.as_str(), callee.source_file.as_path())).copied())
+                .collect())
+            .collect();
+        let mut groups : Vec<Vec<Arc<Definition>>> = crate::scc::strongly_connected_components(&successors)
+            .into_iter()
+            .filter(|group| group.len() > 1 || successors[group[0]].contains(&group[0]))
+            .map(|group| {
+                let mut group : Vec<Arc<Definition>> = group.into_iter().map(|i| Arc::clone(&definitions[i])).collect();
+                group.sort_by(|a, b| (&a.signature.name, &a.source_file).cmp(&(&b.signature.name, &b.source_file)));
+                group
+            })
+            .collect();
+        groups.sort_by(|a, b| (&a[0].signature.name, &a[0].source_file).cmp(&(&b[0].signature.name, &b[0].source_file)));
+        self.recursion_groups = groups;
+    }
+
+    /// Groups of mutually recursive functions, found by `resolve_recursion`
+    pub fn recursion_groups(&self) -> &[Vec<Arc<Definition>>] {
+        &self.recursion_groups
+    }
+
+    /// Write the database to `path`, as JSON when the file name ends in
+    /// `.json` and in a compact binary form otherwise
+    pub fn save(&self, path : &Path) -> Result<()> {
+        let saved = SavedDatabase {
+            format_version : FORMAT_VERSION,
+            source_hashes : self.source_files().into_iter()
+                .filter_map(|file| Some((file.clone(), content_hash(&std::fs::read(&file).ok()?))))
+                .collect(),
+            database : self,
+        };
+        write_serialized(path, &saved)
+    }
+
+    /// Read a database written by `save`. Check `stale_files` before
+    /// trusting it.
+    pub fn load(path : &Path) -> Result<Self> {
+        let saved : SavedDatabase<FunctionDatabase> = read_serialized(path)?;
+        if saved.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "{} was saved in format {}, this version reads format {}",
+                path.display(), saved.format_version, FORMAT_VERSION
+            );
+        }
+        let mut db = saved.database;
+        db.saved_hashes = saved.source_hashes;
+        Ok(db)
+    }
+
+    /// Translation units and every file holding a definition or declaration
+    fn source_files(&self) -> BTreeSet<PathBuf> {
+        let declarations = self.declarations.values().flatten().map(|declaration| declaration.source_file.clone());
+        self.units.iter()
+            .map(|unit| unit.file.clone())
+            .chain(self.iter().map(|def| def.source_file.clone()))
+            .chain(declarations)
+            .collect()
+    }
+
+    /// Files changed or removed since a loaded database was saved. Files
+    /// added to the project since are only noticed through the compilation
+    /// database.
+    pub fn stale_files(&self) -> Vec<PathBuf> {
+        self.saved_hashes.iter()
+            .filter(|(file, hash)| std::fs::read(file).map(|bytes| content_hash(&bytes)).ok() != Some(**hash))
+            .map(|(file, _)| file.clone())
+            .collect()
+    }
+
+    /// Every definition, statics of different files included, by name
     pub fn iter(&self) -> impl Iterator<Item = Arc<Definition>> + '_ {
-        self.functions.values().cloned()
+        let statics = self.statics.values().flat_map(|statics| statics.values());
+        let conflicting = self.conflicting.values().flatten();
+        let mut all : Vec<&Arc<Definition>> = self.functions.values().chain(conflicting).chain(statics).collect();
+        all.sort_by(|a, b| a.signature.name.cmp(&b.signature.name));
+        all.into_iter().cloned()
     }
 
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::types::{FloatKind, IntegerKind, TypeKind};
+
+    fn signature(is_old_style : bool, parameters : &[TypeKind]) -> Signature {
+        Signature {
+            name : "f".to_string(),
+            return_ty : Type::new(TypeKind::Integer(IntegerKind::Int)),
+            args : parameters.iter()
+                .map(|kind| Parameter { ty : Type::new(kind.clone()), ..Default::default() })
+                .collect(),
+            is_old_style,
+            ..Default::default()
+        }
+    }
 
+    const CHAR : TypeKind = TypeKind::Integer(IntegerKind::Char);
+    const INT : TypeKind = TypeKind::Integer(IntegerKind::Int);
+    const FLOAT : TypeKind = TypeKind::Float(FloatKind::Float);
+    const DOUBLE : TypeKind = TypeKind::Float(FloatKind::Double);
+
+    #[test]
+    fn prototype_matches_promoted_old_style_parameters() {
+        let definition = signature(true, &[FLOAT, CHAR]);
+        let prototype = signature(false, &[DOUBLE, INT]);
+        assert!(prototype.is_compatible(&definition));
+        assert!(definition.is_compatible(&prototype));
+    }
+
+    #[test]
+    fn prototype_with_unpromoted_types_mismatches_old_style_definition() {
+        let definition = signature(true, &[FLOAT, CHAR]);
+        let prototype = signature(false, &[FLOAT, CHAR]);
+        assert!(!prototype.is_compatible(&definition));
+        assert!(!definition.is_compatible(&prototype));
+    }
+
+    #[test]
+    fn old_style_declarators_compare_as_written() {
+        assert!(signature(true, &[CHAR]).is_compatible(&signature(true, &[CHAR])));
+        assert!(!signature(true, &[CHAR]).is_compatible(&signature(true, &[INT])));
+    }
+
+    fn definition(name : &str, file : &str, weak : bool) -> Arc<Definition> {
+        Arc::new(Definition {
+            signature : Signature { name : name.to_string(), ..Default::default() },
+            body : "{ }".to_string(),
+            source_file : PathBuf::from(file),
+            attributes : FunctionAttributes { weak, ..Default::default() },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn external_definitions_in_several_files_are_ambiguous() {
+        let mut db = FunctionDatabase::new();
+        db.add_function(definition("main", "a.c", false));
+        db.add_function(definition("main", "b.c", false));
+        assert!(db.get_function_definition("main").is_none());
+        assert!(db.find_function("main").is_err());
+        assert_eq!(db.resolve_call("main", Path::new("b.c")).unwrap().source_file, Path::new("b.c"));
+        assert_eq!(db.definitions_named("main").len(), 2);
+    }
+
+    #[test]
+    fn strong_definition_replaces_every_weak_one() {
+        let mut db = FunctionDatabase::new();
+        db.add_function(definition("handler", "a.c", true));
+        db.add_function(definition("handler", "b.c", true));
+        db.add_function(definition("handler", "c.c", false));
+        assert_eq!(db.find_function("handler").unwrap().unwrap().source_file, Path::new("c.c"));
+        assert_eq!(db.definitions_named("handler").len(), 3);
+    }
+}
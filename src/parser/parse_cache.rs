@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::function_db::{FunctionDatabase, content_hash, read_serialized, write_serialized};
+
+/// Bumped whenever the layout of the cache changes
+const FORMAT_VERSION : u32 = 16;
+
+/// What one compile command contributed to the database, and the inputs it
+/// was parsed from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedUnit {
+    pub file : PathBuf,
+    /// Hash of the directory and arguments of the compile command
+    pub flags_hash : u64,
+    /// Hash of the source file and of every header it includes
+    pub file_hashes : BTreeMap<PathBuf, u64>,
+    pub database : FunctionDatabase,
+}
+
+/// Databases of the translation units parsed on earlier runs, so that only
+/// those whose sources, headers or flags changed are parsed again
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ParseCache {
+    format_version : u32,
+    units : Vec<CachedUnit>,
+    /// Units of the previous run not claimed yet by this one
+    #[serde(skip)]
+    previous : Vec<CachedUnit>,
+    /// Hashes of the files read during this run, each file read once
+    #[serde(skip)]
+    hashes : HashMap<PathBuf, Option<u64>>,
+    /// Units reused during this run
+    #[serde(skip)]
+    pub hits : usize,
+    /// Units parsed during this run
+    #[serde(skip)]
+    pub misses : usize,
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        ParseCache { format_version : FORMAT_VERSION, ..Default::default() }
+    }
+
+    pub fn load(path : &Path) -> Result<Self> {
+        let cache : ParseCache = read_serialized(path)?;
+        if cache.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "{} was saved in format {}, this version reads format {}",
+                path.display(), cache.format_version, FORMAT_VERSION
+            );
+        }
+        Ok(cache)
+    }
+
+    /// Write the units of the last run to `path`; those of earlier runs that
+    /// were not used again are dropped
+    pub fn save(&self, path : &Path) -> Result<()> {
+        write_serialized(path, self)
+    }
+
+    /// Start a run: every cached unit has to be claimed with `take` to be kept
+    pub fn start_run(&mut self) {
+        self.previous.append(&mut self.units);
+        self.hashes.clear();
+        self.hits = 0;
+        self.misses = 0;
+    }
+
+    pub fn flags_hash(directory : &Path, arguments : &[String]) -> u64 {
+        let mut key = directory.display().to_string();
+        for argument in arguments {
+            key.push('\0');
+            key.push_str(argument);
+        }
+        content_hash(key.as_bytes())
+    }
+
+    /// Current hash of `file`, none when it cannot be read
+    pub fn file_hash(&mut self, file : &Path) -> Option<u64> {
+        *self.hashes.entry(file.to_path_buf())
+            .or_insert_with(|| std::fs::read(file).ok().map(|bytes| content_hash(&bytes)))
+    }
+
+    /// The cached unit for `file` compiled with these flags, when neither the
+    /// file nor any header it includes changed since
+    pub fn take(&mut self, file : &Path, flags_hash : u64) -> Option<CachedUnit> {
+        let position = self.previous.iter()
+            .position(|unit| unit.file == file && unit.flags_hash == flags_hash)?;
+        let unit = self.previous.swap_remove(position);
+        let is_current = unit.file_hashes.iter()
+            .all(|(dependency, hash)| self.file_hash(dependency) == Some(*hash));
+        is_current.then_some(unit)
+    }
+
+    /// Keep the units of the previous run this one did not ask for, when it
+    /// only parsed part of the project
+    pub fn keep_unclaimed(&mut self) {
+        self.units.append(&mut self.previous);
+    }
+
+    /// Keep `unit` for the next run
+    pub fn insert(&mut self, unit : CachedUnit, reused : bool) {
+        if reused {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        self.units.push(unit);
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What makes clang parse as a cross compiler would rather than for the host:
+/// type sizes, built-in defines and the toolchain's own headers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Toolchain {
+    /// Target triple, e.g. `arm-none-eabi`
+    pub target : Option<String>,
+    pub sysroot : Option<PathBuf>,
+    /// System include directories, searched after those of the command
+    pub include_dirs : Vec<PathBuf>,
+}
+
+impl Toolchain {
+    /// Ask a GCC cross compiler, named like `arm-none-eabi-gcc`, for its
+    /// target and built-in include directories. `None` for other compilers
+    /// and those that cannot be run.
+    pub fn detect(compiler : &Path) -> Option<Self> {
+        let name = compiler.file_name()?.to_str()?;
+        let prefix = name.strip_suffix(".exe").unwrap_or(name).strip_suffix("-gcc")?;
+        if !prefix.contains('-') {
+            return None;
+        }
+
+        let machine = Command::new(compiler).arg("-dumpmachine").stderr(Stdio::null()).output().ok()?;
+        let target = String::from_utf8_lossy(&machine.stdout).trim().to_string();
+
+        // The search list goes to stderr, between these two lines
+        let search = Command::new(compiler)
+            .args(["-xc", "-E", "-v", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .output()
+            .ok()?;
+        let include_dirs = String::from_utf8_lossy(&search.stderr)
+            .lines()
+            .skip_while(|line| !line.starts_with("#include <...> search starts here:"))
+            .skip(1)
+            .take_while(|line| !line.starts_with("End of search list."))
+            .map(|line| Path::new(line.trim()).canonicalize().unwrap_or_else(|_| PathBuf::from(line.trim())))
+            .collect();
+
+        Some(Toolchain {
+            target : Some(if target.is_empty() { prefix.to_string() } else { target }),
+            sysroot : None,
+            include_dirs,
+        })
+    }
+
+    /// These settings, completed by those of `other`: its target and sysroot
+    /// when unset here, and its include directories after these
+    pub fn or(&self, other : &Toolchain) -> Toolchain {
+        Toolchain {
+            target : self.target.clone().or_else(|| other.target.clone()),
+            sysroot : self.sysroot.clone().or_else(|| other.sysroot.clone()),
+            include_dirs : self.include_dirs.iter().chain(&other.include_dirs).cloned().collect(),
+        }
+    }
+
+    pub fn clang_arguments(&self) -> Vec<String> {
+        let mut arguments = Vec::new();
+        if let Some(target) = &self.target {
+            arguments.push(format!("--target={}", target));
+        }
+        if let Some(sysroot) = &self.sysroot {
+            arguments.push(format!("--sysroot={}", sysroot.display()));
+        }
+        for dir in &self.include_dirs {
+            arguments.push("-isystem".to_string());
+            arguments.push(dir.display().to_string());
+        }
+        arguments
+    }
+}
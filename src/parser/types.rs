@@ -0,0 +1,748 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// A C type as clang resolved it, with its qualifiers. Typedefs keep both
+/// their name and the type they stand for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Type {
+    pub kind : TypeKind,
+    pub is_const : bool,
+    pub is_volatile : bool,
+    pub is_restrict : bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum TypeKind {
+    #[default]
+    Void,
+    Bool,
+    Integer(IntegerKind),
+    Float(FloatKind),
+    /// `_Complex float` and its siblings
+    Complex(FloatKind),
+    /// `_Atomic(T)`, or `T` declared with the `_Atomic` qualifier
+    Atomic(Box<Type>),
+    Pointer(Box<Type>),
+    Array {
+        element : Box<Type>,
+        length : ArrayLength,
+    },
+    Function(FunctionType),
+    Record(RecordType),
+    Enum(EnumType),
+    Typedef {
+        name : String,
+        target : Box<Type>,
+    },
+    /// A type the model does not cover, by its spelling
+    Other(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IntegerKind {
+    /// Plain `char`, whose signedness depends on the target
+    Char,
+    SignedChar,
+    UnsignedChar,
+    Short,
+    UnsignedShort,
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+    Int128,
+    UnsignedInt128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FloatKind {
+    Float,
+    Double,
+    LongDouble,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArrayLength {
+    Fixed(u64),
+    /// `[]`, completed elsewhere or a flexible array member
+    Unknown,
+    /// Sized by an expression evaluated at run time
+    Variable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct FunctionType {
+    pub return_type : Box<Type>,
+    pub parameters : Vec<Type>,
+    pub is_variadic : bool,
+    /// Declared with a parameter list, as opposed to `int f()`
+    pub has_prototype : bool,
+}
+
+/// A struct or union
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct RecordType {
+    pub is_union : bool,
+    /// The tag, or for an anonymous struct or union one made up from the
+    /// file and offset declaring it, the same in every translation unit
+    pub name : String,
+    pub is_anonymous : bool,
+    /// Members in declaration order; unset for an incomplete record and for
+    /// one only reached through pointers, whose layout nothing needs
+    pub fields : Option<Vec<Field>>,
+    /// `__attribute__((packed))`
+    pub is_packed : bool,
+    /// From `__attribute__((aligned(N)))` or `_Alignas`
+    pub alignment : Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct EnumType {
+    /// A synthetic one for an anonymous enum, see `RecordType::name`
+    pub name : String,
+    pub is_anonymous : bool,
+    /// The value of each constant, as clang evaluated its initializer
+    pub constants : BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct Field {
+    /// Unset for anonymous members and unnamed bit-fields
+    pub name : Option<String>,
+    pub ty : Type,
+    pub bit_width : Option<u32>,
+}
+
+/// A `typedef` declaration, as found at file scope
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TypedefDefinition {
+    pub name : String,
+    /// The type the name stands for, itself possibly another typedef
+    pub target : Type,
+    pub source_file : PathBuf,
+    pub line : u32,
+    /// Declared by a header included as a system one
+    pub is_system : bool,
+}
+
+/// Every typedef the parsed translation units declare, by name. A header
+/// included by several units is recorded once; a name declared differently
+/// in several places keeps each of its definitions.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TypeTable {
+    typedefs : BTreeMap<String, Vec<TypedefDefinition>>,
+}
+
+impl TypeTable {
+    pub fn add(&mut self, typedef : TypedefDefinition) {
+        let definitions = self.typedefs.entry(typedef.name.clone()).or_default();
+        if !definitions.contains(&typedef) {
+            definitions.push(typedef);
+        }
+    }
+
+    pub fn merge(&mut self, other : TypeTable) {
+        for typedef in other.typedefs.into_values().flatten() {
+            self.add(typedef);
+        }
+    }
+
+    /// Every definition of the typedef `name`, in the order they were seen
+    pub fn typedefs_named(&self, name : &str) -> &[TypedefDefinition] {
+        self.typedefs.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The typedef `name`, when all its definitions agree on its type
+    pub fn get(&self, name : &str) -> Option<&TypedefDefinition> {
+        let definitions = self.typedefs.get(name)?;
+        let first = definitions.first()?;
+        definitions.iter().all(|typedef| typedef.target == first.target).then_some(first)
+    }
+
+    /// The type `name` finally stands for, typedefs looked through, as for
+    /// `HAL_StatusTypeDef` resolving to its enum
+    pub fn resolve(&self, name : &str) -> Option<Type> {
+        self.get(name).map(|typedef| typedef.target.resolved())
+    }
+}
+
+/// Sizes and alignments, in bytes, of the scalar types of a target ABI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataModel {
+    pub short : (u64, u64),
+    pub int : (u64, u64),
+    pub long : (u64, u64),
+    pub long_long : (u64, u64),
+    pub pointer : (u64, u64),
+    pub float : (u64, u64),
+    pub double : (u64, u64),
+    pub long_double : (u64, u64),
+    /// Largest alignment a member gets, for ABIs like i386 aligning 8-byte
+    /// members to 4 inside structs
+    pub max_field_align : u64,
+}
+
+impl DataModel {
+    /// 32-bit `int`, `long` and pointers, 8-byte aligned `long long` and
+    /// `double`, as on ARM EABI and RISC-V 32
+    pub const ILP32 : DataModel = DataModel {
+        short : (2, 2),
+        int : (4, 4),
+        long : (4, 4),
+        long_long : (8, 8),
+        pointer : (4, 4),
+        float : (4, 4),
+        double : (8, 8),
+        long_double : (8, 8),
+        max_field_align : 8,
+    };
+
+    /// 64-bit `long` and pointers, as on Linux and macOS hosts
+    pub const LP64 : DataModel = DataModel {
+        long : (8, 8),
+        pointer : (8, 8),
+        long_double : (16, 16),
+        max_field_align : 16,
+        ..DataModel::ILP32
+    };
+
+    /// 64-bit pointers with a 32-bit `long`, as on Windows
+    pub const LLP64 : DataModel = DataModel {
+        pointer : (8, 8),
+        ..DataModel::ILP32
+    };
+
+    /// i386 System V: 8-byte types aligned to 4 inside structs
+    pub const I386 : DataModel = DataModel {
+        long_long : (8, 4),
+        double : (8, 4),
+        long_double : (12, 4),
+        max_field_align : 4,
+        ..DataModel::ILP32
+    };
+
+    /// 16-bit `int` and pointers, as on MSP430
+    pub const IP16 : DataModel = DataModel {
+        int : (2, 2),
+        pointer : (2, 2),
+        long : (4, 2),
+        long_long : (8, 2),
+        float : (4, 2),
+        double : (8, 2),
+        long_double : (8, 2),
+        max_field_align : 2,
+        ..DataModel::ILP32
+    };
+
+    /// AVR: 16-bit `int` and pointers, 32-bit `double`, nothing aligned
+    pub const AVR : DataModel = DataModel {
+        short : (2, 1),
+        int : (2, 1),
+        long : (4, 1),
+        long_long : (8, 1),
+        pointer : (2, 1),
+        float : (4, 1),
+        double : (4, 1),
+        long_double : (4, 1),
+        max_field_align : 1,
+    };
+
+    fn integer(&self, kind : IntegerKind) -> (u64, u64) {
+        match kind {
+            IntegerKind::Char | IntegerKind::SignedChar | IntegerKind::UnsignedChar => (1, 1),
+            IntegerKind::Short | IntegerKind::UnsignedShort => self.short,
+            IntegerKind::Int | IntegerKind::UnsignedInt => self.int,
+            IntegerKind::Long | IntegerKind::UnsignedLong => self.long,
+            IntegerKind::LongLong | IntegerKind::UnsignedLongLong => self.long_long,
+            IntegerKind::Int128 | IntegerKind::UnsignedInt128 => (16, 16),
+        }
+    }
+}
+
+impl FromStr for DataModel {
+    type Err = String;
+
+    fn from_str(s : &str) -> Result<Self, Self::Err> {
+        match s {
+            "ilp32" | "arm" | "riscv32" => Ok(DataModel::ILP32),
+            "lp64" => Ok(DataModel::LP64),
+            "llp64" => Ok(DataModel::LLP64),
+            "i386" => Ok(DataModel::I386),
+            "msp430" => Ok(DataModel::IP16),
+            "avr" => Ok(DataModel::AVR),
+            _ => Err(format!("unknown data model '{}', expected ilp32, lp64, llp64, i386, msp430 or avr", s)),
+        }
+    }
+}
+
+impl Type {
+    pub fn new(kind : TypeKind) -> Self {
+        Type { kind, ..Default::default() }
+    }
+
+    /// The type with typedefs looked through, keeping the qualifiers written
+    /// on the typedef name as well as those inside it
+    pub fn resolved(&self) -> Type {
+        match &self.kind {
+            TypeKind::Typedef { target, .. } => {
+                let mut resolved = target.resolved();
+                resolved.is_const |= self.is_const;
+                resolved.is_volatile |= self.is_volatile;
+                resolved.is_restrict |= self.is_restrict;
+                resolved
+            }
+            _ => self.clone(),
+        }
+    }
+
+    pub fn is_void(&self) -> bool {
+        self.resolved().kind == TypeKind::Void
+    }
+
+    /// Whether the two types are compatible in the sense of C11 6.2.7, so
+    /// that a declaration and a definition in different translation units
+    /// may use them for the same object or parameter: typedefs looked
+    /// through, qualifiers equal, an enum compatible with the integer type
+    /// the compiler gives it, arrays of unknown length with any length.
+    pub fn is_compatible_with(&self, other : &Type) -> bool {
+        let (a, b) = (self.resolved(), other.resolved());
+        a.qualifiers() == b.qualifiers() && a.is_compatible_kind(&b)
+    }
+
+    /// Like `is_compatible_with`, for types of function parameters, where
+    /// arrays and functions stand for pointers and qualifiers on the
+    /// parameter itself do not count
+    pub fn is_compatible_parameter(&self, other : &Type) -> bool {
+        let (a, b) = (self.adjusted(), other.adjusted());
+        a.is_compatible_kind(&b)
+    }
+
+    /// The unqualified type a parameter declared with this type has
+    fn adjusted(&self) -> Type {
+        match self.resolved().kind {
+            TypeKind::Array { element, .. } => Type::new(TypeKind::Pointer(element)),
+            TypeKind::Function(function) => Type::new(TypeKind::Pointer(Box::new(Type::new(TypeKind::Function(function))))),
+            kind => Type::new(kind),
+        }
+    }
+
+    /// Whether the types are compatible once qualifiers are set aside; both
+    /// are resolved
+    fn is_compatible_kind(&self, other : &Type) -> bool {
+        match (&self.kind, &other.kind) {
+            (TypeKind::Pointer(a), TypeKind::Pointer(b)) | (TypeKind::Atomic(a), TypeKind::Atomic(b)) => a.is_compatible_with(b),
+            (TypeKind::Array { element : a, length : a_length }, TypeKind::Array { element : b, length : b_length }) => {
+                a.is_compatible_with(b) && match (a_length, b_length) {
+                    (ArrayLength::Fixed(a), ArrayLength::Fixed(b)) => a == b,
+                    _ => true,
+                }
+            }
+            (TypeKind::Function(a), TypeKind::Function(b)) => a.is_compatible_with(b),
+            (TypeKind::Record(a), TypeKind::Record(b)) => a.is_compatible_with(b),
+            (TypeKind::Enum(a), TypeKind::Enum(b)) => a.name == b.name,
+            (TypeKind::Enum(enumeration), TypeKind::Integer(kind)) | (TypeKind::Integer(kind), TypeKind::Enum(enumeration)) => {
+                enumeration.is_compatible_with(*kind)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    /// The type an argument of this type is passed as without a prototype:
+    /// small integers become `int` and `float` becomes `double`
+    pub fn promoted(&self) -> Type {
+        let kind = match self.resolved().kind {
+            TypeKind::Bool => TypeKind::Integer(IntegerKind::Int),
+            TypeKind::Integer(IntegerKind::Char | IntegerKind::SignedChar | IntegerKind::UnsignedChar | IntegerKind::Short | IntegerKind::UnsignedShort) => {
+                TypeKind::Integer(IntegerKind::Int)
+            }
+            TypeKind::Float(FloatKind::Float) => TypeKind::Float(FloatKind::Double),
+            _ => return self.clone(),
+        };
+        Type::new(kind)
+    }
+
+    /// Names of the typedefs spelled in the type, outside of those they
+    /// stand for, e.g. `uint8_t` for `const uint8_t *[4]`
+    pub fn typedef_names(&self, names : &mut BTreeSet<String>) {
+        match &self.kind {
+            TypeKind::Typedef { name, .. } => {
+                names.insert(name.clone());
+            }
+            TypeKind::Pointer(inner) | TypeKind::Atomic(inner) | TypeKind::Array { element : inner, .. } => inner.typedef_names(names),
+            TypeKind::Function(function) => {
+                function.return_type.typedef_names(names);
+                for parameter in &function.parameters {
+                    parameter.typedef_names(names);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Anonymous structs, unions and enums the type refers to by their
+    /// synthetic tag outside of typedefs, those a record's members use
+    /// before it, preferring the copy listing members
+    pub fn anonymous_types<'a>(&'a self, records : &mut Vec<&'a RecordType>, enums : &mut Vec<&'a EnumType>) {
+        match &self.kind {
+            TypeKind::Record(record) => {
+                record.member_types(records, enums);
+                match records.iter_mut().find(|known| known.name == record.name) {
+                    Some(known) if known.fields.is_none() => *known = record,
+                    Some(_) => {}
+                    None if record.is_anonymous => records.push(record),
+                    None => {}
+                }
+            }
+            TypeKind::Enum(enumeration) if enumeration.is_anonymous && !enums.iter().any(|known| known.name == enumeration.name) => {
+                enums.push(enumeration);
+            }
+            TypeKind::Pointer(inner) | TypeKind::Atomic(inner) | TypeKind::Array { element : inner, .. } => inner.anonymous_types(records, enums),
+            TypeKind::Function(function) => {
+                function.return_type.anonymous_types(records, enums);
+                for parameter in &function.parameters {
+                    parameter.anonymous_types(records, enums);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Size and alignment in bytes under `model`, laying out structs with
+    /// their padding and bit-fields as GCC does. `None` for `void`,
+    /// functions, arrays without a constant length and records whose
+    /// members are not known.
+    pub fn size_and_align(&self, model : DataModel) -> Option<(u64, u64)> {
+        let layout = match &self.kind {
+            TypeKind::Void | TypeKind::Function(_) | TypeKind::Other(_) => return None,
+            TypeKind::Bool => (1, 1),
+            TypeKind::Integer(kind) => model.integer(*kind),
+            TypeKind::Float(FloatKind::Float) => model.float,
+            TypeKind::Float(FloatKind::Double) => model.double,
+            TypeKind::Float(FloatKind::LongDouble) => model.long_double,
+            TypeKind::Complex(kind) => {
+                let (size, align) = Type::new(TypeKind::Float(*kind)).size_and_align(model)?;
+                (size * 2, align)
+            }
+            TypeKind::Atomic(value) => value.size_and_align(model)?,
+            TypeKind::Pointer(_) => model.pointer,
+            TypeKind::Enum(_) => model.int,
+            TypeKind::Array { element, length : ArrayLength::Fixed(length) } => {
+                let (size, align) = element.size_and_align(model)?;
+                (size * length, align)
+            }
+            TypeKind::Array { .. } => return None,
+            // `uint32_t` is 4 bytes on every target, even when `model` is not
+            // the one the typedef was parsed for
+            TypeKind::Typedef { name, target } => match fixed_width(name) {
+                Some(size) => (size, size.min(model.long_long.1)),
+                None => target.size_and_align(model)?,
+            },
+            TypeKind::Record(record) => record.size_and_align(model)?,
+        };
+        Some(layout)
+    }
+
+    /// The type as C declarator syntax, declaring `identifier` when given and
+    /// as a type name for casts and `sizeof` otherwise, e.g. `int (*p)[4]`
+    /// or `void (*(*handlers)[8])(int)`
+    pub fn to_c_string(&self, identifier : Option<&str>) -> String {
+        self.declarator(identifier.unwrap_or_default().to_string())
+    }
+
+    /// Wrap the declarator `inner` in this type, from the outside in
+    fn declarator(&self, inner : String) -> String {
+        match &self.kind {
+            TypeKind::Pointer(pointee) => {
+                let mut pointer = "*".to_string();
+                let qualifiers = self.qualifiers();
+                pointer.push_str(&qualifiers.join(" "));
+                if !qualifiers.is_empty() && !inner.is_empty() {
+                    pointer.push(' ');
+                }
+                pointer.push_str(&inner);
+                // Binds tighter than the pointee's `[]` or `()` otherwise would
+                if matches!(pointee.kind, TypeKind::Array { .. } | TypeKind::Function(_)) {
+                    pointer = format!("({})", pointer);
+                }
+                pointee.declarator(pointer)
+            }
+            TypeKind::Array { element, length } => {
+                let length = match length {
+                    ArrayLength::Fixed(length) => length.to_string(),
+                    ArrayLength::Unknown => String::new(),
+                    ArrayLength::Variable => "*".to_string(),
+                };
+                element.declarator(format!("{}[{}]", inner, length))
+            }
+            TypeKind::Function(function) => {
+                let mut parameters : Vec<String> = function.parameters.iter()
+                    .map(|parameter| parameter.to_c_string(None))
+                    .collect();
+                if function.is_variadic {
+                    parameters.push("...".to_string());
+                } else if parameters.is_empty() && function.has_prototype {
+                    parameters.push("void".to_string());
+                }
+                function.return_type.declarator(format!("{}({})", inner, parameters.join(", ")))
+            }
+            // Clang's spelling, qualifiers included
+            TypeKind::Other(spelling) if inner.is_empty() => spelling.clone(),
+            TypeKind::Other(spelling) => crate::rewriter::declarator(spelling, &inner),
+            _ => {
+                let mut specifiers = self.qualifiers();
+                let name = self.specifier();
+                specifiers.push(&name);
+                let specifiers = specifiers.join(" ");
+                if inner.is_empty() {
+                    specifiers
+                } else {
+                    format!("{} {}", specifiers, inner)
+                }
+            }
+        }
+    }
+
+    fn qualifiers(&self) -> Vec<&'static str> {
+        [(self.is_const, "const"), (self.is_volatile, "volatile"), (self.is_restrict, "restrict")]
+            .into_iter()
+            .filter_map(|(set, qualifier)| set.then_some(qualifier))
+            .collect()
+    }
+
+    /// The type specifier naming a type that is not built from another
+    fn specifier(&self) -> String {
+        match &self.kind {
+            TypeKind::Void => "void".to_string(),
+            TypeKind::Bool => "_Bool".to_string(),
+            TypeKind::Integer(kind) => kind.name().to_string(),
+            TypeKind::Float(kind) => kind.name().to_string(),
+            TypeKind::Complex(kind) => format!("_Complex {}", kind.name()),
+            TypeKind::Atomic(value) => format!("_Atomic({})", value.to_c_string(None)),
+            TypeKind::Record(record) => format!("{} {}", record.keyword(), record.name),
+            TypeKind::Enum(enumeration) => format!("enum {}", enumeration.name),
+            TypeKind::Typedef { name, .. } => name.clone(),
+            TypeKind::Pointer(_) | TypeKind::Array { .. } | TypeKind::Function(_) | TypeKind::Other(_) => {
+                unreachable!("declarators and spellings are rendered whole")
+            }
+        }
+    }
+}
+
+/// Bytes in the exact-width integer typedef `name`, such as `uint8_t` or
+/// the `__int32_t` libc headers define it through
+fn fixed_width(name : &str) -> Option<u64> {
+    let bits = name.trim_start_matches('_')
+        .strip_suffix("_t")?
+        .trim_start_matches('u')
+        .strip_prefix("int")?;
+    match bits {
+        "8" => Some(1),
+        "16" => Some(2),
+        "32" => Some(4),
+        "64" => Some(8),
+        _ => None,
+    }
+}
+
+impl RecordType {
+    /// Whether the records are compatible: same tag and, when both list
+    /// their members, members of the same names, compatible types and
+    /// widths in the same order
+    fn is_compatible_with(&self, other : &RecordType) -> bool {
+        if self.is_union != other.is_union || self.name != other.name {
+            return false;
+        }
+        match (&self.fields, &other.fields) {
+            (Some(a), Some(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| {
+                a.name == b.name && a.bit_width == b.bit_width && a.ty.is_compatible_with(&b.ty)
+            }),
+            _ => true,
+        }
+    }
+
+    fn keyword(&self) -> &'static str {
+        if self.is_union { "union" } else { "struct" }
+    }
+
+    /// A definition of the record under its tag, synthetic or not, for
+    /// code that refers to an anonymous one by name. `None` when its members
+    /// are not known.
+    pub fn to_c_definition(&self) -> Option<String> {
+        Some(format!("{} {}{}", self.keyword(), self.name, self.members()?))
+    }
+
+    /// The anonymous types the members of this record refer to, looking
+    /// into anonymous members, which are written out in place
+    fn member_types<'a>(&'a self, records : &mut Vec<&'a RecordType>, enums : &mut Vec<&'a EnumType>) {
+        for field in self.fields.iter().flatten() {
+            match &field.ty.kind {
+                TypeKind::Record(record) if field.name.is_none() && record.is_anonymous => record.member_types(records, enums),
+                _ => field.ty.anonymous_types(records, enums),
+            }
+        }
+    }
+
+    /// ` { members }` and the attributes following them
+    fn members(&self) -> Option<String> {
+        let mut out = " {".to_string();
+        for field in self.fields.as_ref()? {
+            let member = match (&field.name, &field.ty.kind) {
+                // An anonymous member keeps its members in the enclosing scope
+                (None, TypeKind::Record(record)) if record.is_anonymous && field.bit_width.is_none() => {
+                    format!("{}{}", record.keyword(), record.members()?)
+                }
+                (name, _) => field.ty.to_c_string(name.as_deref()),
+            };
+            out.push_str(&format!(" {}", member));
+            if let Some(width) = field.bit_width {
+                out.push_str(&format!(" : {}", width));
+            }
+            out.push(';');
+        }
+        out.push_str(" }");
+        if self.is_packed {
+            out.push_str(" __attribute__((packed))");
+        }
+        if let Some(alignment) = self.alignment {
+            out.push_str(&format!(" __attribute__((aligned({})))", alignment));
+        }
+        Some(out)
+    }
+
+    fn size_and_align(&self, model : DataModel) -> Option<(u64, u64)> {
+        let fields = self.fields.as_ref()?;
+        let mut align = 1;
+        // Offset of the next member, in bits
+        let mut offset = 0u64;
+        let mut size = 0u64;
+        for (index, field) in fields.iter().enumerate() {
+            let is_last = index + 1 == fields.len();
+            let (field_size, field_align) = match (&field.ty.kind, field.ty.size_and_align(model)) {
+                (_, Some(layout)) => layout,
+                // A flexible array member only aligns the end of the struct
+                (TypeKind::Array { element, length : ArrayLength::Unknown }, None) if is_last && !self.is_union => {
+                    (0, element.size_and_align(model)?.1)
+                }
+                _ => return None,
+            };
+            let field_align = if self.is_packed { 1 } else { field_align.min(model.max_field_align) };
+            match field.bit_width {
+                // A zero-width bit-field starts the next unit of its type
+                Some(0) => offset = offset.next_multiple_of(field_align * 8),
+                Some(width) => {
+                    let width = width as u64;
+                    let unit = field_size * 8;
+                    let start = if self.is_union { 0 } else { offset };
+                    // Moved to the next unit rather than straddling two
+                    let start = if !self.is_packed && start / unit != (start + width - 1) / unit {
+                        start.next_multiple_of(field_align * 8)
+                    } else {
+                        start
+                    };
+                    if field.name.is_some() {
+                        align = align.max(field_align);
+                    }
+                    size = size.max(start + width);
+                    if !self.is_union {
+                        offset = start + width;
+                    }
+                }
+                None => {
+                    let start = if self.is_union { 0 } else { offset.next_multiple_of(field_align * 8) };
+                    align = align.max(field_align);
+                    size = size.max(start + field_size * 8);
+                    if !self.is_union {
+                        offset = start + field_size * 8;
+                    }
+                }
+            }
+        }
+        if let Some(alignment) = self.alignment {
+            align = align.max(alignment);
+        }
+        Some((size.max(offset).div_ceil(8).next_multiple_of(align), align))
+    }
+}
+
+impl FunctionType {
+    /// Whether the function types are compatible: the return types, and the
+    /// parameters when both have a prototype. Against one declared without,
+    /// the other must not be variadic nor take arguments that change under
+    /// the default promotions.
+    fn is_compatible_with(&self, other : &FunctionType) -> bool {
+        if !self.return_type.is_compatible_with(&other.return_type) {
+            return false;
+        }
+        match (self.has_prototype, other.has_prototype) {
+            (true, true) => self.is_variadic == other.is_variadic
+                && self.parameters.len() == other.parameters.len()
+                && self.parameters.iter().zip(&other.parameters).all(|(a, b)| a.is_compatible_parameter(b)),
+            (true, false) => self.accepts_promoted_arguments(),
+            (false, true) => other.accepts_promoted_arguments(),
+            (false, false) => true,
+        }
+    }
+
+    fn accepts_promoted_arguments(&self) -> bool {
+        !self.is_variadic && self.parameters.iter().all(|parameter| parameter.is_compatible_parameter(&parameter.promoted()))
+    }
+}
+
+impl EnumType {
+    /// Whether the enum is compatible with the integer type `kind`: the one
+    /// GCC and clang give it, `unsigned int` unless a constant is negative.
+    /// Either is accepted when the constants are not known.
+    fn is_compatible_with(&self, kind : IntegerKind) -> bool {
+        if self.constants.is_empty() {
+            return matches!(kind, IntegerKind::Int | IntegerKind::UnsignedInt);
+        }
+        let expected = if self.constants.values().any(|value| *value < 0) { IntegerKind::Int } else { IntegerKind::UnsignedInt };
+        kind == expected
+    }
+
+    /// A definition of the enum under its tag, constants in value order
+    pub fn to_c_definition(&self) -> String {
+        let mut constants : Vec<(&String, &i64)> = self.constants.iter().collect();
+        constants.sort_by_key(|(name, value)| (**value, *name));
+        let constants : Vec<String> = constants.iter()
+            .map(|(name, value)| format!("{} = {}", name, value))
+            .collect();
+        format!("enum {} {{ {} }}", self.name, constants.join(", "))
+    }
+}
+
+impl IntegerKind {
+    /// The type specifiers naming the kind
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegerKind::Char => "char",
+            IntegerKind::SignedChar => "signed char",
+            IntegerKind::UnsignedChar => "unsigned char",
+            IntegerKind::Short => "short",
+            IntegerKind::UnsignedShort => "unsigned short",
+            IntegerKind::Int => "int",
+            IntegerKind::UnsignedInt => "unsigned int",
+            IntegerKind::Long => "long",
+            IntegerKind::UnsignedLong => "unsigned long",
+            IntegerKind::LongLong => "long long",
+            IntegerKind::UnsignedLongLong => "unsigned long long",
+            IntegerKind::Int128 => "__int128",
+            IntegerKind::UnsignedInt128 => "unsigned __int128",
+        }
+    }
+}
+
+impl FloatKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FloatKind::Float => "float",
+            FloatKind::Double => "double",
+            FloatKind::LongDouble => "long double",
+        }
+    }
+}
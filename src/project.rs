@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::build_system::BuildSystem;
+use crate::call_graph::CallGraph;
+use crate::inliner::{Expansion, InlineOptions, Inliner};
+use crate::parser::ast::AstParser;
+use crate::parser::function_db::FunctionDatabase;
+
+/// A parsed C project, from which call graphs are built and expanded:
+///
+/// ```no_run
+/// use expansion::{InlineOptions, Project};
+///
+/// let project = Project::open("path/to/project")?;
+/// let expansion = project.call_graph("main")?.expand(InlineOptions::default())?;
+/// println!("{}", expansion.source);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Only one project can be parsed at a time in a process, as libclang
+/// allows a single instance, but the parser is gone once `open` returns.
+pub struct Project {
+    root: PathBuf,
+    database: FunctionDatabase,
+}
+
+impl Project {
+    /// Parse the project in `path`, with the compile commands of the
+    /// compile_commands.json at its root, else of the build system found
+    /// there as `code-inliner` would
+    pub fn open(path: impl AsRef<Path>) -> Result<Project> {
+        let root = path.as_ref().canonicalize()?;
+        let build_system = BuildSystem::detect(&root)
+            .ok_or_else(|| anyhow::anyhow!("No compile_commands.json, build files or IDE project in {}", root.display()))?;
+        let database_dir = build_system.database_dir(&root, &[], &[], None)?;
+        Project::parse(&root, AstParser::open(&root, &database_dir)?)
+    }
+
+    /// Parse the project under `root` with `parser`, set up with the
+    /// compile commands, toolchain and flags to use
+    pub fn parse(root: impl AsRef<Path>, parser: AstParser) -> Result<Project> {
+        Ok(Project {
+            root: root.as_ref().canonicalize()?,
+            database: parser.parse_all_files(false)?,
+        })
+    }
+
+    /// A project from a database parsed before, e.g. one `FunctionDatabase::load` read
+    pub fn from_database(root: impl AsRef<Path>, database: FunctionDatabase) -> Project {
+        Project { root: root.as_ref().to_path_buf(), database }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The functions, macros, types and globals of every parsed file
+    pub fn database(&self) -> &FunctionDatabase {
+        &self.database
+    }
+
+    /// The graph of the functions reachable from the function named `entry`
+    pub fn call_graph(&self, entry: &str) -> Result<EntryGraph<'_>> {
+        Ok(EntryGraph {
+            database: &self.database,
+            entry: entry.to_string(),
+            graph: CallGraph::build(&self.database, entry)?,
+        })
+    }
+}
+
+/// The call graph of a project from one entry point
+pub struct EntryGraph<'a> {
+    database: &'a FunctionDatabase,
+    entry: String,
+    graph: CallGraph,
+}
+
+impl EntryGraph<'_> {
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    pub fn graph(&self) -> &CallGraph {
+        &self.graph
+    }
+
+    /// The entry point's source with the calls of the graph inlined as
+    /// `options` allow
+    pub fn expand(&self, options: InlineOptions) -> Result<Expansion> {
+        Inliner::new(self.database, options).expand(&self.entry)
+    }
+}
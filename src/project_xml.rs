@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
+/// An element of the XML project files of IDEs, with what it contains.
+/// Only what those files use is understood: elements, attributes, text,
+/// comments, CDATA and the predefined and numeric entities.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: BTreeMap<String, String>,
+    pub children: Vec<Element>,
+    /// The text directly inside the element, trimmed
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// The first child named `name`
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Text of the first child named `name`
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.as_str())
+    }
+
+    /// Every element below this one named `name`, in document order
+    pub fn descendants_named<'a>(&'a self, name: &str) -> Vec<&'a Element> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            }
+            found.extend(child.descendants_named(name));
+        }
+        found
+    }
+}
+
+/// The root element of an XML document
+pub fn parse(text: &str) -> Result<Element> {
+    let mut stack = vec![Element::default()];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut stack, rest);
+            break;
+        };
+        push_text(&mut stack, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.split_once("-->").map_or("", |(_, after)| after);
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let (data, after) = after.split_once("]]>").unwrap_or((after, ""));
+            if let Some(current) = stack.last_mut() {
+                current.text.push_str(data.trim());
+            }
+            rest = after;
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = rest.split_once('>').map_or("", |(_, after)| after);
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let (name, after) = after.split_once('>').ok_or_else(|| anyhow::anyhow!("Unterminated closing tag"))?;
+            let element = stack.pop().filter(|element| element.name == name.trim())
+                .ok_or_else(|| anyhow::anyhow!("Unexpected closing tag </{}>", name.trim()))?;
+            stack.last_mut()
+                .ok_or_else(|| anyhow::anyhow!("Unexpected closing tag </{}>", name.trim()))?
+                .children.push(element);
+            rest = after;
+        } else {
+            let end = tag_end(rest).ok_or_else(|| anyhow::anyhow!("Unterminated tag"))?;
+            let tag = &rest[1..end];
+            let (tag, closed) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let element = start_tag(tag);
+            if closed {
+                if let Some(current) = stack.last_mut() {
+                    current.children.push(element);
+                }
+            } else {
+                stack.push(element);
+            }
+            rest = &rest[end + 1..];
+        }
+    }
+    if stack.len() != 1 {
+        anyhow::bail!("Unclosed element <{}>", stack.last().map(|element| element.name.as_str()).unwrap_or_default());
+    }
+    stack.pop()
+        .and_then(|document| document.children.into_iter().next())
+        .ok_or_else(|| anyhow::anyhow!("No root element"))
+}
+
+fn push_text(stack: &mut [Element], text: &str) {
+    let text = unescape(text.trim());
+    if let Some(current) = stack.last_mut()
+        && !text.is_empty()
+    {
+        current.text.push_str(&text);
+    }
+}
+
+/// Offset of the `>` closing the tag at the start of `text`, skipping those
+/// inside attribute values
+fn tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return Some(index),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The element a start tag opens, `name attribute="value" ...`
+fn start_tag(tag: &str) -> Element {
+    let tag = tag.trim();
+    let (name, mut rest) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+    let mut attributes = BTreeMap::new();
+    while let Some((key, after)) = rest.split_once('=') {
+        let after = after.trim_start();
+        let Some(quote) = after.chars().next().filter(|c| matches!(c, '"' | '\'')) else {
+            break;
+        };
+        let Some((value, after)) = after[1..].split_once(quote) else {
+            break;
+        };
+        attributes.insert(key.trim().to_string(), unescape(value));
+        rest = after;
+    }
+    Element { name: name.to_string(), attributes, ..Default::default() }
+}
+
+/// `text` with its entity references replaced
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
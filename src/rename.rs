@@ -0,0 +1,106 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::parser::ast::AstParser;
+use crate::parser::function_db::FunctionDatabase;
+use crate::rewriter::{self, TextEdit};
+
+pub struct RenameSummary {
+    /// Number of occurrences renamed in each file
+    pub files: BTreeMap<PathBuf, usize>,
+    /// Occurrences whose text didn't match, typically inside macro expansions
+    pub skipped: usize,
+}
+
+impl RenameSummary {
+    pub fn total(&self) -> usize {
+        self.files.values().sum()
+    }
+}
+
+/// Rename the function `old` to `new` across the whole project. When several
+/// static functions share the name, `file` selects the one defined there.
+pub fn rename_function(
+    parser: &AstParser,
+    db: &FunctionDatabase,
+    old: &str,
+    new: &str,
+    file: Option<&Path>,
+    dry_run: bool,
+) -> Result<RenameSummary> {
+    if !rewriter::is_identifier(new) {
+        anyhow::bail!("{} is not a valid C identifier", new);
+    }
+    // Any definition counts, a static of one file being enough to clash there
+    if let Some(existing) = db.definitions_named(new).first() {
+        anyhow::bail!("A function named {} already exists in {}", new, existing.source_file.display());
+    }
+
+    let (references, shadowing) = parser.find_symbol_references(old, new)?;
+    if references.is_empty() {
+        anyhow::bail!("No function named {} found in the project", old);
+    }
+
+    let wanted_file = file.map(|f| f.canonicalize().unwrap_or_else(|_| f.to_path_buf()));
+    let mut symbols: BTreeSet<&str> = references.iter().map(|r| r.usr.as_str()).collect();
+    if let Some(wanted_file) = &wanted_file {
+        symbols.retain(|usr| {
+            references.iter().any(|r| r.usr == *usr && r.file.ends_with(wanted_file))
+        });
+    }
+    if symbols.len() > 1 {
+        let candidates: Vec<String> = symbols.iter()
+            .filter_map(|usr| references.iter().find(|r| r.usr == *usr && r.is_static))
+            .map(|r| r.file.display().to_string())
+            .collect();
+        anyhow::bail!(
+            "{} is ambiguous, pass --file to pick one of the static definitions in: {}",
+            old,
+            candidates.join(", ")
+        );
+    }
+    let Some(usr) = symbols.into_iter().next() else {
+        anyhow::bail!("No function named {} found in the requested file", old);
+    };
+
+    // The renamed references would refer to that declaration instead
+    if let Some(clash) = shadowing.iter().find(|clash| clash.usr == usr) {
+        anyhow::bail!(
+            "{} is already declared at {}:{}, in scope at the reference in {}:{}",
+            new,
+            clash.file.display(),
+            clash.line,
+            clash.reference_file.display(),
+            clash.reference_line
+        );
+    }
+
+    let mut edits_per_file: BTreeMap<PathBuf, Vec<TextEdit>> = BTreeMap::new();
+    for reference in references.iter().filter(|r| r.usr == usr) {
+        edits_per_file.entry(reference.file.clone()).or_default().push(TextEdit {
+            offset: reference.offset,
+            len: old.len(),
+            replacement: new.to_string(),
+        });
+    }
+
+    let mut summary = RenameSummary { files: BTreeMap::new(), skipped: 0 };
+    for (path, edits) in edits_per_file {
+        let source = std::fs::read_to_string(&path)?;
+        let (valid, invalid): (Vec<TextEdit>, Vec<TextEdit>) = edits.into_iter()
+            .partition(|e| source.get(e.offset..e.offset + e.len) == Some(old));
+        summary.skipped += invalid.len();
+        if valid.is_empty() {
+            continue;
+        }
+
+        if !dry_run {
+            std::fs::write(&path, rewriter::apply_edits(&source, &valid))?;
+        }
+        summary.files.insert(path, valid.len());
+    }
+
+    Ok(summary)
+}
@@ -0,0 +1,1348 @@
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    /// Index of the call site this token names, while it is still pending expansion
+    pub site: Option<usize>,
+    /// Index of the call site report this token stands for in the output
+    pub report: Option<usize>,
+}
+
+impl Token {
+    pub fn new(text: impl Into<String>) -> Self {
+        Token { text: text.into(), site: None, report: None }
+    }
+
+    pub fn is(&self, text: &str) -> bool {
+        self.text == text
+    }
+}
+
+/// Where a call sits inside the statement that contains it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallPosition {
+    /// The call can be evaluated right before `statement`
+    Hoistable(Statement),
+    /// The call is part of a `for`/`while`/`do` header and runs on every iteration
+    LoopCondition,
+    /// The call is only evaluated depending on a `&&`, `||` or `?:` operand
+    ShortCircuit,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    /// First token of the statement
+    pub start: usize,
+    /// Last token of the statement, usually its `;`
+    pub end: usize,
+    /// Declarations can't be wrapped in a block without changing their scope
+    pub is_declaration: bool,
+}
+
+const CONTROL_KEYWORDS: [&str; 4] = ["if", "while", "for", "switch"];
+const LOOP_KEYWORDS: [&str; 2] = ["while", "for"];
+const DECLARATION_KEYWORDS: [&str; 21] = [
+    "void", "char", "short", "int", "long", "float", "double", "signed", "unsigned",
+    "_Bool", "struct", "union", "enum", "const", "volatile", "static", "register",
+    "extern", "auto", "typedef", "_Complex",
+];
+
+const ASM_KEYWORDS: [&str; 3] = ["asm", "__asm", "__asm__"];
+const ASM_QUALIFIERS: [&str; 5] = ["volatile", "__volatile__", "__volatile", "inline", "goto"];
+
+/// Split a body back into tokens. Bodies are stored as clang token spellings
+/// joined by single spaces, so only literals containing spaces need care,
+/// along with inline assembly, which is kept as written and comes back as a
+/// single token running from its keyword to the end of its operands.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        if let Some(end) = asm_statement_end(&chars, i) {
+            tokens.push(Token::new(chars[start..end].iter().collect::<String>()));
+            i = end;
+            continue;
+        }
+        // Encoding prefixes (L"", u8"", u'', U"") belong to the literal
+        while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+            i += 1;
+        }
+        let prefix: String = chars[start..i].iter().collect();
+        let is_literal_prefix = matches!(prefix.as_str(), "" | "L" | "u" | "U" | "u8");
+
+        if i < chars.len() && is_literal_prefix && (chars[i] == '"' || chars[i] == '\'') {
+            let quote = chars[i];
+            i += 1;
+            while i < chars.len() && chars[i] != quote {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+        } else {
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+        }
+
+        tokens.push(Token::new(chars[start..i].iter().collect::<String>()));
+    }
+
+    tokens
+}
+
+/// End of the inline assembly starting at `start`, if a word there is an asm
+/// keyword: after its qualifiers, either a parenthesised GNU statement, a
+/// braced MSVC block, or an MSVC single line
+fn asm_statement_end(chars: &[char], start: usize) -> Option<usize> {
+    let word_end = |from: usize| {
+        (from..chars.len()).find(|&k| !(chars[k].is_alphanumeric() || chars[k] == '_')).unwrap_or(chars.len())
+    };
+    let end = word_end(start);
+    if start > 0 && (chars[start - 1].is_alphanumeric() || chars[start - 1] == '_') {
+        return None;
+    }
+    let keyword: String = chars[start..end].iter().collect();
+    if !ASM_KEYWORDS.contains(&keyword.as_str()) {
+        return None;
+    }
+
+    let mut i = end;
+    loop {
+        while i < chars.len() && chars[i].is_whitespace() {
+            i += 1;
+        }
+        let next = word_end(i);
+        let word: String = chars[i..next].iter().collect();
+        if !ASM_QUALIFIERS.contains(&word.as_str()) {
+            break;
+        }
+        i = next;
+    }
+
+    let (open, close) = match chars.get(i) {
+        Some('(') => ('(', ')'),
+        Some('{') => ('{', '}'),
+        _ => {
+            let line_end = (i..chars.len()).find(|&k| chars[k] == '\n').unwrap_or(chars.len());
+            return Some(line_end);
+        }
+    };
+    let mut depth = 0usize;
+    let mut quote = None;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(_) if c == '\\' => i += 1,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == open => depth += 1,
+            None if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            None => {}
+        }
+        i += 1;
+    }
+    Some(chars.len())
+}
+
+/// Whether a token holds an inline assembly statement
+pub fn is_asm(text: &str) -> bool {
+    ASM_KEYWORDS.iter().any(|keyword| {
+        text.strip_prefix(keyword).is_some_and(|rest| !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+    })
+}
+
+/// Whether the token at `index` is an MSVC-style assembly block, which ends
+/// its statement without a `;`
+pub fn is_asm_block(tokens: &[Token], index: usize) -> bool {
+    is_asm(&tokens[index].text) && !tokens.get(index + 1).is_some_and(|t| t.is(";"))
+}
+
+/// Rename the C identifier `from` in the operands of an inline assembly
+/// statement, outside its string literals
+fn rename_in_asm(text: &str, from: &str, to: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut escaped = false;
+    let mut word = String::new();
+    let flush = |word: &mut String, out: &mut String| {
+        out.push_str(if word == from { to } else { word });
+        word.clear();
+    };
+    for c in text.chars() {
+        if let Some(q) = quote {
+            out.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+        } else if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut out);
+            if c == '"' || c == '\'' {
+                quote = Some(c);
+            }
+            out.push(c);
+        }
+    }
+    flush(&mut word, &mut out);
+    out
+}
+
+pub fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c.is_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn is_opening(text: &str) -> bool {
+    matches!(text, "(" | "[" | "{")
+}
+
+fn is_closing(text: &str) -> bool {
+    matches!(text, ")" | "]" | "}")
+}
+
+/// Index of the bracket closing the one at `open`
+pub fn matching_close(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if is_opening(&token.text) {
+            depth += 1;
+        } else if is_closing(&token.text) {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Index of the bracket opening the one at `close`
+pub fn matching_open(tokens: &[Token], close: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for i in (0..=close).rev() {
+        if is_closing(&tokens[i].text) {
+            depth += 1;
+        } else if is_opening(&tokens[i].text) {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Split on `separator` tokens that are not nested inside brackets
+pub fn split_top_level<'a>(tokens: &'a [Token], separator: &str) -> Vec<&'a [Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        if is_opening(&token.text) {
+            depth += 1;
+        } else if is_closing(&token.text) {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && token.is(separator) {
+            parts.push(&tokens[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&tokens[start..]);
+    parts
+}
+
+/// Rename every use of `from`, leaving struct member accesses alone
+pub fn rename_identifier(tokens: &mut [Token], from: &str, to: &str) {
+    for i in 0..tokens.len() {
+        if is_asm(&tokens[i].text) {
+            tokens[i].text = rename_in_asm(&tokens[i].text, from, to);
+            continue;
+        }
+        if !tokens[i].is(from) {
+            continue;
+        }
+        let is_member = i > 0 && (tokens[i - 1].is(".") || tokens[i - 1].is("->"));
+        if !is_member {
+            tokens[i].text = to.to_string();
+        }
+    }
+}
+
+/// Whether `index` is the name of a label definition, `name :`
+fn is_label_definition(tokens: &[Token], index: usize) -> bool {
+    is_label_name(tokens, index)
+        && !is_keyword(&tokens[index].text)
+        && tokens.get(index + 1).is_some_and(|t| t.is(":"))
+}
+
+/// Names of the labels defined in `tokens`, in order of appearance
+pub fn label_names(tokens: &[Token]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for i in 0..tokens.len() {
+        if is_label_definition(tokens, i) && !names.contains(&tokens[i].text) {
+            names.push(tokens[i].text.clone());
+        }
+    }
+    names
+}
+
+/// Rename the label `from` and every `goto` jumping to it. Labels live in
+/// their own namespace, so variables sharing the name are left alone.
+pub fn rename_label(tokens: &mut [Token], from: &str, to: &str) {
+    for i in 0..tokens.len() {
+        let is_goto_target = i > 0 && tokens[i - 1].is("goto");
+        if tokens[i].is(from) && (is_goto_target || is_label_definition(tokens, i)) {
+            tokens[i].text = to.to_string();
+        }
+    }
+}
+
+/// Render a declaration of `name` with the given type spelling
+pub fn declarator(type_name: &str, name: &str) -> String {
+    if let Some(pos) = type_name.find("(*") {
+        let (head, tail) = type_name.split_at(pos + 2);
+        return format!("{}{}{}", head, name, tail);
+    }
+    if let Some(pos) = type_name.find('[') {
+        let (head, tail) = type_name.split_at(pos);
+        return format!("{} {}{}", head.trim_end(), name, tail);
+    }
+    format!("{} {}", type_name, name)
+}
+
+/// Whether a `{` opens an initializer or compound literal rather than a block
+fn is_expression_brace(tokens: &[Token], open: usize) -> bool {
+    if open == 0 {
+        return false;
+    }
+    let prev = &tokens[open - 1];
+    if prev.is("=") || prev.is(",") || prev.is("return") {
+        return true;
+    }
+    if prev.is(")") {
+        return matching_open(tokens, open - 1)
+            .map(|paren| paren == 0 || !CONTROL_KEYWORDS.contains(&tokens[paren - 1].text.as_str()))
+            .unwrap_or(false);
+    }
+    false
+}
+
+/// Whether the `:` at `colon` ends a label rather than a ternary branch
+fn is_label_colon(tokens: &[Token], colon: usize) -> bool {
+    let mut depth = 0usize;
+    for i in (0..colon).rev() {
+        let text = tokens[i].text.as_str();
+        if is_closing(text) {
+            if depth == 0 && text == "}" {
+                return true;
+            }
+            depth += 1;
+        } else if is_opening(text) {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        } else if depth == 0 {
+            match text {
+                "?" => return false,
+                ";" | ":" => return true,
+                _ => {}
+            }
+        }
+    }
+    true
+}
+
+/// Locate the start of the statement containing `index` and report whether it
+/// sits inside a loop header. Comments preceding the statement are not part of it.
+pub fn statement_start(tokens: &[Token], index: usize) -> Result<usize, CallPosition> {
+    let mut start = enclosing_statement_start(tokens, index)?;
+    while start < index && is_comment(&tokens[start].text) {
+        start += 1;
+    }
+    Ok(start)
+}
+
+fn enclosing_statement_start(tokens: &[Token], index: usize) -> Result<usize, CallPosition> {
+    // The `;` of a `for` header don't end statements
+    if enclosing_loop_header(tokens, index).is_some() {
+        return Err(CallPosition::LoopCondition);
+    }
+    let mut depth = 0usize;
+    let mut k = index;
+
+    while k > 0 {
+        k -= 1;
+        let text = tokens[k].text.as_str();
+
+        if depth == 0 && is_asm_block(tokens, k) {
+            return Ok(k + 1);
+        }
+        if is_closing(text) {
+            if depth == 0 && text == ")" {
+                // Body of a braceless if/while/for/switch
+                if let Some(open) = matching_open(tokens, k)
+                    && open > 0
+                    && CONTROL_KEYWORDS.contains(&tokens[open - 1].text.as_str())
+                {
+                    return Ok(k + 1);
+                }
+            }
+            if depth == 0 && text == "}" {
+                let is_expression = matching_open(tokens, k)
+                    .is_some_and(|open| is_expression_brace(tokens, open));
+                if !is_expression {
+                    return Ok(k + 1);
+                }
+            }
+            depth += 1;
+            continue;
+        }
+
+        if is_opening(text) {
+            if depth > 0 {
+                depth -= 1;
+                continue;
+            }
+            if text == "{" && !is_expression_brace(tokens, k) {
+                return Ok(k + 1);
+            }
+            // Leaving an enclosing bracket: check what it belongs to
+            if text == "(" && k > 0 {
+                let keyword = tokens[k - 1].text.as_str();
+                if LOOP_KEYWORDS.contains(&keyword) {
+                    return Err(CallPosition::LoopCondition);
+                }
+            }
+            continue;
+        }
+
+        if depth == 0 {
+            match text {
+                ";" | "else" | "do" => return Ok(k + 1),
+                ":" if is_label_colon(tokens, k) => return Ok(k + 1),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(0)
+}
+
+/// Index of the last token of the statement starting at `start`
+pub fn statement_end(tokens: &[Token], start: usize) -> Option<usize> {
+    let first = tokens.get(start)?;
+    match first.text.as_str() {
+        "{" => matching_close(tokens, start),
+        "if" => {
+            let close = matching_close(tokens, start + 1)?;
+            let body_end = statement_end(tokens, close + 1)?;
+            if tokens.get(body_end + 1).is_some_and(|t| t.is("else")) {
+                statement_end(tokens, body_end + 2)
+            } else {
+                Some(body_end)
+            }
+        }
+        "while" | "for" | "switch" => {
+            let close = matching_close(tokens, start + 1)?;
+            statement_end(tokens, close + 1)
+        }
+        "do" => {
+            let body_end = statement_end(tokens, start + 1)?;
+            let close = matching_close(tokens, body_end + 2)?;
+            Some(close + 1)
+        }
+        _ if is_asm_block(tokens, start) => Some(start),
+        _ => {
+            let mut depth = 0usize;
+            for (i, token) in tokens.iter().enumerate().skip(start) {
+                if is_opening(&token.text) {
+                    depth += 1;
+                } else if is_closing(&token.text) {
+                    depth = depth.checked_sub(1)?;
+                } else if depth == 0 && token.is(";") {
+                    return Some(i);
+                }
+            }
+            None
+        }
+    }
+}
+
+fn looks_like_declaration(tokens: &[Token]) -> bool {
+    let Some(first) = tokens.first() else {
+        return false;
+    };
+    if DECLARATION_KEYWORDS.contains(&first.text.as_str()) {
+        return true;
+    }
+    if is_keyword(&first.text) {
+        return false;
+    }
+    // `type_t name ...` or `type_t * name ...`, with a typedef name up front
+    let mut rest = tokens.iter().skip(1).skip_while(|t| t.is("*") || t.is("const"));
+    is_identifier(&first.text)
+        && tokens.get(1).is_some_and(|t| is_identifier(&t.text) || t.is("*"))
+        && rest.next().is_some_and(|t| is_identifier(&t.text))
+}
+
+const ASSIGNMENT_OPERATORS: [&str; 11] = ["=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>="];
+
+/// Conditional state of one bracket level while scanning towards a call
+#[derive(Default)]
+struct Level {
+    /// Opening bracket of the level, none for the statement itself
+    open: Option<usize>,
+    /// A `&&`, `||` or `?` guards what follows at this level
+    conditional: bool,
+    /// `?` still waiting for their `:`
+    open_ternaries: usize,
+}
+
+/// Bracket levels enclosing `index` within the statement starting at `start`,
+/// outermost first
+fn conditional_levels(tokens: &[Token], start: usize, index: usize) -> Vec<Level> {
+    let mut levels = vec![Level::default()];
+    for (k, token) in tokens.iter().enumerate().take(index).skip(start) {
+        let text = token.text.as_str();
+        if is_opening(text) {
+            levels.push(Level { open: Some(k), ..Level::default() });
+        } else if is_closing(text) {
+            if levels.len() > 1 {
+                levels.pop();
+            }
+        } else if let Some(level) = levels.last_mut() {
+            match text {
+                "&&" | "||" => level.conditional = true,
+                "?" => {
+                    level.conditional = true;
+                    level.open_ternaries += 1;
+                }
+                ":" => level.open_ternaries = level.open_ternaries.saturating_sub(1),
+                // Whatever follows a comma, or an argument separator, runs anyway
+                "," if level.open_ternaries == 0 => level.conditional = false,
+                _ => {}
+            }
+        }
+    }
+    levels
+}
+
+/// Whether the call at `index` is only conditionally evaluated within the
+/// statement tokens starting at `start`
+fn is_conditionally_evaluated(tokens: &[Token], start: usize, index: usize) -> bool {
+    conditional_levels(tokens, start, index).iter().any(|level| level.conditional)
+}
+
+/// Rewrite the outermost `&&`, `||` or `?:` that guards the call at `index`
+/// into if-statements, so that the call only runs when it did originally.
+/// A `&&`/`||` chain becomes `int TEMP = ( a ) != 0 ; if ( TEMP ) { TEMP = ( b ) != 0 ; }`
+/// placed before its statement, with TEMP in its place. A `?:` is only
+/// rewritten when it makes up a whole expression statement, a `return` or the
+/// right-hand side of an assignment. Returns none when neither applies.
+pub fn lower_short_circuit(tokens: &[Token], index: usize, temp: &str) -> Option<Vec<Token>> {
+    let start = statement_start(tokens, index).ok()?;
+    let end = statement_end(tokens, start)?;
+
+    let levels = conditional_levels(tokens, start, index);
+    let level = levels.iter().find(|level| level.conditional)?;
+    let (low, high) = match level.open {
+        Some(open) => (open + 1, matching_close(tokens, open)?),
+        None => (start, end),
+    };
+
+    // The operand of `,` or of an assignment holding the call
+    let mut segment_start = if tokens[low].is("return") { low + 1 } else { low };
+    let mut segment_end = high;
+    let mut depth = 0usize;
+    for (k, token) in tokens.iter().enumerate().take(high).skip(low) {
+        let text = token.text.as_str();
+        if is_opening(text) {
+            depth += 1;
+        } else if is_closing(text) {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && (text == "," || ASSIGNMENT_OPERATORS.contains(&text)) {
+            if k < index {
+                segment_start = k + 1;
+            } else {
+                segment_end = k;
+                break;
+            }
+        }
+    }
+    // What a `,` sequences before the segment would run after its prefix
+    if has_sequencing_comma(&tokens[start..segment_start]) {
+        return None;
+    }
+    let segment = &tokens[segment_start..segment_end];
+
+    if top_level_position(segment, "?").is_some() {
+        return lower_ternary(tokens, start, end, segment_start, segment_end, level.open.is_none());
+    }
+    // `||` binds looser, so it splits first
+    let (operator, guard) = if top_level_position(segment, "||").is_some() {
+        ("||", format!("if ( ! {} ) {{", temp))
+    } else if top_level_position(segment, "&&").is_some() {
+        ("&&", format!("if ( {} ) {{", temp))
+    } else {
+        return None;
+    };
+
+    let mut prefix = Vec::new();
+    for (position, operand) in split_top_level(segment, operator).into_iter().enumerate() {
+        if position == 0 {
+            prefix.extend(tokenize(&format!("int {} = (", temp)));
+        } else {
+            prefix.extend(tokenize(&guard));
+            prefix.extend(tokenize(&format!("{} = (", temp)));
+        }
+        prefix.extend(operand.iter().cloned());
+        prefix.extend(tokenize(") != 0 ;"));
+        if position > 0 {
+            prefix.push(Token::new("}"));
+        }
+    }
+
+    let declaration_end = tokens[start..=end].iter()
+        .position(|t| t.is("=") || t.is(";"))
+        .map(|p| start + p)
+        .unwrap_or(end);
+    let wrap = !looks_like_declaration(&tokens[start..declaration_end]);
+
+    let mut lowered = tokens[..start].to_vec();
+    if wrap {
+        lowered.push(Token::new("{"));
+    }
+    lowered.extend(prefix);
+    lowered.extend(tokens[start..segment_start].iter().cloned());
+    lowered.push(Token::new(temp));
+    lowered.extend(tokens[segment_end..=end].iter().cloned());
+    if wrap {
+        lowered.push(Token::new("}"));
+    }
+    lowered.extend(tokens[end + 1..].iter().cloned());
+    Some(lowered)
+}
+
+/// Whether `tokens`, from the start of a statement, hold a `,` that
+/// sequences its left operand before whatever follows `tokens`: a comma
+/// operator or declarator `,` of the statement itself or of a parenthesis
+/// still open at the end. Those of argument lists and of groups closed
+/// before the end leave the order free.
+fn has_sequencing_comma(tokens: &[Token]) -> bool {
+    // Open brackets, whether each groups an expression rather than
+    // arguments or initializers, and whether a `,` of it came up
+    let mut groups: Vec<(bool, bool)> = Vec::new();
+    let mut top_level = false;
+    for (k, token) in tokens.iter().enumerate() {
+        let text = token.text.as_str();
+        if is_opening(text) {
+            let previous = if k > 0 { tokens[k - 1].text.as_str() } else { "" };
+            let is_keyword = CONTROL_KEYWORDS.contains(&previous)
+                || DECLARATION_KEYWORDS.contains(&previous)
+                || matches!(previous, "return" | "sizeof" | "case" | "do" | "else");
+            let is_call = (is_identifier(previous) && !is_keyword) || previous == ")" || previous == "]";
+            groups.push((text == "(" && !is_call, false));
+        } else if is_closing(text) {
+            groups.pop();
+        } else if text == "," {
+            match groups.last_mut() {
+                Some((true, comma)) => *comma = true,
+                Some(_) => {}
+                None => top_level = true,
+            }
+        }
+    }
+    top_level || groups.iter().any(|(_, comma)| *comma)
+}
+
+/// Rewrite the loop whose header holds the call at `index` so that its
+/// condition, and the `for` initialisation and step, become plain statements:
+///
+/// * `while ( c ) body` becomes `for ( ; ; ) { if ( ! ( c ) ) break ; body }`
+/// * `do body while ( c ) ;` becomes `for ( ; ; ) { body LABEL : ; if ( ! ( c ) ) break ; }`
+/// * `for ( init ; c ; step ) body` becomes
+///   `{ init ; for ( ; ; ) { if ( ! ( c ) ) break ; body LABEL : ; step ; } }`
+///
+/// `continue` statements of the loop jump to `LABEL` so they still run the
+/// step or condition. Returns none when the call is not in a loop header.
+pub fn lower_loop_condition(tokens: &[Token], index: usize, label: &str) -> Option<Vec<Token>> {
+    let open = enclosing_loop_header(tokens, index)?;
+    let keyword = open.checked_sub(1)?;
+    let close = matching_close(tokens, open)?;
+    let header = &tokens[open + 1..close];
+    let exit_unless = |condition: &[Token]| {
+        if condition.is_empty() {
+            return Vec::new();
+        }
+        let mut check = tokenize("if ( ! (");
+        check.extend(condition.iter().cloned());
+        check.extend(tokenize(") ) break ;"));
+        check
+    };
+
+    let mut lowered;
+    let rest;
+    if tokens[keyword].is("for") {
+        let [init, condition, step] = split_top_level(header, ";")[..] else {
+            return None;
+        };
+        let body_end = statement_end(tokens, close + 1)?;
+        let mut body = tokens[close + 1..=body_end].to_vec();
+        let jumps = !step.is_empty() && replace_continues(&mut body, label);
+
+        lowered = tokens[..keyword].to_vec();
+        if !init.is_empty() {
+            lowered.push(Token::new("{"));
+            lowered.extend(init.iter().cloned());
+            lowered.push(Token::new(";"));
+        }
+        lowered.extend(tokenize("for ( ; ; ) {"));
+        lowered.extend(exit_unless(condition));
+        lowered.extend(body);
+        if jumps {
+            lowered.extend(tokenize(&format!("{} : ;", label)));
+        }
+        if !step.is_empty() {
+            lowered.extend(step.iter().cloned());
+            lowered.push(Token::new(";"));
+        }
+        lowered.push(Token::new("}"));
+        if !init.is_empty() {
+            lowered.push(Token::new("}"));
+        }
+        rest = body_end + 1;
+    } else if let Some(do_keyword) = do_keyword(tokens, keyword) {
+        if !tokens.get(close + 1)?.is(";") {
+            return None;
+        }
+        let mut body = tokens[do_keyword + 1..keyword].to_vec();
+        let jumps = replace_continues(&mut body, label);
+
+        lowered = tokens[..do_keyword].to_vec();
+        lowered.extend(tokenize("for ( ; ; ) {"));
+        lowered.extend(body);
+        if jumps {
+            lowered.extend(tokenize(&format!("{} : ;", label)));
+        }
+        lowered.extend(exit_unless(header));
+        lowered.push(Token::new("}"));
+        rest = close + 2;
+    } else if tokens[keyword].is("while") {
+        let body_end = statement_end(tokens, close + 1)?;
+        lowered = tokens[..keyword].to_vec();
+        lowered.extend(tokenize("for ( ; ; ) {"));
+        lowered.extend(exit_unless(header));
+        lowered.extend(tokens[close + 1..=body_end].iter().cloned());
+        lowered.push(Token::new("}"));
+        rest = body_end + 1;
+    } else {
+        return None;
+    }
+
+    lowered.extend(tokens[rest..].iter().cloned());
+    Some(lowered)
+}
+
+/// Opening parenthesis of the `while`/`for` header around `index`
+fn enclosing_loop_header(tokens: &[Token], index: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for k in (0..index).rev() {
+        let text = tokens[k].text.as_str();
+        if is_closing(text) {
+            depth += 1;
+        } else if is_opening(text) {
+            if depth > 0 {
+                depth -= 1;
+                continue;
+            }
+            if text != "(" {
+                return None;
+            }
+            if k > 0 && LOOP_KEYWORDS.contains(&tokens[k - 1].text.as_str()) {
+                return Some(k);
+            }
+        }
+    }
+    None
+}
+
+/// Turn the `continue` statements belonging to the loop whose body is
+/// `body` into `goto label`, leaving those of nested loops alone
+fn replace_continues(body: &mut Vec<Token>, label: &str) -> bool {
+    let mut replaced = false;
+    let mut k = 0;
+    while k < body.len() {
+        match body[k].text.as_str() {
+            "for" | "while" | "do" => {
+                k = statement_end(body, k).map_or(body.len(), |end| end + 1);
+                continue;
+            }
+            "continue" => {
+                body.splice(k..=k, [Token::new("goto"), Token::new(label)]);
+                replaced = true;
+            }
+            _ => {}
+        }
+        k += 1;
+    }
+    replaced
+}
+
+/// Turn a statement made of `LHS c ? a : b ;` into `if ( c ) { LHS a ; } else { LHS b ; }`
+fn lower_ternary(
+    tokens: &[Token],
+    start: usize,
+    end: usize,
+    segment_start: usize,
+    segment_end: usize,
+    at_statement_level: bool,
+) -> Option<Vec<Token>> {
+    if !at_statement_level || segment_end != end || !tokens[end].is(";") {
+        return None;
+    }
+    let head = &tokens[start..segment_start];
+    let is_assignment = head.last().is_some_and(|t| ASSIGNMENT_OPERATORS.contains(&t.text.as_str()))
+        && !looks_like_declaration(&head[..head.len() - 1]);
+    if !(head.is_empty() || (head.len() == 1 && head[0].is("return")) || is_assignment) {
+        return None;
+    }
+
+    let segment = &tokens[segment_start..segment_end];
+    let question = top_level_position(segment, "?")?;
+    let mut nested = 0usize;
+    let mut depth = 0usize;
+    let mut colon = None;
+    for (k, token) in segment.iter().enumerate().skip(question + 1) {
+        match token.text.as_str() {
+            text if is_opening(text) => depth += 1,
+            text if is_closing(text) => depth = depth.saturating_sub(1),
+            "?" if depth == 0 => nested += 1,
+            ":" if depth == 0 && nested > 0 => nested -= 1,
+            ":" if depth == 0 => {
+                colon = Some(k);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let colon = colon?;
+
+    let mut lowered = tokens[..start].to_vec();
+    lowered.extend(tokenize("if ("));
+    lowered.extend(segment[..question].iter().cloned());
+    lowered.extend(tokenize(") {"));
+    lowered.extend(head.iter().cloned());
+    lowered.extend(segment[question + 1..colon].iter().cloned());
+    lowered.extend(tokenize("; } else {"));
+    lowered.extend(head.iter().cloned());
+    lowered.extend(segment[colon + 1..].iter().cloned());
+    lowered.extend(tokenize("; }"));
+    lowered.extend(tokens[end + 1..].iter().cloned());
+    Some(lowered)
+}
+
+/// Position of `operator` outside any bracket in `tokens`
+fn top_level_position(tokens: &[Token], operator: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (k, token) in tokens.iter().enumerate() {
+        let text = token.text.as_str();
+        if is_opening(text) {
+            depth += 1;
+        } else if is_closing(text) {
+            depth = depth.saturating_sub(1);
+        } else if depth == 0 && text == operator {
+            return Some(k);
+        }
+    }
+    None
+}
+
+/// Work out how the call whose name is at `index` can be expanded
+pub fn classify_call(tokens: &[Token], index: usize) -> CallPosition {
+    let start = match statement_start(tokens, index) {
+        Ok(start) => start,
+        Err(position) => return position,
+    };
+    let Some(end) = statement_end(tokens, start) else {
+        return CallPosition::Unknown;
+    };
+
+    if is_conditionally_evaluated(tokens, start, index) {
+        return CallPosition::ShortCircuit;
+    }
+
+    // Only an if/switch condition may be hoisted along with its whole statement
+    let expression_end = if tokens[start].is("if") || tokens[start].is("switch") {
+        matching_close(tokens, start + 1).unwrap_or(end)
+    } else {
+        end
+    };
+    if index > expression_end {
+        return CallPosition::Unknown;
+    }
+
+    let declaration_end = tokens[start..=end].iter()
+        .position(|t| t.is("=") || t.is(";"))
+        .map(|p| start + p)
+        .unwrap_or(end);
+
+    CallPosition::Hoistable(Statement {
+        start,
+        end,
+        is_declaration: looks_like_declaration(&tokens[start..declaration_end]),
+    })
+}
+
+/// Pretty-print tokens as C source with one statement per line
+pub fn render(tokens: &[Token]) -> String {
+    render_with_lines(tokens).0
+}
+
+/// Like [`render`], also returning the 0-based output line of every token
+pub fn render_with_lines(tokens: &[Token]) -> (String, Vec<usize>) {
+    let mut out = String::new();
+    let mut lines = Vec::with_capacity(tokens.len());
+    let mut line = 0usize;
+    let mut indent = 0usize;
+    let mut paren_depth = 0usize;
+    let mut line_start = true;
+    let mut at_statement_start = true;
+    let mut in_case = false;
+    // Whether each open brace is a block, as opposed to an initializer
+    let mut braces: Vec<bool> = Vec::new();
+
+    let newline = |out: &mut String| {
+        while out.ends_with(' ') {
+            out.pop();
+        }
+        out.push('\n');
+    };
+
+    for (i, token) in tokens.iter().enumerate() {
+        let text = token.text.as_str();
+        let next = tokens.get(i + 1).map(|t| t.text.as_str());
+        let prev = if i > 0 { Some(tokens[i - 1].text.as_str()) } else { None };
+
+        if at_statement_start && (text == "case" || text == "default") {
+            in_case = true;
+        }
+        let is_label = text == ":" && paren_depth == 0
+            && (in_case || (i > 0 && is_label_name(tokens, i - 1)));
+
+        let is_block_brace = match text {
+            "{" => !is_expression_brace(tokens, i),
+            "}" => braces.pop().unwrap_or(true),
+            _ => false,
+        };
+        if text == "{" {
+            braces.push(is_block_brace);
+        }
+
+        if text == "}" && is_block_brace {
+            indent = indent.saturating_sub(1);
+            if !line_start {
+                newline(&mut out);
+                line += 1;
+                line_start = true;
+            }
+        }
+
+        // Preprocessor directives get lines of their own, unindented
+        if is_directive(text) {
+            if !line_start {
+                newline(&mut out);
+                line += 1;
+            }
+            lines.push(line);
+            out.push_str(text);
+            line += text.matches('\n').count();
+            newline(&mut out);
+            line += 1;
+            line_start = true;
+            continue;
+        }
+
+        if line_start {
+            out.push_str(&"    ".repeat(indent));
+            line_start = false;
+        } else {
+            let tight_before = is_label
+                || (text == ";" && prev != Some(":"))
+                || matches!(text, "," | ")" | "]" | "." | "->")
+                || (matches!(text, "++" | "--") && prev.is_some_and(|p| is_identifier(p) || p == ")" || p == "]"))
+                || (text == "(" && prev.is_some_and(|p| is_identifier(p) && !is_keyword(p)))
+                || (text == "[" && prev.is_some_and(|p| is_identifier(p) || p == ")" || p == "]"));
+            let tight_after = prev.is_some_and(|p| matches!(p, "(" | "[" | "." | "->" | "!" | "~"))
+                || (i > 0 && is_unary_operator(tokens, i - 1));
+            if !tight_before && !tight_after {
+                out.push(' ');
+            }
+        }
+        lines.push(line);
+        out.push_str(text);
+        line += text.matches('\n').count();
+
+        match text {
+            "(" | "[" => paren_depth += 1,
+            ")" | "]" => paren_depth = paren_depth.saturating_sub(1),
+            _ => {}
+        }
+
+        let break_line = match text {
+            "{" if is_block_brace => {
+                indent += 1;
+                true
+            }
+            "}" if is_block_brace => match next {
+                Some(";") | Some(",") | Some("else") => false,
+                Some("while") => !closes_do_body(tokens, i),
+                Some(comment) if is_comment(comment) => false,
+                _ => true,
+            },
+            ";" => paren_depth == 0,
+            _ if is_asm_block(tokens, i) => true,
+            // A comment between statements gets the rest of its line
+            _ if is_comment(text) => at_statement_start,
+            _ => is_label && next != Some(";"),
+        };
+
+        if !is_comment(text) {
+            at_statement_start = (matches!(text, "{" | "}") && is_block_brace) || text == ";" || is_label
+                || is_asm_block(tokens, i);
+        }
+        if is_label {
+            in_case = false;
+        }
+
+        if break_line {
+            newline(&mut out);
+            line += 1;
+            line_start = true;
+        }
+    }
+
+    if !line_start {
+        newline(&mut out);
+    }
+    (out, lines)
+}
+
+fn is_directive(text: &str) -> bool {
+    text.starts_with('#')
+}
+
+fn is_comment(text: &str) -> bool {
+    text.starts_with("/*")
+}
+
+fn closes_do_body(tokens: &[Token], close: usize) -> bool {
+    matching_open(tokens, close).is_some_and(|open| open > 0 && tokens[open - 1].is("do"))
+}
+
+/// The `do` whose body, braced or a single statement, ends right before the
+/// `while` at `keyword`
+fn do_keyword(tokens: &[Token], keyword: usize) -> Option<usize> {
+    if !tokens[keyword].is("while") {
+        return None;
+    }
+    let body_end = keyword.checked_sub(1)?;
+    let mut depth = 0usize;
+    for k in (0..keyword).rev() {
+        let text = tokens[k].text.as_str();
+        if is_closing(text) {
+            depth += 1;
+        } else if is_opening(text) {
+            depth = depth.checked_sub(1)?;
+        } else if depth == 0 && text == "do" && statement_end(tokens, k + 1) == Some(body_end) {
+            return Some(k);
+        }
+    }
+    None
+}
+
+/// Whether the identifier at `index` starts a statement, as a label name does
+fn is_label_name(tokens: &[Token], index: usize) -> bool {
+    is_identifier(&tokens[index].text)
+        && (index == 0 || matches!(tokens[index - 1].text.as_str(), "{" | "}" | ";" | ":"))
+}
+
+/// Whether the operator at `index` is used in prefix position (`*p`, `&x`, `-1`)
+fn is_unary_operator(tokens: &[Token], index: usize) -> bool {
+    if !matches!(tokens[index].text.as_str(), "*" | "&" | "-" | "+" | "++" | "--") {
+        return false;
+    }
+    match index.checked_sub(1).map(|p| tokens[p].text.as_str()) {
+        None => true,
+        Some(prev) => (!is_identifier(prev) || is_keyword(prev))
+            && !matches!(prev, ")" | "]")
+            && !prev.starts_with(|c: char| c.is_ascii_digit() || c == '"' || c == '\''),
+    }
+}
+
+fn is_keyword(text: &str) -> bool {
+    matches!(text, "if" | "while" | "for" | "switch" | "return" | "sizeof" | "case"
+        | "goto" | "default" | "else" | "do")
+}
+
+/// Replacement of `len` bytes at `offset` in a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub offset: usize,
+    pub len: usize,
+    pub replacement: String,
+}
+
+/// Apply non-overlapping edits to `source`, last edit first so offsets stay valid
+pub fn apply_edits(source: &str, edits: &[TextEdit]) -> String {
+    let mut sorted: Vec<&TextEdit> = edits.iter().collect();
+    sorted.sort_by_key(|e| std::cmp::Reverse(e.offset));
+
+    let mut out = source.to_string();
+    for edit in sorted {
+        let end = edit.offset + edit.len;
+        if end <= out.len() && out.is_char_boundary(edit.offset) && out.is_char_boundary(end) {
+            out.replace_range(edit.offset..end, &edit.replacement);
+        }
+    }
+    out
+}
+
+/// A declaration statement found in a body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub start: usize,
+    /// Index of the closing `;`
+    pub end: usize,
+    pub names: Vec<String>,
+}
+
+/// Names introduced by the declarators of a declaration statement, e.g.
+/// `a` and `b` for `static int a [ 4 ] , * b = 0 ;`
+pub fn declared_names(tokens: &[Token]) -> Vec<String> {
+    let statement = match tokens.last() {
+        Some(last) if last.is(";") => &tokens[..tokens.len() - 1],
+        _ => tokens,
+    };
+
+    split_top_level(statement, ",")
+        .into_iter()
+        .filter_map(|declarator| {
+            let mut declarator = split_top_level(declarator, "=").into_iter().next()?;
+            // Drop array extents and the parameter list of a function pointer
+            while let Some(last) = declarator.last()
+                && (last.is("]") || last.is(")"))
+            {
+                let open = matching_open(declarator, declarator.len() - 1)?;
+                let is_suffix = last.is("]") || declarator.get(open.wrapping_sub(1)).is_some_and(|t| t.is(")"));
+                if !is_suffix {
+                    break;
+                }
+                declarator = &declarator[..open];
+            }
+            declarator.iter().rev()
+                .find(|t| is_identifier(&t.text) && !DECLARATION_KEYWORDS.contains(&t.text.as_str()))
+                .map(|t| t.text.clone())
+        })
+        .collect()
+}
+
+/// Declarations of `static` locals, in body order
+pub fn static_local_declarations(tokens: &[Token]) -> Vec<Declaration> {
+    let mut declarations = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let at_statement_start = i > 0 && matches!(tokens[i - 1].text.as_str(), "{" | "}" | ";" | ":");
+        if !token.is("static") || !at_statement_start {
+            continue;
+        }
+        if let Some(end) = statement_end(tokens, i) {
+            declarations.push(Declaration {
+                start: i,
+                end,
+                names: declared_names(&tokens[i..=end]),
+            });
+        }
+    }
+    declarations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `source` with the loop around the first call to `function` lowered
+    fn lower_loop(source: &str, function: &str) -> Option<String> {
+        let tokens = tokenize(source);
+        let index = tokens.iter().position(|t| t.is(function))?;
+        lower_loop_condition(&tokens, index, "next").map(|lowered| text(&lowered))
+    }
+
+    fn text(tokens: &[Token]) -> String {
+        tokens.iter().map(|t| t.text.as_str()).collect::<Vec<_>>().join(" ")
+    }
+
+    /// A callee body with its labels renamed as the inliner does for the
+    /// call numbered `id`
+    fn inlined_body(body: &str, id: usize) -> Vec<Token> {
+        let mut tokens = tokenize(body);
+        for label in label_names(&tokenize(body)) {
+            rename_label(&mut tokens, &label, &format!("inl_{}_lbl_{}", id, label));
+        }
+        tokens
+    }
+
+    #[test]
+    fn renames_labels_of_each_inlined_copy() {
+        let body = "{ for ( i = 0 ; i < n ; i ++ ) { if ( buf [ i ] == 0 ) goto found ; } return - 1 ; found : return i ; }";
+        let mut twice = inlined_body(body, 0);
+        twice.extend(inlined_body(body, 1));
+        assert_eq!(label_names(&twice), ["inl_0_lbl_found", "inl_1_lbl_found"]);
+        assert_eq!(
+            text(&inlined_body(body, 1)),
+            "{ for ( i = 0 ; i < n ; i ++ ) { if ( buf [ i ] == 0 ) goto inl_1_lbl_found ; } return - 1 ; inl_1_lbl_found : return i ; }"
+        );
+    }
+
+    #[test]
+    fn renames_labels_beside_case_and_default() {
+        let body = "{ switch ( c ) { case 1 : retry : step ( ) ; if ( busy ( ) ) goto retry ; break ; \
+                    case DONE : default : goto out ; } out : ; }";
+        assert_eq!(label_names(&tokenize(body)), ["retry", "out"]);
+        assert_eq!(
+            text(&inlined_body(body, 0)),
+            "{ switch ( c ) { case 1 : inl_0_lbl_retry : step ( ) ; if ( busy ( ) ) goto inl_0_lbl_retry ; break ; \
+             case DONE : default : goto inl_0_lbl_out ; } inl_0_lbl_out : ; }"
+        );
+    }
+
+    #[test]
+    fn leaves_variables_named_like_labels() {
+        assert_eq!(
+            text(&inlined_body("{ int out = 0 ; goto out ; out : return out ; }", 2)),
+            "{ int out = 0 ; goto inl_2_lbl_out ; inl_2_lbl_out : return out ; }"
+        );
+    }
+
+    #[test]
+    fn lowers_while_condition() {
+        assert_eq!(
+            lower_loop("while ( poll ( ) ) step ( ) ;", "poll").unwrap(),
+            "for ( ; ; ) { if ( ! ( poll ( ) ) ) break ; step ( ) ; }"
+        );
+    }
+
+    #[test]
+    fn lowers_for_condition_with_continue() {
+        assert_eq!(
+            lower_loop("for ( i = 0 ; poll ( i ) ; i ++ ) { if ( skip ( i ) ) continue ; step ( i ) ; }", "poll").unwrap(),
+            "{ i = 0 ; for ( ; ; ) { if ( ! ( poll ( i ) ) ) break ; \
+             { if ( skip ( i ) ) goto next ; step ( i ) ; } next : ; i ++ ; } }"
+        );
+    }
+
+    #[test]
+    fn lowers_for_step() {
+        assert_eq!(
+            lower_loop("for ( ; ; advance ( ) ) step ( ) ;", "advance").unwrap(),
+            "for ( ; ; ) { step ( ) ; advance ( ) ; }"
+        );
+    }
+
+    #[test]
+    fn lowers_do_while_condition_with_continue() {
+        assert_eq!(
+            lower_loop("do { if ( skip ( ) ) continue ; step ( ) ; } while ( poll ( ) ) ; done ( ) ;", "poll").unwrap(),
+            "for ( ; ; ) { { if ( skip ( ) ) goto next ; step ( ) ; } next : ; if ( ! ( poll ( ) ) ) break ; } done ( ) ;"
+        );
+    }
+
+    #[test]
+    fn lowers_do_while_without_braces() {
+        assert_eq!(
+            lower_loop("do step ( ) ; while ( poll ( ) ) ;", "poll").unwrap(),
+            "for ( ; ; ) { step ( ) ; if ( ! ( poll ( ) ) ) break ; }"
+        );
+        assert_eq!(
+            lower_loop("do if ( ready ( ) ) step ( ) ; else wait ( ) ; while ( poll ( ) ) ;", "poll").unwrap(),
+            "for ( ; ; ) { if ( ready ( ) ) step ( ) ; else wait ( ) ; if ( ! ( poll ( ) ) ) break ; }"
+        );
+    }
+
+    #[test]
+    fn leaves_continue_of_nested_loops() {
+        assert_eq!(
+            lower_loop("do { while ( busy ( ) ) continue ; } while ( poll ( ) ) ;", "poll").unwrap(),
+            "for ( ; ; ) { { while ( busy ( ) ) continue ; } if ( ! ( poll ( ) ) ) break ; }"
+        );
+    }
+
+    #[test]
+    fn tells_while_loop_from_preceding_do_while() {
+        assert_eq!(
+            lower_loop("do step ( ) ; while ( more ( ) ) ; while ( poll ( ) ) ;", "poll").unwrap(),
+            "do step ( ) ; while ( more ( ) ) ; for ( ; ; ) { if ( ! ( poll ( ) ) ) break ; ; }"
+        );
+    }
+
+    #[test]
+    fn ignores_calls_outside_loop_headers() {
+        assert_eq!(lower_loop("while ( x ) poll ( ) ;", "poll"), None);
+    }
+
+    /// `source` with the short-circuit guarding the first call to `f` lowered
+    fn lower_guard(source: &str) -> Option<String> {
+        let tokens = tokenize(source);
+        let index = tokens.iter().position(|t| t.is("f"))?;
+        lower_short_circuit(&tokens, index, "sc").map(|lowered| text(&lowered))
+    }
+
+    #[test]
+    fn lowers_guarded_call() {
+        assert_eq!(
+            lower_guard("x = y && f ( y ) ;").unwrap(),
+            "{ int sc = ( y ) != 0 ; if ( sc ) { sc = ( f ( y ) ) != 0 ; } x = sc ; }",
+        );
+    }
+
+    #[test]
+    fn lowers_beside_unsequenced_operands() {
+        assert!(lower_guard("x = g ( a , b ) + ( y && f ( y ) ) ;").is_some());
+        assert!(lower_guard("x = ( y = g ( ) , y ) + ( y || f ( y ) ) ;").is_some());
+    }
+
+    #[test]
+    fn leaves_guards_after_comma_operators() {
+        assert_eq!(lower_guard("x = ( y = f1 ( ) , y && f ( y ) ) ;"), None);
+        assert_eq!(lower_guard("y = f1 ( ) , x = y && f ( y ) ;"), None);
+        assert_eq!(lower_guard("int y = f1 ( ) , x = y && f ( y ) ;"), None);
+        assert_eq!(lower_guard("return ( y = f1 ( ) , g ( y && f ( y ) ) ) ;"), None);
+    }
+}
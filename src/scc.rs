@@ -0,0 +1,82 @@
+/// Strongly connected components of the graph whose node `n` has an edge to
+/// each node of `successors[n]`, by Tarjan's algorithm. Components come
+/// callees first: none has an edge into a later one. Nodes are visited with
+/// an explicit stack, so deep call chains can't overflow the thread's.
+pub fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; successors.len()];
+    let mut lowlink = vec![0; successors.len()];
+    let mut on_stack = vec![false; successors.len()];
+    let mut stack = Vec::new();
+    let mut components = Vec::new();
+    let mut next_index = 0;
+    // Nodes being visited, with the position of the next successor to follow
+    let mut work: Vec<(usize, usize)> = Vec::new();
+
+    for root in 0..successors.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        work.push((root, 0));
+        while let Some((node, position)) = work.pop() {
+            if position == 0 {
+                index[node] = next_index;
+                lowlink[node] = next_index;
+                next_index += 1;
+                stack.push(node);
+                on_stack[node] = true;
+            }
+            if let Some(&successor) = successors[node].get(position) {
+                work.push((node, position + 1));
+                if index[successor] == UNVISITED {
+                    work.push((successor, 0));
+                } else if on_stack[successor] {
+                    lowlink[node] = lowlink[node].min(index[successor]);
+                }
+                continue;
+            }
+
+            if lowlink[node] == index[node] {
+                let mut component = Vec::new();
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+            if let Some(&(caller, _)) = work.last() {
+                lowlink[caller] = lowlink[caller].min(lowlink[node]);
+            }
+        }
+    }
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut components: Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+        components.iter_mut().for_each(|component| component.sort());
+        components
+    }
+
+    #[test]
+    fn finds_cycles_callees_first() {
+        // 0 -> 1 -> 2 -> 1, 2 -> 3, 3 -> 3
+        let successors = vec![vec![1], vec![2], vec![1, 3], vec![3]];
+        assert_eq!(sorted(strongly_connected_components(&successors)), vec![vec![3], vec![1, 2], vec![0]]);
+    }
+
+    #[test]
+    fn follows_deep_chains() {
+        let depth = 200_000;
+        let successors: Vec<Vec<usize>> = (0..depth).map(|i| if i + 1 < depth { vec![i + 1] } else { vec![0] }).collect();
+        let components = strongly_connected_components(&successors);
+        assert_eq!(components.len(), 1);
+        assert_eq!(components[0].len(), depth);
+    }
+}
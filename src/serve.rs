@@ -0,0 +1,409 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::call_graph::{BuildOptions, CallGraph};
+use crate::inliner::{ExpansionMode, InlineOptions, Inliner};
+use crate::parser::function_db::{Definition, FunctionDatabase};
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// A request the database cannot answer, e.g. for an unknown function
+const QUERY_FAILED: i64 = -32000;
+
+/// Largest message body read after a `Content-Length` header
+const MAX_CONTENT_LENGTH: usize = 64 << 20;
+
+/// How a message came, and its response goes back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// One message per line
+    Lines,
+    /// After a `Content-Length` header, as in the Language Server Protocol
+    Headers,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError { code: QUERY_FAILED, message: e.to_string() }
+    }
+}
+
+/// Parameters naming the function a query is about
+#[derive(Debug, Deserialize)]
+struct FunctionParams {
+    function: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallersParams {
+    function: String,
+    /// File of the definition, to pick among same-named statics
+    file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtreeParams {
+    function: String,
+    /// Levels of calls to follow, all when unset
+    depth: Option<usize>,
+}
+
+/// The subset of `InlineOptions` an `expand` request may set
+#[derive(Debug, Deserialize)]
+struct ExpandParams {
+    function: String,
+    #[serde(default)]
+    exclude: Vec<String>,
+    mode: Option<String>,
+    #[serde(default)]
+    annotate_calls: bool,
+    #[serde(default)]
+    expand_macros: bool,
+    #[serde(default)]
+    doc_comments: bool,
+}
+
+/// Answer the JSON-RPC 2.0 requests read from `input` about the functions
+/// of `db` on `output`, until input ends or a `shutdown` request. Messages
+/// come one per line, or framed with Content-Length headers as in LSP, and
+/// each response is framed as its request was. Methods, all taking a
+/// `function` parameter:
+///
+/// - `callers`: the calls reaching its definition, with the calling
+///   function and location; `file` picks among definitions sharing its name
+/// - `callees`: its calls, with where each callee is defined
+/// - `expand`: its body with its callees inlined, taking `exclude`,
+///   `mode`, `annotate_calls`, `expand_macros` and `doc_comments` as the
+///   `inline` command does
+/// - `subtree`: the call graph from it, as `graph --format json` writes,
+///   down to `depth` levels of calls when given
+pub fn run(db: &FunctionDatabase, mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    tracing::info!("Answering queries about {} functions on stdin", db.iter().count());
+    while let Some((message, framing)) = read_message(&mut input)? {
+        let parsed = message.and_then(|text| serde_json::from_str::<Value>(&text).map_err(parse_error));
+        let request = match parsed {
+            Ok(request) => request,
+            Err(error) => {
+                write_message(&mut output, &response(Value::Null, Err(error)), framing)?;
+                continue;
+            }
+        };
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let error = RpcError { code: INVALID_REQUEST, message: "Expected an object with a method".to_string() };
+            write_message(&mut output, &response(request.get("id").cloned().unwrap_or(Value::Null), Err(error)), framing)?;
+            continue;
+        };
+        let params = request.get("params").cloned().unwrap_or(Value::Null);
+        tracing::debug!("{} {}", method, params);
+        let result = if method == "shutdown" { Ok(Value::Null) } else { answer(db, method, params) };
+        // Notifications, without an id, get no response
+        if let Some(id) = request.get("id") {
+            write_message(&mut output, &response(id.clone(), result), framing)?;
+        }
+        if method == "shutdown" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn answer(db: &FunctionDatabase, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+    match method {
+        "callers" => {
+            let CallersParams { function, file } = parse_params(params)?;
+            let target = match &file {
+                Some(file) => Some(db.definition_in(&function, file).ok_or_else(|| RpcError {
+                    code: QUERY_FAILED,
+                    message: format!("No definition of {} in {}", function, file.display()),
+                })?),
+                None => db.find_function(&function)?,
+            };
+            // Without a definition, every call reaches the same external symbol
+            let reaches = |def: &Definition, name: &str| match (&target, db.resolve_call(name, &def.source_file)) {
+                (Some(target), Some(callee)) => Arc::ptr_eq(target, &callee),
+                (None, callee) => callee.is_none(),
+                (Some(_), None) => false,
+            };
+            let callers: Vec<Value> = db.iter()
+                .flat_map(|def| def.calls.iter()
+                    .filter(|call| call.function_name == function && reaches(&def, &call.function_name))
+                    .map(|call| json!({
+                        "caller": def.signature.name,
+                        "file": def.source_file,
+                        "line": call.line,
+                        "column": call.column,
+                    }))
+                    .collect::<Vec<_>>())
+                .collect();
+            Ok(Value::Array(callers))
+        }
+        "callees" => {
+            let FunctionParams { function } = parse_params(params)?;
+            let def = find_function(db, &function)?;
+            let callees: Vec<Value> = def.calls.iter()
+                .map(|call| {
+                    let callee = db.resolve_call(&call.function_name, &def.source_file);
+                    json!({
+                        "callee": call.function_name,
+                        "file": def.source_file,
+                        "line": call.line,
+                        "column": call.column,
+                        "definition": callee.map(|callee| json!({ "file": callee.source_file, "line": callee.line })),
+                    })
+                })
+                .collect();
+            Ok(Value::Array(callees))
+        }
+        "expand" => {
+            let params: ExpandParams = parse_params(params)?;
+            let mode = params.mode.as_deref()
+                .map(str::parse::<ExpansionMode>)
+                .transpose()
+                .map_err(|message| RpcError { code: INVALID_PARAMS, message })?;
+            let options = InlineOptions {
+                exclude: params.exclude.into_iter().collect::<HashSet<_>>(),
+                mode: mode.unwrap_or_default(),
+                annotate_calls: params.annotate_calls,
+                expand_macros: params.expand_macros,
+                doc_comments: params.doc_comments,
+                ..Default::default()
+            };
+            let expansion = Inliner::new(db, options).expand(&params.function)?;
+            Ok(json!({
+                "function": expansion.entry_point,
+                "source": expansion.source,
+                "inlined": expansion.inlined_count(),
+            }))
+        }
+        "subtree" => {
+            let SubtreeParams { function, depth } = parse_params(params)?;
+            find_function(db, &function)?;
+            let options = BuildOptions { max_depth: depth, ..Default::default() };
+            let graph = CallGraph::build_from_roots(db, &function, std::slice::from_ref(&function), &options)?;
+            Ok(graph.json_value()?)
+        }
+        _ => Err(RpcError { code: METHOD_NOT_FOUND, message: format!("Unknown method {}", method) }),
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> std::result::Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError { code: INVALID_PARAMS, message: e.to_string() })
+}
+
+fn find_function(db: &FunctionDatabase, name: &str) -> std::result::Result<Arc<Definition>, RpcError> {
+    db.find_function(name)?
+        .ok_or_else(|| RpcError { code: QUERY_FAILED, message: format!("No definition found for {}", name) })
+}
+
+fn response(id: Value, result: std::result::Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } }),
+    }
+}
+
+/// The next message of `input`, None at its end. A message whose length or
+/// text can't be read comes as the error to answer it with.
+fn read_message(input: &mut impl BufRead) -> Result<Option<(std::result::Result<String, RpcError>, Framing)>> {
+    loop {
+        let mut line = Vec::new();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = match String::from_utf8(line) {
+            Ok(line) => line,
+            Err(e) => return Ok(Some((Err(parse_error(e)), Framing::Lines))),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some((_, length)) = line.split_once(':').filter(|(name, _)| name.trim().eq_ignore_ascii_case("content-length")) else {
+            return Ok(Some((Ok(line.to_string()), Framing::Lines)));
+        };
+        let length = length.trim().parse::<usize>();
+        // Other headers, up to the blank line ending them
+        loop {
+            let mut header = Vec::new();
+            if input.read_until(b'\n', &mut header)? == 0 {
+                return Ok(None);
+            }
+            if header.trim_ascii().is_empty() {
+                break;
+            }
+        }
+        // Without a length the body can't be skipped; it is read as the
+        // messages of the lines it spans
+        let length = match length {
+            Ok(length) => length,
+            Err(e) => return Ok(Some((Err(parse_error(format!("Invalid Content-Length: {}", e))), Framing::Headers))),
+        };
+        if length > MAX_CONTENT_LENGTH {
+            // Skipped without holding it, to go on with the next message
+            std::io::copy(&mut input.by_ref().take(length as u64), &mut std::io::sink())?;
+            let message = format!("Content-Length {} is over the limit of {} bytes", length, MAX_CONTENT_LENGTH);
+            return Ok(Some((Err(parse_error(message)), Framing::Headers)));
+        }
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        return Ok(Some((String::from_utf8(body).map_err(parse_error), Framing::Headers)));
+    }
+}
+
+fn parse_error(e: impl std::fmt::Display) -> RpcError {
+    RpcError { code: PARSE_ERROR, message: e.to_string() }
+}
+
+fn write_message(output: &mut impl Write, message: &Value, framing: Framing) -> Result<()> {
+    let text = serde_json::to_string(message)?;
+    match framing {
+        Framing::Lines => writeln!(output, "{}", text)?,
+        Framing::Headers => write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?,
+    }
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parser::function_db::{CallInfo, Signature};
+
+    fn function(name: &str, file: &str, is_static: bool, callees: &[&str]) -> Arc<Definition> {
+        Arc::new(Definition {
+            signature: Signature { name: name.to_string(), return_type: "void".to_string(), ..Default::default() },
+            body: "{ }".to_string(),
+            source_file: PathBuf::from(file),
+            is_static,
+            calls: callees.iter()
+                .enumerate()
+                .map(|(i, callee)| CallInfo { function_name: callee.to_string(), line: 2 + i as u32, column: 5, ..Default::default() })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// `main` in a.c and `other` in b.c, each calling the static `helper`
+    /// of its own file
+    fn database() -> FunctionDatabase {
+        let mut db = FunctionDatabase::new();
+        db.add_function(function("main", "a.c", false, &["helper"]));
+        db.add_function(function("helper", "a.c", true, &[]));
+        // Another body, or the two would count as copies of one header's
+        db.add_function(Arc::new(Definition { body_hash: 1, ..(*function("helper", "b.c", true, &[])).clone() }));
+        db.add_function(function("other", "b.c", false, &["helper"]));
+        db
+    }
+
+    fn serve(input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        run(&database(), input, &mut output).unwrap();
+        output
+    }
+
+    fn lines(output: &[u8]) -> Vec<Value> {
+        std::str::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    fn frames(mut output: &[u8]) -> Vec<Value> {
+        let mut messages = Vec::new();
+        while !output.is_empty() {
+            let text = std::str::from_utf8(output).unwrap();
+            let (header, rest) = text.split_once("\r\n\r\n").unwrap();
+            let length: usize = header.strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+            messages.push(serde_json::from_str(&rest[..length]).unwrap());
+            output = &rest.as_bytes()[length..];
+        }
+        messages
+    }
+
+    fn framed(body: &[u8]) -> Vec<u8> {
+        let mut message = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        message.extend(body);
+        message
+    }
+
+    #[test]
+    fn answers_line_framed_requests_until_shutdown() {
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"callers","params":{"function":"helper","file":"a.c"}}"#, "\n",
+            "not json\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"nope"}"#, "\n",
+            r#"{"jsonrpc":"2.0","method":"callers","params":{"function":"helper"}}"#, "\n",
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#, "\n",
+            r#"{"jsonrpc":"2.0","id":4,"method":"callers","params":{"function":"helper"}}"#, "\n",
+        );
+        let responses = lines(&serve(input.as_bytes()));
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["id"], 1);
+        let callers: Vec<&Value> = responses[0]["result"].as_array().unwrap().iter().map(|caller| &caller["caller"]).collect();
+        assert_eq!(callers, ["main"]);
+        assert_eq!(responses[1]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[2]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[3], json!({ "jsonrpc": "2.0", "id": 3, "result": null }));
+    }
+
+    #[test]
+    fn answers_header_framed_requests_past_unreadable_ones() {
+        let mut input = b"Content-Length: many\r\n\r\n".to_vec();
+        input.extend(framed(b"\xff\xfe"));
+        input.extend(framed(br#"{"jsonrpc":"2.0","id":1,"method":"callees","params":{"function":"main"}}"#));
+        let responses = frames(&serve(&input));
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[1]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[2]["id"], 1);
+    }
+
+    #[test]
+    fn resolves_callees_from_the_calling_file() {
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"callees","params":{"function":"main"}}"#, "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"callees","params":{"function":"other"}}"#, "\n",
+        );
+        let responses = lines(&serve(input.as_bytes()));
+        assert_eq!(responses[0]["result"][0]["definition"]["file"], "a.c");
+        assert_eq!(responses[1]["result"][0]["definition"]["file"], "b.c");
+    }
+
+    #[test]
+    fn finds_callers_of_the_definition_in_the_file() {
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"callers","params":{"function":"helper","file":"b.c"}}"#, "\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"callers","params":{"function":"helper"}}"#, "\n",
+            r#"{"jsonrpc":"2.0","id":3,"method":"callers","params":{"function":"helper","file":"c.c"}}"#, "\n",
+        );
+        let responses = lines(&serve(input.as_bytes()));
+        assert_eq!(responses[0]["result"], json!([{ "caller": "other", "file": "b.c", "line": 2, "column": 5 }]));
+        assert_eq!(responses[1]["error"]["code"], QUERY_FAILED);
+        assert_eq!(responses[2]["error"]["code"], QUERY_FAILED);
+    }
+
+    #[test]
+    fn refuses_bodies_over_the_length_limit() {
+        let mut input = format!("Content-Length: {}\r\n\r\n", MAX_CONTENT_LENGTH + 1).into_bytes();
+        input.extend(vec![b' '; MAX_CONTENT_LENGTH + 1]);
+        input.extend(framed(br#"{"jsonrpc":"2.0","id":1,"method":"callees","params":{"function":"main"}}"#));
+        let responses = frames(&serve(&input));
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(responses[1]["id"], 1);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::compile_commands::CompileEntry;
+
+/// Defines builds commonly pass, each guessed when a file name ends with
+/// the given text and some file of the tree tests the define
+const GUESSED_DEFINES: [(&str, &str); 3] = [
+    ("HAVE_CONFIG_H", "config.h"),
+    ("USE_HAL_DRIVER", "_hal.h"),
+    ("USE_FULL_LL_DRIVER", "_ll_utils.h"),
+];
+
+/// Best-effort compile commands for a tree without a build system: every C
+/// file compiled with every directory holding headers as an include path,
+/// shallowest first, and the defines `guess_defines` finds. Hidden
+/// directories, such as `.git`, are left out.
+pub fn scan(directory: &Path) -> Result<Vec<CompileEntry>> {
+    let mut files = Vec::new();
+    walk(directory, &mut files)?;
+    files.sort();
+
+    let mut include_dirs: Vec<&Path> = files.iter()
+        .filter(|file| file.extension().is_some_and(|extension| extension == "h"))
+        .filter_map(|header| header.parent())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    include_dirs.sort_by_key(|dir| dir.components().count());
+    let defines = guess_defines(&files);
+    tracing::info!(
+        "Found {} C files and {} directories with headers; guessed defines: {}",
+        files.iter().filter(|file| is_source(file)).count(),
+        include_dirs.len(),
+        if defines.is_empty() { "none".to_string() } else { defines.join(" ") }
+    );
+
+    let flags: Vec<String> = defines.iter()
+        .map(|define| format!("-D{}", define))
+        .chain(include_dirs.iter().map(|dir| format!("-I{}", dir.display())))
+        .collect();
+    Ok(files.iter()
+        .filter(|file| is_source(file))
+        .map(|file| CompileEntry::new(directory, "clang", &flags, file.clone()))
+        .collect())
+}
+
+fn is_source(file: &Path) -> bool {
+    file.extension().is_some_and(|extension| extension == "c")
+}
+
+/// Add the C sources and headers under `directory` to `files`
+fn walk(directory: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(directory)?.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&path, files)?;
+        } else if path.extension().is_some_and(|extension| matches!(extension.to_str(), Some("c" | "h" | "s" | "S"))) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The defines of `GUESSED_DEFINES` the tree suggests, and the STM32 device
+/// its startup file is named after, e.g. `STM32F407xx` for
+/// `startup_stm32f407xx.s`, which the device headers require
+fn guess_defines(files: &[PathBuf]) -> Vec<String> {
+    let name = |file: &PathBuf| file.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let mut candidates: Vec<&str> = GUESSED_DEFINES.iter()
+        .filter(|(_, suffix)| files.iter().any(|file| name(file).ends_with(suffix)))
+        .map(|(define, _)| *define)
+        .collect();
+    let mut defines = Vec::new();
+    for file in files.iter().filter(|file| file.extension().is_some_and(|extension| extension == "c" || extension == "h")) {
+        if candidates.is_empty() {
+            break;
+        }
+        let Ok(text) = std::fs::read_to_string(file) else {
+            continue;
+        };
+        candidates.retain(|define| {
+            let tested = text.contains(define);
+            if tested {
+                defines.push(define.to_string());
+            }
+            !tested
+        });
+    }
+
+    let device = files.iter()
+        .filter_map(|file| name(file).strip_prefix("startup_")?.strip_suffix(".s").map(str::to_string))
+        .find(|device| device.starts_with("stm32"));
+    if let Some(device) = device {
+        // Upper case but for the trailing x standing for any package
+        let model = device.trim_end_matches('x');
+        defines.push(format!("{}{}", model.to_uppercase(), &device[model.len()..]));
+    }
+    defines
+}
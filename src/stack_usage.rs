@@ -0,0 +1,118 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::call_depth::{ChainAnalysis, ChainFrame};
+use crate::call_graph::CallGraph;
+use crate::parser::types::DataModel;
+
+/// A frame of the critical path, with its share of the stack
+#[derive(Debug, Clone, Serialize)]
+pub struct StackFrame {
+    #[serde(flatten)]
+    pub frame: ChainFrame,
+    /// Estimated frame of the function, or of the whole recursion group
+    pub frame_size: usize,
+    /// Stack used from the root down to and including this frame
+    pub cumulative: usize,
+}
+
+/// Worst-case stack depth from one root
+#[derive(Debug, Clone, Serialize)]
+pub struct RootStackUsage {
+    pub root: String,
+    pub worst_case: usize,
+    pub critical_path: Vec<StackFrame>,
+    /// Functions on the critical path sizing their stack at run time
+    pub dynamic: Vec<String>,
+    /// Functions on the critical path whose frame is unknown
+    pub external: Vec<String>,
+}
+
+/// Static estimate of the stack each root needs, from the frame sizes of the
+/// locals of each function along the heaviest call chain. Saved registers,
+/// spills and return addresses are not counted, nor are interrupt frames.
+#[derive(Debug, Clone, Serialize)]
+pub struct StackReport {
+    /// How many times a recursion group is assumed to nest
+    pub recursion_depth: usize,
+    pub roots: Vec<RootStackUsage>,
+}
+
+impl StackReport {
+    /// Frames are sized for `data_model` when given, as clang laid them out
+    /// for the parsed target otherwise
+    pub fn build(call_graph: &CallGraph, recursion_depth: usize, data_model: Option<DataModel>) -> Self {
+        let frame_size = |name: &str| call_graph.node(name)
+            .map(|node| node.function.frame_size(data_model))
+            .unwrap_or(0);
+        let analysis = ChainAnalysis::new(call_graph, recursion_depth, |node| node.function.frame_size(data_model));
+
+        let mut roots = Vec::new();
+        for root in call_graph.roots() {
+            let Some(worst_case) = analysis.cost(root) else {
+                continue;
+            };
+            let mut cumulative = 0;
+            let mut usage = RootStackUsage {
+                root: root.clone(),
+                worst_case,
+                critical_path: Vec::new(),
+                dynamic: Vec::new(),
+                external: Vec::new(),
+            };
+            for frame in analysis.chain(root) {
+                let members = frame.recursion.clone().unwrap_or_else(|| vec![frame.function.clone()]);
+                let mut size: usize = members.iter().map(|name| frame_size(name)).sum();
+                if frame.recursion.is_some() {
+                    size *= recursion_depth;
+                }
+                for node in members.iter().filter_map(|name| call_graph.node(name)) {
+                    if node.function.signature.return_type == "extern" {
+                        usage.external.push(node.function.signature.name.clone());
+                    } else if node.function.uses_vla || node.function.uses_alloca {
+                        usage.dynamic.push(node.function.signature.name.clone());
+                    }
+                }
+                cumulative += size;
+                usage.critical_path.push(StackFrame { frame, frame_size: size, cumulative });
+            }
+            roots.push(usage);
+        }
+        roots.sort_by_key(|usage| std::cmp::Reverse(usage.worst_case));
+
+        StackReport { recursion_depth, roots }
+    }
+
+    pub fn print(&self) {
+        println!("Stack Usage (locals only, worst case per root):");
+        for usage in &self.roots {
+            println!("  {}: {} bytes", usage.root, usage.worst_case);
+            for step in &usage.critical_path {
+                let location = match &step.frame.file {
+                    Some(file) => format!("{}:{}", file.display(), step.frame.line),
+                    None => "(external)".to_string(),
+                };
+                println!(
+                    "    {:>6} {:>8}  {}  {}",
+                    step.frame_size, step.cumulative, step.frame.function, location
+                );
+                if let Some(group) = &step.frame.recursion {
+                    println!("                     recursion through {}, counted {} deep", group.join(", "), self.recursion_depth);
+                }
+            }
+            if !usage.dynamic.is_empty() {
+                println!("    Warning: unbounded, sized at run time by {}", usage.dynamic.join(", "));
+            }
+            if !usage.external.is_empty() {
+                println!("    Not counted, defined outside the project: {}", usage.external.join(", "));
+            }
+        }
+    }
+
+    pub fn save_json(&self, output_path: &Path) -> Result<()> {
+        std::fs::write(output_path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Colors, fonts and layout of the DOT output. Every field is optional in a
+/// theme file; missing ones keep the light theme's value.
+///
+/// ```toml
+/// rankdir = "LR"
+/// [nodes]
+/// entry = "#90EE90"
+/// [edges.loop]
+/// color = "#4ECDC4"
+/// style = "bold"
+/// [labels]
+/// show_metrics = false
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GraphTheme {
+    pub rankdir: String,
+    pub splines: String,
+    pub fontname: String,
+    pub fontsize: u32,
+    pub background: Option<String>,
+    /// Color of labels and cluster borders, Graphviz's default when unset
+    pub font_color: Option<String>,
+    pub nodes: NodeColors,
+    pub edges: EdgeStyles,
+    pub labels: LabelOptions,
+}
+
+/// Fill color of each kind of node
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NodeColors {
+    pub entry: String,
+    #[serde(rename = "static")]
+    pub static_function: String,
+    pub defined: String,
+    pub external: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EdgeStyle {
+    pub color: String,
+    /// Graphviz edge style such as `dashed` or `bold`, solid when unset
+    pub style: Option<String>,
+}
+
+/// Edge style for each `CallContext`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EdgeStyles {
+    pub sequential: EdgeStyle,
+    pub conditional: EdgeStyle,
+    #[serde(rename = "loop")]
+    pub in_loop: EdgeStyle,
+    pub switch: EdgeStyle,
+    /// Calls on a highlighted path
+    pub highlight: EdgeStyle,
+}
+
+/// What node labels show besides the function name
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LabelOptions {
+    pub show_file: bool,
+    pub show_metrics: bool,
+}
+
+impl Default for GraphTheme {
+    fn default() -> Self {
+        GraphTheme {
+            rankdir: "TB".to_string(),
+            splines: "ortho".to_string(),
+            fontname: "Helvetica".to_string(),
+            fontsize: 10,
+            background: None,
+            font_color: None,
+            nodes: NodeColors::default(),
+            edges: EdgeStyles::default(),
+            labels: LabelOptions::default(),
+        }
+    }
+}
+
+impl Default for NodeColors {
+    fn default() -> Self {
+        NodeColors {
+            entry: "#90EE90".to_string(),
+            static_function: "#FFFACD".to_string(),
+            defined: "#E6F3FF".to_string(),
+            external: "#D3D3D3".to_string(),
+        }
+    }
+}
+
+impl Default for EdgeStyle {
+    fn default() -> Self {
+        EdgeStyle { color: "#333333".to_string(), style: None }
+    }
+}
+
+impl EdgeStyle {
+    fn new(color: &str, style: Option<&str>) -> Self {
+        EdgeStyle { color: color.to_string(), style: style.map(String::from) }
+    }
+}
+
+impl Default for EdgeStyles {
+    fn default() -> Self {
+        EdgeStyles {
+            sequential: EdgeStyle::new("#333333", None),
+            conditional: EdgeStyle::new("#333333", Some("dashed")),
+            in_loop: EdgeStyle::new("#4ECDC4", Some("bold")),
+            switch: EdgeStyle::new("#9B59B6", None),
+            highlight: EdgeStyle::new("#E74C3C", None),
+        }
+    }
+}
+
+impl Default for LabelOptions {
+    fn default() -> Self {
+        LabelOptions { show_file: true, show_metrics: true }
+    }
+}
+
+impl GraphTheme {
+    pub fn dark() -> Self {
+        GraphTheme {
+            background: Some("#1E1E1E".to_string()),
+            font_color: Some("#DDDDDD".to_string()),
+            nodes: NodeColors {
+                entry: "#2E7D32".to_string(),
+                static_function: "#6D5D1F".to_string(),
+                defined: "#1F4E79".to_string(),
+                external: "#4A4A4A".to_string(),
+            },
+            edges: EdgeStyles {
+                sequential: EdgeStyle::new("#BBBBBB", None),
+                conditional: EdgeStyle::new("#BBBBBB", Some("dashed")),
+                in_loop: EdgeStyle::new("#4ECDC4", Some("bold")),
+                switch: EdgeStyle::new("#C39BD3", None),
+                highlight: EdgeStyle::new("#FF6B6B", None),
+            },
+            ..Default::default()
+        }
+    }
+
+    /// A preset name, `light` or `dark`, or the path of a TOML theme file
+    pub fn load(spec: &str) -> Result<Self> {
+        match spec {
+            "light" => Ok(GraphTheme::default()),
+            "dark" => Ok(GraphTheme::dark()),
+            path => {
+                let text = std::fs::read_to_string(Path::new(path))
+                    .map_err(|e| anyhow::anyhow!("Cannot read theme {}: {}", path, e))?;
+                toml::from_str(&text).map_err(|e| anyhow::anyhow!("Invalid theme {}: {}", path, e))
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::Result;
+
+use crate::inliner::Expansion;
+use crate::parser::function_db::{Definition, TranslationUnitInfo};
+
+/// Outcome of compiling an expansion with its translation unit's command
+pub struct Verification {
+    pub source: PathBuf,
+    pub success: bool,
+    pub diagnostics: String,
+}
+
+/// Append the expansion, renamed to `<entry>_expanded`, to a copy of the file
+/// defining the entry point and check it with the original compile command,
+/// from the original directory, with the variables the build ran it with
+/// over this process's environment
+pub fn verify_expansion(
+    unit: &TranslationUnitInfo,
+    entry: &Definition,
+    expansion: &Expansion,
+    output_dir: &Path,
+) -> Result<Verification> {
+    let original = std::fs::read_to_string(&entry.source_file)?;
+    let name = &entry.signature.name;
+    let renamed = rename_definition(&expansion.source, name, &format!("{}_expanded", name))
+        .ok_or_else(|| anyhow::anyhow!("No definition of {} in the expansion", name))?;
+
+    let stem = entry.source_file.file_stem().and_then(|s| s.to_str()).unwrap_or("entry");
+    let source = output_dir.join(format!("{}_verify.c", stem));
+    std::fs::write(&source, format!("{}\n{}", original, renamed))?;
+    let source = source.canonicalize()?;
+
+    let (compiler, arguments) = unit.arguments.split_first()
+        .ok_or_else(|| anyhow::anyhow!("Empty compile command for {}", unit.file.display()))?;
+    let output = Command::new(compiler)
+        .args(syntax_only_arguments(arguments, &unit.file, &unit.directory))
+        .arg(&source)
+        .current_dir(&unit.directory)
+        .envs(&unit.environment)
+        .output()
+        .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", compiler, e))?;
+
+    Ok(Verification {
+        source,
+        success: output.status.success(),
+        diagnostics: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// `source` with the name of the function it defines at file scope, the
+/// first `name` token there followed by `(`, changed to `new`. Comments and
+/// literals are skipped, and the rest of the text is kept as it is.
+fn rename_definition(source: &str, name: &str, new: &str) -> Option<String> {
+    let bytes = source.as_bytes();
+    let is_identifier = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    let skip_space = |mut i: usize| {
+        loop {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if bytes[i..].starts_with(b"/*") {
+                i = source[i + 2..].find("*/").map_or(bytes.len(), |end| i + 2 + end + 2);
+            } else if bytes[i..].starts_with(b"//") {
+                i = source[i..].find('\n').map_or(bytes.len(), |end| i + end);
+            } else {
+                return i;
+            }
+        }
+    };
+
+    let mut depth = 0usize;
+    let mut i = skip_space(0);
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'"' | b'\'' => {
+                let quote = bytes[i];
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+            }
+            b if is_identifier(b) => {
+                while i < bytes.len() && is_identifier(bytes[i]) {
+                    i += 1;
+                }
+                let next = skip_space(i);
+                if depth == 0 && &source[start..i] == name && bytes.get(next) == Some(&b'(') {
+                    return Some(format!("{}{}{}", &source[..start], new, &source[i..]));
+                }
+            }
+            b'{' | b'(' | b'[' => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' | b')' | b']' => {
+                depth = depth.saturating_sub(1);
+                i += 1;
+            }
+            _ => i += 1,
+        }
+        i = skip_space(i.min(bytes.len()));
+    }
+    None
+}
+
+/// The original arguments without the input file and output options, set up
+/// to only check syntax and types
+fn syntax_only_arguments(arguments: &[String], file: &Path, directory: &Path) -> Vec<String> {
+    let mut kept = Vec::new();
+    let mut skip_next = false;
+    for argument in arguments {
+        if skip_next {
+            skip_next = false;
+            continue;
+        }
+        match argument.as_str() {
+            "-o" | "-MF" | "-MT" | "-MQ" => skip_next = true,
+            "-c" | "-M" | "-MM" | "-MD" | "-MMD" | "-MP" => {}
+            _ if argument.starts_with("-o") => {}
+            _ if directory.join(argument).canonicalize().is_ok_and(|path| path == file) => {}
+            _ => kept.push(argument.clone()),
+        }
+    }
+    kept.push("-fsyntax-only".to_string());
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_only_the_defined_function() {
+        let source = "/* main( with its callees */\n\
+                      static const char *s = \"main(\";\n\
+                      int main_loop;\n\
+                      int main (void)\n\
+                      { return main(); }\n";
+        assert_eq!(
+            rename_definition(source, "main", "main_expanded").unwrap(),
+            "/* main( with its callees */\n\
+             static const char *s = \"main(\";\n\
+             int main_loop;\n\
+             int main_expanded (void)\n\
+             { return main(); }\n",
+        );
+    }
+
+    #[test]
+    fn skips_calls_in_initializers() {
+        let source = "int (*handlers[])(void) = { f(0) };\nint f(int x) { return x; }";
+        assert_eq!(
+            rename_definition(source, "f", "f_expanded").unwrap(),
+            "int (*handlers[])(void) = { f(0) };\nint f_expanded(int x) { return x; }",
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! Setup shared by the tests parsing the projects of tests/fixtures.
+//!
+//! libclang allows one instance per process, so each test file has a single
+//! test that parses its fixture and checks everything.
+
+use std::path::Path;
+
+use expansion::Project;
+use expansion::compile_commands::{self, CompileEntry};
+
+/// The project of tests/fixtures/`name`, copied where its compile commands
+/// can name it by absolute path, with one command per C file
+pub fn parse_fixture(name: &str) -> Project {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+    let root = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::create_dir_all(&root).unwrap();
+    let mut files: Vec<_> = std::fs::read_dir(&fixture).unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|file| Path::new(file).extension().is_some_and(|extension| extension == "c"))
+        .collect();
+    files.sort();
+    let entries: Vec<CompileEntry> = files.iter()
+        .map(|file| {
+            std::fs::copy(fixture.join(file), root.join(file)).unwrap();
+            CompileEntry::new(&root, "clang", &[], root.join(file))
+        })
+        .collect();
+    compile_commands::write(&entries, &root.join("compile_commands.json")).unwrap();
+    Project::open(&root).unwrap()
+}
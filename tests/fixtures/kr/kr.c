@@ -0,0 +1,22 @@
+/* Old-style definitions, as vendor BSPs still have them */
+
+int add(a, b)
+    int a;
+    char *b;
+{
+    return a + b[0];
+}
+
+double mean(x, n)
+    float x;
+    char n;
+{
+    return x / n;
+}
+
+int scale(f, c)
+    float f;
+    char c;
+{
+    return (int)(f * c);
+}
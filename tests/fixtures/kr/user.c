@@ -0,0 +1,10 @@
+/* Prototypes written without seeing kr.c. Callers pass promoted arguments
+ * to its definitions, so only those of add and mean are right. */
+int add(int, char *);
+double mean(double, int);
+int scale(float, char);
+
+int use(void)
+{
+    return add(1, "x") + (int)mean(2.0, 3) + scale(1.0f, 'c');
+}
@@ -0,0 +1,20 @@
+static void on(void) {}
+static void off(void) {}
+
+static void (*fixed)(void) = on;
+static void (*handler)(void) = on;
+static void (*table[2])(void) = { on, on };
+
+static void set(void (**slot)(void))
+{
+    *slot = off;
+}
+
+void run(void)
+{
+    set(&handler);
+    set(&table[1]);
+    fixed();
+    handler();
+    table[0]();
+}
@@ -0,0 +1,21 @@
+//! Targets of the function pointers of tests/fixtures/pointers
+
+mod common;
+
+use common::parse_fixture;
+
+#[test]
+fn address_taken_pointers_have_unknown_targets() {
+    let project = parse_fixture("pointers");
+    let db = project.database();
+
+    let run = db.get_function_definition("run").unwrap();
+    let target = |callee: &str| {
+        let call = run.calls.iter().find(|call| call.callee_expr.as_deref().is_some_and(|expr| expr.starts_with(callee))).unwrap();
+        db.pointer_target(call.via.as_deref().unwrap())
+    };
+    assert_eq!(target("fixed"), Some("on"));
+    // set may store anything through the address it is given
+    assert_eq!(target("handler"), None);
+    assert_eq!(target("table"), None);
+}
@@ -0,0 +1,34 @@
+//! K&R definitions parsed from tests/fixtures/kr
+
+mod common;
+
+use common::parse_fixture;
+
+#[test]
+fn identifier_list_definitions() {
+    let project = parse_fixture("kr");
+    let db = project.database();
+
+    let parameters = |name: &str| {
+        let def = db.get_function_definition(name).unwrap();
+        assert!(def.signature.is_old_style, "{} is a K&R definition", name);
+        def.signature.args.iter()
+            .map(|arg| (arg.name.clone().unwrap_or_default(), arg.param_type.clone()))
+            .collect::<Vec<_>>()
+    };
+    let owned = |pairs: &[(&str, &str)]| pairs.iter().map(|(a, b)| (a.to_string(), b.to_string())).collect::<Vec<_>>();
+    assert_eq!(parameters("add"), owned(&[("a", "int"), ("b", "char *")]));
+    assert_eq!(parameters("mean"), owned(&[("x", "float"), ("n", "char")]));
+    assert_eq!(parameters("scale"), owned(&[("f", "float"), ("c", "char")]));
+
+    let scale = db.get_function_definition("scale").unwrap();
+    assert_eq!(scale.signature.to_c_definition(), "int scale(f, c)\nfloat f;\nchar c;");
+    assert_eq!(scale.signature.to_c_declaration(), "int scale()");
+
+    // The prototypes of add and mean match once the K&R parameters are
+    // promoted; that of scale declares float and char, which never reach it
+    let mismatches: Vec<String> = db.signature_mismatches().iter()
+        .map(|mismatch| mismatch.declaration.signature.name.clone())
+        .collect();
+    assert_eq!(mismatches, ["scale"]);
+}